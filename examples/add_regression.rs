@@ -0,0 +1,38 @@
+//! Copies an input into the regression corpus as a named fixture.
+//!
+//! Usage: `cargo run --example add_regression -- <name> <input file> <ok|err>`
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+fn main () {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 3 || (args[2] != "ok" && args[2] != "err") {
+        eprintln!("usage: add_regression <name> <input file> <ok|err>");
+        process::exit(2);
+    }
+
+    let (name, input, expect) = (&args[0], &args[1], &args[2]);
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        eprintln!("fixture names may only contain letters, digits, '-' and '_'");
+        process::exit(2);
+    }
+
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("regressions");
+    let target = dir.join(format!("{}.ber", name));
+    if target.exists() {
+        eprintln!("fixture {} already exists", name);
+        process::exit(1);
+    }
+
+    let bytes = fs::read(input).unwrap_or_else(|e| {
+        eprintln!("reading {}: {}", input, e);
+        process::exit(1);
+    });
+
+    fs::write(&target, bytes).unwrap();
+    fs::write(dir.join(format!("{}.expect", name)), format!("{}\n", expect)).unwrap();
+    println!("added {}", target.display());
+}
@@ -0,0 +1,93 @@
+//! A small protocol defined outside the crate through `BerRepr`.
+//!
+//! ```text
+//! Request ::= [APPLICATION 1] SEQUENCE {
+//!     id       INTEGER,
+//!     method   UTF8String,
+//!     params   SEQUENCE OF Param,
+//!     timeout  [0] IMPLICIT INTEGER OPTIONAL }
+//!
+//! Param ::= SEQUENCE { name UTF8String, value UTF8String }
+//! ```
+//!
+//! Usage: `cargo run --example protocol`
+
+use ber::err;
+use ber::repr::{self, BerRepr, Context, Fields};
+use ber::{Number, Tag, TagNumber};
+
+const REQUEST: Number = Number::Application(TagNumber::new(1));
+const TIMEOUT: Number = Number::ContextSpecific(TagNumber::new(0));
+
+#[derive(Debug, PartialEq)]
+struct Param {
+    name:  String,
+    value: String,
+}
+
+impl BerRepr for Param {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        let inner = cx.inner();
+        cx.tag(Tag::sequence(vec![self.name.to_tag(&inner), self.value.to_tag(&inner)]))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Param, err::Error> {
+        let mut fields = Fields::of(tag, cx)?;
+        let param = Param { name: fields.field()?, value: fields.field()? };
+        fields.finish()?;
+        Ok(param)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    id:      i64,
+    method:  String,
+    params:  Vec<Param>,
+    timeout: Option<u64>,
+}
+
+impl BerRepr for Request {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        // the APPLICATION tag is part of the type, so it applies unless the caller
+        // overrides it with its own
+        let cx = Context { implicit: Some(cx.number(REQUEST)), ..*cx };
+        let inner = cx.inner();
+
+        let mut fields = vec![self.id.to_tag(&inner), self.method.to_tag(&inner), self.params.to_tag(&inner)];
+        if let Some(ref timeout) = self.timeout {
+            fields.push(timeout.to_tag(&inner.implicit(TIMEOUT)));
+        }
+        cx.tag(Tag::sequence(fields))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Request, err::Error> {
+        let cx = Context { implicit: Some(cx.number(REQUEST)), ..*cx };
+        let mut fields = Fields::of(tag, &cx)?;
+        let request = Request {
+            id: fields.field()?,
+            method: fields.field()?,
+            params: fields.field()?,
+            timeout: fields.optional(TIMEOUT)?,
+        };
+        fields.finish()?;
+        Ok(request)
+    }
+}
+
+fn main () {
+    let request = Request {
+        id: 42,
+        method: "lookup".into(),
+        params: vec![Param { name: "key".into(), value: "alice".into() }],
+        timeout: Some(30),
+    };
+
+    let mut buf = Vec::new();
+    repr::to_tag(&request).write(&mut buf).unwrap();
+    println!("{}", buf.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+
+    let decoded: Request = repr::from_tag(&Tag::read(&mut &buf[..]).unwrap()).unwrap();
+    assert_eq!(decoded, request);
+    println!("{:?}", decoded);
+}
@@ -0,0 +1,154 @@
+//! Dumps BER structures, or every message in a container file, as an indented tree.
+//!
+//! Usage: `ber-dump [--hex] [--asn1] [--follow] [FILE]`, reading standard input when no
+//! file is given. `--hex` prints tag numbers and offsets in hex, `--asn1` labels tags in
+//! ASN.1 notation such as `[APPLICATION 24]`.
+//!
+//! `--follow` prints each message as soon as all of it has arrived instead of waiting for
+//! the end of the input, and keeps watching FILE for appended messages until killed, for
+//! live-tailing a capture. Standard input is streamed the same way until it ends.
+//!
+//! `ber-dump compare-stats A_DIR B_DIR` instead reads every file under each directory,
+//! skipping with a warning any that don't read, and sets the stats of the two corpora
+//! side by side: totals, and how tags spread over numbers and messages over depths and
+//! sizes, as percentages so corpora of different sizes compare.
+
+extern crate ber;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use ber::format::{FormatOptions, Labels, Radix};
+use ber::registry;
+use ber::ingest::Reassembler;
+use ber::stats::Stats;
+use ber::{container, dump, Tag};
+
+// How long to wait before looking for more of a followed file.
+const POLL: Duration = Duration::from_millis(200);
+
+fn run (input: &[u8], opts: &FormatOptions) -> Result<(), ber::Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let registry = registry::global().read().unwrap_or_else(|e| e.into_inner());
+
+    if container::is_container(input) {
+        for (i, tag) in container::read(&mut &input[..])?.iter().enumerate() {
+            println!("message {}:", i);
+            dump::dump_formatted(tag, &registry, opts, &mut out)?;
+        }
+    } else {
+        dump::dump_formatted(&Tag::read(&mut &input[..])?, &registry, opts, &mut out)?;
+    }
+
+    Ok(())
+}
+
+// Dumps messages from `input` as they complete. At the end of the input, waits for more
+// if `tail` is set, and otherwise stops.
+fn follow (input: &mut dyn Read, tail: bool, opts: &FormatOptions) -> Result<(), ber::Error> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let registry = registry::global().read().unwrap_or_else(|e| e.into_inner());
+
+    let mut reassembler = Reassembler::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            if !tail {
+                return reassembler.finish();
+            }
+            thread::sleep(POLL);
+            continue;
+        }
+
+        reassembler.push(&buf[..n]);
+        while let Some((offset, tag)) = reassembler.next_pdu()? {
+            println!("message at {}:", opts.int(offset));
+            dump::dump_formatted(&tag, &registry, opts, &mut out)?;
+        }
+    }
+}
+
+// Prints the stats of the corpora under `a` and `b` side by side.
+fn compare_stats (a: &str, b: &str) -> io::Result<()> {
+    let mut skipped = |path: &Path, e: ber::Error| eprintln!("ber-dump: {}: {}", path.display(), e);
+    let (mut stats_a, mut stats_b) = (Stats::new(), Stats::new());
+    stats_a.add_dir(Path::new(a), &mut skipped)?;
+    stats_b.add_dir(Path::new(b), &mut skipped)?;
+    print!("{}", stats_a.compare(&stats_b));
+    Ok(())
+}
+
+fn main () {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("compare-stats") {
+        if args.len() != 3 {
+            eprintln!("usage: ber-dump compare-stats A_DIR B_DIR");
+            process::exit(2);
+        }
+        if let Err(e) = compare_stats(&args[1], &args[2]) {
+            eprintln!("ber-dump: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut opts = FormatOptions::default();
+    let mut files = Vec::new();
+    let mut following = false;
+    for arg in args {
+        match arg.as_str() {
+            "--hex" => opts.radix = Radix::Hex,
+            "--asn1" => opts.labels = Labels::Asn1,
+            "--follow" => following = true,
+            _ => files.push(arg),
+        }
+    }
+
+    if following {
+        let result = match files.len() {
+            0 => follow(&mut io::stdin().lock(), false, &opts),
+            1 => fs::File::open(&files[0])
+                .map_err(ber::Error::from)
+                .and_then(|mut f| follow(&mut f, true, &opts)),
+            _ => {
+                eprintln!("usage: ber-dump [--hex] [--asn1] [--follow] [FILE]");
+                process::exit(2);
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("ber-dump: {}", e.display_with(&opts));
+            process::exit(1);
+        }
+        return;
+    }
+
+    let input = match files.len() {
+        0 => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf).map(|_| buf)
+        },
+        1 => fs::read(&files[0]),
+        _ => {
+            eprintln!("usage: ber-dump [--hex] [--asn1] [--follow] [FILE]");
+            process::exit(2);
+        },
+    };
+
+    let input = input.unwrap_or_else(|e| {
+        eprintln!("ber-dump: {}", e);
+        process::exit(1);
+    });
+
+    if let Err(e) = run(&input, &opts) {
+        eprintln!("ber-dump: {}", e.display_with(&opts));
+        process::exit(1);
+    }
+}
@@ -0,0 +1,422 @@
+//! A toolbox for BER messages, one subcommand per job:
+//!
+//! ```text
+//! ber dump [--hex] [--asn1] [FILE]     print the tree, as ber-dump does
+//! ber lint [FILE]                      list every way the input falls short of DER
+//! ber to-der [FILE]                    re-encode as DER
+//! ber extract PATH [FILE]              write out the subtree at PATH, e.g. 0.2.1
+//! ber diff A B                         list structural differences
+//! ber carve [--all] [--min-size N] [FILE]
+//!                                      find BER structures embedded in a binary blob
+//! ber stats [FILE]                     count tags over every message in the input
+//! ber compare-stats A_DIR B_DIR        set the stats of two corpora side by side
+//! ber redact [--path PATH]... [FILE]   zero primitive contents, everywhere by default
+//! ber browse [--hex] [--asn1] FILE     explore the tree interactively (`tui` feature)
+//! ber to-json [FILE]                   export the tree as versioned JSON
+//! ber encode [--definite|--indefinite] [--non-minimal] [FILE]
+//!                                      encode a JSON tree, e.g. a hand-edited export
+//! ber differential PROFILE PROFILE FILE...
+//!                                      list files the two read profiles disagree on
+//! ber record [--profile NAME] FILE...  print a baseline of what each file reads as
+//! ber replay [--profile NAME] BASELINE list files that now read differently
+//! ber normalize [--profile NAME] IN_DIR OUT_DIR
+//!                                      re-encode every file under IN_DIR into OUT_DIR
+//! ```
+//!
+//! `encode` keeps the header forms the JSON asks for; its flags override them, to produce
+//! deliberately unusual encodings for testing other implementations. `--definite` and
+//! `--indefinite` force every constructed length into that form, and `--non-minimal`
+//! pads every long form tag number and definite length to more octets than needed.
+//!
+//! Profiles are `ber`, `der`, `cer`, `lenient` and `strict`, defaulting to `ber`, or to
+//! `der` for `normalize`. `normalize` prints each file as it goes, then the failures and
+//! totals, and fails if any file did.
+//! `record` and `replay` compare two builds of this tool: record a corpus with the old
+//! one, then replay the baseline with the new one before rolling it out.
+//!
+//! `compare-stats` reads every file under each directory, skipping with a warning any
+//! that don't read, and prints totals and how tags spread over numbers and messages over
+//! depths and sizes, as percentages so corpora of different sizes compare.
+//!
+//! Standard input is read when no file is given. Paths are child indices separated by
+//! dots, optionally starting with `root`, as error messages and `diff` print them.
+
+extern crate ber;
+
+use std::env;
+use std::error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use ber::carve::CarveOptions;
+use ber::format::{FormatOptions, Labels, Radix};
+use ber::stats::Stats;
+use ber::testing::{self, Profile};
+use ber::tag::{self, HeaderLayout, LengthForm, LengthPolicy};
+use ber::normalize::{self, Outcome};
+use ber::{container, diff, dump, json, registry, Number, Payload, Tag, WriteOptions};
+
+const USAGE: &str = "usage: ber dump|lint|to-der|extract|diff|carve|stats|compare-stats|redact|browse|to-json|encode|differential|record|replay|normalize [OPTIONS] [FILE]";
+
+type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+
+fn input (file: Option<&String>) -> io::Result<Vec<u8>> {
+    match file {
+        Some(f) if f != "-" => fs::read(f),
+        _ => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf).map(|_| buf)
+        },
+    }
+}
+
+fn read_tag (buf: &[u8]) -> Result<Tag> {
+    Ok(Tag::read_exact_one(&mut &buf[..])?)
+}
+
+// Every message in a container file, or every concatenated message otherwise.
+fn read_all (buf: &[u8]) -> Result<Vec<Tag>> {
+    Ok(container::read_any(buf)?)
+}
+
+fn parse_path (s: &str) -> Result<Vec<usize>> {
+    tag::parse_path(s).ok_or_else(|| format!("bad path {:?}", s).into())
+}
+
+fn write_out (tag: &Tag, der: bool) -> Result<()> {
+    let buf = if der {
+        tag.to_der()?
+    } else {
+        // keep the input's length forms, so only what was asked for changes
+        let opts = WriteOptions { length_policy: LengthPolicy::Preserve, ..WriteOptions::default() };
+        let mut buf = Vec::new();
+        tag.write_with(&mut buf, &opts)?;
+        buf
+    };
+    io::stdout().lock().write_all(&buf)?;
+    Ok(())
+}
+
+fn usage () -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+// The `--hex` and `--asn1` flags, and at most one file.
+fn format_args (args: &[String]) -> (FormatOptions, Option<&String>) {
+    let mut opts = FormatOptions::default();
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--hex" => opts.radix = Radix::Hex,
+            "--asn1" => opts.labels = Labels::Asn1,
+            _ => files.push(arg),
+        }
+    }
+    if files.len() > 1 {
+        usage();
+    }
+    (opts, files.first().cloned())
+}
+
+fn dump (args: &[String]) -> Result<i32> {
+    let (opts, file) = format_args(args);
+    let buf = input(file)?;
+    let registry = registry::global().read().unwrap_or_else(|e| e.into_inner());
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (i, tag) in read_all(&buf)?.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        dump::dump_formatted(tag, &registry, &opts, &mut out)?;
+    }
+    Ok(0)
+}
+
+fn lint (args: &[String]) -> Result<i32> {
+    let buf = input(args.first())?;
+    match ber::check_der(&buf) {
+        Ok(()) => Ok(0),
+        Err(violations) => {
+            for v in violations {
+                println!("{}", v);
+            }
+            Ok(1)
+        },
+    }
+}
+
+fn extract (args: &[String]) -> Result<i32> {
+    let path = parse_path(args.first().unwrap_or_else(|| usage()))?;
+    let tag = read_tag(&input(args.get(1))?)?;
+    match tag.at_path(&path) {
+        Some(sub) => write_out(sub, false).map(|_| 0),
+        None => Err(format!("no tag at {}", args[0]).into()),
+    }
+}
+
+fn diff (args: &[String]) -> Result<i32> {
+    if args.len() != 2 {
+        usage();
+    }
+    let a = read_tag(&fs::read(&args[0])?)?;
+    let b = read_tag(&fs::read(&args[1])?)?;
+
+    let changes = diff::diff(&a, &b);
+    for change in &changes {
+        println!("{}", change);
+    }
+    Ok(if changes.is_empty() { 0 } else { 1 })
+}
+
+fn carve (args: &[String]) -> Result<i32> {
+    let mut opts = CarveOptions::default();
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--all" => opts.constructed_only = false,
+            "--min-size" => opts.min_size = args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage()),
+            _ => file = Some(arg),
+        }
+    }
+
+    for (offset, tag) in ber::carve(&input(file)?, &opts) {
+        println!("{}: {:?}", offset, tag.number);
+    }
+    Ok(0)
+}
+
+fn stats (args: &[String]) -> Result<i32> {
+    let mut stats = Stats::new();
+    for tag in read_all(&input(args.first())?)? {
+        stats.add(&tag);
+    }
+    print!("{}", stats);
+    Ok(0)
+}
+
+// The stats of every message under `dir`, with a warning for each file that won't read.
+fn corpus_stats (dir: &str) -> Result<Stats> {
+    let mut stats = Stats::new();
+    stats.add_dir(Path::new(dir), &mut |path, e| eprintln!("{}: {}", path.display(), e))?;
+    Ok(stats)
+}
+
+fn compare_stats (args: &[String]) -> Result<i32> {
+    if args.len() != 2 {
+        usage();
+    }
+    let (a, b) = (corpus_stats(&args[0])?, corpus_stats(&args[1])?);
+    print!("{}", a.compare(&b));
+    Ok(0)
+}
+
+fn redact (args: &[String]) -> Result<i32> {
+    let mut paths = Vec::new();
+    let mut file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--path" => paths.push(parse_path(args.next().unwrap_or_else(|| usage()))?),
+            _ => file = Some(arg),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(vec![]);
+    }
+
+    let mut tag = read_tag(&input(file)?)?;
+    for path in paths {
+        match tag.at_path_mut(&path) {
+            Some(sub) => sub.redact(),
+            None => return Err(format!("no tag at {:?}", path).into()),
+        }
+    }
+    write_out(&tag, false).map(|_| 0)
+}
+
+#[cfg(feature = "tui")]
+fn browse (args: &[String]) -> Result<i32> {
+    let (opts, file) = format_args(args);
+    // standard input belongs to the terminal while browsing
+    let file = file.unwrap_or_else(|| usage());
+    let tag = read_tag(&fs::read(file)?)?;
+    ber::browse::run(&tag, opts)?;
+    Ok(0)
+}
+
+#[cfg(not(feature = "tui"))]
+fn browse (_: &[String]) -> Result<i32> {
+    Err("this build does not include the tui feature".into())
+}
+
+fn to_json (args: &[String]) -> Result<i32> {
+    let tag = read_tag(&input(args.first())?)?;
+    println!("{}", json::to_string(&tag));
+    Ok(0)
+}
+
+// Applies `encode`'s overrides to every tag in the tree.
+fn mutate (tag: &mut Tag, form: Option<LengthForm>, non_minimal: bool) {
+    if non_minimal {
+        tag.layout = Some(HeaderLayout {
+            // five octets hold any tag number, so every long form one gets padding
+            number_octets: match tag.number {
+                Number::Universal(_) => None,
+                _ => Some(5),
+            },
+            length_octets: Some(4),
+        });
+    }
+    if let Payload::Constructed(ref mut children) = tag.payload {
+        tag.length_form = form.or(tag.length_form);
+        for child in children {
+            mutate(child, form, non_minimal);
+        }
+    }
+}
+
+fn encode (args: &[String]) -> Result<i32> {
+    let mut form = None;
+    let mut non_minimal = false;
+    let mut file = None;
+    for arg in args {
+        match arg.as_str() {
+            "--definite" => form = Some(LengthForm::Definite),
+            "--indefinite" => form = Some(LengthForm::Indefinite),
+            "--non-minimal" => non_minimal = true,
+            _ => file = Some(arg),
+        }
+    }
+
+    let text = String::from_utf8(input(file)?)?;
+    let mut tag = json::read(&text)?;
+    mutate(&mut tag, form, non_minimal);
+    write_out(&tag, false).map(|_| 0)
+}
+
+fn profile (name: &str) -> Result<Profile> {
+    Profile::named(name).ok_or_else(|| format!("unknown profile {:?}", name).into())
+}
+
+// The `--profile` flag and the remaining arguments.
+fn profile_args (args: &[String]) -> Result<(Profile, Vec<&String>)> {
+    let mut profile = Profile::ber();
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => profile = self::profile(args.next().unwrap_or_else(|| usage()))?,
+            _ => rest.push(arg),
+        }
+    }
+    Ok((profile, rest))
+}
+
+fn differential (args: &[String]) -> Result<i32> {
+    if args.len() < 3 {
+        usage();
+    }
+    let (a, b) = (profile(&args[0])?, profile(&args[1])?);
+
+    let mut status = 0;
+    for file in &args[2..] {
+        let buf = fs::read(file)?;
+        if let Some(d) = testing::compare_records(&testing::record(&buf, &a), &testing::record(&buf, &b)) {
+            println!("{}: {}", file, d);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn record (args: &[String]) -> Result<i32> {
+    let (profile, files) = profile_args(args)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for file in files {
+        if file.contains('\t') || file.contains('\n') {
+            return Err(format!("can't record {:?}", file).into());
+        }
+        writeln!(out, "{}\t{}", file, testing::record(&fs::read(file)?, &profile))?;
+    }
+    Ok(0)
+}
+
+fn replay (args: &[String]) -> Result<i32> {
+    let (profile, files) = profile_args(args)?;
+    if files.len() != 1 {
+        usage();
+    }
+
+    let mut status = 0;
+    for line in fs::read_to_string(files[0])?.lines() {
+        let (file, old) = line.split_once('\t').ok_or_else(|| format!("bad baseline line {:?}", line))?;
+        if let Some(d) = testing::compare_records(old, &testing::record(&fs::read(file)?, &profile)) {
+            println!("{}: {}", file, d);
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+fn normalize (args: &[String]) -> Result<i32> {
+    let (profile, dirs) = match args.iter().any(|a| a == "--profile") {
+        true => profile_args(args)?,
+        false => (Profile::der(), args.iter().collect()),
+    };
+    if dirs.len() != 2 {
+        usage();
+    }
+
+    let summary = normalize::normalize_dir(dirs[0].as_ref(), dirs[1].as_ref(), &profile, &mut |p| {
+        let outcome = match p.entry.outcome {
+            Outcome::Converted { before, after } => format!("converted, {} -> {} bytes", before, after),
+            Outcome::Unchanged => "unchanged".to_string(),
+            Outcome::Failed(_) => "failed".to_string(),
+        };
+        eprintln!("[{}/{}] {}: {}", p.done, p.total, p.entry.path.display(), outcome);
+    })?;
+    println!("{}", summary);
+    Ok(if summary.failed() > 0 { 1 } else { 0 })
+}
+
+fn main () {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, rest) = args.split_first().unwrap_or_else(|| usage());
+
+    let result = match command.as_str() {
+        "dump" => dump(rest),
+        "lint" => lint(rest),
+        "to-der" => input(rest.first()).map_err(Into::into)
+            .and_then(|buf| read_tag(&buf))
+            .and_then(|tag| write_out(&tag, true))
+            .map(|_| 0),
+        "extract" => extract(rest),
+        "diff" => diff(rest),
+        "carve" => carve(rest),
+        "stats" => stats(rest),
+        "compare-stats" => compare_stats(rest),
+        "redact" => redact(rest),
+        "browse" => browse(rest),
+        "to-json" => to_json(rest),
+        "encode" => encode(rest),
+        "differential" => differential(rest),
+        "record" => record(rest),
+        "replay" => replay(rest),
+        "normalize" => normalize(rest),
+        _ => usage(),
+    };
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("ber {}: {}", command, e);
+            process::exit(1);
+        },
+    }
+}
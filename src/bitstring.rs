@@ -0,0 +1,171 @@
+//! BIT STRING values (X.690 §8.6).
+
+use crate::err;
+use crate::tag::{Number, Payload, Tag, Type};
+
+/// A string of bits, stored most significant bit first with the unused bits of the
+/// final octet cleared.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct BitString {
+    bytes:  Vec<u8>,
+    unused: u8,
+}
+
+impl BitString {
+    /// Builds a bit string from octets whose last `unused` bits are not part of it.
+    pub fn new (mut bytes: Vec<u8>, unused: u8) -> Result<BitString, err::Error> {
+        if unused > 7 || (bytes.is_empty() && unused != 0) {
+            return Err(err::Error::new(err::Kind::InvalidValue, 0, None));
+        }
+
+        if let Some(last) = bytes.last_mut() {
+            *last &= !((1u8 << unused) - 1);
+        }
+        Ok(BitString { bytes, unused })
+    }
+
+    /// Builds a bit string from individual bits.
+    pub fn from_bits (bits: &[bool]) -> BitString {
+        let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+        for (i, _) in bits.iter().enumerate().filter(|&(_, &b)| b) {
+            bytes[i / 8] |= 0x80 >> (i % 8);
+        }
+
+        BitString {
+            bytes,
+            unused: ((8 - bits.len() % 8) % 8) as u8,
+        }
+    }
+
+    /// Decodes BIT STRING content octets: an unused-bits count, then the bits. BER lets
+    /// the unused bits hold anything, so they are ignored rather than rejected.
+    pub fn from_content (content: &[u8]) -> Result<BitString, err::Error> {
+        match content.split_first() {
+            Some((&unused, bytes)) => BitString::new(bytes.to_vec(), unused),
+            None => Err(err::Error::new(err::Kind::InvalidValue, 0, None)),
+        }
+    }
+
+    pub fn to_content (&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.bytes.len() + 1);
+        out.push(self.unused);
+        out.extend_from_slice(&self.bytes);
+        out
+    }
+
+    /// The octets holding the bits; the unused trailing bits are zero.
+    pub fn as_bytes (&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn unused_bits (&self) -> u8 {
+        self.unused
+    }
+
+    /// The number of bits.
+    pub fn bit_len (&self) -> usize {
+        self.bytes.len() * 8 - self.unused as usize
+    }
+
+    /// Bit `i`, counting from the most significant bit of the first octet.
+    pub fn get (&self, i: usize) -> Option<bool> {
+        if i < self.bit_len() {
+            Some(self.bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+        } else {
+            None
+        }
+    }
+}
+
+impl Tag {
+    /// Interprets the content as a BIT STRING. A constructed encoding is reassembled from
+    /// its segments, which must be universal BIT STRINGs with no unused bits except in the
+    /// last; another type among them is a `Kind::InvalidTypeAndFlavor` error.
+    pub fn as_bit_string (&self) -> Result<BitString, err::Error> {
+        let content = match self.payload {
+            Payload::Primitive(ref v) => v,
+            Payload::Constructed(_) => return self.join_bit_segments(),
+        };
+        BitString::from_content(content).map_err(|mut e| {
+            e.offset = self.offset.unwrap_or(0);
+            e
+        })
+    }
+
+    fn join_bit_segments (&self) -> Result<BitString, err::Error> {
+        let mut segments = Vec::new();
+        self.segments(Type::BitString, &mut segments)?;
+
+        let mut bytes = Vec::new();
+        let mut unused = 0;
+        for (i, segment) in segments.iter().enumerate() {
+            let (&u, b) = segment.content()?.split_first().ok_or_else(|| segment.error(err::Kind::InvalidValue))?;
+            if u != 0 && i + 1 < segments.len() {
+                return Err(segment.error(err::Kind::InvalidValue));
+            }
+            bytes.extend_from_slice(b);
+            unused = u;
+        }
+        BitString::new(bytes, unused).map_err(|mut e| {
+            e.offset = self.offset.unwrap_or(0);
+            e
+        })
+    }
+
+    /// A universal BIT STRING.
+    pub fn bit_string (bits: &BitString) -> Tag {
+        Tag::primitive(Number::Universal(Type::BitString), bits.to_content())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode () {
+        let bits = BitString::from_content(&[0x06, 0x6E, 0x5D, 0xC0]).unwrap();
+        assert_eq!(bits.bit_len(), 18);
+        assert_eq!(bits.unused_bits(), 6);
+        assert_eq!(bits.get(1), Some(true));
+        assert_eq!(bits.get(17), Some(true));
+        assert_eq!(bits.get(18), None);
+        assert_eq!(bits.to_content(), vec![0x06, 0x6E, 0x5D, 0xC0]);
+    }
+
+    #[test]
+    fn test_unused_bits_cleared () {
+        let bits = BitString::from_content(&[0x04, 0xFF]).unwrap();
+        assert_eq!(bits.as_bytes(), &[0xF0]);
+        assert_eq!(Tag::bit_string(&bits).as_bit_string().unwrap(), bits);
+    }
+
+    #[test]
+    fn test_from_bits () {
+        let bits = BitString::from_bits(&[true, false, true]);
+        assert_eq!(bits.to_content(), vec![0x05, 0xA0]);
+        assert_eq!(BitString::from_bits(&[]).to_content(), vec![0x00]);
+    }
+
+    #[test]
+    fn test_constructed () {
+        // the example from X.690 §8.6.4.2
+        let input = [0x23, 0x80, 0x03, 0x03, 0x00, 0x0A, 0x3B, 0x03, 0x05, 0x04, 0x5F, 0x29, 0x1C, 0xD0, 0x00, 0x00];
+        let bits = Tag::read(&mut &input[..]).unwrap().as_bit_string().unwrap();
+        assert_eq!(bits, BitString::from_content(&[0x04, 0x0A, 0x3B, 0x5F, 0x29, 0x1C, 0xD0]).unwrap());
+
+        // only the last segment may leave bits unused
+        let segment = |content: &[u8]| Tag::primitive(Number::Universal(Type::BitString), content.to_vec());
+        let bad = Tag::constructed(Number::Universal(Type::BitString), vec![segment(&[0x04, 0xF0]), segment(&[0x00, 0xFF])]);
+        assert!(matches!(bad.as_bit_string().unwrap_err().kind, err::Kind::InvalidValue));
+        let bad = Tag::constructed(Number::Universal(Type::BitString), vec![Tag::octet_string(vec![0x00])]);
+        assert!(matches!(bad.as_bit_string().unwrap_err().kind, err::Kind::InvalidTypeAndFlavor));
+    }
+
+    #[test]
+    fn test_invalid () {
+        assert!(BitString::from_content(&[]).is_err());
+        assert!(BitString::from_content(&[0x08, 0x00]).is_err());
+        assert!(BitString::from_content(&[0x01]).is_err());
+    }
+}
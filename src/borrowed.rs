@@ -0,0 +1,206 @@
+//! Zero-copy trees parsed straight out of a byte slice.
+//!
+//! A `TagRef` mirrors `Tag`, except that primitive content is a slice of the input
+//! rather than a fresh `Vec<u8>`. For inputs already in memory, such as certificates,
+//! that halves peak memory and leaves one allocation per constructed tag instead of one
+//! per tag. `TagRef::to_tag` copies a tree out when it needs to outlive the input.
+
+use crate::err;
+use crate::tag::{self, Flavor, HeaderLayout, Length, LengthForm, Number, Payload, Tag, Type};
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum PayloadRef<'a> {
+    Primitive(&'a [u8]),
+    Constructed(Vec<TagRef<'a>>),
+}
+
+/// A tag borrowing its primitive content from the input it was read from.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TagRef<'a> {
+    pub number:      Number,
+    /// Offset of the tag's first byte in the input.
+    pub offset:      usize,
+    pub payload:     PayloadRef<'a>,
+    /// As `Tag::length_form`.
+    pub length_form: Option<LengthForm>,
+    /// As `Tag::layout`.
+    pub layout:      Option<HeaderLayout>,
+}
+
+impl<'a> TagRef<'a> {
+    /// Reads one tag from the front of `buf` and advances `buf` past it, like `Tag::read`
+    /// on a slice. Errors are those `Tag::read` gives for the same input, nesting
+    /// deeper than `DEFAULT_MAX_DEPTH` included.
+    pub fn read (buf: &mut &'a [u8]) -> Result<TagRef<'a>, err::Error> {
+        let mut p = Parser { input: buf, pos: 0 };
+        match p.tag(0) {
+            Ok(tag) => {
+                *buf = &buf[p.pos..];
+                Ok(tag)
+            },
+            Err(e) if buf.is_empty() && matches!(e.kind, err::Kind::Truncated { .. }) =>
+                Err(err::Error::new(err::Kind::CleanEof, 0, None)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The content of a primitive tag, `None` for constructed ones.
+    pub fn content (&self) -> Option<&'a [u8]> {
+        match self.payload {
+            PayloadRef::Primitive(v) => Some(v),
+            PayloadRef::Constructed(_) => None,
+        }
+    }
+
+    /// Copies the tree into an owned `Tag`, equal to what `Tag::read` gives.
+    pub fn to_tag (&self) -> Tag {
+        let payload = match self.payload {
+            PayloadRef::Primitive(v) => Payload::Primitive(v.to_vec()),
+            PayloadRef::Constructed(ref children) => Payload::Constructed(children.iter().map(TagRef::to_tag).collect()),
+        };
+        Tag {
+            number: self.number,
+            offset: Some(self.offset),
+            payload,
+            repaired: None,
+            length_form: self.length_form,
+            layout: self.layout,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos:   usize,
+}
+
+impl<'a> Parser<'a> {
+    // `depth` counts the tags this one is inside.
+    fn tag (&mut self, depth: usize) -> Result<TagRef<'a>, err::Error> {
+        let offset = self.pos;
+        if depth >= tag::DEFAULT_MAX_DEPTH {
+            return Err(err::Error::new(err::Kind::TooDeep { limit: tag::DEFAULT_MAX_DEPTH }, offset, None));
+        }
+        let mut r = &self.input[self.pos..];
+        let end = self.input.len();
+
+        let (_class, flavor, number) = tag::read_identifiers(&mut r).map_err(|e| at(e, end - r.len()))?;
+        let number_octets = end - r.len() - offset - 1;
+        let length = tag::read_length(&mut r).map_err(|e| at(e, end - r.len()))?;
+        let layout = HeaderLayout::observed(&number, &length, number_octets, end - r.len() - offset - number_octets - 2);
+        self.pos = end - r.len();
+
+        let payload = match (flavor, length) {
+            (Flavor::Primitive, Length::Indefinite) =>
+                return Err(err::Error::new(err::Kind::InvalidLength, self.pos, None)),
+            (Flavor::Primitive, Length::Some(l)) => {
+                let available = (end - self.pos) as u64;
+                if l > available {
+                    let needed = usize::try_from(l - available).unwrap_or(usize::MAX);
+                    return Err(err::Error::new(err::Kind::Truncated { needed }, end, None));
+                }
+                let content = &self.input[self.pos..self.pos + l as usize];
+                self.pos += l as usize;
+                PayloadRef::Primitive(content)
+            },
+            (Flavor::Constructed, Length::Some(l)) => {
+                let limit = (self.pos as u64).saturating_add(l);
+                let mut children = Vec::new();
+                while (self.pos as u64) < limit {
+                    let child = self.tag(depth + 1)?;
+                    if child.number == Number::Universal(Type::Eoc) {
+                        return Err(err::Error::new(err::Kind::UnexpectedEoc, self.pos, None));
+                    }
+                    children.push(child);
+                    if self.pos as u64 > limit {
+                        // the last child ran past the end of this tag
+                        return Err(err::Error::new(err::Kind::LengthExceedsContainer, self.pos, None));
+                    }
+                }
+                PayloadRef::Constructed(children)
+            },
+            (Flavor::Constructed, Length::Indefinite) => {
+                let mut children = Vec::new();
+                loop {
+                    let child = self.tag(depth + 1)?;
+                    if child.number == Number::Universal(Type::Eoc) {
+                        break;
+                    }
+                    children.push(child);
+                }
+                PayloadRef::Constructed(children)
+            },
+        };
+
+        let length_form = match (flavor, length) {
+            (Flavor::Primitive, _) => None,
+            (Flavor::Constructed, Length::Indefinite) => Some(LengthForm::Indefinite),
+            (Flavor::Constructed, Length::Some(_)) => Some(LengthForm::Definite),
+        };
+
+        Ok(TagRef { number, offset, payload, length_form, layout })
+    }
+}
+
+fn at (mut e: err::Error, offset: usize) -> err::Error {
+    e.offset = offset;
+    tag::truncation(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // SEQUENCE (indefinite) { [1] { INTEGER 5 }, OCTET STRING "abc" (padded length) }
+    const INPUT: [u8; 15] = [
+        0x30, 0x80,
+            0xA1, 0x03, 0x02, 0x01, 0x05,
+            0x04, 0x81, 0x03, 0x61, 0x62, 0x63,
+        0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_read_matches_tag () {
+        let mut buf = &INPUT[..];
+        let tag = TagRef::read(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(tag.to_tag(), Tag::read(&mut &INPUT[..]).unwrap());
+
+        // content points into the input rather than at a copy
+        let children = match tag.payload {
+            PayloadRef::Constructed(ref children) => children,
+            _ => panic!("not constructed"),
+        };
+        let content = children[1].content().unwrap();
+        assert_eq!(content, b"abc");
+        assert!(std::ptr::eq(content, &INPUT[10..13]));
+
+        assert!(matches!(TagRef::read(&mut buf).unwrap_err().kind, err::Kind::CleanEof));
+    }
+
+    #[test]
+    fn test_errors_match_tag () {
+        let inputs: [&[u8]; 6] = [
+            &INPUT[..11],
+            &INPUT[..1],
+            &[0x04, 0x80],
+            &[0x30, 0x02, 0x04, 0x02, 0x00, 0x00],
+            &[0x30, 0x02, 0x00, 0x00],
+            &[0x1F, 0x01],
+        ];
+        for input in inputs {
+            let ours = TagRef::read(&mut &input[..]).unwrap_err();
+            let theirs = Tag::read(&mut &input[..]).unwrap_err();
+            assert_eq!((ours.code(), ours.offset), (theirs.code(), theirs.offset), "{:02x?}", input);
+        }
+    }
+
+    #[test]
+    fn test_deep_nesting () {
+        let input = [0x30, 0x80].repeat(300_000);
+        let ours = TagRef::read(&mut &input[..]).unwrap_err();
+        let theirs = Tag::read(&mut &input[..]).unwrap_err();
+        assert!(matches!(ours.kind, err::Kind::TooDeep { .. }), "{}", ours);
+        assert_eq!((ours.code(), ours.offset), (theirs.code(), theirs.offset));
+    }
+}
@@ -0,0 +1,343 @@
+//! An interactive terminal browser for trees too big to read as a flat dump, behind the
+//! `tui` feature.
+//!
+//! Nodes expand and collapse in a tree on the left; the selected node's hex and decoded
+//! value show on the right. `Browser` holds the navigation state on its own, so it can be
+//! driven without a terminal; `run` wires it to one.
+//!
+//! Keys: arrows or `hjkl` move, expand and collapse, Enter or Space toggles, PageUp and
+//! PageDown jump, `y` copies the selected path to the clipboard (through the terminal,
+//! with OSC 52), `q` or Esc quits.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+
+use crate::dump::{self, Guess};
+use crate::format::FormatOptions;
+use crate::tag::{Number, Payload, Tag, Type};
+use crate::util;
+
+struct Row<'a> {
+    path: Vec<usize>,
+    tag:  &'a Tag,
+}
+
+/// The browsing state over one tree: which nodes are expanded and which is selected.
+pub struct Browser<'a> {
+    root:     &'a Tag,
+    opts:     FormatOptions,
+    expanded: HashSet<Vec<usize>>,
+    // the visible rows, rebuilt whenever a node expands or collapses
+    rows:     Vec<Row<'a>>,
+    cursor:   usize,
+    status:   String,
+}
+
+impl<'a> Browser<'a> {
+    /// A browser over `root` with only the root expanded.
+    pub fn new (root: &'a Tag, opts: FormatOptions) -> Browser<'a> {
+        let mut browser = Browser {
+            root,
+            opts,
+            expanded: HashSet::new(),
+            rows: Vec::new(),
+            cursor: 0,
+            status: String::new(),
+        };
+        browser.expanded.insert(vec![]);
+        browser.rebuild();
+        browser
+    }
+
+    fn rebuild (&mut self) {
+        self.rows.clear();
+        let mut stack = vec![Row { path: vec![], tag: self.root }];
+        while let Some(row) = stack.pop() {
+            if let Payload::Constructed(ref children) = row.tag.payload {
+                if self.expanded.contains(&row.path) {
+                    for (i, child) in children.iter().enumerate().rev() {
+                        let mut path = row.path.clone();
+                        path.push(i);
+                        stack.push(Row { path, tag: child });
+                    }
+                }
+            }
+            self.rows.push(row);
+        }
+        self.cursor = self.cursor.min(self.rows.len() - 1);
+    }
+
+    /// The number of rows currently visible.
+    pub fn len (&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn selected (&self) -> &'a Tag {
+        self.rows[self.cursor].tag
+    }
+
+    /// The child indices from the root to the selected tag.
+    pub fn selected_path (&self) -> &[usize] {
+        &self.rows[self.cursor].path
+    }
+
+    /// The selected path as `ber` and error messages print it, e.g. `root.0.2`.
+    pub fn selected_path_string (&self) -> String {
+        let mut s = "root".to_string();
+        for i in self.selected_path() {
+            s.push_str(&format!(".{}", i));
+        }
+        s
+    }
+
+    /// Moves the selection by `delta` rows, stopping at either end.
+    pub fn move_by (&mut self, delta: isize) {
+        let last = self.rows.len() as isize - 1;
+        self.cursor = (self.cursor as isize + delta).clamp(0, last) as usize;
+    }
+
+    /// Expands the selected tag if it is collapsed, and collapses it otherwise.
+    pub fn toggle (&mut self) {
+        if self.expanded.contains(self.selected_path()) {
+            self.collapse();
+        } else {
+            self.expand();
+        }
+    }
+
+    /// Expands the selected tag; primitives have nothing to expand.
+    pub fn expand (&mut self) {
+        if let Payload::Constructed(_) = self.selected().payload {
+            if self.expanded.insert(self.selected_path().to_vec()) {
+                self.rebuild();
+            }
+        }
+    }
+
+    /// Collapses the selected tag, or moves to its parent if it is already collapsed.
+    pub fn collapse (&mut self) {
+        let path = self.selected_path().to_vec();
+        if self.expanded.remove(&path) {
+            self.rebuild();
+        } else if let Some((_, parent)) = path.split_last() {
+            self.cursor = self.rows.iter().position(|r| r.path == parent).unwrap_or(0);
+        }
+    }
+
+    fn label (&self, row: &Row) -> String {
+        let marker = match row.tag.payload {
+            Payload::Constructed(_) if self.expanded.contains(&row.path) => "▾ ",
+            Payload::Constructed(_) => "▸ ",
+            Payload::Primitive(_) => "  ",
+        };
+        let size = match row.tag.payload {
+            Payload::Constructed(ref c) => format!("{} children", c.len()),
+            Payload::Primitive(ref v) => format!("{} bytes", v.len()),
+        };
+        format!("{:width$}{}{} ({})", "", marker, self.opts.number(&row.tag.number), size, width = row.path.len() * 2)
+    }
+
+    /// Draws the tree, the detail panes and the status line into `frame`.
+    pub fn render (&self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [tree, detail] = Layout::horizontal([Constraint::Percentage(50); 2]).areas(main);
+        let [hex, decoded] = Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(detail);
+
+        // only the rows that fit are turned into lines, keeping the cursor in view
+        let height = tree.height.saturating_sub(2) as usize;
+        let top = (self.cursor + 1).saturating_sub(height);
+        let lines: Vec<Line> = self.rows.iter().enumerate().skip(top).take(height)
+            .map(|(i, row)| {
+                let line = Line::from(self.label(row));
+                if i == self.cursor {
+                    line.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("tree")), tree);
+
+        let tag = self.selected();
+        frame.render_widget(
+            Paragraph::new(hex_lines(tag, hex)).block(Block::bordered().title("content")),
+            hex);
+        frame.render_widget(
+            Paragraph::new(decode(tag)).block(Block::bordered().title("value")),
+            decoded);
+
+        let line = if self.status.is_empty() { self.selected_path_string() } else { self.status.clone() };
+        frame.render_widget(Paragraph::new(line), status);
+    }
+}
+
+const HEX_WIDTH: usize = 16;
+
+// As many lines of hex as fit in `area`, with offsets into the content.
+fn hex_lines (tag: &Tag, area: Rect) -> Vec<Line<'static>> {
+    let content = match tag.payload {
+        Payload::Primitive(ref v) => v,
+        Payload::Constructed(_) => return vec![Line::from("(constructed)")],
+    };
+
+    let rows = area.height.saturating_sub(2) as usize;
+    let mut lines: Vec<Line> = content.chunks(HEX_WIDTH).take(rows)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk.iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            Line::from(format!("{:08x}  {:<width$}  {}", i * HEX_WIDTH, hex.join(" "), ascii, width = HEX_WIDTH * 3 - 1))
+        })
+        .collect();
+    if content.len() > rows * HEX_WIDTH {
+        lines.pop();
+        lines.push(Line::from(format!("... {} bytes in all", content.len())));
+    }
+    lines
+}
+
+/// The value of a primitive in words, as far as its universal type or content allows.
+pub fn decode (tag: &Tag) -> String {
+    let content = match tag.payload {
+        Payload::Primitive(ref v) => v,
+        Payload::Constructed(ref c) => return format!("{} children", c.len()),
+    };
+
+    let decoded = match tag.number {
+        Number::Universal(Type::Boolean) => tag.as_bool().map(|b| b.to_string()).ok(),
+        Number::Universal(Type::Integer) | Number::Universal(Type::Enumerated) =>
+            tag.as_i64().map(|n| n.to_string()).ok(),
+        Number::Universal(Type::ObjectIdentifier) => tag.as_oid().map(|o| o.to_string()).ok(),
+        Number::Universal(Type::RelativeOid) => tag.as_relative_oid().map(|o| o.to_string()).ok(),
+        Number::Universal(Type::Real) => tag.as_f64().map(|f| f.to_string()).ok(),
+        Number::Universal(Type::Null) => Some("NULL".to_string()),
+        _ => tag.as_str().map(|s| format!("{:?}", s)).ok(),
+    };
+
+    decoded.unwrap_or_else(|| match dump::classify(content) {
+        Guess::Text => format!("{:?} ({})", String::from_utf8_lossy(content), Guess::Text),
+        guess => guess.to_string(),
+    })
+}
+
+// OSC 52 asks the terminal to put the text on the clipboard, which works over SSH too.
+fn copy (text: &str) -> io::Result<()> {
+    let mut out = io::stdout();
+    write!(out, "\x1b]52;c;{}\x07", util::base64_encode(text.as_bytes()))?;
+    out.flush()
+}
+
+/// Takes over the terminal to browse `root` until the user quits.
+pub fn run (root: &Tag, opts: FormatOptions) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut browser = Browser::new(root, opts);
+
+    let result = (|| loop {
+        terminal.draw(|frame| browser.render(frame))?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let page = terminal.size()?.height.saturating_sub(3) as isize;
+
+        browser.status.clear();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => browser.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => browser.move_by(1),
+            KeyCode::PageUp => browser.move_by(-page),
+            KeyCode::PageDown => browser.move_by(page),
+            KeyCode::Home => browser.move_by(isize::MIN / 2),
+            KeyCode::End => browser.move_by(isize::MAX / 2),
+            KeyCode::Right | KeyCode::Char('l') => browser.expand(),
+            KeyCode::Left | KeyCode::Char('h') => browser.collapse(),
+            KeyCode::Enter | KeyCode::Char(' ') => browser.toggle(),
+            KeyCode::Char('y') => {
+                let path = browser.selected_path_string();
+                copy(&path)?;
+                browser.status = format!("copied {}", path);
+            },
+            _ => {},
+        }
+    })();
+
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree () -> Tag {
+        Tag::sequence(vec![
+            Tag::integer(42),
+            Tag::sequence(vec![Tag::boolean(true), Tag::utf8_string("hi")]),
+            Tag::null(),
+        ])
+    }
+
+    #[test]
+    fn test_navigation () {
+        let root = tree();
+        let mut b = Browser::new(&root, FormatOptions::default());
+        assert_eq!(b.len(), 4);
+
+        b.move_by(1);
+        b.toggle();
+        assert_eq!(b.len(), 4, "a primitive has nothing to expand");
+
+        b.move_by(1);
+        assert_eq!(b.selected_path(), &[1]);
+        b.expand();
+        assert_eq!(b.len(), 6);
+
+        b.move_by(2);
+        assert_eq!(b.selected_path_string(), "root.1.1");
+        assert_eq!(decode(b.selected()), "\"hi\"");
+
+        // collapsing a leaf goes to its parent, then collapses that
+        b.collapse();
+        assert_eq!(b.selected_path(), &[1]);
+        b.collapse();
+        assert_eq!(b.len(), 4);
+
+        b.move_by(100);
+        assert_eq!(b.selected_path(), &[2]);
+        b.move_by(-100);
+        assert_eq!(b.selected_path(), &[] as &[usize]);
+    }
+
+    #[test]
+    fn test_render () {
+        use ratatui::backend::TestBackend;
+        use ratatui::Terminal;
+
+        let root = tree();
+        let mut b = Browser::new(&root, FormatOptions::default());
+        b.move_by(1);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+        terminal.draw(|frame| b.render(frame)).unwrap();
+
+        let screen: String = terminal.backend().buffer().content().iter().map(|c| c.symbol()).collect();
+        assert!(screen.contains("▾ Universal(Sequence) (3 children)"));
+        assert!(screen.contains("00000000  2a"));
+        assert!(screen.contains("42"));
+        assert!(screen.contains("root.0"));
+    }
+}
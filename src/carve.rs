@@ -0,0 +1,89 @@
+//! Scanning arbitrary binary blobs (firmware images, memory dumps) for embedded BER.
+
+use crate::tag::{self, Number, Payload, Tag, Type};
+
+/// Controls what `carve` considers a plausible structure.
+#[derive(Debug, Clone)]
+pub struct CarveOptions {
+    /// Structures smaller than this many encoded bytes are ignored; tiny TLVs match
+    /// random data far too often to be interesting.
+    pub min_size: usize,
+    /// Only report constructed structures.
+    pub constructed_only: bool,
+}
+
+impl Default for CarveOptions {
+    fn default () -> CarveOptions {
+        CarveOptions {
+            min_size: 16,
+            constructed_only: true,
+        }
+    }
+}
+
+/// Finds well-formed BER structures in `buf`, returning each with the offset it starts
+/// at. Structures are not reported twice: scanning resumes after each match, so nested
+/// elements show up only as children of the outermost match.
+pub fn carve (buf: &[u8], opts: &CarveOptions) -> Vec<(usize, Tag)> {
+    let mut found = Vec::new();
+    let mut pos = 0;
+
+    while pos < buf.len() {
+        if let Some(len) = tag::measure(&buf[pos..]) {
+            if len >= opts.min_size {
+                if let Ok(tag) = Tag::read(&mut &buf[pos..pos + len]) {
+                    if plausible(&tag, opts) {
+                        found.push((pos, tag));
+                        pos += len;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        pos += 1;
+    }
+
+    found
+}
+
+fn plausible (tag: &Tag, opts: &CarveOptions) -> bool {
+    match tag.payload {
+        _ if tag.number == Number::Universal(Type::Eoc) => false,
+        Payload::Primitive(_) => !opts.constructed_only,
+        Payload::Constructed(ref children) => !children.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_carve_embedded () {
+        let der = [0x30, 0x0A, 0x0C, 0x03, 0x64, 0x65, 0x66, 0x04, 0x03, 0x01, 0x02, 0x03];
+
+        let mut blob = vec![0xFF, 0x13, 0x37, 0x00];
+        blob.extend_from_slice(&der);
+        blob.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        blob.extend_from_slice(&der);
+
+        let found = carve(&blob, &CarveOptions { min_size: 8, ..CarveOptions::default() });
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, 4);
+        assert_eq!(found[1].0, 4 + der.len() + 4);
+        assert_eq!(found[0].1.number, Number::Universal(Type::Sequence));
+    }
+
+    #[test]
+    fn test_carve_respects_min_size () {
+        let blob = [0x00, 0x30, 0x03, 0x05, 0x01, 0x00];
+        assert!(carve(&blob, &CarveOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_carve_ignores_huge_declared_lengths () {
+        let blob = [0x30, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+        assert!(carve(&blob, &CarveOptions { min_size: 0, ..CarveOptions::default() }).is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! A small self-describing container for persisting collections of tags, e.g. replay
+//! corpora of parsed messages.
+//!
+//! The layout is the magic `BERC`, a big endian u16 version, a big endian u32 count and
+//! then that many tags back to back, each encoded as by `Tag::write`.
+//!
+//! An `Index` of tag offsets can be built once and stored next to a container so that
+//! tools can seek straight to the messages they need.
+
+use std::cmp::Ordering;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::err;
+use crate::tag::Tag;
+
+pub const MAGIC: [u8; 4] = *b"BERC";
+pub const VERSION: u16 = 1;
+
+/// Size of the fixed header in front of the first tag.
+pub const HEADER_LEN: usize = 10;
+
+/// Whether `buf` starts with a container header.
+pub fn is_container (buf: &[u8]) -> bool {
+    buf.starts_with(&MAGIC)
+}
+
+/// Writes `tags` as a container.
+pub fn write (w: &mut dyn Write, tags: &[Tag]) -> Result<(), err::Error> {
+    if tags.len() > u32::MAX as usize {
+        return Err(err::Error::new(err::Kind::NumberOverflow, 0, None));
+    }
+
+    w.write_all(&MAGIC)?;
+    w.write_u16::<BigEndian>(VERSION)?;
+    w.write_u32::<BigEndian>(tags.len() as u32)?;
+    for tag in tags {
+        tag.write(w)?;
+    }
+    Ok(())
+}
+
+/// Reads and checks the container header, returning the number of tags that follow.
+pub fn read_header (r: &mut dyn Read) -> Result<u32, err::Error> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(err::Error::new(err::Kind::UnrecognizedFormat, 0, None));
+    }
+
+    let version = r.read_u16::<BigEndian>()?;
+    if version != VERSION {
+        return Err(err::Error::new(err::Kind::UnsupportedVersion, 4, None));
+    }
+
+    Ok(r.read_u32::<BigEndian>()?)
+}
+
+/// Reads every tag in a container. Offsets within each tag are relative to the start of
+/// that tag.
+pub fn read (r: &mut dyn Read) -> Result<Vec<Tag>, err::Error> {
+    let count = read_header(r)?;
+
+    // don't trust count for the allocation, a bogus header would reserve gigabytes
+    let mut tags = Vec::new();
+    for _ in 0..count {
+        tags.push(Tag::read(r)?);
+    }
+    Ok(tags)
+}
+
+/// Every tag in `buf`: those of a container if it starts with one, and otherwise those of
+/// messages back to back, as `Tag::read_all` reads them.
+pub fn read_any (buf: &[u8]) -> Result<Vec<Tag>, err::Error> {
+    if is_container(buf) {
+        return read(&mut &buf[..]);
+    }
+
+    let mut r = buf;
+    Tag::read_all(&mut r).map(|m| m.map(|(_, tag)| tag)).collect()
+}
+
+pub const INDEX_MAGIC: [u8; 4] = *b"BERI";
+
+/// The offset of every tag in a container, as a position in the reader the index was
+/// built from, so `get` can seek straight to it. For a container that is a file of its
+/// own that's the offset from the start of the container; for one embedded further into
+/// a reader, the index only holds for readers that embed it in the same place.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Index {
+    offsets: Vec<u64>,
+}
+
+impl Index {
+    /// Builds an index by reading through the container once, from the reader's current
+    /// position.
+    pub fn build<R: Read + Seek> (r: &mut R) -> Result<Index, err::Error> {
+        let count = read_header(r)?;
+
+        let mut offsets = Vec::new();
+        for _ in 0..count {
+            offsets.push(r.stream_position()?);
+            Tag::read(r)?;
+        }
+
+        Ok(Index { offsets })
+    }
+
+    pub fn len (&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The position of the `n`th tag in the reader.
+    pub fn offset (&self, n: usize) -> Option<u64> {
+        self.offsets.get(n).cloned()
+    }
+
+    /// Seeks to and reads the `n`th tag, or returns `None` if there are fewer tags.
+    pub fn get<R: Read + Seek> (&self, r: &mut R, n: usize) -> Result<Option<Tag>, err::Error> {
+        match self.offset(n) {
+            Some(offset) => {
+                r.seek(SeekFrom::Start(offset))?;
+                Ok(Some(Tag::read(r)?))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Binary searches a container whose tags are sorted by the key `f` extracts,
+    /// reading only the tags the search visits. Returns `Ok(index)` of a matching tag or
+    /// `Err(index)` where one would be inserted, like `slice::binary_search_by_key`.
+    pub fn binary_search_by_key<R, K, F> (&self, r: &mut R, key: &K, mut f: F) -> Result<Result<usize, usize>, err::Error>
+        where R: Read + Seek, K: Ord, F: FnMut(&Tag) -> K {
+        let (mut lo, mut hi) = (0, self.len());
+
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            r.seek(SeekFrom::Start(self.offsets[mid]))?;
+
+            match f(&Tag::read(r)?).cmp(key) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
+            }
+        }
+
+        Ok(Err(lo))
+    }
+
+    /// Writes the index: the magic `BERI`, a big endian u16 version, a big endian u32
+    /// count and that many big endian u64 offsets.
+    pub fn write (&self, w: &mut dyn Write) -> Result<(), err::Error> {
+        w.write_all(&INDEX_MAGIC)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_u32::<BigEndian>(self.offsets.len() as u32)?;
+        for &offset in &self.offsets {
+            w.write_u64::<BigEndian>(offset)?;
+        }
+        Ok(())
+    }
+
+    pub fn read (r: &mut dyn Read) -> Result<Index, err::Error> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != INDEX_MAGIC {
+            return Err(err::Error::new(err::Kind::UnrecognizedFormat, 0, None));
+        }
+
+        let version = r.read_u16::<BigEndian>()?;
+        if version != VERSION {
+            return Err(err::Error::new(err::Kind::UnsupportedVersion, 4, None));
+        }
+
+        let count = r.read_u32::<BigEndian>()?;
+        let mut offsets = Vec::new();
+        for _ in 0..count {
+            offsets.push(r.read_u64::<BigEndian>()?);
+        }
+        Ok(Index { offsets })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip () {
+        let payload = vec![0x30, 0x80, 0x0C, 0x03, 0x64, 0x65, 0x66, 0x00, 0x00];
+        let tags = vec![
+            Tag::read(&mut &payload[..]).unwrap(),
+            Tag::read(&mut &payload[..]).unwrap(),
+        ];
+
+        let mut buf = Vec::new();
+        write(&mut buf, &tags).unwrap();
+        assert!(is_container(&buf));
+        assert_eq!(buf.len(), HEADER_LEN + 2 * payload.len());
+
+        assert_eq!(read(&mut &buf[..]).unwrap(), tags);
+    }
+
+    #[test]
+    fn test_index () {
+        use std::io::Cursor;
+        use crate::tag::{Number, Payload, TagNumber};
+
+        let tags: Vec<Tag> = (0..10).map(|i| Tag {
+            number: Number::ContextSpecific(TagNumber::new(i)),
+            offset: None,
+            payload: Payload::Primitive(vec![0; i as usize]),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }).collect();
+
+        let mut buf = Vec::new();
+        write(&mut buf, &tags).unwrap();
+        let mut file = Cursor::new(buf);
+
+        let index = Index::build(&mut file).unwrap();
+        assert_eq!(index.len(), 10);
+        assert_eq!(index.offset(0), Some(HEADER_LEN as u64));
+
+        let mut stored = Vec::new();
+        index.write(&mut stored).unwrap();
+        let index = Index::read(&mut &stored[..]).unwrap();
+
+        assert_eq!(index.get(&mut file, 7).unwrap().unwrap().number, Number::ContextSpecific(TagNumber::new(7)));
+        assert!(index.get(&mut file, 10).unwrap().is_none());
+
+        let size = |t: &Tag| match t.payload {
+            Payload::Primitive(ref v) => v.len(),
+            Payload::Constructed(_) => 0,
+        };
+        assert_eq!(index.binary_search_by_key(&mut file, &4, size).unwrap(), Ok(4));
+        assert_eq!(index.binary_search_by_key(&mut file, &42, size).unwrap(), Err(10));
+
+        // a container 3 bytes into its reader is indexed by positions in that reader
+        let mut embedded = vec![0xAA; 3];
+        write(&mut embedded, &tags).unwrap();
+        let mut file = Cursor::new(embedded);
+        file.set_position(3);
+        let index = Index::build(&mut file).unwrap();
+        assert_eq!(index.offset(0), Some(3 + HEADER_LEN as u64));
+        assert_eq!(index.get(&mut file, 7).unwrap().unwrap().number, Number::ContextSpecific(TagNumber::new(7)));
+    }
+
+    #[test]
+    fn test_bad_header () {
+        assert!(read(&mut &b"NOPE\x00\x01\x00\x00\x00\x00"[..]).is_err());
+        assert!(read(&mut &b"BERC\x00\x02\x00\x00\x00\x00"[..]).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! One message per packet, as SNMP, connectionless LDAP and Kerberos over UDP send them.
+//!
+//! A datagram holds exactly one message, so `parse` rejects anything after it instead of
+//! leaving it for a next read that never comes, and `encode` writes definite lengths and
+//! refuses to build a message too big to send.
+
+use crate::encoder;
+use crate::err;
+use crate::tag::{LengthForm, LengthPolicy, ReadOptions, Tag, WriteOptions};
+
+/// The largest UDP payload over IPv4.
+pub const MAX_SIZE: usize = 65_507;
+
+/// The read options `parse` uses. A packet can't hold more than its own length of
+/// content, so only the nesting depth needs a limit: a full packet of empty headers
+/// would otherwise nest tens of thousands deep.
+pub fn options () -> ReadOptions {
+    ReadOptions { max_depth: 64, ..ReadOptions::default() }
+}
+
+/// The message in `packet`. Bytes after it are a `Kind::TrailingData` error.
+pub fn parse (packet: &[u8]) -> Result<Tag, err::Error> {
+    parse_with(packet, &options())
+}
+
+/// `parse` using `opts`.
+pub fn parse_with (packet: &[u8], opts: &ReadOptions) -> Result<Tag, err::Error> {
+    Tag::read_exact_one_with(&mut &packet[..], opts)
+}
+
+/// `tag` encoded with definite lengths, as long as it fits in `MAX_SIZE` bytes.
+pub fn encode (tag: &Tag) -> Result<Vec<u8>, err::Error> {
+    encode_with(tag, MAX_SIZE)
+}
+
+/// `tag` encoded with definite lengths. A message longer than `max_size` bytes is a
+/// `Kind::BudgetExceeded` error, found before anything is written.
+pub fn encode_with (tag: &Tag, max_size: usize) -> Result<Vec<u8>, err::Error> {
+    let opts = WriteOptions { length_policy: LengthPolicy::Always(LengthForm::Definite), ..WriteOptions::default() };
+    let len = encoder::encoded_len(tag, &opts);
+    if len > max_size {
+        return Err(err::Error::new(err::Kind::BudgetExceeded { limit: max_size, path: Vec::new() }, 0, None));
+    }
+
+    let mut out = Vec::with_capacity(len);
+    tag.write_with(&mut out, &opts)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip () {
+        let message = Tag::sequence(vec![Tag::integer(1), Tag::octet_string(b"public".to_vec())]);
+        let packet = encode(&message).unwrap();
+        assert_eq!(packet[..2], [0x30, 0x0B]);
+        assert_eq!(parse(&packet).unwrap(), Tag::read(&mut &packet[..]).unwrap());
+
+        let mut padded = packet.clone();
+        padded.push(0x00);
+        let e = parse(&padded).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::TrailingData), "{}", e);
+        assert_eq!(e.offset, packet.len());
+
+        let e = encode_with(&message, packet.len() - 1).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::BudgetExceeded { .. }), "{}", e);
+        assert_eq!(encode_with(&message, packet.len()).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_deep_packet () {
+        // a packet of nothing but indefinite SEQUENCE headers
+        let packet = [0x30, 0x80].repeat(1000);
+        let e = parse(&packet).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::TooDeep { limit: 64 }), "{}", e);
+    }
+}
@@ -0,0 +1,318 @@
+//! Push-based incremental decoding, for callers that receive bytes rather than read them.
+//!
+//! A network server holding partial socket buffers can't hand `Tag::read` a blocking
+//! `Read`. Instead it feeds each buffer to a `Decoder` as it arrives and gets back the
+//! `Event`s those bytes completed. Primitive content is passed through in chunks as it
+//! comes in, so nothing is buffered beyond a single header.
+
+use crate::der;
+use crate::err;
+use crate::tag::{self, Flavor, HeaderLayout, Length, Number, Type};
+
+/// Parse progress reported by `Decoder::feed`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Event {
+    /// A complete header. `length` is `None` for indefinite lengths, and `layout` is set
+    /// as `Tag::layout` would be.
+    Start {
+        offset:      usize,
+        number:      Number,
+        constructed: bool,
+        length:      Option<usize>,
+        layout:      Option<HeaderLayout>,
+    },
+    /// The next chunk of the current primitive's content.
+    Content(Vec<u8>),
+    /// The end of the tag most recently started and not yet ended. `offset` is just
+    /// past its last byte, including any end-of-contents octets.
+    End { offset: usize },
+}
+
+// An open tag: where its content ends, or `None` while waiting for end-of-contents.
+struct Frame {
+    end:        Option<usize>,
+    indefinite: bool,
+}
+
+enum State {
+    Header,
+    Content { remaining: usize },
+}
+
+/// Decodes a stream of concatenated BER messages handed over in arbitrary pieces.
+///
+/// After an error the decoder's state is unspecified; start a new one to carry on.
+/// `finish` on a failed decoder still gives an error rather than `Ok`.
+pub struct Decoder {
+    state:  State,
+    header: Vec<u8>,
+    frames: Vec<Frame>,
+    offset: usize,
+}
+
+impl Default for Decoder {
+    fn default () -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    pub fn new () -> Decoder {
+        Decoder {
+            state: State::Header,
+            header: Vec::with_capacity(der::MAX_HEADER),
+            frames: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Stream offset of the next byte to be fed.
+    pub fn offset (&self) -> usize {
+        self.offset
+    }
+
+    /// The most bytes the next `feed` can take without reading past the end of the
+    /// current tag's header or content. Feeding no more than this never consumes bytes
+    /// of the message after the current one.
+    pub fn wanted (&self) -> usize {
+        match self.state {
+            State::Header => 1,
+            State::Content { remaining } => remaining,
+        }
+    }
+
+    /// Whether the decoder is between top level messages.
+    pub fn is_idle (&self) -> bool {
+        matches!(self.state, State::Header) && self.header.is_empty() && self.frames.is_empty()
+    }
+
+    /// Consumes `buf` and returns the events it completed, in order.
+    pub fn feed (&mut self, mut buf: &[u8]) -> Result<Vec<Event>, err::Error> {
+        let mut events = Vec::new();
+
+        while !buf.is_empty() {
+            match self.state {
+                State::Header => {
+                    self.header.push(buf[0]);
+                    buf = &buf[1..];
+                    self.offset += 1;
+
+                    if der::header_complete(&self.header) {
+                        self.start(&mut events)?;
+                    } else if self.header.len() >= der::MAX_HEADER {
+                        let start = self.offset - self.header.len();
+                        return Err(err::Error::new(err::Kind::NumberOverflow, start, None));
+                    }
+                },
+                State::Content { remaining } => {
+                    let n = remaining.min(buf.len());
+                    events.push(Event::Content(buf[..n].to_vec()));
+                    buf = &buf[n..];
+                    self.offset += n;
+
+                    if n == remaining {
+                        self.state = State::Header;
+                        events.push(Event::End { offset: self.offset });
+                        self.close_definite(&mut events);
+                    } else {
+                        self.state = State::Content { remaining: remaining - n };
+                    }
+                },
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Checks that the stream ended between messages.
+    pub fn finish (self) -> Result<(), err::Error> {
+        let needed = match self.state {
+            State::Content { remaining } => remaining,
+            // at least one more header byte, or two end-of-contents octets
+            State::Header if !self.header.is_empty() => 1,
+            State::Header => match self.frames.last() {
+                None => return Ok(()),
+                Some(&Frame { end: None, .. }) => 2,
+                Some(&Frame { end: Some(end), .. }) => match end.checked_sub(self.offset) {
+                    Some(needed) => needed,
+                    // a feed already failed on a child running past this tag
+                    None => return Err(err::Error::new(err::Kind::LengthExceedsContainer, self.offset, None)),
+                },
+            },
+        };
+        Err(err::Error::new(err::Kind::Truncated { needed }, self.offset, None))
+    }
+
+    // Acts on the complete header in `self.header`.
+    fn start (&mut self, events: &mut Vec<Event>) -> Result<(), err::Error> {
+        let start = self.offset - self.header.len();
+        let fail = |kind| err::Error::new(kind, start, None);
+
+        let mut r = &self.header[..];
+        let (_, flavor, number) = tag::read_identifiers(&mut r).map_err(|e| fail(e.kind))?;
+        let number_octets = self.header.len() - r.len() - 1;
+        let read_length = tag::read_length(&mut r).map_err(|e| fail(e.kind))?;
+        let layout = HeaderLayout::observed(&number, &read_length, number_octets, self.header.len() - number_octets - 2);
+        let length = match read_length {
+            Length::Indefinite => None,
+            Length::Some(l) => Some(usize::try_from(l).map_err(|_| fail(err::Kind::NumberOverflow))?),
+        };
+        self.header.clear();
+
+        let end = match length {
+            Some(l) => Some(self.offset.checked_add(l).ok_or_else(|| fail(err::Kind::NumberOverflow))?),
+            None => None,
+        };
+        if let Some(parent) = self.frames.last().and_then(|f| f.end) {
+            if self.offset > parent || end.is_some_and(|end| end > parent) {
+                return Err(fail(err::Kind::LengthExceedsContainer));
+            }
+        }
+
+        let eoc = number == Number::Universal(Type::Eoc) && flavor == Flavor::Primitive && length == Some(0);
+        if eoc && self.frames.last().is_some_and(|f| f.indefinite) {
+            self.frames.pop();
+            events.push(Event::End { offset: self.offset });
+            self.close_definite(events);
+            return Ok(());
+        }
+        if number == Number::Universal(Type::Eoc) && self.frames.last().is_some_and(|f| !f.indefinite) {
+            return Err(fail(err::Kind::UnexpectedEoc));
+        }
+
+        let constructed = flavor == Flavor::Constructed;
+        events.push(Event::Start { offset: start, number, constructed, length, layout });
+
+        match (constructed, length) {
+            (false, None) => return Err(fail(err::Kind::InvalidLength)),
+            (false, Some(0)) | (true, Some(0)) => {
+                events.push(Event::End { offset: self.offset });
+                self.close_definite(events);
+            },
+            (false, Some(l)) => self.state = State::Content { remaining: l },
+            (true, _) => self.frames.push(Frame { end, indefinite: length.is_none() }),
+        }
+        Ok(())
+    }
+
+    // Ends every open definite length tag whose content finishes at the current offset.
+    fn close_definite (&mut self, events: &mut Vec<Event>) {
+        while self.frames.last().is_some_and(|f| f.end == Some(self.offset)) {
+            self.frames.pop();
+            events.push(Event::End { offset: self.offset });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::TagNumber;
+
+    // SEQUENCE (indefinite) { [1] { INTEGER 5 }, OCTET STRING "abc" }, then NULL
+    const STREAM: [u8; 18] = [
+        0x30, 0x80,
+            0xA1, 0x03, 0x02, 0x01, 0x05,
+            0x04, 0x03, 0x61, 0x62, 0x63,
+        0x00, 0x00,
+        0x05, 0x00,
+        0x00, 0x00,
+    ];
+
+    // Joins adjacent content chunks so differently split feeds compare equal.
+    fn normalize (events: Vec<Event>) -> Vec<Event> {
+        let mut out: Vec<Event> = Vec::new();
+        for e in events {
+            match (out.last_mut(), e) {
+                (Some(Event::Content(ref mut a)), Event::Content(b)) => a.extend(b),
+                (_, e) => out.push(e),
+            }
+        }
+        out
+    }
+
+    fn start (offset: usize, number: Number, constructed: bool, length: Option<usize>) -> Event {
+        Event::Start { offset, number, constructed, length, layout: None }
+    }
+
+    #[test]
+    fn test_feed () {
+        let expected = vec![
+            start(0, Number::Universal(Type::Sequence), true, None),
+            start(2, Number::ContextSpecific(TagNumber::new(1)), true, Some(3)),
+            start(4, Number::Universal(Type::Integer), false, Some(1)),
+            Event::Content(vec![0x05]),
+            Event::End { offset: 7 },
+            Event::End { offset: 7 },
+            start(7, Number::Universal(Type::OctetString), false, Some(3)),
+            Event::Content(b"abc".to_vec()),
+            Event::End { offset: 12 },
+            Event::End { offset: 14 },
+            start(14, Number::Universal(Type::Null), false, Some(0)),
+            Event::End { offset: 16 },
+            // outside any indefinite tag, end-of-contents octets are an ordinary tag
+            start(16, Number::Universal(Type::Eoc), false, Some(0)),
+            Event::End { offset: 18 },
+        ];
+
+        for size in 1..=STREAM.len() {
+            let mut decoder = Decoder::new();
+            let mut events = Vec::new();
+            for chunk in STREAM.chunks(size) {
+                events.extend(decoder.feed(chunk).unwrap());
+            }
+            assert_eq!(normalize(events), expected, "chunk size {}", size);
+            assert!(decoder.is_idle());
+            decoder.finish().unwrap();
+        }
+
+        // a padded length is reported as a read would record it
+        let mut decoder = Decoder::new();
+        let events = decoder.feed(&[0x04, 0x81, 0x01]).unwrap();
+        let layout = HeaderLayout { number_octets: None, length_octets: Some(1) };
+        assert_eq!(events, vec![Event::Start {
+            offset: 0,
+            number: Number::Universal(Type::OctetString),
+            constructed: false,
+            length: Some(1),
+            layout: Some(layout),
+        }]);
+        assert_eq!(decoder.wanted(), 1);
+    }
+
+    #[test]
+    fn test_errors () {
+        let mut decoder = Decoder::new();
+        let e = decoder.feed(&[0x04, 0x80]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength));
+
+        // the child runs past the end of its parent
+        let mut decoder = Decoder::new();
+        let e = decoder.feed(&[0x30, 0x02, 0x04, 0x02, 0x00, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer));
+        assert_eq!(e.offset, 2);
+
+        // an end-of-contents marker inside a definite length
+        let e = Decoder::new().feed(&[0x30, 0x02, 0x00, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::UnexpectedEoc));
+        assert_eq!(e.offset, 2);
+
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0x30, 0x80, 0x04, 0x03, 0x61]).unwrap();
+        let e = decoder.finish().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 2 }));
+
+        let mut decoder = Decoder::new();
+        decoder.feed(&[0x30, 0x80]).unwrap();
+        assert!(!decoder.is_idle());
+        let e = decoder.finish().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 2 }));
+
+        // finishing after a failed feed, with the stream already past the open tag
+        let mut decoder = Decoder::new();
+        let e = decoder.feed(&[0x30, 0x0B, 0xA5, 0x01, 0x7F, 0x6E, 0x02, 0x00, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer));
+        let e = decoder.finish().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer), "{}", e);
+    }
+}
@@ -0,0 +1,1148 @@
+//! Distinguished Encoding Rules validation straight over bytes.
+//!
+//! `check_der` never builds a tree or allocates payloads, so gateways can cheaply turn
+//! away non-canonical input before doing any real work on it. `DerValidatingWriter`
+//! does the same for a stream, without buffering it. `Tag::write_der` produces DER from
+//! a tree, and `Tag::write_cer` its streaming sibling CER; `Tag::canonicalize` orders a
+//! tree's SETs in place for any other writer.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::slice;
+use std::vec;
+
+use crate::layer::WriteLayer;
+use crate::tag::{self, Length, Number, Payload, Tag, Type};
+
+/// The ways an encoding can fall short of DER.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ViolationKind {
+    /// Not valid BER to begin with: truncated, lengths exceeding their container,
+    /// unsupported sizes. Checking stops at the first one of these.
+    Malformed,
+    IndefiniteLength,
+    /// The length uses more octets than needed.
+    NonMinimalLength,
+    /// The tag number uses the long form when the short form fits, or has padding.
+    NonMinimalTagNumber,
+    /// A string type uses the constructed form.
+    ConstructedString,
+    /// A BOOLEAN whose content is not a single 0x00 or 0xFF.
+    InvalidBoolean,
+    /// An INTEGER or ENUMERATED with redundant leading octets.
+    NonMinimalInteger,
+    /// A BIT STRING with a bad unused-bits count or non-zero unused bits.
+    InvalidBitString,
+    /// SET members that are not in canonical order.
+    UnsortedSet,
+    /// Bytes after the end of the top level element.
+    TrailingData,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Violation {
+    /// Offset of the start of the offending element, or of the trailing data.
+    pub offset: usize,
+    pub kind:   ViolationKind,
+}
+
+// Bounds the recursion on hostile input.
+const MAX_DEPTH: usize = 128;
+
+/// Checks that `buf` holds exactly one DER encoded element, returning every violation
+/// found.
+pub fn check_der (buf: &[u8]) -> Result<(), Vec<Violation>> {
+    let mut violations = Vec::new();
+
+    if let Some(len) = check_element(buf, 0, 0, &mut violations) {
+        if len < buf.len() {
+            violations.push(Violation { offset: len, kind: ViolationKind::TrailingData });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// The parsed identifier and length octets of an element.
+pub(crate) struct Header {
+    pub class:       u8,
+    pub constructed: bool,
+    pub number:      u64,
+    /// `None` for the indefinite form.
+    pub length:      Option<usize>,
+    pub size:        usize,
+}
+
+/// Parses the header at the start of `buf`, reporting non-canonical header encodings
+/// through `violation`. Returns `None` if the header is malformed or truncated.
+pub(crate) fn read_header (buf: &[u8], violation: &mut dyn FnMut(ViolationKind)) -> Option<Header> {
+    let id = *buf.first()?;
+    let class = id >> 6;
+    let constructed = id & 0x20 != 0;
+    let mut pos = 1;
+
+    let number = if id & 0x1F == 0x1F {
+        if class == 0 {
+            return None;
+        }
+
+        if *buf.get(pos)? == 0x80 {
+            violation(ViolationKind::NonMinimalTagNumber);
+        }
+
+        let mut n = 0u64;
+        loop {
+            let b = *buf.get(pos)?;
+            pos += 1;
+            if n >> 57 != 0 {
+                return None;
+            }
+            n = n << 7 | (b & 0x7F) as u64;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+
+        if n < 0x1F {
+            violation(ViolationKind::NonMinimalTagNumber);
+        }
+        n
+    } else {
+        (id & 0x1F) as u64
+    };
+
+    let b = *buf.get(pos)?;
+    pos += 1;
+
+    let length = if b == 0x80 {
+        violation(ViolationKind::IndefiniteLength);
+        None
+    } else if b & 0x80 != 0 {
+        let count = (b & 0x7F) as usize;
+        if count > 8 {
+            return None;
+        }
+
+        let octets = buf.get(pos..pos + count)?;
+        pos += count;
+
+        let l = octets.iter().fold(0u64, |acc, &o| acc << 8 | o as u64);
+        if octets[0] == 0 || l < 0x80 {
+            violation(ViolationKind::NonMinimalLength);
+        }
+        Some(usize::try_from(l).ok()?)
+    } else {
+        Some(b as usize)
+    };
+
+    Some(Header {
+        class,
+        constructed,
+        number,
+        length,
+        size: pos,
+    })
+}
+
+fn is_string_type (number: u64) -> bool {
+    matches!(number, 3 | 4 | 7 | 12 | 18..=22 | 25..=30)
+}
+
+// Checks the element at the start of `buf`, whose first byte is at `base` in the
+// original input. Returns its encoded size, or `None` once it hits something malformed.
+fn check_element (buf: &[u8], base: usize, depth: usize, out: &mut Vec<Violation>) -> Option<usize> {
+    let malformed = |out: &mut Vec<Violation>| {
+        out.push(Violation { offset: base, kind: ViolationKind::Malformed });
+        None
+    };
+
+    if depth > MAX_DEPTH {
+        return malformed(out);
+    }
+
+    let header = match read_header(buf, &mut |kind| out.push(Violation { offset: base, kind })) {
+        Some(h) => h,
+        None => return malformed(out),
+    };
+    let universal = header.class == 0;
+
+    if !header.constructed {
+        let len = match header.length {
+            Some(l) if l <= buf.len() - header.size => l,
+            _ => return malformed(out),
+        };
+        let content = &buf[header.size..header.size + len];
+
+        if universal {
+            let kind = match header.number {
+                1 if content.len() != 1 || (content[0] != 0x00 && content[0] != 0xFF) =>
+                    Some(ViolationKind::InvalidBoolean),
+                2 | 10 if content.is_empty() => return malformed(out),
+                2 | 10 if content.len() > 1 && ((content[0] == 0x00 && content[1] & 0x80 == 0) ||
+                                               (content[0] == 0xFF && content[1] & 0x80 != 0)) =>
+                    Some(ViolationKind::NonMinimalInteger),
+                3 if !bit_string_ok(content) => Some(ViolationKind::InvalidBitString),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                out.push(Violation { offset: base, kind });
+            }
+        }
+
+        return Some(header.size + len);
+    }
+
+    if universal && is_string_type(header.number) {
+        out.push(Violation { offset: base, kind: ViolationKind::ConstructedString });
+    }
+
+    // walk the children, remembering where each one is for the SET ordering check
+    let mut children = Vec::new();
+    let mut pos = header.size;
+    let end = match header.length {
+        Some(l) if l <= buf.len() - header.size => Some(header.size + l),
+        Some(_) => return malformed(out),
+        None => None,
+    };
+
+    loop {
+        match end {
+            Some(end) if pos == end => break,
+            Some(end) if pos > end => return malformed(out),
+            None if buf[pos..].starts_with(&[0x00, 0x00]) => {
+                pos += 2;
+                break;
+            },
+            _ => {},
+        }
+
+        let limit = end.unwrap_or(buf.len());
+        let len = check_element(&buf[pos..limit], base + pos, depth + 1, out)?;
+        children.push(&buf[pos..pos + len]);
+        pos += len;
+    }
+
+    if universal && header.number == 17 && !set_sorted(&children) {
+        out.push(Violation { offset: base, kind: ViolationKind::UnsortedSet });
+    }
+
+    Some(pos)
+}
+
+fn bit_string_ok (content: &[u8]) -> bool {
+    match content.split_first() {
+        None => false,
+        Some((&unused, rest)) => {
+            if unused > 7 || (rest.is_empty() && unused != 0) {
+                return false;
+            }
+            let last = rest.last().cloned().unwrap_or(0);
+            last & ((1u8 << unused) - 1) == 0
+        },
+    }
+}
+
+// The identifier octets sort SET members by (class, number) as long as the constructed
+// bit does not differ, which is the only case DER's tag ordering and plain byte ordering
+// disagree; SET OF members (equal tags) are compared by their whole encodings.
+pub(crate) fn set_order (a: &[u8], b: &[u8]) -> Ordering {
+    let key = |c: &[u8]| read_header(c, &mut |_| {}).map(|h| (h.class, h.number));
+
+    match (key(a), key(b)) {
+        (Some(ka), Some(kb)) if ka != kb => ka.cmp(&kb),
+        _ => a.cmp(b),
+    }
+}
+
+fn set_sorted (children: &[&[u8]]) -> bool {
+    children.windows(2).all(|w| set_order(w[0], w[1]) != Ordering::Greater)
+}
+
+impl Tag {
+    /// Writes the tag in DER: definite lengths in their shortest form throughout, the
+    /// members of each universal SET in canonical order, and constructed universal
+    /// strings joined into one primitive. Content octets are written as they are
+    /// otherwise, so primitives must already hold their DER content. Segments that don't
+    /// join are an `io::ErrorKind::InvalidData` error.
+    pub fn write_der (&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_all(&self.to_der()?)
+    }
+
+    /// `write_der` into a new buffer.
+    pub fn to_der (&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        encode_der(self, &mut out, false, &mut None)?;
+        Ok(out)
+    }
+
+    #[cfg(feature = "digest")]
+    // `to_der` with the content of BOOLEANs, INTEGERs, ENUMERATEDs and BIT STRINGs put
+    // into its DER form too, so that every BER encoding of a value gives the same bytes.
+    pub(crate) fn to_normalized_der (&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        encode_der(self, &mut out, true, &mut None)?;
+        Ok(out)
+    }
+
+    /// Puts the members of every universal SET in this tree into DER order in place, so
+    /// the tree is canonical whichever writer encodes it. `write_der` already orders
+    /// SETs as it goes and doesn't need this. A tree `to_der` can't encode is left as
+    /// it was.
+    pub fn canonicalize (&mut self) -> io::Result<()> {
+        // one encoding of the tree finds the order of every SET, before anything moves
+        let mut orders = Some(Vec::new());
+        encode_der(self, &mut Vec::new(), false, &mut orders)?;
+        let mut orders = orders.unwrap_or_default().into_iter();
+
+        // the walk takes each constructed tag's children out while it orders them, and
+        // keeps its own stack of those tags: each with the children still to do and
+        // those done. It meets the SETs in the order the encoding finished them.
+        let mut stack: Vec<(Tag, vec::IntoIter<Tag>, Vec<Tag>)> = Vec::new();
+        let mut current = mem::replace(self, Tag::null());
+        loop {
+            let mut done = match current.payload {
+                Payload::Constructed(ref mut children) if !segmented(&current.number) => {
+                    let todo = mem::take(children).into_iter();
+                    stack.push((current, todo, Vec::new()));
+                    None
+                },
+                _ => Some(current),
+            };
+
+            loop {
+                let (_, todo, finished) = match stack.last_mut() {
+                    Some(open) => open,
+                    None => {
+                        *self = done.unwrap_or_else(Tag::null);
+                        return Ok(());
+                    },
+                };
+                finished.extend(done.take());
+                if let Some(child) = todo.next() {
+                    current = child;
+                    break;
+                }
+
+                let (mut tag, _, mut children) = stack.pop().expect("the stack was just peeked");
+                if tag.number == Number::Universal(Type::Set) {
+                    let order = orders.next().unwrap_or_default();
+                    let mut members: Vec<_> = children.into_iter().map(Some).collect();
+                    children = order.iter().filter_map(|&i| members[i].take()).collect();
+                }
+                tag.payload = Payload::Constructed(children);
+                done = Some(tag);
+            }
+        }
+    }
+}
+
+// Writes a tag DER encodes without looking at its children: a primitive, or a
+// constructed string joined into one. `normalize` puts the content into DER form.
+fn encode_der_primitive (tag: &Tag, out: &mut Vec<u8>, normalize: bool) -> io::Result<()> {
+    let content = match tag.payload {
+        Payload::Primitive(ref v) => Cow::Borrowed(&v[..]),
+        // DER has no constructed strings
+        Payload::Constructed(_) => {
+            let content = match tag.number {
+                Number::Universal(Type::BitString) => tag.as_bit_string().map(|bits| Cow::Owned(bits.to_content())),
+                _ => tag.as_bytes(),
+            };
+            content.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        },
+    };
+    let content = if normalize { normalized_content(&tag.number, content) } else { content };
+    tag::write_identifiers(out, &tag.number.class(), &tag::Flavor::Primitive, &tag.number)?;
+    tag::write_length(out, &Length::Some(content.len() as u64))?;
+    out.extend_from_slice(&content);
+    Ok(())
+}
+
+// The DER form of content BER allows several encodings of: any true BOOLEAN is 0xFF,
+// INTEGERs and ENUMERATEDs lose redundant leading octets, and a BIT STRING's unused
+// bits are zero. Anything malformed is left as it is.
+fn normalized_content<'a> (number: &Number, content: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+    match *number {
+        Number::Universal(Type::Boolean) if content.len() == 1 && content[0] != 0x00 && content[0] != 0xFF =>
+            Cow::Owned(vec![0xFF]),
+        Number::Universal(Type::Integer) | Number::Universal(Type::Enumerated) => {
+            let redundant = content.windows(2)
+                .take_while(|w| (w[0] == 0x00 && w[1] < 0x80) || (w[0] == 0xFF && w[1] >= 0x80))
+                .count();
+            match content {
+                _ if redundant == 0 => content,
+                Cow::Borrowed(c) => Cow::Borrowed(&c[redundant..]),
+                Cow::Owned(mut c) => {
+                    c.drain(..redundant);
+                    Cow::Owned(c)
+                },
+            }
+        },
+        Number::Universal(Type::BitString) if content.len() > 1 && (1..8).contains(&content[0]) => {
+            let mask = 0xFFu8 << content[0];
+            let last = content.len() - 1;
+            if content[last] & !mask == 0 {
+                return content;
+            }
+            let mut content = content.into_owned();
+            content[last] &= mask;
+            Cow::Owned(content)
+        },
+        _ => content,
+    }
+}
+
+// An open constructed tag being encoded: the children still to encode, and the
+// encodings of those done.
+struct Open<'a> {
+    tag:      &'a Tag,
+    children: slice::Iter<'a, Tag>,
+    encoded:  Vec<Vec<u8>>,
+}
+
+// Encodes the tree with a stack of its own rather than the real one. Members are
+// encoded separately so a SET can be put in order; `orders` collects, for each SET as it
+// is finished, the original positions of its members in DER order.
+fn encode_der (tag: &Tag, out: &mut Vec<u8>, normalize: bool, orders: &mut Option<Vec<Vec<usize>>>) -> io::Result<()> {
+    let mut stack: Vec<Open> = Vec::new();
+    let mut current = tag;
+    loop {
+        let mut done = match current.payload {
+            Payload::Constructed(ref children) if !segmented(&current.number) => {
+                stack.push(Open { tag: current, children: children.iter(), encoded: Vec::new() });
+                None
+            },
+            _ => {
+                let mut buf = Vec::new();
+                encode_der_primitive(current, &mut buf, normalize)?;
+                Some(buf)
+            },
+        };
+
+        loop {
+            let open = match stack.last_mut() {
+                Some(open) => open,
+                None => {
+                    out.extend_from_slice(&done.unwrap_or_default());
+                    return Ok(());
+                },
+            };
+            open.encoded.extend(done.take());
+            if let Some(child) = open.children.next() {
+                current = child;
+                break;
+            }
+
+            let Open { tag, mut encoded, .. } = stack.pop().expect("the stack was just peeked");
+            if tag.number == Number::Universal(Type::Set) {
+                let mut order: Vec<usize> = (0..encoded.len()).collect();
+                order.sort_by(|&a, &b| set_order(&encoded[a], &encoded[b]));
+                let mut members: Vec<_> = encoded.into_iter().map(Some).collect();
+                encoded = order.iter().filter_map(|&i| members[i].take()).collect();
+                if let Some(ref mut orders) = *orders {
+                    orders.push(order);
+                }
+            }
+            let len = encoded.iter().map(Vec::len).sum::<usize>();
+            let mut buf = Vec::new();
+            tag::write_identifiers(&mut buf, &tag.number.class(), &tag::Flavor::Constructed, &tag.number)?;
+            tag::write_length(&mut buf, &Length::Some(len as u64))?;
+            for member in encoded {
+                buf.extend_from_slice(&member);
+            }
+            done = Some(buf);
+        }
+    }
+}
+
+/// The most content octets CER allows in one primitive string, or in one segment of a
+/// constructed one.
+pub const CER_SEGMENT_LEN: usize = 1000;
+
+impl Tag {
+    /// Writes the tag in CER: constructed tags with indefinite lengths, primitives with
+    /// definite lengths in their shortest form, and universal string primitives of more
+    /// than `CER_SEGMENT_LEN` content octets split into constructed encodings of
+    /// `CER_SEGMENT_LEN`-octet segments. Everything is written as it goes, except the
+    /// members of a universal SET, which are encoded first to be put in order.
+    pub fn write_cer (&self, w: &mut dyn Write) -> io::Result<()> {
+        encode_cer(self, w)
+    }
+
+    /// `write_cer` into a new buffer.
+    pub fn to_cer (&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        encode_cer(self, &mut out)?;
+        Ok(out)
+    }
+}
+
+// The encodings of a SET's members, to be put in order before they're written.
+type Members = Vec<Vec<u8>>;
+
+// Encodes the tree with a stack of its own rather than the real one, writing as it goes
+// except within a SET, whose members are each encoded into a buffer of their own to be
+// put in order.
+fn encode_cer (tag: &Tag, w: &mut dyn Write) -> io::Result<()> {
+    // open constructed tags, with the children still to write and, for a SET, the
+    // encodings of those done
+    let mut stack: Vec<(slice::Iter<Tag>, Option<Members>)> = Vec::new();
+    // the buffers of the SET members being encoded, innermost last
+    let mut bufs: Vec<Vec<u8>> = Vec::new();
+    let mut next = Some(tag);
+    loop {
+        let finished = match next.take() {
+            Some(tag) => {
+                if matches!(stack.last(), Some((_, Some(_)))) {
+                    bufs.push(Vec::new());
+                }
+                let out: &mut dyn Write = match bufs.last_mut() {
+                    Some(buf) => buf,
+                    None => &mut *w,
+                };
+                match tag.payload {
+                    Payload::Constructed(ref children) => {
+                        tag::write_identifiers(out, &tag.number.class(), &tag::Flavor::Constructed, &tag.number)?;
+                        tag::write_length(out, &Length::Indefinite)?;
+                        let set = tag.number == Number::Universal(Type::Set);
+                        stack.push((children.iter(), set.then(Vec::new)));
+                        false
+                    },
+                    Payload::Primitive(ref v) => {
+                        encode_cer_primitive(tag, v, out)?;
+                        true
+                    },
+                }
+            },
+            // every child of the innermost open tag is written
+            None => {
+                let (_, members) = match stack.pop() {
+                    Some(open) => open,
+                    None => return Ok(()),
+                };
+                let out: &mut dyn Write = match bufs.last_mut() {
+                    Some(buf) => buf,
+                    None => &mut *w,
+                };
+                if let Some(mut members) = members {
+                    members.sort_by(|a, b| set_order(a, b));
+                    for member in members {
+                        out.write_all(&member)?;
+                    }
+                }
+                out.write_all(&[0x00, 0x00])?;
+                true
+            },
+        };
+
+        // a finished member of a SET joins the others
+        if let (true, Some((_, Some(members)))) = (finished, stack.last_mut()) {
+            members.extend(bufs.pop());
+        }
+        next = stack.last_mut().and_then(|(children, _)| children.next());
+    }
+}
+
+// Writes a primitive, splitting a long string into segments.
+fn encode_cer_primitive (tag: &Tag, v: &[u8], w: &mut dyn Write) -> io::Result<()> {
+    let class = tag.number.class();
+
+    if v.len() <= CER_SEGMENT_LEN || !segmented(&tag.number) {
+        tag::write_identifiers(w, &class, &tag::Flavor::Primitive, &tag.number)?;
+        tag::write_length(w, &Length::Some(v.len() as u64))?;
+        return w.write_all(v);
+    }
+
+    tag::write_identifiers(w, &class, &tag::Flavor::Constructed, &tag.number)?;
+    tag::write_length(w, &Length::Indefinite)?;
+
+    if tag.number == Number::Universal(Type::BitString) {
+        // every segment carries its own unused bits octet, and only the last
+        // one may have any
+        let (&unused, bits) = v.split_first().expect("longer than a segment");
+        let mut segments = bits.chunks(CER_SEGMENT_LEN - 1).peekable();
+        while let Some(segment) = segments.next() {
+            let unused = if segments.peek().is_some() { 0 } else { unused };
+            write_primitive(w, Type::BitString, &[&[unused], segment])?;
+        }
+    } else {
+        // restricted character strings are segmented as OCTET STRINGs
+        for segment in v.chunks(CER_SEGMENT_LEN) {
+            write_primitive(w, Type::OctetString, &[segment])?;
+        }
+    }
+
+    w.write_all(&[0x00, 0x00])
+}
+
+fn segmented (number: &Number) -> bool {
+    match *number {
+        Number::Universal(t) => is_string_type(t as u64),
+        _ => false,
+    }
+}
+
+fn write_primitive (w: &mut dyn Write, t: Type, parts: &[&[u8]]) -> io::Result<()> {
+    let number = Number::Universal(t);
+    let len = parts.iter().map(|p| p.len()).sum::<usize>();
+    tag::write_identifiers(w, &number.class(), &tag::Flavor::Primitive, &number)?;
+    tag::write_length(w, &Length::Some(len as u64))?;
+    for part in parts {
+        w.write_all(part)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Violation {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DER violation at {}: {:?}", self.offset, self.kind)
+    }
+}
+
+impl error::Error for Violation {}
+
+// Identifier (1 + 9 number octets) plus length (1 + 8 octets).
+pub(crate) const MAX_HEADER: usize = 19;
+
+struct Frame {
+    end:      usize,
+    is_set:   bool,
+    last_key: Option<(u8, u64)>,
+}
+
+// What is left to check about a primitive's content octets.
+enum Content {
+    Plain,
+    Boolean,
+    Integer(Option<u8>),
+    BitString(u8),
+}
+
+enum State {
+    Header,
+    Content { start: usize, content: Content, index: usize, len: usize },
+}
+
+/// An incremental DER checker fed a byte stream in arbitrary chunks. It accepts any
+/// number of consecutive top level elements and stops at the first violation.
+///
+/// Unlike `check_der` it holds no element bytes, so SET members are only checked for
+/// tag order; SET OF ordering needs whole encodings and is not verified.
+pub struct Validator {
+    offset:       usize,
+    header:       [u8; MAX_HEADER],
+    header_len:   usize,
+    header_start: usize,
+    state:        State,
+    frames:       Vec<Frame>,
+    failed:       Option<Violation>,
+}
+
+impl Default for Validator {
+    fn default () -> Validator {
+        Validator::new()
+    }
+}
+
+impl Validator {
+    pub fn new () -> Validator {
+        Validator {
+            offset: 0,
+            header: [0; MAX_HEADER],
+            header_len: 0,
+            header_start: 0,
+            state: State::Header,
+            frames: Vec::new(),
+            failed: None,
+        }
+    }
+
+    /// Total bytes accepted so far.
+    pub fn offset (&self) -> usize {
+        self.offset
+    }
+
+    /// Checks the next chunk of the stream. Once a violation has been returned every
+    /// later call returns it again.
+    pub fn feed (&mut self, buf: &[u8]) -> Result<(), Violation> {
+        if let Some(v) = self.failed {
+            return Err(v);
+        }
+
+        for &b in buf {
+            if let Err(v) = self.byte(b) {
+                self.failed = Some(v);
+                return Err(v);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the stream ended on an element boundary.
+    pub fn finish (&self) -> Result<(), Violation> {
+        if let Some(v) = self.failed {
+            return Err(v);
+        }
+
+        match self.state {
+            State::Header if self.header_len == 0 && self.frames.is_empty() => Ok(()),
+            _ => Err(Violation { offset: self.offset, kind: ViolationKind::Malformed }),
+        }
+    }
+
+    fn byte (&mut self, b: u8) -> Result<(), Violation> {
+        self.offset += 1;
+
+        match self.state {
+            State::Header => {
+                if self.header_len == 0 {
+                    self.header_start = self.offset - 1;
+                }
+                self.header[self.header_len] = b;
+                self.header_len += 1;
+
+                if header_complete(&self.header[..self.header_len]) {
+                    self.start_element()?;
+                } else if self.header_len == MAX_HEADER {
+                    return Err(self.violation(ViolationKind::Malformed));
+                }
+            },
+            State::Content { start, ref mut content, ref mut index, len } => {
+                let i = *index;
+                *index += 1;
+
+                let bad = match *content {
+                    Content::Plain => None,
+                    Content::Boolean if b != 0x00 && b != 0xFF => Some(ViolationKind::InvalidBoolean),
+                    Content::Boolean => None,
+                    Content::Integer(None) => {
+                        *content = Content::Integer(Some(b));
+                        None
+                    },
+                    Content::Integer(Some(first)) if i == 1 &&
+                        ((first == 0x00 && b & 0x80 == 0) || (first == 0xFF && b & 0x80 != 0)) =>
+                        Some(ViolationKind::NonMinimalInteger),
+                    Content::Integer(Some(_)) => None,
+                    Content::BitString(_) if i == 0 => {
+                        if b > 7 || (len == 1 && b != 0) {
+                            Some(ViolationKind::InvalidBitString)
+                        } else {
+                            *content = Content::BitString(b);
+                            None
+                        }
+                    },
+                    Content::BitString(unused) if i == len - 1 && b & ((1u8 << unused) - 1) != 0 =>
+                        Some(ViolationKind::InvalidBitString),
+                    Content::BitString(_) => None,
+                };
+
+                if let Some(kind) = bad {
+                    return Err(Violation { offset: start, kind });
+                }
+
+                if *index == len {
+                    self.state = State::Header;
+                    self.close_frames();
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn violation (&self, kind: ViolationKind) -> Violation {
+        Violation { offset: self.header_start, kind }
+    }
+
+    fn start_element (&mut self) -> Result<(), Violation> {
+        let mut first = None;
+        let header = read_header(&self.header[..self.header_len], &mut |kind| {
+            first = first.or(Some(kind));
+        });
+        self.header_len = 0;
+
+        if let Some(kind) = first {
+            return Err(self.violation(kind));
+        }
+
+        let header = match header {
+            Some(h) => h,
+            None => return Err(self.violation(ViolationKind::Malformed)),
+        };
+        // indefinite lengths were reported as a violation above
+        let len = header.length.unwrap_or(0);
+        let end = match self.offset.checked_add(len) {
+            Some(end) => end,
+            None => return Err(self.violation(ViolationKind::Malformed)),
+        };
+        let universal = header.class == 0;
+
+        if let Some(parent) = self.frames.last_mut() {
+            if end > parent.end {
+                return Err(Violation { offset: self.header_start, kind: ViolationKind::Malformed });
+            }
+
+            if parent.is_set {
+                let key = (header.class, header.number);
+                if parent.last_key.is_some_and(|last| key < last) {
+                    return Err(Violation { offset: self.header_start, kind: ViolationKind::UnsortedSet });
+                }
+                parent.last_key = Some(key);
+            }
+        }
+
+        if header.constructed {
+            if universal && is_string_type(header.number) {
+                return Err(self.violation(ViolationKind::ConstructedString));
+            }
+
+            self.frames.push(Frame {
+                end,
+                is_set: universal && header.number == 17,
+                last_key: None,
+            });
+            self.close_frames();
+            return Ok(());
+        }
+
+        let content = match header.number {
+            1 if universal && len != 1 => return Err(self.violation(ViolationKind::InvalidBoolean)),
+            1 if universal => Content::Boolean,
+            2 | 10 if universal && len == 0 => return Err(self.violation(ViolationKind::Malformed)),
+            2 | 10 if universal => Content::Integer(None),
+            3 if universal && len == 0 => return Err(self.violation(ViolationKind::InvalidBitString)),
+            3 if universal => Content::BitString(0),
+            _ => Content::Plain,
+        };
+
+        if len == 0 {
+            self.close_frames();
+        } else {
+            self.state = State::Content { start: self.header_start, content, index: 0, len };
+        }
+        Ok(())
+    }
+
+    fn close_frames (&mut self) {
+        while self.frames.last().is_some_and(|f| f.end == self.offset) {
+            self.frames.pop();
+        }
+    }
+}
+
+// Whether `buf` holds a complete set of identifier and length octets.
+pub(crate) fn header_complete (buf: &[u8]) -> bool {
+    let mut pos = 1;
+
+    if buf[0] & 0x1F == 0x1F {
+        loop {
+            match buf.get(pos) {
+                None => return false,
+                Some(&b) => {
+                    pos += 1;
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                },
+            }
+        }
+    }
+
+    match buf.get(pos) {
+        None => false,
+        Some(&b) if b & 0x80 != 0 && b != 0x80 => buf.len() > pos + (b & 0x7F) as usize,
+        Some(_) => true,
+    }
+}
+
+/// An `io::Write` adapter that checks everything written through it is DER, passing
+/// bytes on to the inner writer only once they have been validated. A violation fails
+/// the write with an `InvalidData` error wrapping the `Violation`, whose offset counts
+/// from the first byte written through the adapter.
+pub struct DerValidatingWriter<W: Write> {
+    inner:     W,
+    validator: Validator,
+}
+
+impl<W: Write> DerValidatingWriter<W> {
+    pub fn new (inner: W) -> DerValidatingWriter<W> {
+        DerValidatingWriter {
+            inner,
+            validator: Validator::new(),
+        }
+    }
+
+    /// Checks that the stream ended on an element boundary and returns the inner writer.
+    pub fn finish (self) -> io::Result<W> {
+        self.validator.finish().map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v))?;
+        Ok(self.inner)
+    }
+
+    pub fn get_ref (&self) -> &W {
+        &self.inner
+    }
+
+    /// The number of bytes validated and written so far.
+    pub fn offset (&self) -> usize {
+        self.validator.offset()
+    }
+}
+
+impl<W: Write> WriteLayer for DerValidatingWriter<W> {
+    type Inner = W;
+
+    fn inner (&self) -> &W {
+        &self.inner
+    }
+
+    fn inner_mut (&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the inner writer without checking where the stream ended; see `finish`.
+    fn into_inner (self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for DerValidatingWriter<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.validator.feed(buf).map_err(|v| io::Error::new(io::ErrorKind::InvalidData, v))?;
+        // the validator has accepted all of buf, so all of it has to go through
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds (buf: &[u8]) -> Vec<ViolationKind> {
+        match check_der(buf) {
+            Ok(()) => vec![],
+            Err(v) => v.into_iter().map(|v| v.kind).collect(),
+        }
+    }
+
+    #[test]
+    fn test_valid () {
+        let buf = [0x30, 0x0B, 0x01, 0x01, 0xFF, 0x02, 0x02, 0x00, 0x80, 0x03, 0x02, 0x06, 0x40];
+        assert_eq!(check_der(&buf), Ok(()));
+    }
+
+    #[test]
+    fn test_violations () {
+        assert_eq!(kinds(&[0x30, 0x80, 0x05, 0x00, 0x00, 0x00]), vec![ViolationKind::IndefiniteLength]);
+        assert_eq!(kinds(&[0x04, 0x81, 0x01, 0x00]), vec![ViolationKind::NonMinimalLength]);
+        assert_eq!(kinds(&[0x9F, 0x05, 0x00]), vec![ViolationKind::NonMinimalTagNumber]);
+        assert_eq!(kinds(&[0x24, 0x03, 0x04, 0x01, 0x00]), vec![ViolationKind::ConstructedString]);
+        assert_eq!(kinds(&[0x01, 0x01, 0x01]), vec![ViolationKind::InvalidBoolean]);
+        assert_eq!(kinds(&[0x02, 0x02, 0x00, 0x01]), vec![ViolationKind::NonMinimalInteger]);
+        assert_eq!(kinds(&[0x03, 0x02, 0x01, 0x01]), vec![ViolationKind::InvalidBitString]);
+        assert_eq!(kinds(&[0x31, 0x06, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01]), vec![ViolationKind::UnsortedSet]);
+        assert_eq!(kinds(&[0x05, 0x00, 0x00]), vec![ViolationKind::TrailingData]);
+        assert_eq!(kinds(&[0x30, 0x05, 0x02, 0x01]), vec![ViolationKind::Malformed]);
+    }
+
+    #[test]
+    fn test_offsets () {
+        let buf = [0x30, 0x06, 0x01, 0x01, 0x01, 0x02, 0x01, 0x00];
+        assert_eq!(check_der(&buf), Err(vec![Violation { offset: 2, kind: ViolationKind::InvalidBoolean }]));
+    }
+
+    #[test]
+    fn test_validating_writer () {
+        let buf = [0x30, 0x0B, 0x01, 0x01, 0xFF, 0x02, 0x02, 0x00, 0x80, 0x03, 0x02, 0x06, 0x40];
+
+        let mut w = DerValidatingWriter::new(Vec::new());
+        for chunk in buf.chunks(3) {
+            w.write_all(chunk).unwrap();
+        }
+        assert_eq!(w.finish().unwrap(), buf);
+    }
+
+    #[test]
+    fn test_validating_writer_violation () {
+        let buf = [0x30, 0x06, 0x02, 0x01, 0x05, 0x01, 0x01, 0x01];
+
+        let mut w = DerValidatingWriter::new(Vec::new());
+        w.write_all(&buf[..4]).unwrap();
+        let e = w.write_all(&buf[4..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let v = e.get_ref().unwrap().downcast_ref::<Violation>().unwrap();
+        assert_eq!(*v, Violation { offset: 5, kind: ViolationKind::InvalidBoolean });
+        assert_eq!(w.get_ref().len(), 4);
+
+        let mut w = DerValidatingWriter::new(Vec::new());
+        let e = w.write_all(&[0x04, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err();
+        let v = e.get_ref().unwrap().downcast_ref::<Violation>().unwrap();
+        assert_eq!(*v, Violation { offset: 0, kind: ViolationKind::Malformed });
+    }
+
+    #[test]
+    fn test_validator_agrees_with_check_der () {
+        let inputs: [&[u8]; 7] = [
+            &[0x30, 0x80, 0x05, 0x00, 0x00, 0x00],
+            &[0x04, 0x81, 0x01, 0x00],
+            &[0x24, 0x03, 0x04, 0x01, 0x00],
+            &[0x02, 0x02, 0x00, 0x01],
+            &[0x03, 0x02, 0x01, 0x01],
+            &[0x31, 0x06, 0x81, 0x01, 0x00, 0x80, 0x01, 0x00],
+            // a length that overflows the offset it ends at
+            &[0x04, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        ];
+
+        for input in inputs.iter() {
+            let mut v = Validator::new();
+            let streamed = v.feed(input).and_then(|_| v.finish()).unwrap_err();
+            // the streaming check points at the misplaced member rather than the SET
+            assert_eq!(vec![streamed.kind], kinds(input));
+        }
+
+        let mut v = Validator::new();
+        assert!(v.feed(&[0x30, 0x03, 0x02]).is_ok());
+        assert!(v.finish().is_err());
+    }
+
+    #[test]
+    fn test_write_der () {
+        let tag = Tag::sequence(vec![
+            Tag::set(vec![Tag::integer(2), Tag::integer(1), Tag::null()]),
+            Tag::octet_string(vec![0xAB; 0x7F]),
+            Tag::octet_string(vec![0xCD; 0x80]),
+        ]);
+
+        let der = tag.to_der().unwrap();
+        assert_eq!(check_der(&der), Ok(()));
+        assert_eq!(&der[..15], &[
+            0x30, 0x82, 0x01, 0x0E,
+            0x31, 0x08, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, 0x05, 0x00,
+            0x04,
+        ]);
+        assert_eq!(&der[15..16], &[0x7F]);
+        assert_eq!(&der[16 + 0x7F..16 + 0x7F + 3], &[0x04, 0x81, 0x80]);
+
+        // reading it back gives the same tree, modulo the reordered SET
+        let back = Tag::read(&mut &der[..]).unwrap();
+        assert_eq!(back.to_der().unwrap(), der);
+
+        // constructed strings come out primitive, and bad segments are refused
+        let segmented = Tag::constructed(Number::Universal(Type::OctetString), vec![
+            Tag::octet_string(vec![1, 2]),
+            Tag::octet_string(vec![3]),
+        ]);
+        assert_eq!(segmented.to_der().unwrap(), [0x04, 0x03, 1, 2, 3]);
+        let bits = Tag::constructed(Number::Universal(Type::BitString), vec![
+            Tag::primitive(Number::Universal(Type::BitString), vec![0x00, 0xFF]),
+            Tag::primitive(Number::Universal(Type::BitString), vec![0x04, 0xF0]),
+        ]);
+        assert_eq!(bits.to_der().unwrap(), [0x03, 0x03, 0x04, 0xFF, 0xF0]);
+        let bad = Tag::constructed(Number::Universal(Type::OctetString), vec![Tag::null()]);
+        assert_eq!(bad.to_der().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_canonicalize () {
+        let mut tag = Tag::sequence(vec![
+            Tag::set(vec![
+                Tag::octet_string(vec![2]),
+                Tag::set(vec![Tag::integer(300), Tag::integer(5)]),
+                Tag::integer(1),
+            ]),
+        ]);
+        tag.canonicalize().unwrap();
+
+        let mut ber = Vec::new();
+        tag.write(&mut ber).unwrap();
+        assert_eq!(ber, vec![
+            0x30, 0x80,
+            0x31, 0x80,
+            0x02, 0x01, 0x01,
+            0x04, 0x01, 0x02,
+            0x31, 0x80, 0x02, 0x01, 0x05, 0x02, 0x02, 0x01, 0x2C, 0x00, 0x00,
+            0x00, 0x00,
+            0x00, 0x00,
+        ]);
+
+        // nothing left for the DER writer to reorder
+        let der = tag.to_der().unwrap();
+        assert_eq!(Tag::read(&mut &der[..]).unwrap().to_der().unwrap(), der);
+        assert_eq!(check_der(&der), Ok(()));
+
+        // a tree that doesn't encode is left alone
+        let segments = vec![Tag::octet_string(vec![1]), Tag::integer(2)];
+        let mut tag = Tag::set(vec![Tag::integer(9), Tag::constructed(Number::Universal(Type::OctetString), segments), Tag::null()]);
+        assert_eq!(tag.canonicalize().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(tag.child(0), Some(&Tag::integer(9)));
+    }
+
+    #[test]
+    fn test_deep_trees () {
+        // SEQUENCEs and SETs around a SET, deeper than a recursive encoder could go, and
+        // with every SET's members encoded once rather than once per SET around them
+        let depth = 50_000;
+        let inner = Tag::set(vec![Tag::null(), Tag::integer(1)]);
+        let mut tag = (1..depth).fold(inner, |tag, i| if i % 2 == 0 { Tag::set(vec![tag]) } else { Tag::sequence(vec![tag]) });
+
+        let der = tag.to_der().unwrap();
+        assert_eq!(der[der.len() - 7..], [0x31, 0x05, 0x02, 0x01, 0x01, 0x05, 0x00]);
+        let cer = tag.to_cer().unwrap();
+        assert_eq!(cer.len(), 4 * depth + 5);
+        tag.canonicalize().unwrap();
+        assert_eq!(tag.to_der().unwrap(), der);
+        let innermost = (0..depth - 1).fold(&tag, |tag, _| tag.child(0).unwrap());
+        assert_eq!(innermost.child(0), Some(&Tag::integer(1)));
+    }
+
+    #[test]
+    fn test_write_cer () {
+        let tag = Tag::sequence(vec![
+            Tag::set(vec![Tag::integer(2), Tag::null()]),
+            Tag::octet_string(vec![0xAB; 10]),
+        ]);
+        assert_eq!(tag.to_cer().unwrap(), vec![
+            0x30, 0x80,
+            0x31, 0x80, 0x02, 0x01, 0x02, 0x05, 0x00, 0x00, 0x00,
+            0x04, 0x0A, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB, 0xAB,
+            0x00, 0x00,
+        ]);
+
+        // 2500 octets go out as segments of 1000, 1000 and 500
+        let cer = Tag::octet_string(vec![0xCD; 2500]).to_cer().unwrap();
+        let back = Tag::read(&mut &cer[..]).unwrap();
+        let segments = match back.payload {
+            Payload::Constructed(ref c) => c.iter().map(|s| s.content().unwrap().len()).collect::<Vec<_>>(),
+            _ => panic!("not segmented"),
+        };
+        assert_eq!(segments, vec![1000, 1000, 500]);
+        assert_eq!(back.to_cer().unwrap(), cer);
+
+        // exactly 1000 octets stay primitive
+        assert_eq!(&Tag::octet_string(vec![0; 1000]).to_cer().unwrap()[..4], &[0x04, 0x82, 0x03, 0xE8]);
+
+        // BIT STRING segments hold 999 octets of bits after their unused bits octet
+        let mut content = vec![3];
+        content.extend(vec![0xF8; 2000]);
+        let cer = Tag::primitive(Number::Universal(Type::BitString), content).to_cer().unwrap();
+        let back = Tag::read(&mut &cer[..]).unwrap();
+        let segments = match back.payload {
+            Payload::Constructed(ref c) => c.iter().map(|s| s.content().unwrap().to_vec()).collect::<Vec<_>>(),
+            _ => panic!("not segmented"),
+        };
+        assert_eq!(segments.iter().map(Vec::len).collect::<Vec<_>>(), vec![1000, 1000, 3]);
+        assert_eq!(segments.iter().map(|s| s[0]).collect::<Vec<_>>(), vec![0, 0, 3]);
+    }
+}
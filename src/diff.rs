@@ -0,0 +1,108 @@
+//! Structural comparison of two trees, for telling apart messages that should be the
+//! same. Offsets and length forms are ignored; only numbers, shape and content count.
+
+use std::fmt;
+
+use crate::tag::{Number, Payload, Tag};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Change {
+    /// The tags have different numbers; their contents are not compared.
+    Number(Number, Number),
+    /// One tag is primitive and the other constructed.
+    Shape,
+    /// Both are primitive with different content.
+    Content,
+    /// A child only the first tree has.
+    Removed,
+    /// A child only the second tree has.
+    Added,
+}
+
+/// One place the trees differ. `path` gives the child indices from the root.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Difference {
+    pub path:   Vec<usize>,
+    pub change: Change,
+}
+
+impl fmt::Display for Difference {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("root")?;
+        for i in &self.path {
+            write!(f, ".{}", i)?;
+        }
+        match self.change {
+            Change::Number(ref a, ref b) => write!(f, ": number {:?} became {:?}", a, b),
+            Change::Shape => f.write_str(": primitive and constructed"),
+            Change::Content => f.write_str(": content differs"),
+            Change::Removed => f.write_str(": removed"),
+            Change::Added => f.write_str(": added"),
+        }
+    }
+}
+
+/// Lists the differences between `a` and `b` in document order. Children are matched
+/// up by position, so an inserted child shows up as changes to everything after it.
+pub fn diff (a: &Tag, b: &Tag) -> Vec<Difference> {
+    let mut out = Vec::new();
+    diff_at(a, b, &mut Vec::new(), &mut out);
+    out
+}
+
+fn diff_at (a: &Tag, b: &Tag, path: &mut Vec<usize>, out: &mut Vec<Difference>) {
+    let mut push = |change| out.push(Difference { path: path.clone(), change });
+
+    if a.number != b.number {
+        return push(Change::Number(a.number, b.number));
+    }
+
+    match (&a.payload, &b.payload) {
+        (Payload::Primitive(x), Payload::Primitive(y)) => if x != y {
+            push(Change::Content);
+        },
+        (Payload::Constructed(x), Payload::Constructed(y)) => {
+            for i in 0..x.len().max(y.len()) {
+                path.push(i);
+                match (x.get(i), y.get(i)) {
+                    (Some(x), Some(y)) => diff_at(x, y, path, out),
+                    (Some(_), None) => out.push(Difference { path: path.clone(), change: Change::Removed }),
+                    (None, Some(_)) => out.push(Difference { path: path.clone(), change: Change::Added }),
+                    (None, None) => unreachable!(),
+                }
+                path.pop();
+            }
+        },
+        _ => push(Change::Shape),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::Type;
+
+    #[test]
+    fn test_diff () {
+        let a = Tag::sequence(vec![
+            Tag::integer(1),
+            Tag::octet_string(vec![1, 2]),
+            Tag::sequence(vec![Tag::null(), Tag::null()]),
+        ]);
+        let b = Tag::sequence(vec![
+            Tag::integer(1),
+            Tag::octet_string(vec![1, 3]),
+            Tag::sequence(vec![Tag::boolean(true)]),
+        ]);
+
+        assert!(diff(&a, &a).is_empty());
+
+        let changes = diff(&a, &b);
+        assert_eq!(changes, vec![
+            Difference { path: vec![1], change: Change::Content },
+            Difference { path: vec![2, 0], change: Change::Number(Number::Universal(Type::Null), Number::Universal(Type::Boolean)) },
+            Difference { path: vec![2, 1], change: Change::Removed },
+        ]);
+        assert_eq!(changes[2].to_string(), "root.2.1: removed");
+    }
+}
@@ -0,0 +1,73 @@
+//! Hashes of the DER form of a tree, for deduplicating and comparing messages that
+//! were stored in different BER encodings.
+//!
+//! Two encodings of the same value, say one with indefinite lengths and a constructed
+//! OCTET STRING and one in DER, give the same `Tag::canonical_digest`. Beyond what
+//! `write_der` does, the content of BOOLEANs, INTEGERs, ENUMERATEDs and BIT STRINGs is
+//! put into its DER form before it's hashed, so a TRUE of 0x01 hashes as one of 0xFF.
+//! Other content is hashed as it is. The hash function is any RustCrypto `Digest`:
+//!
+//! ```text
+//! let hash = tag.canonical_digest::<sha2::Sha256>()?;
+//! ```
+
+use std::io;
+
+use ::digest::{Digest, Output};
+
+use crate::tag::Tag;
+
+impl Tag {
+    /// The `D` hash of the tree's canonical DER encoding. The errors are those of
+    /// `write_der`.
+    pub fn canonical_digest<D: Digest> (&self) -> io::Result<Output<D>> {
+        Ok(D::digest(self.to_normalized_der()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sha2::{Digest, Sha256};
+
+    use crate::tag::Tag;
+
+    fn digest (input: &[u8]) -> Vec<u8> {
+        Tag::read(&mut &input[..]).unwrap().canonical_digest::<Sha256>().unwrap().to_vec()
+    }
+
+    #[test]
+    fn test_encodings_agree () {
+        // SET { INTEGER 2, INTEGER 1 }, OCTET STRING "abc" in DER, then in indefinite
+        // lengths with the SET out of order and the string in segments
+        let der = [0x30, 0x0D, 0x31, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x02, 0x04, 0x03, 0x61, 0x62, 0x63];
+        let ber = [
+            0x30, 0x80,
+                0x31, 0x80, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01, 0x00, 0x00,
+                0x24, 0x80, 0x04, 0x01, 0x61, 0x04, 0x02, 0x62, 0x63, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(digest(&der), digest(&ber));
+        assert_eq!(digest(&der), Sha256::digest(der).to_vec());
+
+        let other = Tag::sequence(vec![Tag::integer(1)]).canonical_digest::<Sha256>().unwrap();
+        assert_ne!(digest(&der), other.to_vec());
+    }
+
+    #[test]
+    fn test_content_agrees () {
+        for (der, ber) in [
+            // TRUE
+            (&[0x30, 0x03, 0x01, 0x01, 0xFF][..], &[0x30, 0x03, 0x01, 0x01, 0x01][..]),
+            // INTEGER 1 and -1 with redundant leading octets
+            (&[0x02, 0x01, 0x01], &[0x02, 0x03, 0x00, 0x00, 0x01]),
+            (&[0x02, 0x01, 0xFF], &[0x02, 0x02, 0xFF, 0xFF]),
+            // BIT STRING 1111 with its unused bits set, primitive and constructed
+            (&[0x03, 0x02, 0x04, 0xF0], &[0x03, 0x02, 0x04, 0xF3]),
+            (&[0x03, 0x02, 0x04, 0xF0], &[0x23, 0x80, 0x03, 0x02, 0x04, 0xF3, 0x00, 0x00]),
+        ] {
+            assert!(crate::check_der(der).is_ok());
+            assert_eq!(digest(der), digest(ber), "{:02x?}", ber);
+        }
+        assert_ne!(digest(&[0x02, 0x01, 0x01]), digest(&[0x02, 0x02, 0x00, 0x81]));
+    }
+}
@@ -0,0 +1,372 @@
+//! Random access into large messages over `Read + Seek`.
+//!
+//! A `Document` reads headers only as a lookup needs them and remembers what it found:
+//! the children of each constructed tag as far as they have been scanned, and where each
+//! indefinite length tag ends. Jumping to the Nth child of a path reads the headers of
+//! the siblings before it and seeks over their content, so pulling one field out of a
+//! gigabyte file touches a few hundred bytes of it.
+//!
+//! A document over bytes already in memory can also hand out any node as a zero-copy
+//! `TagRef`. With the `mmap` feature, `Document::open_mmap` maps a file to get there
+//! without reading it first.
+
+use std::collections::HashMap;
+#[cfg(feature = "mmap")]
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+use crate::borrowed::TagRef;
+use crate::err;
+use crate::layer::Tracked;
+use crate::tag::{self, Flavor, Length, Number, Tag, Type};
+
+/// The header of one tag in a `Document`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Node {
+    /// Stream position of the tag's first byte.
+    pub offset:      u64,
+    pub number:      Number,
+    pub constructed: bool,
+    pub header_len:  u64,
+    /// Declared content length, `None` for the indefinite form.
+    pub length:      Option<u64>,
+}
+
+impl Node {
+    /// Stream position of the first content byte. A `Document` never hands out a node
+    /// whose content would start past `u64::MAX`; this saturates for one built by hand.
+    pub fn content_offset (&self) -> u64 {
+        self.offset.saturating_add(self.header_len)
+    }
+
+    // Where a definite length tag ends, `None` for the indefinite form.
+    fn definite_end (&self) -> Result<Option<u64>, err::Error> {
+        let end = match self.length {
+            Some(l) => self.offset.checked_add(self.header_len).and_then(|start| start.checked_add(l)),
+            None => return Ok(None),
+        };
+        match end {
+            Some(end) => Ok(Some(end)),
+            None => Err(err::Error::new(err::Kind::NumberOverflow, self.offset as usize, None)),
+        }
+    }
+
+    fn is_eoc (&self) -> bool {
+        self.number == Number::Universal(Type::Eoc) && !self.constructed && self.length == Some(0)
+    }
+}
+
+// The children of one constructed tag found so far.
+#[derive(Default)]
+struct Children {
+    nodes: Vec<Node>,
+    done:  bool,
+}
+
+/// A message indexed lazily for random access.
+pub struct Document<R> {
+    r:        R,
+    root:     u64,
+    children: HashMap<u64, Children>,
+    // where indefinite length tags end, by offset
+    ends:     HashMap<u64, u64>,
+}
+
+impl<R: Read + Seek> Document<R> {
+    /// A document whose root tag starts at the current position of `r`.
+    pub fn new (mut r: R) -> Result<Document<R>, err::Error> {
+        let root = r.stream_position()?;
+        Ok(Document {
+            r,
+            root,
+            children: HashMap::new(),
+            ends: HashMap::new(),
+        })
+    }
+
+    pub fn root (&mut self) -> Result<Node, err::Error> {
+        self.header(self.root)
+    }
+
+    /// The node at `path`, a list of child indices from the root; `None` if some index
+    /// is past the end of its parent's children or the path runs into a primitive.
+    pub fn at_path (&mut self, path: &[usize]) -> Result<Option<Node>, err::Error> {
+        let mut node = self.root()?;
+        for &i in path {
+            node = match self.child(&node, i)? {
+                Some(child) => child,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(node))
+    }
+
+    /// The `n`th child of `parent`, reading only the headers of those before it.
+    pub fn child (&mut self, parent: &Node, n: usize) -> Result<Option<Node>, err::Error> {
+        if !parent.constructed {
+            return Ok(None);
+        }
+
+        loop {
+            let known = self.children.entry(parent.offset).or_default();
+            if let Some(node) = known.nodes.get(n) {
+                return Ok(Some(*node));
+            }
+            if known.done {
+                return Ok(None);
+            }
+
+            let pos = match known.nodes.last().copied() {
+                Some(last) => self.end_of(&last)?,
+                None => parent.content_offset(),
+            };
+            let limit = parent.definite_end()?;
+
+            let next = if limit.is_some_and(|limit| pos >= limit) {
+                None
+            } else {
+                let node = self.header(pos)?;
+                let end = self.end_of(&node)?;
+                if limit.is_some_and(|limit| end > limit) {
+                    // the child runs past the end of its parent
+                    return Err(err::Error::new(err::Kind::LengthExceedsContainer, node.offset as usize, None));
+                }
+                if parent.length.is_some() && node.number == Number::Universal(Type::Eoc) {
+                    return Err(err::Error::new(err::Kind::UnexpectedEoc, node.offset as usize, None));
+                }
+                Some(node).filter(|node| !(parent.length.is_none() && node.is_eoc()))
+            };
+
+            let known = self.children.entry(parent.offset).or_default();
+            match next {
+                Some(node) => known.nodes.push(node),
+                None => known.done = true,
+            }
+        }
+    }
+
+    /// Decodes the whole subtree at `node`. Offsets in it are relative to the node.
+    pub fn read (&mut self, node: &Node) -> Result<Tag, err::Error> {
+        self.r.seek(SeekFrom::Start(node.offset))?;
+        Tag::read(&mut self.r).map_err(|e| err::Error { offset: node.offset as usize + e.offset, ..e })
+    }
+
+    /// Where `node` ends, scanning an indefinite length tag for its end-of-contents the
+    /// first time it is asked about. The scan keeps its own stack, so indefinite tags
+    /// nested however deep can't overflow the real one. A definite length that ends past
+    /// `u64::MAX` is a `Kind::NumberOverflow` error.
+    pub fn end_of (&mut self, node: &Node) -> Result<u64, err::Error> {
+        if let Some(end) = self.known_end(node)? {
+            return Ok(end);
+        }
+
+        // the indefinite tags being scanned, innermost last, each with where its next
+        // child starts
+        let mut open = vec![(node.offset, node.content_offset())];
+        while let Some(&(offset, pos)) = open.last() {
+            let child = self.header(pos)?;
+            let end = match self.known_end(&child)? {
+                Some(end) => end,
+                None => {
+                    open.push((child.offset, child.content_offset()));
+                    continue;
+                },
+            };
+
+            open.pop();
+            if child.is_eoc() {
+                self.ends.insert(offset, end);
+                if let Some(parent) = open.last_mut() {
+                    parent.1 = end;
+                }
+            } else {
+                open.push((offset, end));
+            }
+        }
+        Ok(self.ends[&node.offset])
+    }
+
+    // Where `node` ends, if that's known without scanning.
+    fn known_end (&self, node: &Node) -> Result<Option<u64>, err::Error> {
+        match node.definite_end()? {
+            Some(end) => Ok(Some(end)),
+            None => Ok(self.ends.get(&node.offset).copied()),
+        }
+    }
+
+    pub fn into_inner (self) -> R {
+        self.r
+    }
+
+    fn header (&mut self, offset: u64) -> Result<Node, err::Error> {
+        self.r.seek(SeekFrom::Start(offset))?;
+        let mut r = Tracked::new(&mut self.r);
+        let header = tag::read_identifiers(&mut r).and_then(|x| tag::read_length(&mut r).map(|l| (x, l)));
+        let header_len = r.tell() as u64;
+        let ((_class, flavor, number), length) = header.map_err(|mut e| {
+            e.offset = offset.saturating_add(header_len) as usize;
+            tag::truncation(e)
+        })?;
+
+        let length = match (flavor, length) {
+            (Flavor::Primitive, Length::Indefinite) =>
+                return Err(err::Error::new(err::Kind::InvalidLength, offset as usize, None)),
+            (_, Length::Indefinite) => None,
+            (_, Length::Some(l)) => Some(l),
+        };
+        if offset.checked_add(header_len).is_none() {
+            return Err(err::Error::new(err::Kind::NumberOverflow, offset as usize, None));
+        }
+        Ok(Node { offset, number, constructed: flavor == Flavor::Constructed, header_len, length })
+    }
+}
+
+impl<B: AsRef<[u8]>> Document<Cursor<B>> {
+    /// All of the input.
+    pub fn bytes (&self) -> &[u8] {
+        self.r.get_ref().as_ref()
+    }
+
+    /// The subtree at `node`, borrowing its content from the input. Offsets in it are
+    /// relative to the node.
+    pub fn tag_ref (&self, node: &Node) -> Result<TagRef<'_>, err::Error> {
+        let start = usize::try_from(node.offset).unwrap_or(usize::MAX).min(self.bytes().len());
+        TagRef::read(&mut &self.bytes()[start..]).map_err(|e| err::Error { offset: start + e.offset, ..e })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Document<Cursor<memmap2::Mmap>> {
+    /// Maps the file at `path` into memory and indexes it from its first byte.
+    ///
+    /// The file must not change while it is mapped: another process truncating or
+    /// rewriting it can crash this one or change what it reads.
+    pub fn open_mmap<P: AsRef<Path>> (path: P) -> Result<Self, err::Error> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is only ever read, and the caller keeps the file unchanged
+        // while it is mapped, as documented above
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Document::new(Cursor::new(map))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{self, Cursor};
+
+    // Counts the bytes actually read, to show what a lookup skipped.
+    struct Counting {
+        inner: Cursor<Vec<u8>>,
+        read:  usize,
+    }
+
+    impl Read for Counting {
+        fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Counting {
+        fn seek (&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    // SEQUENCE { OCTET STRING (1000 bytes), SEQUENCE (indefinite) { OCTET STRING (1000) },
+    //   SET { INTEGER 7 } }
+    fn input () -> Vec<u8> {
+        let mut v = vec![0x30, 0x82, 0x07, 0xE1, 0x04, 0x82, 0x03, 0xE8];
+        v.extend_from_slice(&[0xAA; 1000]);
+        v.extend_from_slice(&[0x30, 0x80, 0x04, 0x82, 0x03, 0xE8]);
+        v.extend_from_slice(&[0xBB; 1000]);
+        v.extend_from_slice(&[0x00, 0x00, 0x31, 0x03, 0x02, 0x01, 0x07]);
+        v
+    }
+
+    #[test]
+    fn test_random_access () {
+        let input = input();
+        let mut doc = Document::new(Counting { inner: Cursor::new(input.clone()), read: 0 }).unwrap();
+
+        let int = doc.at_path(&[2, 0]).unwrap().unwrap();
+        assert_eq!(int.offset, 2018);
+        assert_eq!(int.number, Number::Universal(Type::Integer));
+        assert!(doc.r.read < 40, "read {} bytes", doc.r.read);
+
+        assert_eq!(doc.read(&int).unwrap(), Tag::read(&mut &input[2018..]).unwrap());
+        assert_eq!(doc.at_path(&[3]).unwrap(), None);
+        assert_eq!(doc.at_path(&[0, 0]).unwrap(), None);
+
+        let root = doc.root().unwrap();
+        assert_eq!(doc.end_of(&root).unwrap(), input.len() as u64);
+        let inner = doc.at_path(&[1]).unwrap().unwrap();
+        assert_eq!(doc.end_of(&inner).unwrap(), 2016);
+        assert_eq!(doc.child(&inner, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tag_ref () {
+        let input = input();
+        let mut doc = Document::new(Cursor::new(&input[..])).unwrap();
+        let set = doc.at_path(&[2]).unwrap().unwrap();
+
+        let tag = doc.tag_ref(&set).unwrap();
+        assert_eq!(tag.to_tag(), Tag::read(&mut &input[2016..]).unwrap());
+        let content = match tag.payload {
+            crate::borrowed::PayloadRef::Constructed(ref children) => children[0].content().unwrap(),
+            _ => panic!("not constructed"),
+        };
+        assert!(std::ptr::eq(content, &input[2020..2021]));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_open_mmap () {
+        let path = std::env::temp_dir().join(format!("ber-document-{}.der", std::process::id()));
+        std::fs::write(&path, input()).unwrap();
+
+        let mut doc = Document::open_mmap(&path).unwrap();
+        let int = doc.at_path(&[2, 0]).unwrap().unwrap();
+        assert_eq!(doc.tag_ref(&int).unwrap().content(), Some(&[0x07][..]));
+
+        drop(doc);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_errors () {
+        // the child claims more than its parent holds
+        let mut doc = Document::new(Cursor::new(vec![0x30, 0x03, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00])).unwrap();
+        let e = doc.at_path(&[0]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer));
+
+        let mut doc = Document::new(Cursor::new(vec![0x30, 0x80, 0x04, 0x01])).unwrap();
+        let e = doc.at_path(&[1]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+
+        // lengths that end past any stream position, on a parent and on a sibling
+        let huge = [0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        for input in [[&[0x30][..], &huge, &[0x05, 0x00]].concat(), [&[0x30, 0x80, 0x04][..], &huge].concat()] {
+            let mut doc = Document::new(Cursor::new(input)).unwrap();
+            let e = doc.at_path(&[1]).unwrap_err();
+            assert!(matches!(e.kind, err::Kind::NumberOverflow), "{}", e);
+        }
+    }
+
+    #[test]
+    fn test_deep_nesting () {
+        let depth = 300_000;
+        let input = [[0x30, 0x80].repeat(depth), [0x00, 0x00].repeat(depth)].concat();
+        let mut doc = Document::new(Cursor::new(&input[..])).unwrap();
+        let root = doc.root().unwrap();
+        assert_eq!(doc.end_of(&root).unwrap(), input.len() as u64);
+        let inner = doc.child(&root, 0).unwrap().unwrap();
+        assert_eq!(doc.end_of(&inner).unwrap(), input.len() as u64 - 2);
+    }
+}
@@ -0,0 +1,177 @@
+//! Human readable dumps of tag trees, for poking at structures of unknown origin.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str;
+
+use crate::format::FormatOptions;
+use crate::registry::{self, Registry};
+use crate::tag::{self, Number, Payload, Tag, Type};
+
+/// A best guess at what a primitive payload holds.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Guess {
+    /// Printable UTF-8.
+    Text,
+    /// A complete BER encoding, e.g. the contents of an OCTET STRING wrapping a
+    /// certificate extension.
+    NestedBer,
+    /// High entropy bytes: keys, signatures, ciphertext or compressed data.
+    Random,
+    /// None of the above.
+    Unknown,
+}
+
+impl fmt::Display for Guess {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Guess::Text => "likely UTF-8 text",
+            Guess::NestedBer => "likely nested BER",
+            Guess::Random => "likely random/key material",
+            Guess::Unknown => "unknown",
+        })
+    }
+}
+
+// Below this size byte statistics say nothing useful.
+const MIN_RANDOM_LEN: usize = 16;
+
+/// Guesses what `buf` holds.
+pub fn classify (buf: &[u8]) -> Guess {
+    if buf.len() >= 2 && buf[0] != 0x00 && tag::measure(buf) == Some(buf.len()) {
+        return Guess::NestedBer;
+    }
+
+    if let Ok(s) = str::from_utf8(buf) {
+        if !s.is_empty() && s.chars().all(|c| !c.is_control() || c == '\t' || c == '\r' || c == '\n') {
+            return Guess::Text;
+        }
+    }
+
+    if buf.len() >= MIN_RANDOM_LEN {
+        // compare against the best entropy this many bytes could possibly have
+        let max = (buf.len().min(256) as f64).log2();
+        if entropy(buf) / max > 0.9 {
+            return Guess::Random;
+        }
+    }
+
+    Guess::Unknown
+}
+
+/// Shannon entropy of `buf` in bits per byte.
+pub fn entropy (buf: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in buf {
+        counts[b as usize] += 1;
+    }
+
+    let len = buf.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Writes an indented dump of `tag` and its children, one tag per line, labelling each
+/// primitive payload with its `Guess`. Tags with a decoder in the global registry are
+/// described by it as well.
+pub fn dump (tag: &Tag, w: &mut dyn Write) -> io::Result<()> {
+    let registry = registry::global().read().unwrap_or_else(|e| e.into_inner());
+    dump_with(tag, &registry, w)
+}
+
+/// Like `dump`, but consults `registry` instead of the global registry.
+pub fn dump_with (tag: &Tag, registry: &Registry, w: &mut dyn Write) -> io::Result<()> {
+    dump_formatted(tag, registry, &FormatOptions::default(), w)
+}
+
+/// Like `dump_with`, with tag numbers and offsets rendered per `opts`.
+pub fn dump_formatted (tag: &Tag, registry: &Registry, opts: &FormatOptions, w: &mut dyn Write) -> io::Result<()> {
+    for (depth, t) in tag.iter() {
+        dump_line(t, depth, registry, opts, w)?;
+    }
+    Ok(())
+}
+
+// Writes the line for `tag` alone; its children get lines of their own.
+fn dump_line (tag: &Tag, depth: usize, registry: &Registry, opts: &FormatOptions, w: &mut dyn Write) -> io::Result<()> {
+    write!(w, "{:width$}{}", "", opts.number(&tag.number), width = depth * 2)?;
+    if let Some(offset) = tag.offset {
+        write!(w, " @{}", opts.int(offset))?;
+    }
+    if let Some(description) = registry.describe(tag) {
+        write!(w, " [{}]", description)?;
+    }
+
+    match tag.payload {
+        Payload::Primitive(ref v) => {
+            let guess = if tag.number == Number::Universal(Type::Null) {
+                Guess::Unknown
+            } else {
+                classify(v)
+            };
+            write!(w, " ({} bytes, {})", v.len(), guess)?;
+
+            match guess {
+                Guess::Text => writeln!(w, " {:?}", String::from_utf8_lossy(v)),
+                _ => {
+                    write!(w, " ")?;
+                    for b in v.iter().take(16) {
+                        write!(w, "{:02x}", b)?;
+                    }
+                    writeln!(w, "{}", if v.len() > 16 { "..." } else { "" })
+                },
+            }
+        },
+        Payload::Constructed(ref children) => writeln!(w, " ({} children)", children.len()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::{Labels, Radix};
+
+    #[test]
+    fn test_classify () {
+        assert_eq!(classify(b"hello, world"), Guess::Text);
+        assert_eq!(classify(&[0x30, 0x03, 0x02, 0x01, 0x05]), Guess::NestedBer);
+
+        let random: Vec<u8> = (0..64u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert_eq!(classify(&random), Guess::Random);
+
+        assert_eq!(classify(&[0u8; 64]), Guess::Unknown);
+    }
+
+    #[test]
+    fn test_dump () {
+        let payload = vec![0x30, 0x80, 0x0C, 0x03, 0x64, 0x65, 0x66, 0x00, 0x00];
+        let tag = Tag::read(&mut &payload[..]).unwrap();
+
+        let mut out = Vec::new();
+        dump_with(&tag, &Registry::new(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Universal(Sequence) @0 (1 children)\n  Universal(Utf8String) @2 (3 bytes, likely UTF-8 text) \"def\"\n"
+            );
+
+        let mut registry = Registry::new();
+        registry.register_number(Number::Universal(Type::Utf8String), |_: &Tag| Some("greeting".to_string()));
+
+        let mut out = Vec::new();
+        dump_with(&tag, &registry, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("@2 [greeting] (3 bytes"));
+
+        let opts = FormatOptions { radix: Radix::Hex, labels: Labels::Asn1 };
+        let mut out = Vec::new();
+        dump_formatted(&tag, &Registry::new(), &opts, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "SEQUENCE @0x0 (1 children)\n  UTF8String @0x2 (3 bytes, likely UTF-8 text) \"def\"\n"
+            );
+    }
+}
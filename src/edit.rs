@@ -0,0 +1,112 @@
+//! Changing the shape of a tree: adding, removing and replacing children, for tools that
+//! read a structure, alter it and write it back out, such as stripping an extension
+//! from a certificate.
+//!
+//! An offset says where a tag was read from, and an edit moves everything after it, so
+//! every edit clears the offsets of the whole tree it's made on. `recompute_offsets`
+//! sets them again to where writing the edited tree would put each tag.
+
+use std::io;
+use std::mem;
+
+use crate::encoder;
+use crate::tag::{Payload, Tag, WriteOptions};
+
+impl Tag {
+    /// Adds `child` after the last child. A primitive tag has no children to add to,
+    /// and hands `child` back.
+    pub fn push_child (&mut self, child: Tag) -> Result<(), Tag> {
+        let len = self.len();
+        self.insert_child(len, child)
+    }
+
+    /// Adds `child` so that it becomes the `i`th child. Hands `child` back if this tag
+    /// is primitive or has fewer than `i` children.
+    pub fn insert_child (&mut self, i: usize, child: Tag) -> Result<(), Tag> {
+        match self.payload {
+            Payload::Constructed(ref mut children) if i <= children.len() => children.insert(i, child),
+            _ => return Err(child),
+        }
+        self.clear_offsets();
+        Ok(())
+    }
+
+    /// Takes out the `i`th child, `None` if there isn't one or this tag is primitive.
+    pub fn remove_child (&mut self, i: usize) -> Option<Tag> {
+        let child = match self.payload {
+            Payload::Constructed(ref mut children) if i < children.len() => children.remove(i),
+            _ => return None,
+        };
+        self.clear_offsets();
+        Some(child)
+    }
+
+    /// Puts `tag` at `path`, as `at_path` follows it, and returns the tag it replaced.
+    /// Hands `tag` back if nothing is at `path`. The empty path replaces this tag.
+    pub fn replace (&mut self, path: &[usize], tag: Tag) -> Result<Tag, Tag> {
+        let old = match self.at_path_mut(path) {
+            Some(at) => mem::replace(at, tag),
+            None => return Err(tag),
+        };
+        self.clear_offsets();
+        Ok(old)
+    }
+
+    /// Forgets where every tag in the tree was read from.
+    pub fn clear_offsets (&mut self) {
+        for (_, t) in self.iter_mut() {
+            *t.offset = None;
+        }
+    }
+
+    /// Sets the offset of every tag in the tree to where it starts when the tree is
+    /// written with `opts`, the root at 0. Fails as writing would, leaving the offsets
+    /// as they were.
+    pub fn recompute_offsets (&mut self, opts: &WriteOptions) -> io::Result<()> {
+        let offsets = encoder::offsets(self, opts)?;
+        for ((_, t), offset) in self.iter_mut().zip(offsets) {
+            *t.offset = Some(offset);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::{LengthForm, LengthPolicy};
+
+    #[test]
+    fn test_strip_child () {
+        // SEQUENCE { INTEGER 1, SEQUENCE { NULL }, BOOLEAN TRUE }
+        let input = [0x30, 0x0A, 0x02, 0x01, 0x01, 0x30, 0x02, 0x05, 0x00, 0x01, 0x01, 0xFF];
+        let mut tag = Tag::read(&mut &input[..]).unwrap();
+
+        let removed = tag.remove_child(1).unwrap();
+        assert!(removed.is_constructed());
+        assert!(tag.iter().all(|(_, t)| t.offset.is_none()));
+        assert!(tag.remove_child(2).is_none());
+        assert_eq!(tag.child(0).unwrap().as_primitive(), Some(&[0x01][..]));
+
+        let opts = WriteOptions { length_policy: LengthPolicy::Always(LengthForm::Definite), ..WriteOptions::default() };
+        tag.recompute_offsets(&opts).unwrap();
+        let mut out = Vec::new();
+        tag.write_with(&mut out, &opts).unwrap();
+        assert_eq!(out, [0x30, 0x06, 0x02, 0x01, 0x01, 0x01, 0x01, 0xFF]);
+        assert_eq!(tag, Tag::read(&mut &out[..]).unwrap());
+    }
+
+    #[test]
+    fn test_insert_and_replace () {
+        let mut tag = Tag::sequence(vec![Tag::integer(1)]);
+        assert!(tag.push_child(Tag::null()).is_ok());
+        assert!(tag.insert_child(0, Tag::integer(0)).is_ok());
+        assert!(tag.insert_child(4, Tag::null()).is_err());
+        assert_eq!(tag.replace(&[2], Tag::integer(2)).unwrap(), Tag::null());
+        assert!(tag.replace(&[0, 0], Tag::null()).is_err());
+        assert_eq!(tag, Tag::sequence(vec![Tag::integer(0), Tag::integer(1), Tag::integer(2)]));
+
+        let mut leaf = Tag::null();
+        assert!(leaf.push_child(Tag::null()).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Encoding many small messages without allocating for each one.
+//!
+//! `Tag::write_with` measures a tree before writing it, into a table of lengths it
+//! throws away afterwards, and its caller usually collects the output in a fresh `Vec`.
+//! An `Encoder` keeps its output buffer and the table between calls, so once they have
+//! grown to fit the largest message, encoding allocates nothing.
+
+use std::io::{self, Write};
+use std::slice;
+
+use crate::layer::Tracked;
+use crate::tag::{self, Flavor, Length, LengthForm, Payload, Tag, WriteOptions};
+
+/// Reusable buffers for encoding tags one after another.
+#[derive(Default)]
+pub struct Encoder {
+    opts:  WriteOptions,
+    out:   Vec<u8>,
+    // content length of every tag, in document order
+    sizes: Vec<usize>,
+}
+
+impl Encoder {
+    pub fn new () -> Encoder {
+        Encoder::with_options(WriteOptions::default())
+    }
+
+    pub fn with_options (opts: WriteOptions) -> Encoder {
+        Encoder {
+            opts,
+            out: Vec::new(),
+            sizes: Vec::new(),
+        }
+    }
+
+    /// Encodes `tag` as `Tag::write_with` would under this encoder's options. The
+    /// result borrows the encoder's buffer, so it lasts until the next call.
+    pub fn encode (&mut self, tag: &Tag) -> io::Result<&[u8]> {
+        self.out.clear();
+        self.sizes.clear();
+        measure(tag, &self.opts, &mut self.sizes)?;
+
+        emit(&mut self.out, tag, &self.opts, &self.sizes, &mut |_| {})?;
+        Ok(&self.out)
+    }
+
+    /// The capacity currently held for output, for sizing or monitoring pools.
+    pub fn capacity (&self) -> usize {
+        self.out.capacity()
+    }
+}
+
+// The length of `tag`'s encoding under `opts`, without writing it anywhere, however
+// deep the tree: the measuring walk keeps its own stack, so `opts.max_depth` is a
+// policy for writers to enforce rather than something counting needs.
+pub(crate) fn encoded_len (tag: &Tag, opts: &WriteOptions) -> usize {
+    let opts = WriteOptions { max_depth: usize::MAX, ..opts.clone() };
+    // with no depth to exceed, counting into a `Count` can't fail
+    measure(tag, &opts, &mut Vec::new()).unwrap_or(0)
+}
+
+// Counts what would be written, to size headers without writing them anywhere.
+struct Count(usize);
+
+impl Write for Count {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn header (w: &mut dyn Write, tag: &Tag, opts: &WriteOptions, content: usize) -> io::Result<()> {
+    let (form, layout) = tag.header_form(opts);
+    let (flavor, length) = match tag.payload {
+        Payload::Primitive(_) => (Flavor::Primitive, Length::Some(content as u64)),
+        Payload::Constructed(_) if form == LengthForm::Definite => (Flavor::Constructed, Length::Some(content as u64)),
+        Payload::Constructed(_) => (Flavor::Constructed, Length::Indefinite),
+    };
+    tag::write_header(w, &tag.number.class(), &flavor, &tag.number, &length, &layout)
+}
+
+// An open constructed tag being measured: its place in the table of lengths, the
+// children still to measure and the length of those measured so far.
+struct Open<'a> {
+    tag:      &'a Tag,
+    index:    usize,
+    children: slice::Iter<'a, Tag>,
+    content:  usize,
+}
+
+// The error for a tree nested deeper than `opts.max_depth` allows.
+pub(crate) fn too_deep (opts: &WriteOptions) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("tags nested deeper than {}", opts.max_depth))
+}
+
+// Records the content length of `tag` and everything under it in `sizes`, in document
+// order, returning the length of its whole encoding. The walk keeps its own stack, so
+// only `opts.max_depth` bounds how deep a tree may be.
+pub(crate) fn measure (tag: &Tag, opts: &WriteOptions, sizes: &mut Vec<usize>) -> io::Result<usize> {
+    let mut stack: Vec<Open> = Vec::new();
+    let mut current = tag;
+    loop {
+        if stack.len() >= opts.max_depth {
+            return Err(too_deep(opts));
+        }
+        let index = sizes.len();
+        sizes.push(0);
+        let mut done = match current.payload {
+            Payload::Primitive(ref v) => {
+                sizes[index] = v.len();
+                let mut count = Count(0);
+                header(&mut count, current, opts, v.len())?;
+                Some(count.0 + v.len())
+            },
+            Payload::Constructed(ref children) => {
+                stack.push(Open { tag: current, index, children: children.iter(), content: 0 });
+                None
+            },
+        };
+
+        // close every tag that is now complete, until one has another child to measure
+        loop {
+            let open = match stack.last_mut() {
+                Some(open) => open,
+                None => return Ok(done.unwrap_or(0)),
+            };
+            open.content += done.take().unwrap_or(0);
+            if let Some(child) = open.children.next() {
+                current = child;
+                break;
+            }
+
+            let Open { tag, index, content, .. } = stack.pop().expect("the stack was just peeked");
+            sizes[index] = content;
+            let trailer = if tag.header_form(opts).0 == LengthForm::Indefinite { 2 } else { 0 };
+            let mut count = Count(0);
+            header(&mut count, tag, opts, content)?;
+            done = Some(count.0 + content + trailer);
+        }
+    }
+}
+
+// Writes `tag` using the lengths `measure` recorded for it, calling `mark` with the
+// writer before each tag.
+fn emit<W: Write> (w: &mut W, tag: &Tag, opts: &WriteOptions, sizes: &[usize], mark: &mut dyn FnMut(&W)) -> io::Result<()> {
+    // the siblings still to write at each level, and whether the tag holding them is
+    // closed by an end-of-contents marker
+    let mut stack = vec![(false, slice::from_ref(tag).iter())];
+    let mut next = 0;
+
+    while let Some((indefinite, siblings)) = stack.last_mut() {
+        let tag = match siblings.next() {
+            Some(tag) => tag,
+            None => {
+                let indefinite = *indefinite;
+                stack.pop();
+                if indefinite {
+                    w.write_all(&[0x00, 0x00])?;
+                }
+                continue;
+            },
+        };
+
+        mark(w);
+        header(w, tag, opts, sizes[next])?;
+        next += 1;
+        match tag.payload {
+            Payload::Primitive(ref v) => w.write_all(v)?,
+            Payload::Constructed(ref children) =>
+                stack.push((tag.header_form(opts).0 == LengthForm::Indefinite, children.iter())),
+        }
+    }
+    Ok(())
+}
+
+// Writes `tag` as `Tag::write_with` does: measured first, so a tree too deep for
+// `opts` fails before anything is written.
+pub(crate) fn write_tree (w: &mut dyn Write, tag: &Tag, opts: &WriteOptions) -> io::Result<()> {
+    let mut sizes = Vec::new();
+    measure(tag, opts, &mut sizes)?;
+    emit(&mut &mut *w, tag, opts, &sizes, &mut |_| {})
+}
+
+// Where each tag of `tag` starts in its encoding under `opts`, in document order.
+pub(crate) fn offsets (tag: &Tag, opts: &WriteOptions) -> io::Result<Vec<usize>> {
+    let mut sizes = Vec::new();
+    measure(tag, opts, &mut sizes)?;
+
+    let mut starts = Vec::with_capacity(sizes.len());
+    emit(&mut Tracked::new(io::sink()), tag, opts, &sizes, &mut |w| starts.push(w.tell()))?;
+    Ok(starts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::LengthPolicy;
+
+    // SEQUENCE (indefinite) { [1] (padded length) { INTEGER 5 }, OCTET STRING (200 bytes) }
+    fn input () -> Vec<u8> {
+        let mut v = vec![0x30, 0x80, 0xA1, 0x81, 0x03, 0x02, 0x01, 0x05, 0x04, 0x81, 0xC8];
+        v.extend_from_slice(&[0xAB; 200]);
+        v.extend_from_slice(&[0x00, 0x00]);
+        v
+    }
+
+    #[test]
+    fn test_matches_write_with () {
+        let tag = Tag::read(&mut &input()[..]).unwrap();
+
+        for policy in [LengthPolicy::Always(LengthForm::Indefinite), LengthPolicy::Always(LengthForm::Definite), LengthPolicy::Preserve] {
+            let opts = WriteOptions { length_policy: policy, ..WriteOptions::default() };
+            let mut expected = Vec::new();
+            tag.write_with(&mut expected, &opts).unwrap();
+
+            let mut encoder = Encoder::with_options(opts);
+            assert_eq!(encoder.encode(&tag).unwrap(), &expected[..], "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_reuses_buffers () {
+        let tag = Tag::read(&mut &input()[..]).unwrap();
+        let mut encoder = Encoder::new();
+
+        let first = encoder.encode(&tag).unwrap().to_vec();
+        let capacity = encoder.capacity();
+        for _ in 0..10 {
+            assert_eq!(encoder.encode(&tag).unwrap(), &first[..]);
+        }
+        assert_eq!(encoder.capacity(), capacity);
+    }
+}
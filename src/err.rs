@@ -3,82 +3,433 @@ use std::error;
 use std::fmt;
 use std::io;
 
-use byteorder;
+use crate::der::ViolationKind;
+use crate::format::FormatOptions;
+use crate::report::Report;
+use crate::tag::{Number, ReadOptions, Type};
 
+/// What went wrong. New kinds are added as the readers learn to tell failures apart, so
+/// matches need a wildcard arm.
+///
+/// Kinds compare equal when they are the same variant with the same details; I/O errors
+/// compare by their `io::ErrorKind` alone.
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum Kind {
     InvalidTypeAndFlavor,
+    UnknownType,
     InvalidLength,
     NumberOverflow,
+    InvalidBase64,
+    UnrecognizedFormat,
+    UnsupportedVersion,
+    NotPrimitive,
+    IntegerOverflow,
+    InvalidValue,
+    NodesExhausted { capacity: usize },
+    ScratchExhausted { capacity: usize, needed: usize },
+    InvalidOid,
+    /// The input ended partway through a tag; at least `needed` more bytes were
+    /// required.
+    Truncated { needed: usize },
+    /// The input ended cleanly at a tag boundary, before the tag started.
+    CleanEof,
+    /// The content isn't valid for the character string type given.
+    InvalidString(Type),
+    /// A read went over `ReadOptions::max_total_payload_bytes` or
+    /// `ReadOptions::max_element_bytes`, whichever `limit` is; `path` gives the child
+    /// indices from the root to the tag that did it.
+    BudgetExceeded { limit: usize, path: Vec<usize> },
+    /// A strict DER read met something DER forbids.
+    NotDer(ViolationKind),
+    /// A constructed tag had more children than `ReadOptions::max_children_per_node`;
+    /// `path` gives the child indices from the root to that tag.
+    TooManyChildren { limit: usize, path: Vec<usize> },
+    /// A read passed `ReadOptions::deadline` or `ReadOptions::max_tags` after starting
+    /// `tags` tags.
+    DeadlineExceeded { tags: usize },
+    /// The reader had no input ready (`io::ErrorKind::WouldBlock`). What was read before
+    /// is lost, so retrying means starting the tag again from buffered input; the
+    /// `decoder` module suits non-blocking sources better.
+    Pending,
+    /// A read was interrupted (`io::ErrorKind::Interrupted`) somewhere that doesn't retry
+    /// by itself. Trying again may succeed.
+    Interrupted,
+    /// Tags nested deeper than `ReadOptions::max_depth`.
+    TooDeep { limit: usize },
+    /// More input followed a tag that was meant to be the whole of it. The error's
+    /// offset is where the extra input starts.
+    TrailingData,
+    /// A frame's message didn't match the checksum sent with it.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// A tag's content runs past the end of the definite length constructed tag holding
+    /// it.
+    LengthExceedsContainer,
+    /// An end-of-contents marker where no indefinite length tag is open to end.
+    UnexpectedEoc,
     Io(io::Error),
-    Byteorder(byteorder::Error),
 }
 
+impl Kind {
+    /// A stable numeric code for this kind of error. Codes are never reused or
+    /// renumbered, so FFI callers and log pipelines can match on them.
+    pub fn code (&self) -> u32 {
+        match *self {
+            Kind::InvalidTypeAndFlavor => 1,
+            Kind::InvalidLength => 2,
+            Kind::NumberOverflow => 3,
+            Kind::Io(_) => 4,
+            Kind::UnknownType => 5,
+            Kind::InvalidBase64 => 6,
+            Kind::UnrecognizedFormat => 7,
+            Kind::UnsupportedVersion => 8,
+            Kind::NotPrimitive => 9,
+            Kind::IntegerOverflow => 10,
+            Kind::InvalidValue => 11,
+            Kind::NodesExhausted { .. } => 12,
+            Kind::ScratchExhausted { .. } => 13,
+            Kind::InvalidOid => 14,
+            Kind::Truncated { .. } => 15,
+            Kind::CleanEof => 16,
+            Kind::InvalidString(_) => 17,
+            Kind::BudgetExceeded { .. } => 18,
+            Kind::NotDer(_) => 19,
+            Kind::TooManyChildren { .. } => 20,
+            Kind::DeadlineExceeded { .. } => 21,
+            Kind::Pending => 22,
+            Kind::Interrupted => 23,
+            Kind::TooDeep { .. } => 24,
+            Kind::TrailingData => 25,
+            Kind::ChecksumMismatch { .. } => 26,
+            Kind::LengthExceedsContainer => 27,
+            Kind::UnexpectedEoc => 28,
+        }
+    }
+
+    /// The human readable description of this kind of error, without any of the
+    /// structured details. Kept separate so it can be swapped for a translation.
+    pub fn message (&self) -> &'static str {
+        match *self {
+            Kind::InvalidTypeAndFlavor  => "tag number and flavor mismatch",
+            Kind::UnknownType => "unknown universal tag number",
+            Kind::InvalidLength => "Indefinite length is only allowed for constructed tags",
+            Kind::NumberOverflow => "tag number or length too large to represent",
+            Kind::InvalidBase64 => "invalid base64 transfer encoding",
+            Kind::UnrecognizedFormat => "input is not in the expected container format",
+            Kind::UnsupportedVersion => "unsupported format version",
+            Kind::NotPrimitive => "expected a primitive tag",
+            Kind::IntegerOverflow => "integer does not fit the requested type",
+            Kind::InvalidValue => "content octets are not a valid encoding of the value",
+            Kind::NodesExhausted { .. } => "node buffer is full",
+            Kind::ScratchExhausted { .. } => "scratch buffer is too small for the content",
+            Kind::InvalidOid => "invalid object identifier",
+            Kind::Truncated { .. } => "input ended in the middle of a tag",
+            Kind::CleanEof => "no more input",
+            Kind::InvalidString(_) => "invalid characters for the string type",
+            Kind::BudgetExceeded { .. } => "payload budget exceeded",
+            Kind::NotDer(_) => "encoding is not valid DER",
+            Kind::TooManyChildren { .. } => "too many children in a constructed tag",
+            Kind::DeadlineExceeded { .. } => "read deadline exceeded",
+            Kind::Pending => "input is not ready yet",
+            Kind::Interrupted => "read was interrupted",
+            Kind::TooDeep { .. } => "tags nested too deeply",
+            Kind::TrailingData => "input continues after the tag",
+            Kind::ChecksumMismatch { .. } => "frame checksum mismatch",
+            Kind::LengthExceedsContainer => "tag runs past the end of its parent",
+            Kind::UnexpectedEoc => "end-of-contents outside an indefinite length tag",
+            Kind::Io(_) => "I/O error",
+        }
+    }
+}
+
+impl PartialEq for Kind {
+    fn eq (&self, other: &Kind) -> bool {
+        match (self, other) {
+            (Kind::Io(a), Kind::Io(b)) => a.kind() == b.kind(),
+            (Kind::NodesExhausted { capacity: a }, Kind::NodesExhausted { capacity: b }) => a == b,
+            (Kind::ScratchExhausted { capacity: a, needed: n }, Kind::ScratchExhausted { capacity: b, needed: m }) => (a, n) == (b, m),
+            (Kind::Truncated { needed: a }, Kind::Truncated { needed: b }) => a == b,
+            (Kind::InvalidString(a), Kind::InvalidString(b)) => a == b,
+            (Kind::BudgetExceeded { limit: a, path: p }, Kind::BudgetExceeded { limit: b, path: q }) => (a, p) == (b, q),
+            (Kind::NotDer(a), Kind::NotDer(b)) => a == b,
+            (Kind::TooManyChildren { limit: a, path: p }, Kind::TooManyChildren { limit: b, path: q }) => (a, p) == (b, q),
+            (Kind::DeadlineExceeded { tags: a }, Kind::DeadlineExceeded { tags: b }) => a == b,
+            (Kind::TooDeep { limit: a }, Kind::TooDeep { limit: b }) => a == b,
+            (Kind::ChecksumMismatch { expected: a, actual: x }, Kind::ChecksumMismatch { expected: b, actual: y }) => (a, x) == (b, y),
+            // every kind with details is handled above, so the rest are equal by variant
+            _ => self.code() == other.code() && !matches!(self,
+                Kind::Io(_) | Kind::NodesExhausted { .. } | Kind::ScratchExhausted { .. } | Kind::Truncated { .. }
+                | Kind::InvalidString(_) | Kind::BudgetExceeded { .. } | Kind::NotDer(_) | Kind::TooManyChildren { .. }
+                | Kind::DeadlineExceeded { .. } | Kind::TooDeep { .. } | Kind::ChecksumMismatch { .. }),
+        }
+    }
+}
+
+impl Eq for Kind {}
+
+/// One step down a tree: the child at `index` of a constructed tag numbered `number`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Step {
+    pub number: Number,
+    pub index:  usize,
+}
+
+/// Errors compare equal when their kinds, offsets, paths and causes all do, so tests can
+/// assert on the exact failure.
+#[derive(PartialEq, Eq)]
 pub struct Error {
     pub kind:   Kind,
     pub offset: usize,
+    /// The steps from the root down to the tag that failed, where the reader knows
+    /// them; empty for the root itself. `Tag::read` and the readers built on it fill
+    /// this in.
+    pub path:   Vec<Step>,
     pub cause:  Option<Box<Error>>,
 }
 
 impl Error {
     pub fn new (kind: Kind, offset: usize, cause: Option<Box<Error>>) -> Error {
         Error {
-            kind: kind,
-            offset: offset,
-            cause: cause,
+            kind,
+            offset,
+            path: Vec::new(),
+            cause,
         }
     }
 
+    /// The stable numeric code of this error's kind, see `Kind::code`.
+    pub fn code (&self) -> u32 {
+        self.kind.code()
+    }
+
+    /// Whether the same read might succeed later: the input wasn't ready or the read
+    /// was interrupted, as opposed to the input being bad.
+    pub fn is_retryable (&self) -> bool {
+        matches!(self.kind, Kind::Pending | Kind::Interrupted)
+    }
+
     pub fn wrap (self, kind: Kind, offset: usize) -> Error {
         Error::new(kind, offset, Some(Box::new(self)))
     }
+
+    /// A self-contained diagnostic of this error against the `input` that caused it,
+    /// for attaching to bug reports.
+    pub fn to_report (&self, input: &[u8]) -> Report {
+        Report::new(self, input, 0)
+    }
+
+    /// `to_report` for an error from a read with `opts`, which it records. The error's
+    /// offset counts from `opts.base_offset`, and `input` is what the read was given.
+    pub fn to_report_with (&self, input: &[u8], opts: &ReadOptions) -> Report {
+        Report::new(self, input, opts.base_offset).with_options(opts)
+    }
+
+    /// Displays the error with the offset and any type it names rendered per `opts`.
+    pub fn display_with<'a> (&'a self, opts: &'a FormatOptions) -> impl fmt::Display + 'a {
+        Formatted { e: self, opts }
+    }
+
+    // Writes straight into the formatter so no allocation happens; the structured parts
+    // (offset, code) come first and the human text last, then the causes in turn.
+    fn fmt_with (&self, f: &mut fmt::Formatter, opts: &FormatOptions) -> fmt::Result {
+        write!(f, "error at {} (E{}): {}", opts.int(self.offset), self.code(), self.kind.message())?;
+        match self.kind {
+            Kind::Io(ref e) => write!(f, ": {}", e)?,
+            Kind::NodesExhausted { capacity } => write!(f, " (capacity {})", capacity)?,
+            Kind::ScratchExhausted { capacity, needed } => write!(f, " (capacity {}, needed {})", capacity, needed)?,
+            Kind::Truncated { needed } => write!(f, " (needed {} more bytes)", needed)?,
+            Kind::InvalidString(t) => write!(f, " ({})", opts.universal(t))?,
+            Kind::BudgetExceeded { limit, ref path } => {
+                write!(f, " (limit {} bytes, at root", opts.int(limit))?;
+                for i in path {
+                    write!(f, ".{}", i)?;
+                }
+                f.write_str(")")?;
+            },
+            Kind::NotDer(v) => write!(f, " ({:?})", v)?,
+            Kind::DeadlineExceeded { tags } => write!(f, " (after {} tags)", opts.int(tags))?,
+            Kind::TooDeep { limit } => write!(f, " (limit {})", opts.int(limit))?,
+            Kind::ChecksumMismatch { expected, actual } => write!(f, " (expected {:08x}, got {:08x})", expected, actual)?,
+            Kind::TooManyChildren { limit, ref path } => {
+                write!(f, " (limit {}, at root", opts.int(limit))?;
+                for i in path {
+                    write!(f, ".{}", i)?;
+                }
+                f.write_str(")")?;
+            },
+            _ => {},
+        }
+        for (i, step) in self.path.iter().enumerate() {
+            f.write_str(if i == 0 { " in " } else { "." })?;
+            write!(f, "{}[{}]", opts.number(&step.number), opts.int(step.index))?;
+        }
+        if let Some(ref cause) = self.cause {
+            f.write_str(", caused by ")?;
+            cause.fmt_with(f, opts)?;
+        }
+        Ok(())
+    }
+}
+
+struct Formatted<'a> {
+    e:    &'a Error,
+    opts: &'a FormatOptions,
+}
+
+impl<'a> fmt::Display for Formatted<'a> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.e.fmt_with(f, self.opts)
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", *self)
+        self.fmt_with(f, &FormatOptions::default())
     }
 }
 
 impl fmt::Debug for Error {
     fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "error at {}: {}", self.offset, error::Error::description(self))
+        fmt::Display::fmt(self, f)
     }
 }
 
 impl error::Error for Error {
-    fn description (&self) -> &str {
-        match self.kind {
-            Kind::InvalidTypeAndFlavor  => "tag number and flavor mismatch",
-            Kind::InvalidLength => "Indefinite length is only allowed for constructed tags",
-            Kind::NumberOverflow => "BER number is larger than 8 bytes",
-            Kind::Io(ref x) => error::Error::description(x),
-            Kind::Byteorder(ref x) => error::Error::description(x),
+    /// The error this one wraps, or else the I/O error behind a `Kind::Io`.
+    fn source (&self) -> Option<&(dyn error::Error + 'static)> {
+        match (&self.cause, &self.kind) {
+            (Some(cause), _) => Some(&**cause),
+            (None, Kind::Io(e)) => Some(e),
+            _ => None,
         }
     }
+}
 
-    fn cause (&self) -> Option<&error::Error> {
-        match self.cause {
-            Some(ref c) => Some(&**c),
-            None => None,
+impl From<io::Error> for Kind {
+    /// Sorts out the I/O errors a parser can say something more precise about: running
+    /// out of input, input not being ready and interruption.
+    fn from (err: io::Error) -> Kind {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Kind::Truncated { needed: 1 },
+            io::ErrorKind::WouldBlock => Kind::Pending,
+            io::ErrorKind::Interrupted => Kind::Interrupted,
+            _ => Kind::Io(err),
         }
     }
 }
 
 impl From<io::Error> for Error {
     fn from (err: io::Error) -> Error {
-        Error {
-            kind: Kind::Io(err),
-            offset: 0,
-            cause: None,
-        }
+        Error::new(Kind::from(err), 0, None)
     }
 }
-impl From<byteorder::Error> for Error {
-    fn from (err: byteorder::Error) -> Error {
-        Error {
-            kind: Kind::Byteorder(err),
-            offset: 0,
-            cause: None,
-        }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::{Labels, Radix};
+
+    #[test]
+    fn test_codes_are_stable () {
+        let kinds = [
+            Kind::InvalidTypeAndFlavor,
+            Kind::InvalidLength,
+            Kind::NumberOverflow,
+            Kind::Io(io::Error::other("x")),
+            Kind::UnknownType,
+            Kind::InvalidBase64,
+            Kind::UnrecognizedFormat,
+            Kind::UnsupportedVersion,
+            Kind::NotPrimitive,
+            Kind::IntegerOverflow,
+            Kind::InvalidValue,
+            Kind::NodesExhausted { capacity: 0 },
+            Kind::ScratchExhausted { capacity: 0, needed: 0 },
+            Kind::InvalidOid,
+            Kind::Truncated { needed: 0 },
+            Kind::CleanEof,
+            Kind::InvalidString(Type::Utf8String),
+            Kind::BudgetExceeded { limit: 0, path: vec![] },
+            Kind::NotDer(ViolationKind::IndefiniteLength),
+            Kind::TooManyChildren { limit: 0, path: vec![] },
+            Kind::DeadlineExceeded { tags: 0 },
+            Kind::Pending,
+            Kind::Interrupted,
+            Kind::TooDeep { limit: 0 },
+            Kind::TrailingData,
+            Kind::ChecksumMismatch { expected: 0, actual: 0 },
+            Kind::LengthExceedsContainer,
+            Kind::UnexpectedEoc,
+        ];
+        let codes: Vec<u32> = kinds.iter().map(Kind::code).collect();
+        assert_eq!(codes, (1..=28).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_error_is_send_and_sync () {
+        fn check<T: error::Error + Send + Sync + 'static> () {}
+        check::<Error>();
+    }
+
+    #[test]
+    fn test_chain () {
+        let io = Error::new(Kind::Io(io::Error::other("boom")), 4, None);
+        let e = io.wrap(Kind::UnrecognizedFormat, 0);
+        assert_eq!(e.to_string(), "error at 0 (E7): input is not in the expected container format, caused by error at 4 (E4): I/O error: boom");
+
+        let source = error::Error::source(&e).unwrap();
+        assert_eq!(source.to_string(), "error at 4 (E4): I/O error: boom");
+        let inner = source.source().unwrap();
+        assert!(inner.downcast_ref::<io::Error>().is_some());
+        assert!(inner.source().is_none());
+    }
+
+    #[test]
+    fn test_equality () {
+        let e = Error::new(Kind::Truncated { needed: 2 }, 7, None);
+        assert_eq!(e, Error::new(Kind::Truncated { needed: 2 }, 7, None));
+        assert_ne!(e, Error::new(Kind::Truncated { needed: 3 }, 7, None));
+        assert_ne!(e, Error::new(Kind::Truncated { needed: 2 }, 8, None));
+        let caused = |offset| Error::new(Kind::Truncated { needed: 2 }, 7, Some(Box::new(Error::new(Kind::CleanEof, offset, None))));
+        assert_eq!(caused(0), caused(0));
+        assert_ne!(caused(0), caused(1));
+
+        assert_eq!(Kind::CleanEof, Kind::CleanEof);
+        assert_ne!(Kind::CleanEof, Kind::InvalidOid);
+        assert_eq!(Kind::from(io::Error::other("a")), Kind::from(io::Error::other("b")));
+        assert_ne!(Kind::from(io::Error::other("a")), Kind::Io(io::ErrorKind::BrokenPipe.into()));
+    }
+
+    #[test]
+    fn test_from_io () {
+        let kind = |k: io::ErrorKind| Error::from(io::Error::from(k)).kind;
+        assert!(matches!(kind(io::ErrorKind::UnexpectedEof), Kind::Truncated { needed: 1 }));
+        assert!(matches!(kind(io::ErrorKind::WouldBlock), Kind::Pending));
+        assert!(matches!(kind(io::ErrorKind::Interrupted), Kind::Interrupted));
+        assert!(matches!(kind(io::ErrorKind::InvalidData), Kind::Io(_)));
+
+        assert!(Error::from(io::Error::from(io::ErrorKind::WouldBlock)).is_retryable());
+        assert!(!Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)).is_retryable());
+    }
+
+    #[test]
+    fn test_display () {
+        let e = Error::new(Kind::NumberOverflow, 12, None);
+        assert_eq!(e.to_string(), "error at 12 (E3): tag number or length too large to represent");
+
+        let e = Error::new(Kind::Io(io::Error::other("boom")), 0, None);
+        assert_eq!(e.to_string(), "error at 0 (E4): I/O error: boom");
+
+        let e = Error::new(Kind::InvalidString(Type::Ia5String), 26, None);
+        let opts = FormatOptions { radix: Radix::Hex, labels: Labels::Asn1 };
+        assert_eq!(e.display_with(&opts).to_string(), "error at 0x1a (E17): invalid characters for the string type (IA5String)");
+
+        let path = vec![
+            Step { number: Number::Universal(Type::Sequence), index: 0 },
+            Step { number: Number::Universal(Type::Set), index: 2 },
+        ];
+        let e = Error { path, ..Error::new(Kind::InvalidLength, 9, None) };
+        let opts = FormatOptions { labels: Labels::Asn1, ..FormatOptions::default() };
+        assert_eq!(e.display_with(&opts).to_string(), "error at 9 (E2): Indefinite length is only allowed for constructed tags in SEQUENCE[0].SET[2]");
     }
 }
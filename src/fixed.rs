@@ -0,0 +1,264 @@
+//! Decoding into caller-provided fixed buffers, for targets with a few kilobytes of RAM
+//! and no allocator to spare.
+//!
+//! `decode` reads one element and lays its tree out flat in a `&mut [Node]`, in document
+//! order, copying primitive content into a `&mut [u8]` scratch buffer. Running out of
+//! either is reported with a dedicated error instead of growing anything.
+
+use std::io::Read;
+
+use crate::err;
+use crate::tag::{self, Class, Flavor, Length, Number, Type};
+use crate::util::TrackedRead;
+
+/// One decoded element.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Node {
+    pub class:       Class,
+    pub constructed: bool,
+    /// The tag number within its class.
+    pub number:      u32,
+    /// Offset of the element's first byte in the input.
+    pub offset:      usize,
+    /// Declared content length, `None` for the indefinite form.
+    pub length:      Option<usize>,
+    /// Index of the enclosing node, `None` for the root.
+    pub parent:      Option<usize>,
+    pub depth:       usize,
+    /// Where a primitive's content starts in the scratch buffer.
+    pub data:        usize,
+    // where a definite length element ends in the input
+    end:             Option<usize>,
+}
+
+impl Default for Node {
+    fn default () -> Node {
+        Node {
+            class: Class::Universal,
+            constructed: false,
+            number: 0,
+            offset: 0,
+            length: None,
+            parent: None,
+            depth: 0,
+            data: 0,
+            end: None,
+        }
+    }
+}
+
+/// A decoded tree borrowing the caller's buffers.
+#[derive(Debug)]
+pub struct Decoded<'n, 's> {
+    pub nodes:   &'n [Node],
+    pub scratch: &'s [u8],
+}
+
+impl<'n, 's> Decoded<'n, 's> {
+    /// The content of a primitive node; empty for constructed nodes.
+    pub fn content (&self, node: &Node) -> &'s [u8] {
+        match (node.constructed, node.length) {
+            (false, Some(len)) => &self.scratch[node.data..node.data + len],
+            _ => &[],
+        }
+    }
+
+    /// Indices of the direct children of the node at `index`.
+    pub fn children (&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes.iter().enumerate()
+            .skip(index + 1)
+            .take_while(move |&(_, n)| n.depth > self.nodes[index].depth)
+            .filter(move |&(_, n)| n.parent == Some(index))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Decodes one element from `r` into `nodes` and `scratch`.
+pub fn decode<'n, 's> (r: &mut dyn Read, nodes: &'n mut [Node], scratch: &'s mut [u8]) -> Result<Decoded<'n, 's>, err::Error> {
+    let mut r = TrackedRead::new(r);
+    let mut count = 0;
+    let mut used = 0;
+    // the innermost constructed node still open
+    let mut open: Option<usize> = None;
+
+    loop {
+        let offset = r.tell();
+        let (class, flavor, number) = tag::read_identifiers(&mut r).map_err(|e| at(e, offset))?;
+        let length = tag::read_length(&mut r).map_err(|e| at(e, offset))?;
+
+        if let Some(p) = open {
+            if nodes[p].length.is_none() && number == Number::Universal(Type::Eoc) && length == Length::Some(0) {
+                // end of an indefinite constructed node
+                open = close(nodes, Some(p), r.tell(), true)?;
+                if open.is_none() {
+                    break;
+                }
+                continue;
+            }
+            if nodes[p].length.is_some() && number == Number::Universal(Type::Eoc) {
+                return Err(err::Error::new(err::Kind::UnexpectedEoc, offset, None));
+            }
+        }
+
+        let length = match length {
+            Length::Some(l) => Some(usize::try_from(l).map_err(|_| err::Error::new(err::Kind::NumberOverflow, offset, None))?),
+            Length::Indefinite if flavor == Flavor::Primitive =>
+                return Err(err::Error::new(err::Kind::InvalidLength, offset, None)),
+            Length::Indefinite => None,
+        };
+
+        let end = match length {
+            Some(l) => Some(r.tell().checked_add(l).ok_or_else(|| err::Error::new(err::Kind::NumberOverflow, offset, None))?),
+            None => None,
+        };
+        // a child can't end past its parent, whether it says so in its length or in its
+        // header alone
+        if let Some(parent_end) = open.and_then(|p| nodes[p].end) {
+            if end.unwrap_or(r.tell()) > parent_end {
+                return Err(err::Error::new(err::Kind::LengthExceedsContainer, offset, None));
+            }
+        }
+
+        if count == nodes.len() {
+            return Err(err::Error::new(err::Kind::NodesExhausted { capacity: nodes.len() }, offset, None));
+        }
+
+        let index = count;
+        nodes[index] = Node {
+            class,
+            constructed: flavor == Flavor::Constructed,
+            number: number.value(),
+            offset,
+            length,
+            parent: open,
+            depth: open.map(|p| nodes[p].depth + 1).unwrap_or(0),
+            data: used,
+            end,
+        };
+        count += 1;
+
+        if flavor == Flavor::Primitive {
+            let len = length.unwrap_or(0);
+            if len > scratch.len() - used {
+                let kind = err::Kind::ScratchExhausted { capacity: scratch.len(), needed: used + len };
+                return Err(err::Error::new(kind, offset, None));
+            }
+            r.read_exact(&mut scratch[used..used + len]).map_err(|e| at(e.into(), r.tell()))?;
+            used += len;
+
+            open = close(nodes, open, r.tell(), false)?;
+        } else if length == Some(0) {
+            open = close(nodes, open, r.tell(), false)?;
+            open = match open {
+                // the empty node itself was the root
+                None => break,
+                o => o,
+            };
+            continue;
+        } else {
+            open = Some(index);
+            continue;
+        }
+
+        if open.is_none() {
+            break;
+        }
+    }
+
+    Ok(Decoded {
+        nodes: &nodes[..count],
+        scratch: &scratch[..used],
+    })
+}
+
+fn at (mut e: err::Error, offset: usize) -> err::Error {
+    e.offset = offset;
+    e
+}
+
+// Closes every definite length node that ends at `pos`, starting with `open` itself if
+// `closing` says its EOC was just read. Returns the node left open.
+fn close (nodes: &[Node], mut open: Option<usize>, pos: usize, mut closing: bool) -> Result<Option<usize>, err::Error> {
+    while let Some(p) = open {
+        let node = &nodes[p];
+        if !closing {
+            match node.end {
+                Some(end) => {
+                    // only an indefinite child, measured at its EOC, can still run past
+                    if pos > end {
+                        return Err(err::Error::new(err::Kind::LengthExceedsContainer, node.offset, None));
+                    }
+                    if pos < end {
+                        break;
+                    }
+                },
+                None => break,
+            }
+        }
+
+        closing = false;
+        open = node.parent;
+    }
+
+    Ok(open)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode () {
+        let input = [0x30, 0x80, 0x0C, 0x03, 0x64, 0x65, 0x66, 0x30, 0x03, 0x02, 0x01, 0x07, 0x05, 0x00, 0x00, 0x00];
+        let mut nodes = [Node::default(); 8];
+        let mut scratch = [0u8; 16];
+
+        let decoded = decode(&mut &input[..], &mut nodes, &mut scratch).unwrap();
+        assert_eq!(decoded.nodes.len(), 5);
+        assert_eq!(decoded.content(&decoded.nodes[1]), b"def");
+        assert_eq!(decoded.nodes[3].parent, Some(2));
+        assert_eq!(decoded.content(&decoded.nodes[3]), &[0x07]);
+        assert_eq!(decoded.nodes[4].number, Type::Null as u32);
+        assert_eq!(decoded.nodes[4].depth, 1);
+        assert_eq!(decoded.children(0).collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(decoded.scratch.len(), 4);
+    }
+
+    #[test]
+    fn test_capacity () {
+        let input = [0x30, 0x06, 0x04, 0x01, 0xAA, 0x04, 0x01, 0xBB];
+
+        let mut nodes = [Node::default(); 2];
+        let mut scratch = [0u8; 16];
+        let e = decode(&mut &input[..], &mut nodes, &mut scratch).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::NodesExhausted { capacity: 2 }));
+        assert_eq!(e.offset, 5);
+
+        let mut nodes = [Node::default(); 3];
+        let mut scratch = [0u8; 1];
+        let e = decode(&mut &input[..], &mut nodes, &mut scratch).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::ScratchExhausted { capacity: 1, needed: 2 }));
+    }
+
+    #[test]
+    fn test_bad_lengths () {
+        let decode = |input: &[u8]| decode(&mut &input[..], &mut [Node::default(); 4], &mut [0u8; 16]).map(|d| d.nodes.len());
+
+        // a length that ends past any offset
+        let e = decode(&[0x30, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF8, 0x84, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::NumberOverflow), "{}", e);
+
+        // a child longer than its parent is refused from its header, before its content
+        // would be read into the scratch buffer
+        let e = decode(&[0x30, 0x03, 0x04, 0x20, 0xAA]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer), "{}", e);
+        assert_eq!(e.offset, 2);
+        let e = decode(&[0x30, 0x01, 0x04, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer), "{}", e);
+
+        // an indefinite child is only measured at its EOC
+        let e = decode(&[0x30, 0x03, 0x30, 0x80, 0x00, 0x00]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::LengthExceedsContainer), "{}", e);
+        assert_eq!(decode(&[0x30, 0x04, 0x30, 0x80, 0x00, 0x00]).unwrap(), 2);
+    }
+}
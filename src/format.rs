@@ -0,0 +1,166 @@
+//! How tag numbers and offsets are rendered in human readable output: the dump printer,
+//! error messages and the `ber-dump` tool all take a `FormatOptions`.
+
+use std::fmt;
+
+use crate::tag::{Number, Type};
+
+/// The base numbers are printed in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Radix {
+    Decimal,
+    /// `0x` prefixed lowercase hex.
+    Hex,
+}
+
+/// How a tag number is labelled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Labels {
+    /// The `Number` variant as Rust prints it, e.g. `Application(24)`.
+    Rust,
+    /// ASN.1 notation, e.g. `[APPLICATION 24]`, `[0]` or `SEQUENCE`.
+    Asn1,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FormatOptions {
+    /// Used for tag numbers and offsets alike.
+    pub radix:  Radix,
+    pub labels: Labels,
+}
+
+impl Default for FormatOptions {
+    fn default () -> FormatOptions {
+        FormatOptions {
+            radix: Radix::Decimal,
+            labels: Labels::Rust,
+        }
+    }
+}
+
+/// The ASN.1 name of a universal type.
+pub fn asn1_name (t: Type) -> &'static str {
+    match t {
+        Type::Eoc => "EOC",
+        Type::Boolean => "BOOLEAN",
+        Type::Integer => "INTEGER",
+        Type::BitString => "BIT STRING",
+        Type::OctetString => "OCTET STRING",
+        Type::Null => "NULL",
+        Type::ObjectIdentifier => "OBJECT IDENTIFIER",
+        Type::ObjectDescriptor => "ObjectDescriptor",
+        Type::External => "EXTERNAL",
+        Type::Real => "REAL",
+        Type::Enumerated => "ENUMERATED",
+        Type::EmbeddedPdv => "EMBEDDED PDV",
+        Type::Utf8String => "UTF8String",
+        Type::RelativeOid => "RELATIVE-OID",
+        Type::Sequence => "SEQUENCE",
+        Type::Set => "SET",
+        Type::NumericString => "NumericString",
+        Type::PrintableString => "PrintableString",
+        Type::T61String => "TeletexString",
+        Type::VideotexString => "VideotexString",
+        Type::Ia5String => "IA5String",
+        Type::UtcTime => "UTCTime",
+        Type::GeneralizedTime => "GeneralizedTime",
+        Type::GraphicString => "GraphicString",
+        Type::VisibleString => "VisibleString",
+        Type::GeneralString => "GeneralString",
+        Type::UniversalString => "UniversalString",
+        Type::CharacterString => "CHARACTER STRING",
+        Type::BmpString => "BMPString",
+    }
+}
+
+impl FormatOptions {
+    /// A `Display` of `n` in the configured radix.
+    pub fn int<N: fmt::Display + fmt::LowerHex> (&self, n: N) -> impl fmt::Display {
+        Int { n, radix: self.radix }
+    }
+
+    /// A `Display` of the tag number `n` with the configured labels and radix.
+    pub fn number<'a> (&'a self, n: &'a Number) -> impl fmt::Display + 'a {
+        NumberLabel { n, opts: self }
+    }
+
+    /// A `Display` of the universal type `t` with the configured labels.
+    pub fn universal (&self, t: Type) -> impl fmt::Display {
+        TypeLabel { t, labels: self.labels }
+    }
+}
+
+struct Int<N> {
+    n:     N,
+    radix: Radix,
+}
+
+impl<N: fmt::Display + fmt::LowerHex> fmt::Display for Int<N> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.radix {
+            Radix::Decimal => write!(f, "{}", self.n),
+            Radix::Hex => write!(f, "{:#x}", self.n),
+        }
+    }
+}
+
+struct TypeLabel {
+    t:      Type,
+    labels: Labels,
+}
+
+impl fmt::Display for TypeLabel {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.labels {
+            Labels::Rust => write!(f, "{:?}", self.t),
+            Labels::Asn1 => f.write_str(asn1_name(self.t)),
+        }
+    }
+}
+
+struct NumberLabel<'a> {
+    n:    &'a Number,
+    opts: &'a FormatOptions,
+}
+
+impl<'a> fmt::Display for NumberLabel<'a> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opts = self.opts;
+        match (opts.labels, self.n) {
+            (Labels::Rust, &Number::Universal(t)) => write!(f, "Universal({})", opts.universal(t)),
+            (Labels::Asn1, &Number::Universal(t)) => write!(f, "{}", opts.universal(t)),
+            (Labels::Rust, &Number::Application(n)) => write!(f, "Application({})", opts.int(n)),
+            (Labels::Rust, &Number::ContextSpecific(n)) => write!(f, "ContextSpecific({})", opts.int(n)),
+            (Labels::Rust, &Number::Private(n)) => write!(f, "Private({})", opts.int(n)),
+            (Labels::Asn1, &Number::Application(n)) => write!(f, "[APPLICATION {}]", opts.int(n)),
+            (Labels::Asn1, &Number::ContextSpecific(n)) => write!(f, "[{}]", opts.int(n)),
+            (Labels::Asn1, &Number::Private(n)) => write!(f, "[PRIVATE {}]", opts.int(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FormatOptions, Labels, Radix};
+    use crate::tag::{Number, TagNumber, Type};
+
+    #[test]
+    fn test_labels () {
+        let rust = FormatOptions::default();
+        let hex = FormatOptions { radix: Radix::Hex, ..rust };
+        let asn1 = FormatOptions { labels: Labels::Asn1, ..rust };
+        let asn1_hex = FormatOptions { radix: Radix::Hex, labels: Labels::Asn1 };
+
+        let app = Number::Application(TagNumber::new(24));
+        assert_eq!(rust.number(&app).to_string(), "Application(24)");
+        assert_eq!(hex.number(&app).to_string(), "Application(0x18)");
+        assert_eq!(asn1.number(&app).to_string(), "[APPLICATION 24]");
+        assert_eq!(asn1_hex.number(&app).to_string(), "[APPLICATION 0x18]");
+        assert_eq!(asn1.number(&Number::ContextSpecific(TagNumber::new(3))).to_string(), "[3]");
+        assert_eq!(asn1.number(&Number::Private(TagNumber::new(1))).to_string(), "[PRIVATE 1]");
+
+        let seq = Number::Universal(Type::Sequence);
+        assert_eq!(rust.number(&seq).to_string(), format!("{:?}", seq));
+        assert_eq!(asn1_hex.number(&seq).to_string(), "SEQUENCE");
+    }
+}
@@ -0,0 +1,390 @@
+//! Outer length-prefix framings that some transports put in front of each BER message,
+//! e.g. LDAP behind certain load balancers or proprietary message buses.
+//!
+//! A `Framing` strips and checks the prefix before the TLV is parsed, and adds it back
+//! when writing.
+//!
+//! Raw serial and UDP links also corrupt and cut frames short without saying so. A
+//! framing with a `Checksum` follows each message with its CRC, and `Framing::frames`
+//! reports a frame that fails the check and carries on with the next one.
+
+use std::io::{self, Cursor, Read, Write};
+use std::iter::FusedIterator;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use crate::err;
+use crate::tag::{ReadOptions, Tag};
+use crate::util::{self, TrackedRead};
+
+/// Width of the length prefix.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Width {
+    Two,
+    Four,
+}
+
+impl Width {
+    fn octets (self) -> usize {
+        match self {
+            Width::Two => 2,
+            Width::Four => 4,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// A CRC over the message octets, written after them in four octets of the framing's
+/// byte order. The prefix doesn't count them.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Checksum {
+    /// CRC-32 as in Ethernet and zlib.
+    Crc32,
+    /// CRC-32C (Castagnoli) as in iSCSI and SCTP.
+    Crc32c,
+}
+
+impl Checksum {
+    /// The checksum of `data`.
+    pub fn of (self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32 => util::crc32(data),
+            Checksum::Crc32c => util::crc32c(data),
+        }
+    }
+}
+
+/// How each message is framed.
+#[derive(Debug, Clone)]
+pub struct Framing {
+    pub width: Width,
+    pub endian: Endian,
+    /// Whether the prefix counts its own octets as well as the message's.
+    pub includes_prefix: bool,
+    /// Frames declaring more than this many message octets are rejected before anything
+    /// is allocated.
+    pub max_len: usize,
+    pub checksum: Option<Checksum>,
+}
+
+impl Framing {
+    /// A big endian prefix of `width` octets counting only the message, with frames capped
+    /// at 16 MiB and no checksum.
+    pub fn new (width: Width) -> Framing {
+        Framing {
+            width,
+            endian: Endian::Big,
+            includes_prefix: false,
+            max_len: 16 * 1024 * 1024,
+            checksum: None,
+        }
+    }
+
+    /// Reads one frame and returns the message octets inside it. The error is
+    /// `Kind::CleanEof` if the input ends before the prefix starts, and
+    /// `Kind::ChecksumMismatch` if the message fails its checksum; the whole frame has
+    /// been read either way, so the next read starts at the next frame.
+    pub fn read_frame (&self, r: &mut dyn Read) -> Result<Vec<u8>, err::Error> {
+        let width = self.width.octets();
+        let mut prefix = [0; 4];
+        fill(r, &mut prefix[..width], 0, true)?;
+
+        let declared = match (self.width, self.endian) {
+            (Width::Two, Endian::Big) => BigEndian::read_u16(&prefix) as u64,
+            (Width::Two, Endian::Little) => LittleEndian::read_u16(&prefix) as u64,
+            (Width::Four, Endian::Big) => BigEndian::read_u32(&prefix) as u64,
+            (Width::Four, Endian::Little) => LittleEndian::read_u32(&prefix) as u64,
+        };
+        let len = if self.includes_prefix {
+            match declared.checked_sub(width as u64) {
+                Some(len) => len,
+                None => return Err(err::Error::new(err::Kind::InvalidLength, 0, None)),
+            }
+        } else {
+            declared
+        };
+        if len > self.max_len as u64 {
+            return Err(err::Error::new(err::Kind::InvalidLength, 0, None));
+        }
+        let len = len as usize;
+
+        let mut buf = Vec::with_capacity(len);
+        r.take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() < len {
+            return Err(err::Error::new(err::Kind::Truncated { needed: len - buf.len() }, width + buf.len(), None));
+        }
+
+        if let Some(checksum) = self.checksum {
+            let mut trailer = [0; 4];
+            fill(r, &mut trailer, width + len, false)?;
+            let expected = match self.endian {
+                Endian::Big => BigEndian::read_u32(&trailer),
+                Endian::Little => LittleEndian::read_u32(&trailer),
+            };
+            let actual = checksum.of(&buf);
+            if actual != expected {
+                return Err(err::Error::new(err::Kind::ChecksumMismatch { expected, actual }, width + len, None));
+            }
+        }
+        Ok(buf)
+    }
+
+    /// The frames of a stream, one after another until it ends between two of them.
+    pub fn frames<'a> (&'a self, r: &'a mut dyn Read) -> Frames<'a> {
+        Frames {
+            framing: self,
+            r: TrackedRead::new(r),
+            done: false,
+        }
+    }
+
+    /// Reads one frame and parses the tag inside it. The tag must fill the frame exactly.
+    /// Offsets, in the tag and in errors alike, are relative to the start of the frame,
+    /// prefix included.
+    pub fn read (&self, r: &mut dyn Read) -> Result<Tag, err::Error> {
+        self.read_with(r, &ReadOptions::default())
+    }
+
+    /// `read` using `opts` for the tag. Offsets count from `opts.base_offset` at the
+    /// start of the frame.
+    pub fn read_with (&self, r: &mut dyn Read, opts: &ReadOptions) -> Result<Tag, err::Error> {
+        let start = self.width.octets() + opts.base_offset;
+        let frame = self.read_frame(r).map_err(|e| err::Error { offset: opts.base_offset + e.offset, ..e })?;
+
+        let mut cursor = Cursor::new(&frame[..]);
+        let tag = Tag::read_with(&mut cursor, &ReadOptions { base_offset: start, ..opts.clone() }).map_err(|mut e| {
+            // a tag running off the end of its frame is a framing mismatch, not an EOF
+            if let err::Kind::Truncated { .. } | err::Kind::CleanEof = e.kind {
+                e.kind = err::Kind::InvalidLength;
+            }
+            e
+        })?;
+        if cursor.position() as usize != frame.len() {
+            return Err(err::Error::new(err::Kind::InvalidLength, start + cursor.position() as usize, None));
+        }
+        Ok(tag)
+    }
+
+    /// Writes `tag` with its prefix.
+    pub fn write (&self, tag: &Tag, w: &mut dyn Write) -> Result<(), err::Error> {
+        let mut buf = Vec::new();
+        tag.write(&mut buf)?;
+        self.write_frame(&buf, w)
+    }
+
+    /// Writes already encoded message octets with their prefix.
+    pub fn write_frame (&self, message: &[u8], w: &mut dyn Write) -> Result<(), err::Error> {
+        let width = self.width.octets();
+        let len = message.len() as u64 + if self.includes_prefix { width as u64 } else { 0 };
+        let max = match self.width {
+            Width::Two => u16::MAX as u64,
+            Width::Four => u32::MAX as u64,
+        };
+        if message.len() > self.max_len || len > max {
+            return Err(err::Error::new(err::Kind::InvalidLength, 0, None));
+        }
+
+        let mut prefix = [0; 4];
+        match (self.width, self.endian) {
+            (Width::Two, Endian::Big) => BigEndian::write_u16(&mut prefix, len as u16),
+            (Width::Two, Endian::Little) => LittleEndian::write_u16(&mut prefix, len as u16),
+            (Width::Four, Endian::Big) => BigEndian::write_u32(&mut prefix, len as u32),
+            (Width::Four, Endian::Little) => LittleEndian::write_u32(&mut prefix, len as u32),
+        }
+        w.write_all(&prefix[..width])?;
+        w.write_all(message)?;
+
+        if let Some(checksum) = self.checksum {
+            let mut trailer = [0; 4];
+            match self.endian {
+                Endian::Big => BigEndian::write_u32(&mut trailer, checksum.of(message)),
+                Endian::Little => LittleEndian::write_u32(&mut trailer, checksum.of(message)),
+            }
+            w.write_all(&trailer)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads exactly `buf.len()` octets found `offset` octets into a frame. Running out before
+// the first is `Kind::CleanEof` if `at_boundary`, since no frame had started.
+fn fill (r: &mut dyn Read, buf: &mut [u8], offset: usize, at_boundary: bool) -> Result<(), err::Error> {
+    let mut got = 0;
+    while got < buf.len() {
+        match r.read(&mut buf[got..]) {
+            Ok(0) => {
+                let kind = if got == 0 && at_boundary {
+                    err::Kind::CleanEof
+                } else {
+                    err::Kind::Truncated { needed: buf.len() - got }
+                };
+                return Err(err::Error::new(kind, offset + got, None));
+            },
+            Ok(n) => got += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(err::Error::new(e.into(), offset + got, None)),
+        }
+    }
+    Ok(())
+}
+
+/// The message octets of each frame in a stream, with the stream offset of the frame,
+/// from `Framing::frames`. Errors carry stream offsets.
+///
+/// A frame failing its checksum gives a `Kind::ChecksumMismatch` error and iteration
+/// goes on with the frame after it. Any other error ends the iteration, since there is
+/// no telling where the next frame starts.
+pub struct Frames<'a> {
+    framing: &'a Framing,
+    r:       TrackedRead<'a>,
+    done:    bool,
+}
+
+impl<'a> Frames<'a> {
+    /// Stream offset of the next frame.
+    pub fn offset (&self) -> usize {
+        self.r.tell()
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<(usize, Vec<u8>), err::Error>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let offset = self.r.tell();
+        match self.framing.read_frame(&mut self.r) {
+            Ok(message) => Some(Ok((offset, message))),
+            Err(e) => {
+                match e.kind {
+                    err::Kind::CleanEof => {
+                        self.done = true;
+                        return None;
+                    },
+                    err::Kind::ChecksumMismatch { .. } => {},
+                    _ => self.done = true,
+                }
+                Some(Err(err::Error { offset: offset + e.offset, ..e }))
+            },
+        }
+    }
+}
+
+impl<'a> FusedIterator for Frames<'a> {}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{Checksum, Endian, Framing, Width};
+    use crate::err;
+    use crate::tag::{ReadOptions, Tag};
+
+    #[test]
+    fn test_roundtrip () {
+        let tag = Tag::sequence(vec![Tag::integer(5), Tag::null()]);
+        let mut encoded = Vec::new();
+        tag.write(&mut encoded).unwrap();
+
+        for &(width, endian, includes_prefix) in &[
+            (Width::Two, Endian::Big, false),
+            (Width::Four, Endian::Little, true),
+        ] {
+            let framing = Framing { endian, includes_prefix, ..Framing::new(width) };
+
+            let mut buf = Vec::new();
+            framing.write(&tag, &mut buf).unwrap();
+            framing.write(&tag, &mut buf).unwrap();
+
+            let mut r = Cursor::new(buf);
+            for _ in 0..2 {
+                let mut reencoded = Vec::new();
+                framing.read(&mut r).unwrap().write(&mut reencoded).unwrap();
+                assert_eq!(reencoded, encoded);
+            }
+            assert!(matches!(framing.read(&mut r).unwrap_err().kind, err::Kind::CleanEof));
+        }
+    }
+
+    #[test]
+    fn test_prefix_must_match_tag () {
+        let framing = Framing::new(Width::Two);
+
+        // trailing octet inside the frame
+        let e = framing.read(&mut Cursor::new(vec![0x00, 0x03, 0x05, 0x00, 0xAA])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength));
+        assert_eq!(e.offset, 4);
+
+        // tag longer than the frame
+        let e = framing.read(&mut Cursor::new(vec![0x00, 0x02, 0x04, 0x02, 0xAA])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength));
+
+        // frame longer than the input
+        let e = framing.read(&mut Cursor::new(vec![0x00, 0x04, 0x05, 0x00])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 2 }));
+
+        let small = Framing { max_len: 1, ..Framing::new(Width::Four) };
+        let e = small.read(&mut Cursor::new(vec![0x00, 0x00, 0x00, 0x02, 0x05, 0x00])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength));
+    }
+
+    #[test]
+    fn test_offsets () {
+        // SEQUENCE { NULL } framed 100 bytes into a capture, then a bad frame
+        let framing = Framing::new(Width::Two);
+        let opts = ReadOptions::builder().base_offset(100).build();
+
+        let tag = framing.read_with(&mut &[0x00, 0x04, 0x30, 0x02, 0x05, 0x00][..], &opts).unwrap();
+        assert_eq!((tag.offset, tag.at_path(&[0]).unwrap().offset), (Some(102), Some(104)));
+
+        // the NULL's length octet is missing, and the frame ends after its identifier
+        let e = framing.read_with(&mut &[0x00, 0x03, 0x30, 0x02, 0x05][..], &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength), "{}", e);
+        assert_eq!(e.offset, 105);
+        let e = framing.read_with(&mut &[0x00, 0x05, 0x30, 0x02, 0x05, 0x00, 0xAA][..], &opts).unwrap_err();
+        assert_eq!(e.offset, 106);
+        let e = framing.read_with(&mut &[0x00, 0x04, 0x30][..], &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+        assert_eq!(e.offset, 103);
+    }
+
+    #[test]
+    fn test_checksum () {
+        let tag = Tag::sequence(vec![Tag::integer(5), Tag::null()]);
+        let mut encoded = Vec::new();
+        tag.write(&mut encoded).unwrap();
+
+        for checksum in [Checksum::Crc32, Checksum::Crc32c] {
+            let framing = Framing { checksum: Some(checksum), ..Framing::new(Width::Two) };
+            let mut buf = Vec::new();
+            for _ in 0..3 {
+                framing.write(&tag, &mut buf).unwrap();
+            }
+            let frame = buf.len() / 3;
+            assert_eq!(frame, 2 + encoded.len() + 4);
+
+            // the middle frame is corrupted in transit, the last cut short
+            buf[frame + 4] ^= 0x01;
+            buf.pop();
+
+            let mut r = Cursor::new(buf);
+            let mut frames = framing.frames(&mut r);
+            assert_eq!(frames.next().unwrap().unwrap(), (0, encoded.clone()));
+            let e = frames.next().unwrap().unwrap_err();
+            assert!(matches!(e.kind, err::Kind::ChecksumMismatch { .. }), "{}", e);
+            assert_eq!(e.offset, frame + 2 + encoded.len());
+            let e = frames.next().unwrap().unwrap_err();
+            assert!(matches!(e.kind, err::Kind::Truncated { needed: 1 }), "{}", e);
+            assert_eq!(e.offset, 3 * frame - 1);
+            assert!(frames.next().is_none());
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! The arithmetic of identifier and length octets, as plain functions over slices, for
+//! code that wants BER headers without the tree model around them.
+//!
+//! Encoders write the shortest form to the front of a buffer and say how many octets
+//! they used, or `None` if the buffer was too short; `tag_number_len` and `length_len`
+//! size the buffer beforehand. Decoders read from the front of a slice and say how many
+//! octets they consumed. A length of `None` is the indefinite form.
+//!
+//! The decoders accept what `Tag::read` does, padded forms included, with the same
+//! errors: `Kind::Truncated` at the end of the slice when it stops short, and
+//! `Kind::NumberOverflow` for a number past `u32` or a length past `u64`. Unlike
+//! `Tag::read` they don't care which class uses the long form of a number.
+
+use crate::err;
+use crate::tag::Class;
+
+// Identifier octets past the first that a `u32` number can need.
+const MAX_NUMBER_OCTETS: usize = 5;
+
+/// The octets of an identifier numbered `number`.
+pub fn tag_number_len (number: u32) -> usize {
+    if number < 0x1F {
+        1
+    } else {
+        1 + (32 - number.leading_zeros() as usize).div_ceil(7)
+    }
+}
+
+/// The octets of `length`.
+pub fn length_len (length: Option<u64>) -> usize {
+    match length {
+        Some(l) if l >= 0x80 => 1 + (64 - l.leading_zeros() as usize).div_ceil(8),
+        _ => 1,
+    }
+}
+
+/// Writes the identifier of a tag in `class`, constructed or not, numbered `number`.
+pub fn encode_tag_number (class: Class, constructed: bool, number: u32, out: &mut [u8]) -> Option<usize> {
+    let len = tag_number_len(number);
+    let out = out.get_mut(..len)?;
+
+    let first = (class as u8) << 6 | (constructed as u8) << 5;
+    if len == 1 {
+        out[0] = first | number as u8;
+        return Some(1);
+    }
+
+    out[0] = first | 0x1F;
+    for (i, b) in out[1..].iter_mut().rev().enumerate() {
+        *b = (number >> (7 * i)) as u8 & 0x7F | if i == 0 { 0 } else { 0x80 };
+    }
+    Some(len)
+}
+
+/// Reads an identifier: its class, whether it's constructed, its number and its octets.
+pub fn decode_tag_number (buf: &[u8]) -> Result<(Class, bool, u32, usize), err::Error> {
+    let first = *buf.first().ok_or_else(|| truncated(buf))?;
+    let class = match first >> 6 {
+        0 => Class::Universal,
+        1 => Class::Application,
+        2 => Class::ContextSpecific,
+        _ => Class::Private,
+    };
+    let constructed = first & 0x20 != 0;
+    if first & 0x1F != 0x1F {
+        return Ok((class, constructed, (first & 0x1F) as u32, 1));
+    }
+
+    let mut number = 0u64;
+    for (i, &b) in buf[1..].iter().take(MAX_NUMBER_OCTETS).enumerate() {
+        number = number << 7 | (b & 0x7F) as u64;
+        if b & 0x80 == 0 {
+            let number = u32::try_from(number).map_err(|_| overflow())?;
+            return Ok((class, constructed, number, i + 2));
+        }
+    }
+    if buf.len() > MAX_NUMBER_OCTETS {
+        return Err(overflow());
+    }
+    Err(truncated(buf))
+}
+
+/// Writes `length`, `None` being the indefinite form.
+pub fn encode_length (length: Option<u64>, out: &mut [u8]) -> Option<usize> {
+    let len = length_len(length);
+    let out = out.get_mut(..len)?;
+
+    match length {
+        None => out[0] = 0x80,
+        Some(l) if len == 1 => out[0] = l as u8,
+        Some(l) => {
+            out[0] = 0x80 | (len - 1) as u8;
+            out[1..].copy_from_slice(&l.to_be_bytes()[9 - len..]);
+        },
+    }
+    Some(len)
+}
+
+/// Reads a length, `None` being the indefinite form, and its octets.
+pub fn decode_length (buf: &[u8]) -> Result<(Option<u64>, usize), err::Error> {
+    let first = *buf.first().ok_or_else(|| truncated(buf))?;
+    if first == 0x80 {
+        return Ok((None, 1));
+    }
+    if first & 0x80 == 0 {
+        return Ok((Some(first as u64), 1));
+    }
+
+    let count = (first & 0x7F) as usize;
+    if count > 8 {
+        return Err(overflow());
+    }
+    let octets = buf.get(1..1 + count).ok_or_else(|| truncated(buf))?;
+    let length = octets.iter().fold(0u64, |l, &b| l << 8 | b as u64);
+    Ok((Some(length), 1 + count))
+}
+
+fn truncated (buf: &[u8]) -> err::Error {
+    err::Error::new(err::Kind::Truncated { needed: 1 }, buf.len(), None)
+}
+
+fn overflow () -> err::Error {
+    err::Error::new(err::Kind::NumberOverflow, 0, None)
+}
+
+#[cfg(kani)]
+mod verification {
+    use super::*;
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn length_round_trips () {
+        let l: Option<u64> = kani::any();
+        let mut buf = [0; 9];
+        let n = encode_length(l, &mut buf).unwrap();
+        assert!(decode_length(&buf).unwrap() == (l, n));
+    }
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn tag_number_round_trips () {
+        let number: u32 = kani::any();
+        let mut buf = [0; 6];
+        let n = encode_tag_number(Class::Private, true, number, &mut buf).unwrap();
+        assert!(decode_tag_number(&buf).unwrap() == (Class::Private, true, number, n));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::{self, Flavor, HeaderLayout, Length, Number, TagNumber};
+
+    // Numbers and lengths either side of every change in encoded size.
+    fn boundaries (bits: u32, step: u32) -> Vec<u64> {
+        let mut values = vec![0, 1, 0x1E, 0x1F, 0x7F, 0x80];
+        for shift in (step..bits).step_by(step as usize) {
+            values.extend([(1u64 << shift) - 1, 1 << shift]);
+        }
+        values.push(if bits == 64 { u64::MAX } else { (1 << bits) - 1 });
+        values
+    }
+
+    #[test]
+    fn test_tag_numbers () {
+        // every single octet identifier
+        for b in 0..=0xFFu8 {
+            let decoded = decode_tag_number(&[b]);
+            if b & 0x1F == 0x1F {
+                assert!(matches!(decoded.unwrap_err().kind, err::Kind::Truncated { .. }));
+                continue;
+            }
+            let (class, constructed, number, n) = decoded.unwrap();
+            let mut out = [0; 1];
+            assert_eq!(encode_tag_number(class, constructed, number, &mut out), Some(n));
+            assert_eq!(out, [b]);
+        }
+
+        // long forms match what the writer produces, and read back
+        for number in boundaries(32, 7) {
+            let number = number as u32;
+            let mut out = [0; 6];
+            let n = encode_tag_number(Class::ContextSpecific, false, number, &mut out).unwrap();
+            assert_eq!(n, tag_number_len(number));
+
+            let mut written = Vec::new();
+            let tag_number = Number::ContextSpecific(TagNumber::new(number));
+            tag::write_header(&mut written, &Class::ContextSpecific, &Flavor::Primitive, &tag_number, &Length::Some(0), &HeaderLayout::default()).unwrap();
+            assert_eq!(out[..n], written[..n]);
+            assert_eq!(decode_tag_number(&out).unwrap(), (Class::ContextSpecific, false, number, n));
+            assert!(encode_tag_number(Class::ContextSpecific, false, number, &mut out[..n - 1]).is_none());
+            assert!(matches!(decode_tag_number(&out[..n - 1]).unwrap_err().kind, err::Kind::Truncated { .. }));
+        }
+
+        // padded, and too big
+        assert_eq!(decode_tag_number(&[0xBF, 0x80, 0x01]).unwrap(), (Class::ContextSpecific, true, 1, 3));
+        assert!(matches!(decode_tag_number(&[0x1F, 0x90, 0x80, 0x80, 0x80, 0x00]).unwrap_err().kind, err::Kind::NumberOverflow));
+        assert!(matches!(decode_tag_number(&[0x1F, 0x80, 0x80, 0x80, 0x80, 0x80]).unwrap_err().kind, err::Kind::NumberOverflow));
+    }
+
+    #[test]
+    fn test_lengths () {
+        for l in boundaries(64, 8).into_iter().map(Some).chain([None]) {
+            let mut out = [0; 9];
+            let n = encode_length(l, &mut out).unwrap();
+            assert_eq!(n, length_len(l));
+
+            let mut written = Vec::new();
+            tag::write_length(&mut written, &l.map_or(Length::Indefinite, Length::Some)).unwrap();
+            assert_eq!(out[..n], written[..]);
+            assert_eq!(decode_length(&out).unwrap(), (l, n));
+            assert!(encode_length(l, &mut out[..n - 1]).is_none());
+            if n > 1 {
+                assert!(matches!(decode_length(&out[..n - 1]).unwrap_err().kind, err::Kind::Truncated { .. }));
+            }
+        }
+
+        // every first octet reads as the reader reads it
+        for b in 0..=0xFFu8 {
+            let buf = [b, 0, 0, 0, 0, 0, 0, 0, 1];
+            let ours = decode_length(&buf).map(|(l, _)| l).map_err(|e| e.code());
+            let theirs = tag::read_length(&mut &buf[..])
+                .map(|l| match l { Length::Some(l) => Some(l), Length::Indefinite => None })
+                .map_err(|e| e.code());
+            assert_eq!(ours, theirs, "{:02x}", b);
+        }
+        assert!(matches!(decode_length(&[]).unwrap_err().kind, err::Kind::Truncated { .. }));
+    }
+}
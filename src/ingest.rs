@@ -0,0 +1,172 @@
+//! Reassembly of BER PDUs carried across decrypted TLS application-data records, as seen
+//! by LDAPS or secure SNMP analysis behind a TLS-terminating capture.
+//!
+//! Records don't line up with PDUs: one record may hold several PDUs or only part of one.
+//! A `Reassembler` buffers records and hands out each PDU once all of it has arrived,
+//! using the `needed` count of a `Kind::Truncated` error to avoid re-parsing until enough
+//! new bytes are in.
+
+use std::io::Cursor;
+
+use crate::err;
+use crate::tag::{ReadOptions, Tag};
+
+pub struct Reassembler {
+    buf: Vec<u8>,
+    // stream offset of buf[0]
+    base: usize,
+    // don't try to parse again until buf holds at least this many bytes
+    wanted: usize,
+    opts: ReadOptions,
+    /// A PDU still incomplete with this many bytes buffered is rejected rather than
+    /// waited for.
+    pub max_pending: usize,
+}
+
+impl Default for Reassembler {
+    fn default () -> Reassembler {
+        Reassembler::new()
+    }
+}
+
+impl Reassembler {
+    pub fn new () -> Reassembler {
+        Reassembler::with_options(ReadOptions::default())
+    }
+
+    pub fn with_options (opts: ReadOptions) -> Reassembler {
+        Reassembler {
+            buf: Vec::new(),
+            base: opts.base_offset,
+            wanted: 0,
+            opts: ReadOptions { base_offset: 0, ..opts },
+            max_pending: 16 * 1024 * 1024,
+        }
+    }
+
+    /// Appends the plaintext of one record.
+    pub fn push (&mut self, record: &[u8]) {
+        self.buf.extend_from_slice(record);
+    }
+
+    /// Number of bytes buffered but not yet returned as part of a PDU.
+    pub fn pending (&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the next complete PDU and its offset in the stream, or `None` if more
+    /// records are needed. Offsets within the tag are relative to the start of the PDU.
+    pub fn next_pdu (&mut self) -> Result<Option<(usize, Tag)>, err::Error> {
+        if self.buf.is_empty() || self.buf.len() < self.wanted {
+            return Ok(None);
+        }
+
+        let mut cursor = Cursor::new(&self.buf[..]);
+        match Tag::read_with(&mut cursor, &self.opts) {
+            Ok(tag) => {
+                let consumed = cursor.position() as usize;
+                let offset = self.base;
+                self.buf.drain(..consumed);
+                self.base += consumed;
+                self.wanted = 0;
+                Ok(Some((offset, tag)))
+            },
+            Err(e) => match e.kind {
+                err::Kind::Truncated { needed } => {
+                    self.wanted = self.buf.len().saturating_add(needed);
+                    if self.wanted > self.max_pending {
+                        return Err(err::Error::new(err::Kind::InvalidLength, self.base, None));
+                    }
+                    Ok(None)
+                },
+                _ => Err(err::Error { offset: self.base + e.offset, ..e }),
+            },
+        }
+    }
+
+    /// Ends the stream. Fails with `Kind::Truncated` if part of a PDU is still buffered.
+    pub fn finish (self) -> Result<(), err::Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            let needed = self.wanted.saturating_sub(self.buf.len()).max(1);
+            Err(err::Error::new(err::Kind::Truncated { needed }, self.base + self.buf.len(), None))
+        }
+    }
+}
+
+/// Reassembles every PDU in a complete sequence of records.
+pub fn pdus<I, R> (records: I) -> Result<Vec<(usize, Tag)>, err::Error>
+    where I: IntoIterator<Item = R>, R: AsRef<[u8]> {
+    let mut reassembler = Reassembler::new();
+    let mut out = Vec::new();
+
+    for record in records {
+        reassembler.push(record.as_ref());
+        while let Some(pdu) = reassembler.next_pdu()? {
+            out.push(pdu);
+        }
+    }
+
+    reassembler.finish()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pdus, Reassembler};
+    use crate::err;
+    use crate::tag::{ReadOptions, Tag};
+
+    #[test]
+    fn test_across_records () {
+        let mut stream = Vec::new();
+        let messages = vec![
+            Tag::sequence(vec![Tag::integer(1), Tag::octet_string(vec![0xAB; 300])]),
+            Tag::sequence(vec![Tag::integer(2)]),
+            Tag::null(),
+        ];
+        let mut offsets = Vec::new();
+        for message in &messages {
+            offsets.push(stream.len());
+            message.write(&mut stream).unwrap();
+        }
+
+        for size in &[1, 7, 64, stream.len()] {
+            let got = pdus(stream.chunks(*size)).unwrap();
+            assert_eq!(got.iter().map(|&(o, _)| o).collect::<Vec<_>>(), offsets);
+
+            for ((_, tag), message) in got.iter().zip(&messages) {
+                let (mut a, mut b) = (Vec::new(), Vec::new());
+                tag.write(&mut a).unwrap();
+                message.write(&mut b).unwrap();
+                assert_eq!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_incomplete_and_oversized () {
+        let e = pdus(vec![vec![0x05, 0x00, 0x04, 0x03, 0x01]]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 2 }));
+
+        let mut reassembler = Reassembler::new();
+        reassembler.max_pending = 8;
+        reassembler.push(&[0x04, 0x10, 0x00]);
+        assert!(matches!(reassembler.next_pdu().unwrap_err().kind, err::Kind::InvalidLength));
+    }
+
+    #[test]
+    fn test_error_keeps_path () {
+        // NULL, then SEQUENCE { NULL }, whose NULL is one level deeper than allowed
+        let opts = ReadOptions::builder().max_depth(1).base_offset(100).build();
+        let mut reassembler = Reassembler::with_options(opts);
+        reassembler.push(&[0x05, 0x00, 0x30, 0x02, 0x05, 0x00]);
+        assert_eq!(reassembler.next_pdu().unwrap().unwrap().0, 100);
+
+        let e = reassembler.next_pdu().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::TooDeep { .. }), "{}", e);
+        assert_eq!(e.offset, 104);
+        assert_eq!(e.path.len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! INTEGER and ENUMERATED content octets (X.690 §8.3, §8.4): big endian two's complement.
+
+use crate::err;
+use crate::tag::{Number, Payload, Tag, Type};
+
+/// Minimal two's complement content octets for `n`.
+pub(crate) fn encode_i64 (n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let mut start = 0;
+    while start < 7 &&
+        ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0) || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+impl Tag {
+    /// A universal INTEGER with minimal content octets.
+    pub fn integer (n: i64) -> Tag {
+        Tag::primitive(Number::Universal(Type::Integer), encode_i64(n))
+    }
+
+    /// A universal INTEGER holding an unsigned value, including those above `i64::MAX`.
+    pub fn unsigned (n: u64) -> Tag {
+        let mut content = vec![0];
+        content.extend_from_slice(&n.to_be_bytes());
+        let start = content.windows(2).position(|w| w[0] != 0 || w[1] & 0x80 != 0).unwrap_or(content.len() - 1);
+        content.drain(..start);
+        Tag::primitive(Number::Universal(Type::Integer), content)
+    }
+
+    /// A universal ENUMERATED holding `v`'s value.
+    pub fn enumerated<E: Into<i64>> (v: E) -> Tag {
+        Tag::primitive(Number::Universal(Type::Enumerated), encode_i64(v.into()))
+    }
+
+    /// Interprets the content as an ENUMERATED and converts it to a caller's enum. Values
+    /// the enum doesn't accept are an `InvalidValue` error.
+    pub fn as_enumerated<E: TryFrom<i64>> (&self) -> Result<E, err::Error> {
+        E::try_from(self.as_i64()?).map_err(|_| self.error(err::Kind::InvalidValue))
+    }
+
+    /// The primitive content octets, or a `NotPrimitive` error.
+    pub(crate) fn content (&self) -> Result<&[u8], err::Error> {
+        match self.payload {
+            Payload::Primitive(ref v) => Ok(v),
+            Payload::Constructed(_) => Err(self.error(err::Kind::NotPrimitive)),
+        }
+    }
+
+    pub(crate) fn error (&self, kind: err::Kind) -> err::Error {
+        err::Error::new(kind, self.offset.unwrap_or(0), None)
+    }
+
+    /// Interprets the content as a signed INTEGER. The tag number is not checked, so this
+    /// works just as well on implicitly tagged integers.
+    pub fn as_i64 (&self) -> Result<i64, err::Error> {
+        let v = self.integer_content()?;
+
+        // strip redundant sign octets, then the rest must fit
+        let mut start = 0;
+        while start + 1 < v.len() &&
+            ((v[start] == 0x00 && v[start + 1] & 0x80 == 0) || (v[start] == 0xFF && v[start + 1] & 0x80 != 0)) {
+            start += 1;
+        }
+        let v = &v[start..];
+        if v.len() > 8 {
+            return Err(self.error(err::Kind::IntegerOverflow));
+        }
+
+        let init = if v[0] & 0x80 != 0 { -1i64 } else { 0 };
+        Ok(v.iter().fold(init, |acc, &b| (acc << 8) | b as i64))
+    }
+
+    /// Interprets the content as a non-negative INTEGER.
+    pub fn as_u64 (&self) -> Result<u64, err::Error> {
+        let v = self.integer_content()?;
+
+        if v[0] & 0x80 != 0 {
+            return Err(self.error(err::Kind::IntegerOverflow));
+        }
+
+        let start = v.iter().position(|&b| b != 0).unwrap_or(v.len());
+        let v = &v[start..];
+        if v.len() > 8 {
+            return Err(self.error(err::Kind::IntegerOverflow));
+        }
+
+        Ok(v.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    }
+
+    fn integer_content (&self) -> Result<&[u8], err::Error> {
+        let v = self.content()?;
+        if v.is_empty() {
+            // X.690 requires at least one content octet
+            return Err(self.error(err::Kind::InvalidValue));
+        }
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tag::{Number, Payload, Tag, Type};
+
+    fn int (v: &[u8]) -> Tag {
+        Tag {
+            number: Number::Universal(Type::Integer),
+            offset: None,
+            payload: Payload::Primitive(v.to_vec()),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn test_as_i64 () {
+        assert_eq!(int(&[0x00]).as_i64().unwrap(), 0);
+        assert_eq!(int(&[0x7F]).as_i64().unwrap(), 127);
+        assert_eq!(int(&[0x00, 0x80]).as_i64().unwrap(), 128);
+        assert_eq!(int(&[0x80]).as_i64().unwrap(), -128);
+        assert_eq!(int(&[0xFF, 0x7F]).as_i64().unwrap(), -129);
+        assert_eq!(int(&[0xFF]).as_i64().unwrap(), -1);
+        assert_eq!(int(&[0x80, 0, 0, 0, 0, 0, 0, 0]).as_i64().unwrap(), i64::MIN);
+        assert_eq!(int(&[0x00, 0x00, 0x01]).as_i64().unwrap(), 1);
+
+        assert!(int(&[0x00, 0x80, 0, 0, 0, 0, 0, 0, 0]).as_i64().is_err());
+        assert!(int(&[]).as_i64().is_err());
+    }
+
+    #[test]
+    fn test_constructors () {
+        for &n in &[0i64, 1, 127, 128, 255, 256, -1, -128, -129, i64::MIN, i64::MAX] {
+            assert_eq!(Tag::integer(n).as_i64().unwrap(), n);
+        }
+        assert_eq!(Tag::integer(128).payload, Payload::Primitive(vec![0x00, 0x80]));
+        assert_eq!(Tag::integer(-128).payload, Payload::Primitive(vec![0x80]));
+
+        for &n in &[0u64, 127, 128, u64::MAX] {
+            assert_eq!(Tag::unsigned(n).as_u64().unwrap(), n);
+        }
+        assert_eq!(Tag::unsigned(0).payload, Payload::Primitive(vec![0x00]));
+        assert_eq!(Tag::unsigned(u64::MAX).payload, Payload::Primitive(vec![0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]));
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum ResultCode {
+        Success,
+        Busy,
+    }
+
+    impl From<ResultCode> for i64 {
+        fn from (c: ResultCode) -> i64 {
+            match c {
+                ResultCode::Success => 0,
+                ResultCode::Busy => 51,
+            }
+        }
+    }
+
+    impl TryFrom<i64> for ResultCode {
+        type Error = ();
+
+        fn try_from (v: i64) -> Result<ResultCode, ()> {
+            match v {
+                0 => Ok(ResultCode::Success),
+                51 => Ok(ResultCode::Busy),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_enumerated () {
+        let tag = Tag::enumerated(ResultCode::Busy);
+        assert_eq!(tag.number, Number::Universal(Type::Enumerated));
+        assert_eq!(tag.payload, Payload::Primitive(vec![51]));
+        assert_eq!(tag.as_enumerated::<ResultCode>().unwrap(), ResultCode::Busy);
+        assert_eq!(Tag::enumerated(ResultCode::Success).as_enumerated::<ResultCode>().unwrap(), ResultCode::Success);
+
+        assert!(Tag::enumerated(7).as_enumerated::<ResultCode>().is_err());
+    }
+
+    #[test]
+    fn test_as_u64 () {
+        assert_eq!(int(&[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).as_u64().unwrap(), u64::MAX);
+        assert_eq!(int(&[0x01, 0x00]).as_u64().unwrap(), 256);
+        assert!(int(&[0xFF]).as_u64().is_err());
+        assert!(int(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0]).as_u64().is_err());
+
+        let constructed = Tag {
+            number: Number::Universal(Type::Integer),
+            offset: None,
+            payload: Payload::Constructed(vec![]),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        };
+        assert!(constructed.as_u64().is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Deduplicated trees for corpora with heavy redundancy, e.g. the same certificate
+//! repeated across thousands of messages.
+//!
+//! An `Interner` turns tags into `SharedTag`s in which identical subtrees are one `Arc`,
+//! however many times and wherever they occur. Offsets are dropped, since the same subtree
+//! sits at different offsets in different messages.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::sync::Arc;
+
+use crate::err;
+use crate::tag::{Number, Payload, ReadOptions, Tag};
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum SharedPayload {
+    Primitive(Vec<u8>),
+    Constructed(Vec<Arc<SharedTag>>),
+}
+
+/// A tag whose children are shared with every identical subtree seen by its `Interner`.
+#[derive(PartialEq, Eq, Debug)]
+pub struct SharedTag {
+    pub number:  Number,
+    pub payload: SharedPayload,
+    hash:        u64,
+}
+
+impl SharedTag {
+    /// A hash of the number and content of the whole subtree. Identical subtrees hash
+    /// the same in every run of a given build, but not necessarily across Rust versions.
+    pub fn content_hash (&self) -> u64 {
+        self.hash
+    }
+
+    /// Copies the subtree back into an ordinary tree, without offsets.
+    pub fn to_tag (&self) -> Tag {
+        match self.payload {
+            SharedPayload::Primitive(ref v) => Tag::primitive(self.number, v.clone()),
+            SharedPayload::Constructed(ref children) =>
+                Tag::constructed(self.number, children.iter().map(|c| c.to_tag()).collect()),
+        }
+    }
+}
+
+// Children are interned, so hashing a node only needs their hashes, not their subtrees.
+fn content_hash (number: &Number, payload: &SharedPayload) -> u64 {
+    let mut h = DefaultHasher::new();
+    number.hash(&mut h);
+    match *payload {
+        SharedPayload::Primitive(ref v) => {
+            0u8.hash(&mut h);
+            v.hash(&mut h);
+        },
+        SharedPayload::Constructed(ref children) => {
+            1u8.hash(&mut h);
+            children.len().hash(&mut h);
+            for child in children {
+                child.hash.hash(&mut h);
+            }
+        },
+    }
+    h.finish()
+}
+
+/// The table of unique subtrees, keyed by content hash.
+#[derive(Default)]
+pub struct Interner {
+    map: HashMap<u64, Vec<Arc<SharedTag>>>,
+}
+
+impl Interner {
+    pub fn new () -> Interner {
+        Interner::default()
+    }
+
+    /// Reads one tag and interns it. Only one message is ever held unshared.
+    pub fn read (&mut self, r: &mut dyn Read, opts: &ReadOptions) -> Result<Arc<SharedTag>, err::Error> {
+        Ok(self.intern(&Tag::read_with(r, opts)?))
+    }
+
+    /// Returns the shared copy of `tag`, adding any subtrees not seen before.
+    pub fn intern (&mut self, tag: &Tag) -> Arc<SharedTag> {
+        let payload = match tag.payload {
+            Payload::Primitive(ref v) => SharedPayload::Primitive(v.clone()),
+            Payload::Constructed(ref children) =>
+                SharedPayload::Constructed(children.iter().map(|c| self.intern(c)).collect()),
+        };
+        let hash = content_hash(&tag.number, &payload);
+
+        let bucket = self.map.entry(hash).or_default();
+        // equal children are the same Arc, so this comparison stops at the first level
+        if let Some(existing) = bucket.iter().find(|t| t.number == tag.number && t.payload == payload) {
+            return existing.clone();
+        }
+
+        let shared = Arc::new(SharedTag { number: tag.number, payload, hash });
+        bucket.push(shared.clone());
+        shared
+    }
+
+    /// The number of unique subtrees.
+    pub fn len (&self) -> usize {
+        self.map.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The unique subtrees with the given content hash; more than one only on a collision.
+    pub fn get (&self, hash: u64) -> &[Arc<SharedTag>] {
+        self.map.get(&hash).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    /// The whole content-hash map.
+    pub fn map (&self) -> &HashMap<u64, Vec<Arc<SharedTag>>> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Interner, SharedPayload};
+    use crate::tag::{ReadOptions, Tag};
+
+    fn certificate () -> Tag {
+        Tag::sequence(vec![Tag::integer(7), Tag::octet_string(vec![0xAB; 64])])
+    }
+
+    #[test]
+    fn test_shares_subtrees () {
+        let mut interner = Interner::new();
+
+        let mut buf = Vec::new();
+        for i in 0..3 {
+            Tag::sequence(vec![Tag::integer(i), certificate()]).write(&mut buf).unwrap();
+        }
+
+        let mut r = &buf[..];
+        let messages: Vec<_> = (0..3).map(|_| interner.read(&mut r, &ReadOptions::default()).unwrap()).collect();
+
+        let cert = |m: &Arc<super::SharedTag>| match m.payload {
+            SharedPayload::Constructed(ref c) => c[1].clone(),
+            _ => unreachable!(),
+        };
+        assert!(Arc::ptr_eq(&cert(&messages[0]), &cert(&messages[2])));
+        assert!(!Arc::ptr_eq(&messages[0], &messages[1]));
+
+        // four distinct INTEGERs, the OCTET STRING, the certificate and three messages
+        assert_eq!(interner.len(), 4 + 1 + 1 + 3);
+        assert_eq!(interner.get(cert(&messages[1]).content_hash()).len(), 1);
+
+        let mut original = Vec::new();
+        certificate().write(&mut original).unwrap();
+        let mut shared = Vec::new();
+        cert(&messages[0]).to_tag().write(&mut shared).unwrap();
+        assert_eq!(shared, original);
+    }
+}
@@ -0,0 +1,237 @@
+//! Certificate extraction from Java keystores (JKS and JCEKS).
+//!
+//! The keystore layout is walked entry by entry. Entries this module does not understand
+//! (JCEKS secret keys are Java serialized objects) or damaged regions are skipped by
+//! resynchronizing on the next length-prefixed DER certificate, so a single bad entry
+//! does not hide the rest of the chain.
+
+use std::str;
+
+use crate::err;
+use crate::tag::{self, Tag};
+
+const JKS_MAGIC:   u32 = 0xFEED_FEED;
+const JCEKS_MAGIC: u32 = 0xCECE_CECE;
+
+const PRIVATE_KEY_ENTRY:  u32 = 1;
+const TRUSTED_CERT_ENTRY: u32 = 2;
+
+/// A certificate found in a keystore.
+#[derive(Debug)]
+pub struct Certificate {
+    /// The alias of the entry holding the certificate, unknown for certificates found
+    /// after resynchronizing.
+    pub alias:  Option<String>,
+    /// Offset of the DER certificate within the keystore.
+    pub offset: usize,
+    pub tag:    Tag,
+}
+
+/// The result of scanning a keystore.
+#[derive(Debug)]
+pub struct Extracted {
+    pub certificates: Vec<Certificate>,
+    /// Problems that forced a resync, in the order they were hit.
+    pub errors:       Vec<err::Error>,
+}
+
+/// Extracts every certificate from a JKS or JCEKS keystore.
+pub fn certificates (buf: &[u8]) -> Result<Extracted, err::Error> {
+    let mut c = Cursor { buf, pos: 0 };
+
+    match c.u32() {
+        Some(JKS_MAGIC) | Some(JCEKS_MAGIC) => {},
+        _ => return Err(err::Error::new(err::Kind::UnrecognizedFormat, 0, None)),
+    }
+
+    let mut out = Extracted {
+        certificates: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    if let Err(offset) = walk(&mut c, &mut out) {
+        out.errors.push(err::Error::new(err::Kind::UnrecognizedFormat, offset, None));
+        resync(buf, offset, &mut out);
+    }
+
+    Ok(out)
+}
+
+// Walks the entries in order, returning the offset of the first thing it cannot follow.
+fn walk (c: &mut Cursor, out: &mut Extracted) -> Result<(), usize> {
+    let version = c.u32().ok_or(c.pos)?;
+    let count = c.u32().ok_or(c.pos)?;
+
+    for _ in 0..count {
+        let start = c.pos;
+        let kind = c.u32().ok_or(start)?;
+        let alias = c.utf().ok_or(start)?;
+        c.skip(8).ok_or(start)?; // creation timestamp
+
+        match kind {
+            PRIVATE_KEY_ENTRY => {
+                let key_len = c.u32().ok_or(start)? as usize;
+                c.skip(key_len).ok_or(start)?;
+
+                let chain = c.u32().ok_or(start)?;
+                for _ in 0..chain {
+                    cert(c, version, &alias, out)?;
+                }
+            },
+            TRUSTED_CERT_ENTRY => cert(c, version, &alias, out)?,
+            _ => return Err(start),
+        }
+    }
+
+    Ok(())
+}
+
+fn cert (c: &mut Cursor, version: u32, alias: &str, out: &mut Extracted) -> Result<(), usize> {
+    let start = c.pos;
+
+    if version == 2 {
+        c.utf().ok_or(start)?; // certificate type, "X.509"
+    }
+
+    let len = c.u32().ok_or(start)? as usize;
+    let offset = c.pos;
+    let der = c.take(len).ok_or(start)?;
+
+    if tag::measure(der) != Some(len) {
+        return Err(offset);
+    }
+
+    match Tag::read(&mut &der[..]) {
+        Ok(tag) => out.certificates.push(Certificate {
+            alias: Some(alias.to_string()),
+            offset,
+            tag,
+        }),
+        Err(e) => return Err(offset + e.offset),
+    }
+
+    Ok(())
+}
+
+// Scans forward from `from` for DER structures whose size matches the big endian u32
+// in front of them, which is how every certificate in a keystore is stored.
+fn resync (buf: &[u8], from: usize, out: &mut Extracted) {
+    let mut pos = from.max(4);
+
+    while pos < buf.len() {
+        let prefix = &buf[pos - 4..pos];
+        let declared = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+
+        if buf[pos] == 0x30 && tag::measure(&buf[pos..]) == Some(declared) {
+            match Tag::read(&mut &buf[pos..pos + declared]) {
+                Ok(tag) => {
+                    out.certificates.push(Certificate {
+                        alias: None,
+                        offset: pos,
+                        tag,
+                    });
+                    pos += declared;
+                    continue;
+                },
+                Err(e) => out.errors.push(e.wrap(err::Kind::UnrecognizedFormat, pos)),
+            }
+        }
+
+        pos += 1;
+    }
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take (&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let ret = self.buf.get(self.pos..end)?;
+        self.pos = end;
+        Some(ret)
+    }
+
+    fn skip (&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u32 (&mut self) -> Option<u32> {
+        let b = self.take(4)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn utf (&mut self) -> Option<String> {
+        let len = self.take(2)?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        // Java's modified UTF-8 agrees with UTF-8 for everything but NUL and
+        // supplementary characters, neither of which show up in aliases in practice
+        let s = str::from_utf8(self.take(len)?).ok()?;
+        Some(s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CERT: [u8; 7] = [0x30, 0x05, 0x0C, 0x03, 0x64, 0x65, 0x66];
+
+    fn utf (out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn trusted (out: &mut Vec<u8>, alias: &str) {
+        out.extend_from_slice(&TRUSTED_CERT_ENTRY.to_be_bytes());
+        utf(out, alias);
+        out.extend_from_slice(&[0; 8]);
+        utf(out, "X.509");
+        out.extend_from_slice(&(CERT.len() as u32).to_be_bytes());
+        out.extend_from_slice(&CERT);
+    }
+
+    #[test]
+    fn test_jks_trusted_certs () {
+        let mut ks = Vec::new();
+        ks.extend_from_slice(&JKS_MAGIC.to_be_bytes());
+        ks.extend_from_slice(&2u32.to_be_bytes());
+        ks.extend_from_slice(&2u32.to_be_bytes());
+        trusted(&mut ks, "root");
+        trusted(&mut ks, "intermediate");
+        ks.extend_from_slice(&[0; 20]);
+
+        let out = certificates(&ks).unwrap();
+        assert!(out.errors.is_empty());
+        assert_eq!(out.certificates.len(), 2);
+        assert_eq!(out.certificates[1].alias.as_deref(), Some("intermediate"));
+        assert_eq!(out.certificates[1].offset, ks.len() - 20 - CERT.len());
+    }
+
+    #[test]
+    fn test_jceks_resync_past_secret_key () {
+        let mut ks = Vec::new();
+        ks.extend_from_slice(&JCEKS_MAGIC.to_be_bytes());
+        ks.extend_from_slice(&2u32.to_be_bytes());
+        ks.extend_from_slice(&2u32.to_be_bytes());
+        // a secret key entry, whose serialized body this module can't follow
+        ks.extend_from_slice(&3u32.to_be_bytes());
+        utf(&mut ks, "secret");
+        ks.extend_from_slice(&[0; 8]);
+        ks.extend_from_slice(&[0xAC, 0xED, 0x00, 0x05, 0x73, 0x72]);
+        trusted(&mut ks, "root");
+
+        let out = certificates(&ks).unwrap();
+        assert_eq!(out.errors.len(), 1);
+        assert_eq!(out.certificates.len(), 1);
+        assert_eq!(out.certificates[0].alias, None);
+        assert_eq!(out.certificates[0].offset, ks.len() - CERT.len());
+    }
+
+    #[test]
+    fn test_not_a_keystore () {
+        assert!(certificates(&[0x30, 0x00]).is_err());
+    }
+}
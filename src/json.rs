@@ -0,0 +1,514 @@
+//! Machine-readable JSON export of tag trees.
+//!
+//! The output is an object carrying the schema version next to the root node:
+//!
+//! ```text
+//! {"ber_tree_version":1,"root":NODE}
+//! ```
+//!
+//! where each `NODE` is
+//!
+//! ```text
+//! {"class":"universal"|"application"|"context"|"private","number":N,"offset":N|null,
+//!  "constructed":false,"content":"<lowercase hex>"}
+//! {"class":...,"number":N,"offset":N|null,"constructed":true,"children":[NODE,...]}
+//! ```
+//!
+//! Nodes also carry `"length_form":"definite"|"indefinite"` when the tag has a
+//! `length_form`, and `"number_octets":N` and `"length_octets":N` when its `layout` has
+//! them. `read` turns a tree back into a `Tag`, so a hand-edited tree can be encoded, and
+//! those fields let it ask for specific header forms under `LengthPolicy::Preserve`.
+//! A node a `registry::Decoder` describes also carries `"description":"..."`, which
+//! `read` ignores.
+//!
+//! Compatibility: within one `ber_tree_version` fields are only ever added, never removed,
+//! renamed or given a different meaning, so consumers should ignore fields they don't
+//! know. Any other change bumps `TREE_VERSION`, whatever happens to `Tag` internally.
+
+use std::io::{self, Write};
+use std::mem;
+use std::slice;
+
+use crate::err;
+use crate::registry::{self, Registry};
+use crate::tag::{Class, HeaderLayout, LengthForm, Number, Payload, Tag};
+
+/// The schema version written as `ber_tree_version`.
+pub const TREE_VERSION: u32 = 1;
+
+/// Writes `tag` as a versioned JSON tree, describing tags that have a decoder in the
+/// global registry. The walk keeps its own stack, so a tree nested however deep can't
+/// overflow the real one.
+pub fn write (tag: &Tag, w: &mut dyn Write) -> io::Result<()> {
+    let registry = registry::global().read().unwrap_or_else(|e| e.into_inner());
+    write_with(tag, &registry, w)
+}
+
+/// Like `write`, but consults `registry` instead of the global registry.
+pub fn write_with (tag: &Tag, registry: &Registry, w: &mut dyn Write) -> io::Result<()> {
+    write!(w, "{{\"ber_tree_version\":{},\"root\":", TREE_VERSION)?;
+
+    // the nodes still to write at each level, and whether none has been written yet
+    let mut stack = vec![(slice::from_ref(tag).iter(), true)];
+    while let Some((siblings, first)) = stack.last_mut() {
+        let tag = match siblings.next() {
+            Some(tag) => tag,
+            None => {
+                stack.pop();
+                // the root has no array of children to close
+                if !stack.is_empty() {
+                    w.write_all(b"]}")?;
+                }
+                continue;
+            },
+        };
+        if !mem::replace(first, false) {
+            w.write_all(b",")?;
+        }
+
+        write_fields(tag, w)?;
+        if let Some(description) = registry.describe(tag) {
+            w.write_all(b",\"description\":")?;
+            write_string(&description, w)?;
+        }
+        match tag.payload {
+            Payload::Primitive(ref v) => {
+                w.write_all(b",\"constructed\":false,\"content\":\"")?;
+                for b in v {
+                    write!(w, "{:02x}", b)?;
+                }
+                w.write_all(b"\"}")?;
+            },
+            Payload::Constructed(ref children) => {
+                w.write_all(b",\"constructed\":true,\"children\":[")?;
+                stack.push((children.iter(), true));
+            },
+        }
+    }
+    w.write_all(b"}")
+}
+
+/// `write` into a string.
+pub fn to_string (tag: &Tag) -> String {
+    let mut buf = Vec::new();
+    write(tag, &mut buf).expect("writing to a Vec can't fail");
+    String::from_utf8(buf).expect("the writer only emits ASCII")
+}
+
+// Opens a node and writes every field but its content or children.
+fn write_fields (tag: &Tag, w: &mut dyn Write) -> io::Result<()> {
+    let class = match tag.number.class() {
+        Class::Universal => "universal",
+        Class::Application => "application",
+        Class::ContextSpecific => "context",
+        Class::Private => "private",
+    };
+    write!(w, "{{\"class\":\"{}\",\"number\":{},\"offset\":", class, tag.number.value())?;
+    match tag.offset {
+        Some(offset) => write!(w, "{}", offset)?,
+        None => w.write_all(b"null")?,
+    }
+    match tag.length_form {
+        Some(LengthForm::Definite) => w.write_all(b",\"length_form\":\"definite\"")?,
+        Some(LengthForm::Indefinite) => w.write_all(b",\"length_form\":\"indefinite\"")?,
+        None => {},
+    }
+    if let Some(layout) = tag.layout {
+        if let Some(n) = layout.number_octets {
+            write!(w, ",\"number_octets\":{}", n)?;
+        }
+        if let Some(n) = layout.length_octets {
+            write!(w, ",\"length_octets\":{}", n)?;
+        }
+    }
+    Ok(())
+}
+
+// Writes `s` as a JSON string, escaping everything outside printable ASCII so the
+// output stays ASCII.
+fn write_string (s: &str, w: &mut dyn Write) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_all(b"\\\"")?,
+            '\\' => w.write_all(b"\\\\")?,
+            ' '..='~' => write!(w, "{}", c)?,
+            _ => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    write!(w, "\\u{:04x}", unit)?;
+                }
+            },
+        }
+    }
+    w.write_all(b"\"")
+}
+
+/// Reads a tree written by `write`, or by hand to the same schema. Unknown fields are
+/// ignored. Malformed JSON or nodes are `Kind::UnrecognizedFormat` at the byte offset
+/// where reading stopped, and any version but `TREE_VERSION` is
+/// `Kind::UnsupportedVersion`.
+pub fn read (text: &str) -> Result<Tag, err::Error> {
+    let mut p = Parser { text: text.as_bytes(), pos: 0 };
+    let doc = p.value()?;
+    p.skip_whitespace();
+    if p.pos != p.text.len() {
+        return Err(p.error());
+    }
+
+    match doc.field("ber_tree_version") {
+        Some(&Value { kind: Json::Number(ref v), .. }) if *v == TREE_VERSION.to_string() => {},
+        Some(_) => return Err(err::Error::new(err::Kind::UnsupportedVersion, 0, None)),
+        None => return Err(err::Error::new(err::Kind::UnrecognizedFormat, 0, None)),
+    }
+    match doc.field("root") {
+        Some(root) => read_node(root),
+        None => Err(err::Error::new(err::Kind::UnrecognizedFormat, 0, None)),
+    }
+}
+
+fn read_node (node: &Value) -> Result<Tag, err::Error> {
+    let at = node.at;
+    let bad = || err::Error::new(err::Kind::UnrecognizedFormat, at, None);
+    let uint = |name: &str| -> Result<Option<u64>, err::Error> {
+        match node.field(name) {
+            None | Some(&Value { kind: Json::Null, .. }) => Ok(None),
+            Some(&Value { kind: Json::Number(ref n), .. }) => n.parse().map(Some).map_err(|_| bad()),
+            Some(_) => Err(bad()),
+        }
+    };
+
+    let class = match node.field("class").and_then(Value::as_str) {
+        Some("universal") => Class::Universal,
+        Some("application") => Class::Application,
+        Some("context") => Class::ContextSpecific,
+        Some("private") => Class::Private,
+        _ => return Err(bad()),
+    };
+    let value = uint("number")?.and_then(|n| u32::try_from(n).ok()).ok_or_else(bad)?;
+    let number = Number::from_parts(class, value)
+        .ok_or_else(|| err::Error::new(err::Kind::UnknownType, at, None))?;
+
+    let payload = match node.field("constructed") {
+        Some(&Value { kind: Json::Bool(false), .. }) => {
+            let hex = node.field("content").and_then(Value::as_str).ok_or_else(bad)?;
+            Payload::Primitive(from_hex(hex).ok_or_else(bad)?)
+        },
+        Some(&Value { kind: Json::Bool(true), .. }) => match node.field("children") {
+            Some(&Value { kind: Json::Array(ref children), .. }) =>
+                Payload::Constructed(children.iter().map(read_node).collect::<Result<_, _>>()?),
+            _ => return Err(bad()),
+        },
+        _ => return Err(bad()),
+    };
+
+    let length_form = match node.field("length_form").map(Value::as_str) {
+        None => None,
+        Some(Some("definite")) => Some(LengthForm::Definite),
+        Some(Some("indefinite")) => Some(LengthForm::Indefinite),
+        Some(_) => return Err(bad()),
+    };
+    let octets = |name| -> Result<Option<u8>, err::Error> {
+        uint(name)?.map(|n| u8::try_from(n).map_err(|_| bad())).transpose()
+    };
+    let layout = HeaderLayout { number_octets: octets("number_octets")?, length_octets: octets("length_octets")? };
+
+    let offset = uint("offset")?.map(|o| usize::try_from(o).map_err(|_| bad())).transpose()?;
+    Ok(Tag {
+        number,
+        offset,
+        payload,
+        repaired: None,
+        length_form,
+        layout: Some(layout).filter(|l| *l != HeaderLayout::default()),
+    })
+}
+
+fn from_hex (hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+// Just enough JSON for the schema. Numbers keep their text, since only a few are ever
+// looked at and those must be exact integers.
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+struct Value {
+    kind: Json,
+    // where the value starts in the text
+    at:   usize,
+}
+
+impl Value {
+    fn field (&self, name: &str) -> Option<&Value> {
+        match self.kind {
+            Json::Object(ref fields) => fields.iter().find(|f| f.0 == name).map(|f| &f.1),
+            _ => None,
+        }
+    }
+
+    fn as_str (&self) -> Option<&str> {
+        match self.kind {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// Nesting deeper than this is rejected rather than risking the stack.
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos:  usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error (&self) -> err::Error {
+        err::Error::new(err::Kind::UnrecognizedFormat, self.pos, None)
+    }
+
+    fn skip_whitespace (&mut self) {
+        while self.text.get(self.pos).is_some_and(|b| b" \t\r\n".contains(b)) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat (&mut self, b: u8) -> bool {
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect (&mut self, b: u8) -> Result<(), err::Error> {
+        if self.eat(b) { Ok(()) } else { Err(self.error()) }
+    }
+
+    fn value (&mut self) -> Result<Value, err::Error> {
+        self.value_at(0)
+    }
+
+    fn value_at (&mut self, depth: usize) -> Result<Value, err::Error> {
+        self.skip_whitespace();
+        let at = self.pos;
+        if depth > MAX_DEPTH {
+            return Err(self.error());
+        }
+
+        let kind = match self.text.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let name = self.string()?;
+                        self.expect(b':')?;
+                        fields.push((name, self.value_at(depth + 1)?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Json::Object(fields)
+            },
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value_at(depth + 1)?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Json::Array(items)
+            },
+            Some(b'"') => Json::String(self.string()?),
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let len = self.text[self.pos..].iter()
+                    .take_while(|b| b"+-.eE0123456789".contains(b))
+                    .count();
+                let n = String::from_utf8_lossy(&self.text[self.pos..self.pos + len]).into_owned();
+                self.pos += len;
+                Json::Number(n)
+            },
+            _ => {
+                let rest = &self.text[self.pos..];
+                let (kind, len) = if rest.starts_with(b"null") {
+                    (Json::Null, 4)
+                } else if rest.starts_with(b"true") {
+                    (Json::Bool(true), 4)
+                } else if rest.starts_with(b"false") {
+                    (Json::Bool(false), 5)
+                } else {
+                    return Err(self.error());
+                };
+                self.pos += len;
+                kind
+            },
+        };
+
+        Ok(Value { kind, at })
+    }
+
+    fn string (&mut self) -> Result<String, err::Error> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return Err(self.error());
+        }
+        self.pos += 1;
+
+        let mut out = Vec::new();
+        loop {
+            match *self.text.get(self.pos).ok_or_else(|| self.error())? {
+                b'"' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    let c = match *self.text.get(self.pos).ok_or_else(|| self.error())? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.escaped_char()?,
+                        _ => return Err(self.error()),
+                    };
+                    let mut buf = [0; 4];
+                    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                },
+                b => out.push(b),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+
+        String::from_utf8(out).map_err(|_| self.error())
+    }
+
+    // The character of a `\u` escape, with `pos` on the `u`; leaves `pos` on the last
+    // hex digit, of the second escape for a surrogate pair.
+    fn escaped_char (&mut self) -> Result<char, err::Error> {
+        let unit = |p: &mut Parser, start: usize| -> Result<u32, err::Error> {
+            p.text.get(start..start + 4)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .ok_or_else(|| p.error())
+        };
+
+        let high = unit(self, self.pos + 1)?;
+        self.pos += 4;
+        let c = if (0xD800..0xDC00).contains(&high) {
+            if self.text.get(self.pos + 1..self.pos + 3) != Some(b"\\u") {
+                return Err(self.error());
+            }
+            let low = unit(self, self.pos + 3)?;
+            self.pos += 6;
+            0x10000 + ((high - 0xD800) << 10) + low.wrapping_sub(0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(c).ok_or_else(|| self.error())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::err;
+    use crate::registry::Registry;
+    use crate::tag::{LengthForm, LengthPolicy, Number, Tag, TagNumber, WriteOptions};
+
+    #[test]
+    fn test_schema () {
+        let tag = Tag::sequence(vec![
+            Tag::integer(5),
+            Tag::primitive(Number::ContextSpecific(TagNumber::new(2)), vec![0xAB]),
+            Tag::set(vec![]),
+        ]);
+        assert_eq!(super::to_string(&tag), concat!(
+            r#"{"ber_tree_version":1,"root":{"class":"universal","number":16,"offset":null,"constructed":true,"children":["#,
+            r#"{"class":"universal","number":2,"offset":null,"constructed":false,"content":"05"},"#,
+            r#"{"class":"context","number":2,"offset":null,"constructed":false,"content":"ab"},"#,
+            r#"{"class":"universal","number":17,"offset":null,"constructed":true,"children":[]}"#,
+            r#"]}}"#,
+        ));
+
+        let read = Tag::read(&mut &[0x05, 0x00][..]).unwrap();
+        assert!(super::to_string(&read).contains(r#""offset":0,"#));
+    }
+
+    #[test]
+    fn test_write_deep () {
+        let depth = 200_000;
+        let tag = (1..depth).fold(Tag::null(), |tag, _| Tag::sequence(vec![tag]));
+        let json = super::to_string(&tag);
+        assert_eq!(json.matches(r#""number":16"#).count(), depth - 1);
+        assert!(json.ends_with(&format!(r#""content":""}}{}}}"#, "]}".repeat(depth - 1))));
+    }
+
+    #[test]
+    fn test_descriptions () {
+        let mut registry = Registry::new();
+        registry.register_number(Number::ContextSpecific(TagNumber::new(0)), |_: &Tag| Some("version \"é\"".to_string()));
+        let tag = Tag::sequence(vec![Tag::primitive(Number::ContextSpecific(TagNumber::new(0)), vec![0x02])]);
+
+        let mut out = Vec::new();
+        super::write_with(&tag, &registry, &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#""number":0,"offset":null,"description":"version \"\u00e9\"","constructed":false"#));
+        assert_eq!(json.matches("description").count(), 1);
+        assert_eq!(super::read(&json).unwrap(), tag);
+    }
+
+    #[test]
+    fn test_read () {
+        // a non-minimal header survives export, import and a preserving write
+        let payload = vec![0x30, 0x80, 0x9F, 0x02, 0x82, 0x00, 0x01, 0xAB, 0x31, 0x00, 0x00, 0x00];
+        let tag = Tag::read(&mut &payload[..]).unwrap();
+        let back = super::read(&super::to_string(&tag)).unwrap();
+        assert_eq!(back, tag);
+
+        let mut buf = Vec::new();
+        back.write_with(&mut buf, &WriteOptions { length_policy: LengthPolicy::Preserve, ..WriteOptions::default() }).unwrap();
+        assert_eq!(buf, payload);
+
+        // hand written, with whitespace, escapes and fields the schema doesn't have
+        let text = r#"{ "ber_tree_version": 1, "comment": "\u00e9\ud83d\ude00", "root": {
+            "class": "application", "number": 3, "constructed": true, "length_form": "definite",
+            "children": [ { "class": "universal", "number": 4, "constructed": false, "content": "CAFE", "extra": [1.5e3, null] } ]
+        } }"#;
+        let tag = super::read(text).unwrap();
+        assert_eq!(tag.number, Number::Application(TagNumber::new(3)));
+        assert_eq!(tag.length_form, Some(LengthForm::Definite));
+        assert_eq!(tag.layout, None);
+        assert_eq!(tag.at_path(&[0]).unwrap().content().unwrap(), &[0xCA, 0xFE]);
+
+        let e = super::read(r#"{"ber_tree_version":2,"root":{}}"#).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::UnsupportedVersion));
+        let e = super::read(r#"{"ber_tree_version":1,"root":{"class":"universal","number":4,"constructed":false,"content":"abc"}}"#).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::UnrecognizedFormat));
+        assert_eq!(e.offset, 29);
+        let e = super::read(r#"{"ber_tree_version":1,"root":[}"#).unwrap_err();
+        assert_eq!(e.offset, 30);
+    }
+}
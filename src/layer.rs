@@ -0,0 +1,422 @@
+//! Reader and writer middleware: small wrappers that each watch or restrict the bytes
+//! passing through, stacked in any combination with a `ReaderStack` or `WriterStack`.
+//!
+//! ```ignore
+//! let mut r = ReaderStack::new(socket).limit(64 * 1024).track().digest(|b: &[u8]| hasher.update(b));
+//! let tag = Tag::read(&mut r)?;
+//! let consumed = r.get_ref().inner().tell();
+//! ```
+//!
+//! Layers are added innermost first, so above the digest sees only what the limit lets
+//! through. Any other wrapper, such as one tracing reads, joins a stack with
+//! `ReaderStack::with`.
+//!
+//! `Tracked`, `Limited` and `Digested` work the same over writers, and a `WriterStack`
+//! can also check that what goes through is DER. Every layer keeps its state, so byte
+//! counts and digests can be read off the stack once the read or write is done.
+
+use std::io::{self, Read, Write};
+
+use crate::der::DerValidatingWriter;
+
+/// A reader wrapping another.
+pub trait ReadLayer: Read {
+    type Inner: Read;
+
+    fn inner (&self) -> &Self::Inner;
+    fn inner_mut (&mut self) -> &mut Self::Inner;
+    fn into_inner (self) -> Self::Inner;
+}
+
+/// A writer wrapping another.
+pub trait WriteLayer: Write {
+    type Inner: Write;
+
+    fn inner (&self) -> &Self::Inner;
+    fn inner_mut (&mut self) -> &mut Self::Inner;
+    fn into_inner (self) -> Self::Inner;
+}
+
+/// Counts the bytes read or written through it. Interrupted reads are retried rather
+/// than passed on.
+pub struct Tracked<R> {
+    inner:      R,
+    read_bytes: usize,
+}
+
+impl<R> Tracked<R> {
+    pub fn new (inner: R) -> Tracked<R> {
+        Tracked {
+            inner,
+            read_bytes: 0,
+        }
+    }
+
+    /// Like `new`, but counting from `position` instead of zero, for an inner stream
+    /// that starts partway into something larger.
+    pub fn starting_at (inner: R, position: usize) -> Tracked<R> {
+        Tracked {
+            inner,
+            read_bytes: position,
+        }
+    }
+
+    /// The number of bytes read or written so far, plus any starting position.
+    pub fn tell (&self) -> usize {
+        self.read_bytes
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Ok(count) => {
+                    self.read_bytes += count;
+                    return Ok(count);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Tracked<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.read_bytes += count;
+        Ok(count)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fails any read or write past a fixed number of bytes. Unlike `Read::take`, running
+/// into the limit is an error rather than an end of input, so an oversized message can't
+/// pass for a truncated one.
+pub struct Limited<R> {
+    inner:     R,
+    limit:     usize,
+    remaining: usize,
+}
+
+impl<R> Limited<R> {
+    pub fn new (inner: R, limit: usize) -> Limited<R> {
+        Limited {
+            inner,
+            limit,
+            remaining: limit,
+        }
+    }
+
+    /// The number of bytes that can still be read.
+    pub fn remaining (&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let max = self.allowance(buf.len())?;
+        let count = self.inner.read(&mut buf[..max])?;
+        self.remaining -= count;
+        Ok(count)
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let max = self.allowance(buf.len())?;
+        let count = self.inner.write(&buf[..max])?;
+        self.remaining -= count;
+        Ok(count)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R> Limited<R> {
+    // How much of a `len` byte transfer may go ahead.
+    fn allowance (&self, len: usize) -> io::Result<usize> {
+        if self.remaining == 0 {
+            let msg = format!("limit of {} bytes exceeded", self.limit);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        Ok(len.min(self.remaining))
+    }
+}
+
+/// Anything that can be fed the bytes passing through a `Digested` layer: a hash, a
+/// checksum, a copy. Closures taking `&[u8]` are digests.
+pub trait Digest {
+    fn update (&mut self, data: &[u8]);
+}
+
+impl<F: FnMut(&[u8])> Digest for F {
+    fn update (&mut self, data: &[u8]) {
+        self(data)
+    }
+}
+
+/// Feeds every byte read or written through it to a `Digest`.
+pub struct Digested<R, D> {
+    inner:  R,
+    digest: D,
+}
+
+impl<R, D: Digest> Digested<R, D> {
+    pub fn new (inner: R, digest: D) -> Digested<R, D> {
+        Digested {
+            inner,
+            digest,
+        }
+    }
+
+    pub fn digest (&self) -> &D {
+        &self.digest
+    }
+
+    /// The reader and the digest, for finishing a hash.
+    pub fn into_parts (self) -> (R, D) {
+        (self.inner, self.digest)
+    }
+}
+
+impl<R: Read, D: Digest> Read for Digested<R, D> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.digest.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+impl<W: Write, D: Digest> Write for Digested<W, D> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.digest.update(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+macro_rules! layer {
+    ($t:ident<R $(, $p:ident: $b:ident)*>) => {
+        layer!(ReadLayer, Read, $t<R $(, $p: $b)*>);
+        layer!(WriteLayer, Write, $t<R $(, $p: $b)*>);
+    };
+    ($layer:ident, $io:ident, $t:ident<R $(, $p:ident: $b:ident)*>) => {
+        impl<R: $io $(, $p: $b)*> $layer for $t<R $(, $p)*> {
+            type Inner = R;
+
+            fn inner (&self) -> &R {
+                &self.inner
+            }
+
+            fn inner_mut (&mut self) -> &mut R {
+                &mut self.inner
+            }
+
+            fn into_inner (self) -> R {
+                self.inner
+            }
+        }
+    };
+}
+
+layer!(Tracked<R>);
+layer!(Limited<R>);
+layer!(Digested<R, D: Digest>);
+
+/// Builds a stack of layers over a reader, and reads through all of them.
+pub struct ReaderStack<R> {
+    top: R,
+}
+
+impl<R: Read> ReaderStack<R> {
+    pub fn new (r: R) -> ReaderStack<R> {
+        ReaderStack { top: r }
+    }
+
+    /// Adds any layer on top, e.g. one tracing reads.
+    pub fn with<L: Read, F: FnOnce(R) -> L> (self, layer: F) -> ReaderStack<L> {
+        ReaderStack { top: layer(self.top) }
+    }
+
+    /// Adds a `Limited` layer.
+    pub fn limit (self, limit: usize) -> ReaderStack<Limited<R>> {
+        self.with(|r| Limited::new(r, limit))
+    }
+
+    /// Adds a `Tracked` layer.
+    pub fn track (self) -> ReaderStack<Tracked<R>> {
+        self.with(Tracked::new)
+    }
+
+    /// Adds a `Digested` layer.
+    pub fn digest<D: Digest> (self, digest: D) -> ReaderStack<Digested<R, D>> {
+        self.with(|r| Digested::new(r, digest))
+    }
+
+    /// The top layer.
+    pub fn get_ref (&self) -> &R {
+        &self.top
+    }
+
+    pub fn get_mut (&mut self) -> &mut R {
+        &mut self.top
+    }
+
+    pub fn into_inner (self) -> R {
+        self.top
+    }
+}
+
+impl<R: Read> Read for ReaderStack<R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.top.read(buf)
+    }
+}
+
+/// Builds a stack of layers over a writer, and writes through all of them.
+pub struct WriterStack<W> {
+    top: W,
+}
+
+impl<W: Write> WriterStack<W> {
+    pub fn new (w: W) -> WriterStack<W> {
+        WriterStack { top: w }
+    }
+
+    /// Adds any layer on top.
+    pub fn with<L: Write, F: FnOnce(W) -> L> (self, layer: F) -> WriterStack<L> {
+        WriterStack { top: layer(self.top) }
+    }
+
+    /// Adds a `Limited` layer.
+    pub fn limit (self, limit: usize) -> WriterStack<Limited<W>> {
+        self.with(|w| Limited::new(w, limit))
+    }
+
+    /// Adds a `Tracked` layer.
+    pub fn track (self) -> WriterStack<Tracked<W>> {
+        self.with(Tracked::new)
+    }
+
+    /// Adds a `Digested` layer.
+    pub fn digest<D: Digest> (self, digest: D) -> WriterStack<Digested<W, D>> {
+        self.with(|w| Digested::new(w, digest))
+    }
+
+    /// Adds a `DerValidatingWriter` layer, failing any write that isn't DER. Call its
+    /// `finish` to check the output also ended on an element boundary.
+    pub fn validate (self) -> WriterStack<DerValidatingWriter<W>> {
+        self.with(DerValidatingWriter::new)
+    }
+
+    /// The top layer.
+    pub fn get_ref (&self) -> &W {
+        &self.top
+    }
+
+    pub fn get_mut (&mut self) -> &mut W {
+        &mut self.top
+    }
+
+    pub fn into_inner (self) -> W {
+        self.top
+    }
+}
+
+impl<W: Write> Write for WriterStack<W> {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.top.write(buf)
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.top.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::err;
+    use crate::tag::Tag;
+
+    // SEQUENCE { NULL, OCTET STRING "abc" }, then a trailing NULL
+    const INPUT: [u8; 11] = [0x30, 0x07, 0x05, 0x00, 0x04, 0x03, 0x61, 0x62, 0x63, 0x05, 0x00];
+
+    #[test]
+    fn test_stack () {
+        let mut seen = Vec::new();
+        let mut r = ReaderStack::new(&INPUT[..])
+            .limit(9)
+            .track()
+            .digest(|b: &[u8]| seen.extend_from_slice(b));
+
+        let tag = Tag::read(&mut r).unwrap();
+        assert_eq!(tag, Tag::read(&mut &INPUT[..]).unwrap());
+        assert_eq!(r.get_ref().inner().tell(), 9);
+        assert_eq!(r.get_ref().inner().inner().remaining(), 0);
+
+        // the limit is an error, not the end of the input
+        let e = Tag::read(&mut r).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Io(ref io) if io.kind() == io::ErrorKind::InvalidData), "{}", e);
+
+        assert_eq!(seen, &INPUT[..9]);
+    }
+
+    #[test]
+    fn test_custom_layer () {
+        struct Upper<R>(R);
+        impl<R: Read> Read for Upper<R> {
+            fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = self.0.read(buf)?;
+                buf[..n].make_ascii_uppercase();
+                Ok(n)
+            }
+        }
+
+        let mut out = String::new();
+        ReaderStack::new(&b"abc"[..]).with(Upper).track().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "ABC");
+    }
+
+    #[test]
+    fn test_writer_stack () {
+        let tag = Tag::read(&mut &INPUT[..9]).unwrap();
+        let mut sum = 0u32;
+        let mut w = WriterStack::new(Vec::new())
+            .limit(9)
+            .digest(|b: &[u8]| sum += b.iter().map(|&x| x as u32).sum::<u32>())
+            .track()
+            .validate();
+
+        tag.write_der(&mut w).unwrap();
+        assert_eq!(w.get_ref().get_ref().tell(), 9);
+        let out = w.into_inner().finish().unwrap().into_inner().into_parts().0.into_inner();
+        assert_eq!(out, &INPUT[..9]);
+        assert_eq!(sum, INPUT[..9].iter().map(|&x| x as u32).sum::<u32>());
+
+        // BER is refused before it reaches the writer, and so is anything over the limit
+        let mut w = WriterStack::new(Vec::new()).track().validate();
+        assert!(w.write_all(&[0x30, 0x80]).is_err());
+        assert_eq!(w.get_ref().get_ref().tell(), 0);
+        let mut w = WriterStack::new(Vec::new()).limit(8);
+        assert!(tag.write_der(&mut w).is_err());
+    }
+}
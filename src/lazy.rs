@@ -0,0 +1,229 @@
+//! Structure-only decoding over seekable input.
+//!
+//! `LazyTag::read` walks the headers of a message and seeks over primitive content,
+//! recording where it lies instead of reading it. Tools that only look at the shape of
+//! a message never pay for its megabytes of content; `LazyTag::load` fetches one
+//! primitive's content when it is actually wanted. Offsets are positions in the stream,
+//! not relative to where the read began.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::err;
+use crate::layer::Tracked;
+use crate::tag::{self, Flavor, HeaderLayout, Length, LengthForm, Number, Payload, Tag, Type};
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum LazyPayload {
+    /// Content not yet read: `length` bytes at `offset`.
+    Primitive { offset: u64, length: u64 },
+    Constructed(Vec<LazyTag>),
+}
+
+/// A tag whose primitive content stays in the stream until loaded.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LazyTag {
+    pub number:      Number,
+    pub offset:      u64,
+    pub payload:     LazyPayload,
+    /// As `Tag::length_form`.
+    pub length_form: Option<LengthForm>,
+    /// As `Tag::layout`.
+    pub layout:      Option<HeaderLayout>,
+}
+
+impl LazyTag {
+    /// Reads the structure of one tag starting at the current position, leaving `r`
+    /// just after it. Content that would run past the end of the stream is
+    /// `Kind::Truncated`, as with `Tag::read`, though it is never read, and nesting
+    /// deeper than `DEFAULT_MAX_DEPTH` is `Kind::TooDeep`.
+    pub fn read<R: Read + Seek> (r: &mut R) -> Result<LazyTag, err::Error> {
+        let start = r.stream_position()?;
+        let end = r.seek(SeekFrom::End(0))?;
+        r.seek(SeekFrom::Start(start))?;
+
+        let mut p = Parser { r, pos: start, end };
+        p.tag(0).map_err(|mut e| {
+            if matches!(e.kind, err::Kind::Truncated { .. }) && start == end {
+                e.kind = err::Kind::CleanEof;
+            }
+            e
+        })
+    }
+
+    /// Reads the content of a primitive tag from `r`, which must be the stream this
+    /// tag was read from. Constructed tags have no content of their own and give
+    /// `Kind::NotPrimitive`.
+    pub fn load<R: Read + Seek> (&self, r: &mut R) -> Result<Vec<u8>, err::Error> {
+        match self.payload {
+            LazyPayload::Primitive { offset, length } => {
+                r.seek(SeekFrom::Start(offset))?;
+                let mut buf = Vec::new();
+                r.by_ref().take(length).read_to_end(&mut buf)?;
+                if (buf.len() as u64) < length {
+                    let needed = usize::try_from(length - buf.len() as u64).unwrap_or(usize::MAX);
+                    return Err(err::Error::new(err::Kind::Truncated { needed }, offset as usize + buf.len(), None));
+                }
+                Ok(buf)
+            },
+            LazyPayload::Constructed(_) => Err(err::Error::new(err::Kind::NotPrimitive, self.offset as usize, None)),
+        }
+    }
+
+    /// Loads every primitive's content into an ordinary tree. Its offsets are stream
+    /// positions like this tree's.
+    pub fn to_tag<R: Read + Seek> (&self, r: &mut R) -> Result<Tag, err::Error> {
+        let payload = match self.payload {
+            LazyPayload::Primitive { .. } => Payload::Primitive(self.load(r)?),
+            LazyPayload::Constructed(ref children) =>
+                Payload::Constructed(children.iter().map(|c| c.to_tag(r)).collect::<Result<_, _>>()?),
+        };
+        Ok(Tag {
+            number: self.number,
+            offset: Some(self.offset as usize),
+            payload,
+            repaired: None,
+            length_form: self.length_form,
+            layout: self.layout,
+        })
+    }
+}
+
+struct Parser<'a, R> {
+    r:   &'a mut R,
+    pos: u64,
+    end: u64,
+}
+
+impl<'a, R: Read + Seek> Parser<'a, R> {
+    // `depth` counts the tags this one is inside.
+    fn tag (&mut self, depth: usize) -> Result<LazyTag, err::Error> {
+        let offset = self.pos;
+        if depth >= tag::DEFAULT_MAX_DEPTH {
+            return Err(err::Error::new(err::Kind::TooDeep { limit: tag::DEFAULT_MAX_DEPTH }, offset as usize, None));
+        }
+
+        let mut header = Tracked::new(&mut *self.r);
+        let identifiers = tag::read_identifiers(&mut header);
+        let number_octets = header.tell();
+        let length = identifiers.and_then(|x| tag::read_length(&mut header).map(|l| (x, l)));
+        self.pos += header.tell() as u64;
+        let ((_class, flavor, number), length) = length.map_err(|mut e| {
+            e.offset = self.pos as usize;
+            tag::truncation(e)
+        })?;
+        let layout = HeaderLayout::observed(&number, &length, number_octets - 1, (self.pos - offset) as usize - number_octets - 1);
+
+        let payload = match (flavor, length) {
+            (Flavor::Primitive, Length::Indefinite) =>
+                return Err(err::Error::new(err::Kind::InvalidLength, self.pos as usize, None)),
+            (Flavor::Primitive, Length::Some(l)) => {
+                let available = self.end.saturating_sub(self.pos);
+                if l > available {
+                    let needed = usize::try_from(l - available).unwrap_or(usize::MAX);
+                    return Err(err::Error::new(err::Kind::Truncated { needed }, self.end as usize, None));
+                }
+                let content = LazyPayload::Primitive { offset: self.pos, length: l };
+                self.pos = self.r.seek(SeekFrom::Current(l as i64))?;
+                content
+            },
+            (Flavor::Constructed, Length::Some(l)) => {
+                let limit = self.pos.saturating_add(l);
+                let mut children = Vec::new();
+                while self.pos < limit {
+                    let child = self.tag(depth + 1)?;
+                    if child.number == Number::Universal(Type::Eoc) {
+                        return Err(err::Error::new(err::Kind::UnexpectedEoc, self.pos as usize, None));
+                    }
+                    children.push(child);
+                    if self.pos > limit {
+                        // the last child ran past the end of this tag
+                        return Err(err::Error::new(err::Kind::LengthExceedsContainer, self.pos as usize, None));
+                    }
+                }
+                LazyPayload::Constructed(children)
+            },
+            (Flavor::Constructed, Length::Indefinite) => {
+                let mut children = Vec::new();
+                loop {
+                    let child = self.tag(depth + 1)?;
+                    if child.number == Number::Universal(Type::Eoc) {
+                        break;
+                    }
+                    children.push(child);
+                }
+                LazyPayload::Constructed(children)
+            },
+        };
+
+        let length_form = match (flavor, length) {
+            (Flavor::Primitive, _) => None,
+            (Flavor::Constructed, Length::Indefinite) => Some(LengthForm::Indefinite),
+            (Flavor::Constructed, Length::Some(_)) => Some(LengthForm::Definite),
+        };
+
+        Ok(LazyTag { number, offset, payload, length_form, layout })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    // a NULL to skip, then SEQUENCE (indefinite) { [1] { INTEGER 5 }, OCTET STRING "abc" }
+    const INPUT: [u8; 17] = [
+        0x05, 0x00,
+        0x30, 0x80,
+            0xA1, 0x03, 0x02, 0x01, 0x05,
+            0x04, 0x81, 0x03, 0x61, 0x62, 0x63,
+        0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_read_and_load () {
+        let mut r = Cursor::new(INPUT.to_vec());
+        r.set_position(2);
+        let tag = LazyTag::read(&mut r).unwrap();
+        assert_eq!(r.position(), 17);
+        assert!(matches!(LazyTag::read(&mut r).unwrap_err().kind, err::Kind::CleanEof));
+
+        let octets = match tag.payload {
+            LazyPayload::Constructed(ref children) => &children[1],
+            _ => panic!("not constructed"),
+        };
+        assert_eq!(octets.payload, LazyPayload::Primitive { offset: 12, length: 3 });
+        assert_eq!(octets.load(&mut r).unwrap(), b"abc");
+        assert!(matches!(tag.load(&mut r).unwrap_err().kind, err::Kind::NotPrimitive));
+
+        // read from the start of a stream, the full tree is what `Tag::read` gives
+        let mut r = Cursor::new(&INPUT[2..]);
+        let tag = LazyTag::read(&mut r).unwrap();
+        assert_eq!(tag.to_tag(&mut r).unwrap(), Tag::read(&mut &INPUT[2..]).unwrap());
+    }
+
+    #[test]
+    fn test_errors_match_tag () {
+        let inputs: [&[u8]; 5] = [
+            &INPUT[2..13],
+            &[0x04, 0x80],
+            &[0x30, 0x02, 0x04, 0x02, 0x00, 0x00],
+            &[0x30, 0x02, 0x00, 0x00],
+            &[0x1F, 0x01],
+        ];
+        for input in inputs {
+            let ours = LazyTag::read(&mut Cursor::new(input)).unwrap_err();
+            let theirs = Tag::read(&mut &input[..]).unwrap_err();
+            assert_eq!((ours.code(), ours.offset), (theirs.code(), theirs.offset), "{:02x?}", input);
+        }
+    }
+
+    #[test]
+    fn test_deep_nesting () {
+        let input = [0x30, 0x80].repeat(300_000);
+        let ours = LazyTag::read(&mut Cursor::new(&input[..])).unwrap_err();
+        let theirs = Tag::read(&mut &input[..]).unwrap_err();
+        assert!(matches!(ours.kind, err::Kind::TooDeep { .. }), "{}", ours);
+        assert_eq!((ours.code(), ours.offset), (theirs.code(), theirs.offset));
+    }
+}
@@ -1,8 +1,94 @@
 extern crate byteorder;
 
+pub mod bitstring;
+pub mod borrowed;
+#[cfg(feature = "tui")]
+pub mod browse;
+pub mod carve;
+pub mod container;
+pub mod datagram;
+pub mod decoder;
+pub mod der;
+pub mod diff;
+#[cfg(feature = "digest")]
+pub mod digest;
+pub mod document;
+pub mod encoder;
+pub mod dump;
+pub mod edit;
 pub mod err;
+pub mod fixed;
+pub mod format;
+pub mod framing;
+pub mod header;
+pub mod ingest;
+pub mod json;
+pub mod layer;
+pub mod lazy;
+mod integer;
+pub mod intern;
+#[cfg(feature = "jks")]
+pub mod jks;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+pub mod normalize;
+pub mod oid;
+pub mod parser;
+pub mod pem;
+mod real;
+pub mod recover;
+pub mod registry;
+pub mod report;
+pub mod repr;
+pub mod retag;
+pub mod smime;
+pub mod stats;
+pub mod stream;
+mod string;
 pub mod tag;
+pub mod template;
+pub mod testing;
+#[cfg(feature = "chrono")]
+pub mod time;
 pub mod util;
+mod value;
+pub mod visit;
 
+pub use bitstring::BitString;
+pub use borrowed::TagRef;
+pub use carve::carve;
+pub use der::check_der;
 pub use err::Error;
-pub use tag::{Tag, Number, Payload};
+pub use oid::{Oid, RelativeOid};
+pub use tag::{EmptyValues, Tag, TagNumber, Number, Payload, ReadOptions, Rules, WriteOptions};
+
+/// Compile-time flags for each optional feature, so code can branch on what this build
+/// of the crate includes without repeating the feature names in its own `cfg`s.
+pub mod feature {
+    pub const CHRONO: bool = cfg!(feature = "chrono");
+    pub const DIGEST: bool = cfg!(feature = "digest");
+    pub const JKS: bool = cfg!(feature = "jks");
+    pub const MMAP: bool = cfg!(feature = "mmap");
+    pub const TOKIO: bool = cfg!(feature = "tokio");
+    pub const TUI: bool = cfg!(feature = "tui");
+}
+
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "chrono")]
+    "chrono",
+    #[cfg(feature = "digest")]
+    "digest",
+    #[cfg(feature = "jks")]
+    "jks",
+    #[cfg(feature = "mmap")]
+    "mmap",
+    #[cfg(feature = "tokio")]
+    "tokio",
+    #[cfg(feature = "tui")]
+    "tui",
+];
+
+/// Names of the optional features this build of the crate was compiled with.
+pub fn features () -> &'static [&'static str] {
+    FEATURES
+}
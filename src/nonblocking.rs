@@ -0,0 +1,346 @@
+//! Decoding from a tokio `AsyncRead` and encoding to an `AsyncWrite`, behind the `tokio`
+//! feature.
+//!
+//! `EventReader` drives a `decoder::Decoder` from an async reader, and `Tag::read_async`
+//! builds a tree from its events, so a server awaits each message as its bytes arrive
+//! instead of buffering it whole first. Neither reads past the end of a message, which
+//! means a message at a time is read with one header byte per read call: wrap unbuffered
+//! readers such as sockets in a `tokio::io::BufReader`.
+//!
+//! Going the other way, `Tag::write_async` streams a tree out, and an `Encoder` streams
+//! a message that never exists as a tree at all, opening and closing indefinite length
+//! constructed tags around content as the caller produces it.
+
+use std::collections::VecDeque;
+use std::io;
+use std::slice;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::decoder::{Decoder, Event};
+use crate::encoder;
+use crate::err;
+use crate::tag::{self, Flavor, HeaderLayout, Length, LengthForm, Number, Payload, ReadOptions, Tag, WriteOptions};
+
+// The most content read per call, so a long primitive is passed on in pieces.
+const CHUNK: usize = 8 * 1024;
+
+/// The events of the messages read from an async reader.
+pub struct EventReader<R> {
+    reader:  R,
+    decoder: Decoder,
+    pending: VecDeque<Event>,
+    buf:     Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> EventReader<R> {
+    pub fn new (reader: R) -> EventReader<R> {
+        EventReader {
+            reader,
+            decoder: Decoder::new(),
+            pending: VecDeque::new(),
+            buf: vec![0; CHUNK],
+        }
+    }
+
+    /// Stream offset of the next byte to be read.
+    pub fn offset (&self) -> usize {
+        self.decoder.offset()
+    }
+
+    /// The next event, or `None` once the reader ends between messages. Ending anywhere
+    /// else is a `Kind::Truncated` error.
+    pub async fn next (&mut self) -> Result<Option<Event>, err::Error> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+
+            let wanted = self.decoder.wanted().min(CHUNK);
+            let n = self.reader.read(&mut self.buf[..wanted]).await
+                .map_err(|e| err::Error::new(err::Kind::Io(e), self.decoder.offset(), None))?;
+            if n == 0 {
+                return std::mem::take(&mut self.decoder).finish().map(|_| None);
+            }
+            self.pending.extend(self.decoder.feed(&self.buf[..n])?);
+        }
+    }
+
+    pub fn into_inner (self) -> R {
+        self.reader
+    }
+}
+
+impl Tag {
+    /// Reads one message from `r` like `Tag::read`, without blocking while waiting for
+    /// its bytes. Nothing after the message is consumed.
+    pub async fn read_async<R: AsyncRead + Unpin> (r: &mut R) -> Result<Tag, err::Error> {
+        let opts = ReadOptions::default();
+        let mut events = EventReader::new(r);
+        let mut stack: Vec<Tag> = Vec::new();
+
+        loop {
+            let event = match events.next().await? {
+                Some(event) => event,
+                None => return Err(err::Error::new(err::Kind::CleanEof, events.offset(), None)),
+            };
+
+            match event {
+                Event::Start { offset, number, constructed, length, layout } => {
+                    let (payload, length_form) = if constructed {
+                        let form = if length.is_some() { LengthForm::Definite } else { LengthForm::Indefinite };
+                        (Payload::Constructed(Vec::new()), Some(form))
+                    } else {
+                        // as with `Tag::read`, the declared length only earns so much up front
+                        let prealloc = length.unwrap_or(0).min(opts.prealloc_cap);
+                        (Payload::Primitive(Vec::with_capacity(prealloc)), None)
+                    };
+                    stack.push(Tag { number, offset: Some(offset), payload, repaired: None, length_form, layout });
+                },
+                Event::Content(bytes) => {
+                    if let Some(Tag { payload: Payload::Primitive(ref mut v), .. }) = stack.last_mut() {
+                        v.extend_from_slice(&bytes);
+                    }
+                },
+                Event::End { .. } => {
+                    let tag = stack.pop().expect("the decoder ends only tags it started");
+                    match stack.last_mut() {
+                        Some(Tag { payload: Payload::Constructed(ref mut children), .. }) => children.push(tag),
+                        _ => return Ok(tag),
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Tag {
+    /// Writes the tag like `Tag::write`, without blocking while `w` catches up.
+    pub async fn write_async<W: AsyncWrite + Unpin> (&self, w: &mut W) -> io::Result<()> {
+        self.write_async_with(w, &WriteOptions::default()).await
+    }
+
+    /// Writes the tag like `Tag::write_with`, a header or primitive at a time. The tree
+    /// is measured before anything is written, so a definite length costs no buffering
+    /// and a tree deeper than `opts.max_depth` is rejected up front.
+    pub async fn write_async_with<W: AsyncWrite + Unpin> (&self, w: &mut W, opts: &WriteOptions) -> io::Result<()> {
+        let mut sizes = Vec::new();
+        encoder::measure(self, opts, &mut sizes)?;
+
+        // the siblings still to write at each level, and whether the tag holding them
+        // is closed by an end-of-contents marker
+        let mut stack = vec![(false, slice::from_ref(self).iter())];
+        let mut header = Vec::new();
+        let mut next = 0;
+
+        while let Some((indefinite, siblings)) = stack.last_mut() {
+            let tag = match siblings.next() {
+                Some(tag) => tag,
+                None => {
+                    let indefinite = *indefinite;
+                    stack.pop();
+                    if indefinite {
+                        w.write_all(&[0x00, 0x00]).await?;
+                    }
+                    continue;
+                },
+            };
+
+            header.clear();
+            encoder::header(&mut header, tag, opts, sizes[next])?;
+            next += 1;
+            w.write_all(&header).await?;
+            match tag.payload {
+                Payload::Primitive(ref v) => w.write_all(v).await?,
+                Payload::Constructed(ref children) =>
+                    stack.push((tag.header_form(opts).0 == LengthForm::Indefinite, children.iter())),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a message to an async writer piece by piece, as the caller produces it.
+/// Constructed tags opened with `start` have indefinite lengths, so nothing needs to be
+/// known about their content up front.
+pub struct Encoder<W> {
+    writer: W,
+    opts:   WriteOptions,
+    depth:  usize,
+}
+
+impl<W: AsyncWrite + Unpin> Encoder<W> {
+    pub fn new (writer: W) -> Encoder<W> {
+        Encoder::with_options(writer, WriteOptions::default())
+    }
+
+    /// `opts` applies to whole tags written with `tag`, and its `max_depth` to `start`
+    /// as well.
+    pub fn with_options (writer: W, opts: WriteOptions) -> Encoder<W> {
+        Encoder {
+            writer,
+            opts,
+            depth: 0,
+        }
+    }
+
+    /// The number of constructed tags started and not yet ended.
+    pub fn depth (&self) -> usize {
+        self.depth
+    }
+
+    /// Opens an indefinite length constructed tag, unless that would nest deeper than
+    /// the options' `max_depth`.
+    pub async fn start (&mut self, number: Number) -> io::Result<()> {
+        if self.depth >= self.opts.max_depth {
+            return Err(encoder::too_deep(&self.opts));
+        }
+        let mut header = Vec::new();
+        tag::write_header(&mut header, &number.class(), &Flavor::Constructed, &number, &Length::Indefinite, &HeaderLayout::default())?;
+        self.writer.write_all(&header).await?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Writes a primitive tag holding `content`.
+    pub async fn primitive (&mut self, number: Number, content: &[u8]) -> io::Result<()> {
+        let mut header = Vec::new();
+        tag::write_header(&mut header, &number.class(), &Flavor::Primitive, &number, &Length::Some(content.len() as u64), &HeaderLayout::default())?;
+        self.writer.write_all(&header).await?;
+        self.writer.write_all(content).await
+    }
+
+    /// Writes a whole tag.
+    pub async fn tag (&mut self, tag: &Tag) -> io::Result<()> {
+        tag.write_async_with(&mut self.writer, &self.opts).await
+    }
+
+    /// Closes the constructed tag started most recently.
+    pub async fn end (&mut self) -> io::Result<()> {
+        if self.depth == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no constructed tag to end"));
+        }
+        self.writer.write_all(&[0x00, 0x00]).await?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// Flushes the writer and returns it, once every constructed tag has been ended.
+    pub async fn finish (mut self) -> io::Result<W> {
+        if self.depth > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "constructed tags left open"));
+        }
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::future::Future;
+
+    use crate::tag::{LengthPolicy, Type};
+
+    fn block_on<F: Future> (f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(f)
+    }
+
+    const MESSAGES: [u8; 16] = [
+        // SEQUENCE (indefinite) { [1] { INTEGER 5 }, OCTET STRING "abc" }
+        0x30, 0x80,
+            0xA1, 0x03, 0x02, 0x01, 0x05,
+            0x04, 0x03, 0x61, 0x62, 0x63,
+        0x00, 0x00,
+        // NULL
+        0x05, 0x00,
+    ];
+
+    #[test]
+    fn test_read_async () {
+        block_on(async {
+            // bytes trickle in a few at a time from the other end of a pipe
+            let (mut tx, mut rx) = tokio::io::duplex(3);
+            tokio::spawn(async move {
+                tx.write_all(&MESSAGES).await.unwrap();
+            });
+            let first = Tag::read_async(&mut rx).await.unwrap();
+            let second = Tag::read_async(&mut rx).await.unwrap();
+            let end = Tag::read_async(&mut rx).await.unwrap_err();
+
+            let mut r = &MESSAGES[..];
+            assert_eq!(first, Tag::read(&mut r).unwrap());
+            assert_eq!(second, Tag::read(&mut &r[..]).unwrap());
+            assert!(matches!(end.kind, err::Kind::CleanEof));
+        });
+    }
+
+    #[test]
+    fn test_truncated () {
+        block_on(async {
+            let mut r = &MESSAGES[..9];
+            let e = Tag::read_async(&mut r).await.unwrap_err();
+            assert!(matches!(e.kind, err::Kind::Truncated { .. }));
+
+            let mut events = EventReader::new(&MESSAGES[14..]);
+            assert!(matches!(events.next().await, Ok(Some(Event::Start { .. }))));
+            assert!(matches!(events.next().await, Ok(Some(Event::End { offset: 2 }))));
+            assert!(matches!(events.next().await, Ok(None)));
+        });
+    }
+
+    #[test]
+    fn test_write_async () {
+        block_on(async {
+            let tag = Tag::read(&mut &MESSAGES[..]).unwrap();
+
+            for policy in [LengthPolicy::Always(LengthForm::Indefinite), LengthPolicy::Always(LengthForm::Definite), LengthPolicy::Preserve] {
+                let opts = WriteOptions { length_policy: policy, ..WriteOptions::default() };
+                let mut expected = Vec::new();
+                tag.write_with(&mut expected, &opts).unwrap();
+
+                let mut out = Vec::new();
+                tag.write_async_with(&mut out, &opts).await.unwrap();
+                assert_eq!(out, expected, "{:?}", policy);
+            }
+        });
+    }
+
+    #[test]
+    fn test_encoder () {
+        block_on(async {
+            let mut encoder = Encoder::new(Vec::new());
+            encoder.start(Number::Universal(Type::Sequence)).await.unwrap();
+            encoder.tag(&Tag::read(&mut &MESSAGES[2..7]).unwrap()).await.unwrap();
+            encoder.primitive(Number::Universal(Type::OctetString), b"abc").await.unwrap();
+            assert_eq!(encoder.depth(), 1);
+            assert!(Encoder::new(Vec::new()).end().await.is_err());
+            encoder.end().await.unwrap();
+            encoder.primitive(Number::Universal(Type::Null), &[]).await.unwrap();
+
+            // [1] was read with a definite length, and the default options rewrite it
+            let mut expected = Vec::new();
+            Tag::read(&mut &MESSAGES[..]).unwrap().write(&mut expected).unwrap();
+            expected.extend_from_slice(&[0x05, 0x00]);
+            assert_eq!(encoder.finish().await.unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn test_max_depth () {
+        block_on(async {
+            let opts = WriteOptions { max_depth: 1, ..WriteOptions::default() };
+            let tag = Tag::sequence(vec![Tag::sequence(vec![])]);
+            let mut out = Vec::new();
+            let e = tag.write_async_with(&mut out, &opts).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert!(out.is_empty());
+
+            let mut encoder = Encoder::with_options(Vec::new(), opts);
+            encoder.start(Number::Universal(Type::Sequence)).await.unwrap();
+            assert!(encoder.start(Number::Universal(Type::Sequence)).await.is_err());
+            assert_eq!(encoder.depth(), 1);
+        });
+    }
+}
@@ -0,0 +1,148 @@
+//! Converting an archive of BER files to a canonical encoding in one pass, for moving
+//! legacy artifacts into storage that expects DER.
+//!
+//! `normalize_dir` reads every file under a directory with a `Profile`'s read options,
+//! writes it again with the profile's writer to the same relative path under another
+//! directory, and sums up what happened to each file. Files that don't read are left
+//! out of the output and reported instead of stopping the batch.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::tag::Tag;
+use crate::testing::Profile;
+use crate::util;
+
+/// What became of one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Re-encoded, from `before` bytes to `after`.
+    Converted { before: usize, after: usize },
+    /// Already in the profile's encoding, and copied as it was.
+    Unchanged,
+    /// Couldn't be read or written; nothing was written for it.
+    Failed(String),
+}
+
+/// A file and what became of it.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Path relative to the input directory, and to the output directory.
+    pub path:    PathBuf,
+    pub outcome: Outcome,
+}
+
+/// How far a batch has got, handed to the progress callback after each file.
+pub struct Progress<'a> {
+    pub done:  usize,
+    pub total: usize,
+    pub entry: &'a Entry,
+}
+
+/// Every file of a batch, in the order they were handled.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub entries: Vec<Entry>,
+}
+
+impl Summary {
+    pub fn converted (&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Converted { .. }))
+    }
+
+    pub fn unchanged (&self) -> usize {
+        self.count(|o| *o == Outcome::Unchanged)
+    }
+
+    pub fn failed (&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Failed(_)))
+    }
+
+    fn count (&self, f: impl Fn(&Outcome) -> bool) -> usize {
+        self.entries.iter().filter(|e| f(&e.outcome)).count()
+    }
+}
+
+impl fmt::Display for Summary {
+    /// One line per failure, then the totals.
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            if let Outcome::Failed(ref e) = entry.outcome {
+                writeln!(f, "{}: {}", entry.path.display(), e)?;
+            }
+        }
+        write!(f, "{} files: {} converted, {} unchanged, {} failed",
+            self.entries.len(), self.converted(), self.unchanged(), self.failed())
+    }
+}
+
+/// Re-encodes every file under `in_dir`, subdirectories included, into the same place
+/// under `out_dir`, calling `progress` after each one. Each file must hold exactly one
+/// message. Only failing to list `in_dir` or to create directories under `out_dir` is
+/// an error; anything wrong with a single file goes in the summary.
+pub fn normalize_dir (in_dir: &Path, out_dir: &Path, profile: &Profile, progress: &mut dyn FnMut(&Progress)) -> io::Result<Summary> {
+    let files = util::files_under(in_dir)?;
+    let mut summary = Summary::default();
+
+    for (i, path) in files.iter().enumerate() {
+        let target = out_dir.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let outcome = match normalize_file(&in_dir.join(path), &target, profile) {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome::Failed(e),
+        };
+
+        summary.entries.push(Entry { path: path.clone(), outcome });
+        progress(&Progress { done: i + 1, total: files.len(), entry: summary.entries.last().unwrap() });
+    }
+    Ok(summary)
+}
+
+fn normalize_file (from: &Path, to: &Path, profile: &Profile) -> Result<Outcome, String> {
+    let input = fs::read(from).map_err(|e| e.to_string())?;
+    let tag = Tag::read_exact_one_with(&mut &input[..], &profile.read).map_err(|e| e.to_string())?;
+
+    let mut output = Vec::with_capacity(input.len());
+    (profile.write)(&tag, &mut output).map_err(|e| e.to_string())?;
+    fs::write(to, &output).map_err(|e| e.to_string())?;
+
+    Ok(if output == input {
+        Outcome::Unchanged
+    } else {
+        Outcome::Converted { before: input.len(), after: output.len() }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_dir () {
+        let root = std::env::temp_dir().join(format!("ber-normalize-{}", std::process::id()));
+        let (input, output) = (root.join("in"), root.join("out"));
+        fs::create_dir_all(input.join("nested")).unwrap();
+
+        // SEQUENCE (indefinite) { INTEGER 5 }, already DER, and not BER at all
+        fs::write(input.join("legacy.ber"), [0x30, 0x80, 0x02, 0x01, 0x05, 0x00, 0x00]).unwrap();
+        fs::write(input.join("nested/good.der"), [0x30, 0x03, 0x02, 0x01, 0x05]).unwrap();
+        fs::write(input.join("notes.txt"), b"hello").unwrap();
+
+        let mut seen = Vec::new();
+        let summary = normalize_dir(&input, &output, &Profile::der(), &mut |p| seen.push((p.done, p.total))).unwrap();
+        let written = (fs::read(output.join("legacy.ber")).unwrap(), output.join("notes.txt").exists());
+        fs::remove_dir_all(&root).ok();
+        assert_eq!(written, (vec![0x30, 0x03, 0x02, 0x01, 0x05], false));
+
+        assert_eq!(seen, [(1, 3), (2, 3), (3, 3)]);
+        let outcomes: Vec<_> = summary.entries.iter().map(|e| (e.path.to_str().unwrap(), &e.outcome)).collect();
+        assert_eq!(outcomes[0], ("legacy.ber", &Outcome::Converted { before: 7, after: 5 }));
+        assert_eq!(outcomes[1], ("nested/good.der", &Outcome::Unchanged));
+        assert!(matches!(outcomes[2], ("notes.txt", Outcome::Failed(_))));
+        assert!(summary.to_string().ends_with("3 files: 1 converted, 1 unchanged, 1 failed"));
+    }
+}
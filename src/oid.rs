@@ -0,0 +1,302 @@
+//! OBJECT IDENTIFIER and RELATIVE-OID values (X.690 §8.19, §8.20).
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::err;
+use crate::tag::{Number, Tag, Type};
+
+/// An object identifier as its sequence of arcs.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub struct Oid {
+    arcs: Vec<u64>,
+}
+
+impl Oid {
+    /// Builds an OID from its arcs, which must number at least two, with a first arc
+    /// of 0, 1 or 2 and a second arc below 40 unless the first is 2.
+    pub fn new (arcs: Vec<u64>) -> Result<Oid, err::Error> {
+        let valid = arcs.len() >= 2 && arcs[0] <= 2 && (arcs[0] == 2 || arcs[1] < 40) &&
+            arcs[1].checked_add(arcs[0] * 40).is_some();
+
+        if valid {
+            Ok(Oid { arcs })
+        } else {
+            Err(err::Error::new(err::Kind::InvalidOid, 0, None))
+        }
+    }
+
+    pub fn arcs (&self) -> &[u64] {
+        &self.arcs
+    }
+
+    /// Decodes OBJECT IDENTIFIER content octets.
+    pub fn from_content (content: &[u8]) -> Result<Oid, err::Error> {
+        let subs = decode_subidentifiers(content)?;
+
+        // the first subidentifier packs the first two arcs
+        let first = subs[0];
+        let mut arcs = match first {
+            0..=39 => vec![0, first],
+            40..=79 => vec![1, first - 40],
+            _ => vec![2, first - 80],
+        };
+        arcs.extend_from_slice(&subs[1..]);
+
+        Ok(Oid { arcs })
+    }
+
+    /// The arcs of `self` below `base`, or `None` if `base` isn't a proper prefix of it.
+    pub fn relative_to (&self, base: &Oid) -> Option<RelativeOid> {
+        if self.arcs.len() > base.arcs.len() && self.arcs.starts_with(&base.arcs) {
+            Some(RelativeOid { arcs: self.arcs[base.arcs.len()..].to_vec() })
+        } else {
+            None
+        }
+    }
+
+    /// Encodes the OID as content octets.
+    pub fn to_content (&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_subidentifier(&mut out, self.arcs[0] * 40 + self.arcs[1]);
+        for &arc in &self.arcs[2..] {
+            encode_subidentifier(&mut out, arc);
+        }
+        out
+    }
+}
+
+/// A RELATIVE-OID: arcs that only name an object once appended to some base OID.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub struct RelativeOid {
+    arcs: Vec<u64>,
+}
+
+impl RelativeOid {
+    /// Builds a relative OID from at least one arc.
+    pub fn new (arcs: Vec<u64>) -> Result<RelativeOid, err::Error> {
+        if arcs.is_empty() {
+            return Err(err::Error::new(err::Kind::InvalidOid, 0, None));
+        }
+        Ok(RelativeOid { arcs })
+    }
+
+    pub fn arcs (&self) -> &[u64] {
+        &self.arcs
+    }
+
+    /// Decodes RELATIVE-OID content octets, where every subidentifier is one arc.
+    pub fn from_content (content: &[u8]) -> Result<RelativeOid, err::Error> {
+        Ok(RelativeOid { arcs: decode_subidentifiers(content)? })
+    }
+
+    pub fn to_content (&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &arc in &self.arcs {
+            encode_subidentifier(&mut out, arc);
+        }
+        out
+    }
+
+    /// The absolute OID these arcs name under `base`.
+    pub fn resolve (&self, base: &Oid) -> Oid {
+        let mut arcs = base.arcs.clone();
+        arcs.extend_from_slice(&self.arcs);
+        Oid { arcs }
+    }
+}
+
+/// Splits content octets into base 128 subidentifiers, rejecting padding, overflow and
+/// truncation.
+pub(crate) fn decode_subidentifiers (content: &[u8]) -> Result<Vec<u64>, err::Error> {
+    let invalid = |offset| Err(err::Error::new(err::Kind::InvalidOid, offset, None));
+
+    if content.is_empty() {
+        return invalid(0);
+    }
+
+    let mut subs = Vec::new();
+    let mut value = 0u64;
+    let mut start = true;
+
+    for (i, &b) in content.iter().enumerate() {
+        if start && b == 0x80 {
+            // non-minimal padding
+            return invalid(i);
+        }
+        if value >> 57 != 0 {
+            return invalid(i);
+        }
+
+        value = value << 7 | (b & 0x7F) as u64;
+        start = b & 0x80 == 0;
+        if start {
+            subs.push(value);
+            value = 0;
+        }
+    }
+
+    if !start {
+        return invalid(content.len());
+    }
+    Ok(subs)
+}
+
+pub(crate) fn encode_subidentifier (out: &mut Vec<u8>, mut value: u64) {
+    let mut groups = [0u8; 10];
+    let mut n = 0;
+
+    loop {
+        groups[n] = (value & 0x7F) as u8;
+        n += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+
+    for i in (0..n).rev() {
+        out.push(groups[i] | if i > 0 { 0x80 } else { 0 });
+    }
+}
+
+fn write_dotted (arcs: &[u64], f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, arc) in arcs.iter().enumerate() {
+        if i > 0 {
+            f.write_str(".")?;
+        }
+        write!(f, "{}", arc)?;
+    }
+    Ok(())
+}
+
+fn parse_dotted (s: &str) -> Result<Vec<u64>, err::Error> {
+    s.split('.')
+        .map(|arc| {
+            if arc.is_empty() || !arc.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(err::Error::new(err::Kind::InvalidOid, 0, None));
+            }
+            arc.parse().map_err(|_| err::Error::new(err::Kind::InvalidOid, 0, None))
+        })
+        .collect()
+}
+
+impl fmt::Display for Oid {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_dotted(&self.arcs, f)
+    }
+}
+
+impl FromStr for Oid {
+    type Err = err::Error;
+
+    /// Parses the dotted form, e.g. `1.2.840.113549`.
+    fn from_str (s: &str) -> Result<Oid, err::Error> {
+        Oid::new(parse_dotted(s)?)
+    }
+}
+
+impl fmt::Display for RelativeOid {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_dotted(&self.arcs, f)
+    }
+}
+
+impl FromStr for RelativeOid {
+    type Err = err::Error;
+
+    /// Parses the dotted form, e.g. `1.1`.
+    fn from_str (s: &str) -> Result<RelativeOid, err::Error> {
+        RelativeOid::new(parse_dotted(s)?)
+    }
+}
+
+impl Tag {
+    /// Interprets the content as an OBJECT IDENTIFIER.
+    pub fn as_oid (&self) -> Result<Oid, err::Error> {
+        Oid::from_content(self.content()?).map_err(|mut e| {
+            e.offset += self.offset.unwrap_or(0);
+            e
+        })
+    }
+
+    /// A universal OBJECT IDENTIFIER.
+    pub fn oid (oid: &Oid) -> Tag {
+        Tag::primitive(Number::Universal(Type::ObjectIdentifier), oid.to_content())
+    }
+
+    /// Interprets the content as a RELATIVE-OID.
+    pub fn as_relative_oid (&self) -> Result<RelativeOid, err::Error> {
+        RelativeOid::from_content(self.content()?).map_err(|mut e| {
+            e.offset += self.offset.unwrap_or(0);
+            e
+        })
+    }
+
+    /// A universal RELATIVE-OID.
+    pub fn relative_oid (oid: &RelativeOid) -> Tag {
+        Tag::primitive(Number::Universal(Type::RelativeOid), oid.to_content())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RSA: [u8; 9] = [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01];
+
+    #[test]
+    fn test_round_trip () {
+        let oid: Oid = "1.2.840.113549.1.1.1".parse().unwrap();
+        assert_eq!(oid.arcs(), &[1, 2, 840, 113549, 1, 1, 1]);
+        assert_eq!(oid.to_content(), RSA);
+        assert_eq!(Oid::from_content(&RSA).unwrap(), oid);
+        assert_eq!(oid.to_string(), "1.2.840.113549.1.1.1");
+
+        let tag = Tag::oid(&oid);
+        assert_eq!(tag.as_oid().unwrap(), oid);
+    }
+
+    #[test]
+    fn test_joint_iso_itu () {
+        // 2.999 packs into a first subidentifier of 1079
+        let oid: Oid = "2.999.3".parse().unwrap();
+        assert_eq!(oid.to_content(), vec![0x88, 0x37, 0x03]);
+        assert_eq!(Oid::from_content(&[0x88, 0x37, 0x03]).unwrap(), oid);
+    }
+
+    #[test]
+    fn test_relative () {
+        let base: Oid = "1.2.840.113549".parse().unwrap();
+        let relative = RelativeOid::from_content(&[0x01, 0x01, 0x01]).unwrap();
+        assert_eq!(relative.to_string(), "1.1.1");
+        assert_eq!(relative.resolve(&base), "1.2.840.113549.1.1.1".parse().unwrap());
+
+        let full = Oid::from_content(&RSA).unwrap();
+        assert_eq!(full.relative_to(&base), Some(relative.clone()));
+        assert_eq!(base.relative_to(&base), None);
+        assert_eq!(full.relative_to(&"1.3".parse().unwrap()), None);
+
+        // unlike an OID the first subidentifier is a plain arc
+        let large: RelativeOid = "840.113549".parse().unwrap();
+        assert_eq!(large.to_content(), vec![0x86, 0x48, 0x86, 0xF7, 0x0D]);
+        assert_eq!(Tag::relative_oid(&large).as_relative_oid().unwrap(), large);
+
+        assert!("".parse::<RelativeOid>().is_err());
+        assert!(RelativeOid::from_content(&[]).is_err());
+        assert!(RelativeOid::from_content(&[0x81]).is_err());
+    }
+
+    #[test]
+    fn test_invalid () {
+        assert!("1".parse::<Oid>().is_err());
+        assert!("3.1".parse::<Oid>().is_err());
+        assert!("1.40".parse::<Oid>().is_err());
+        assert!("1..2".parse::<Oid>().is_err());
+        assert!("1.+2".parse::<Oid>().is_err());
+        assert!(Oid::from_content(&[]).is_err());
+        assert!(Oid::from_content(&[0x2A, 0x80, 0x01]).is_err());
+        assert!(Oid::from_content(&[0x2A, 0x86]).is_err());
+        assert!(Oid::from_content(&[0xFF; 11]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Parse state kept between messages, for servers decoding many of them.
+//!
+//! `Tag::read_with` starts from nothing on every call and leaves a fresh allocation for
+//! every tag it returns. A `Parser` holds its options and scratch state across reads,
+//! and trees handed back through `Parser::recycle` become an arena of emptied buffers
+//! that later reads fill before allocating. A `Pool` lends parsers out per connection or
+//! task and takes them back when the loan ends.
+
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use crate::err;
+use crate::tag::{ReadOptions, ReadState, Tag};
+
+/// Options and reusable state for reading tags one after another.
+#[derive(Default)]
+pub struct Parser {
+    opts:  ReadOptions,
+    state: ReadState,
+}
+
+impl Parser {
+    pub fn new () -> Parser {
+        Parser::with_options(ReadOptions::default())
+    }
+
+    pub fn with_options (opts: ReadOptions) -> Parser {
+        Parser {
+            opts,
+            state: ReadState::default(),
+        }
+    }
+
+    pub fn options (&self) -> &ReadOptions {
+        &self.opts
+    }
+
+    /// Reads one tag as `Tag::read_with` would under this parser's options, taking its
+    /// buffers from trees recycled earlier where it can.
+    pub fn read (&mut self, r: &mut dyn Read) -> Result<Tag, err::Error> {
+        self.state.reset();
+        Tag::read_in(r, &self.opts, &mut self.state)
+    }
+
+    /// Takes back a tree once the caller is done with it, keeping its buffers for later
+    /// reads.
+    pub fn recycle (&mut self, tag: Tag) {
+        self.state.recycle(tag);
+    }
+
+    /// How many recycled buffers are waiting to be reused.
+    pub fn spares (&self) -> usize {
+        self.state.spares()
+    }
+
+    /// Forgets everything about the last read, such as how much of the payload budget
+    /// it used, keeping the recycled buffers. `read` does this itself; a `Pool` does it
+    /// before lending a parser out again.
+    pub fn reset (&mut self) {
+        self.state.reset();
+    }
+}
+
+/// Parsers sharing one set of options, lent out one at a time.
+pub struct Pool {
+    opts:    ReadOptions,
+    parsers: Mutex<Vec<Parser>>,
+}
+
+impl Pool {
+    pub fn new (opts: ReadOptions) -> Pool {
+        Pool {
+            opts,
+            parsers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// An idle parser, or a new one if every parser is lent out.
+    pub fn get (&self) -> Pooled<'_> {
+        let parser = self.parsers.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        Pooled {
+            pool: self,
+            parser: Some(parser.unwrap_or_else(|| Parser::with_options(self.opts.clone()))),
+        }
+    }
+
+    /// How many parsers are waiting to be lent out.
+    pub fn idle (&self) -> usize {
+        self.parsers.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// A parser on loan from a `Pool`, reset and returned when dropped.
+pub struct Pooled<'a> {
+    pool:   &'a Pool,
+    parser: Option<Parser>,
+}
+
+impl<'a> Deref for Pooled<'a> {
+    type Target = Parser;
+
+    fn deref (&self) -> &Parser {
+        self.parser.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for Pooled<'a> {
+    fn deref_mut (&mut self) -> &mut Parser {
+        self.parser.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for Pooled<'a> {
+    fn drop (&mut self) {
+        if let Some(mut parser) = self.parser.take() {
+            parser.reset();
+            self.pool.parsers.lock().unwrap_or_else(|e| e.into_inner()).push(parser);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // SEQUENCE { INTEGER 5, SEQUENCE (indefinite) { OCTET STRING "abc" } }
+    const INPUT: [u8; 14] = [
+        0x30, 0x0C,
+            0x02, 0x01, 0x05,
+            0x30, 0x80,
+                0x04, 0x03, 0x61, 0x62, 0x63,
+            0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_reuses_buffers () {
+        let mut parser = Parser::new();
+
+        let tag = parser.read(&mut &INPUT[..]).unwrap();
+        assert_eq!(tag, Tag::read(&mut &INPUT[..]).unwrap());
+        assert_eq!(parser.spares(), 0);
+
+        // two content buffers and two child lists come back, and the next read uses them all
+        parser.recycle(tag);
+        assert_eq!(parser.spares(), 4);
+        let tag = parser.read(&mut &INPUT[..]).unwrap();
+        assert_eq!(parser.spares(), 0);
+        assert_eq!(tag, Tag::read(&mut &INPUT[..]).unwrap());
+    }
+
+    #[test]
+    fn test_state_does_not_carry_over () {
+        let opts = ReadOptions { max_total_payload_bytes: 4, ..ReadOptions::default() };
+        let mut parser = Parser::with_options(opts);
+
+        // each message fits the budget on its own
+        for _ in 0..3 {
+            parser.read(&mut &INPUT[..]).unwrap();
+        }
+        let e = parser.read(&mut &[0x04, 0x05, 1, 2, 3, 4, 5][..]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::BudgetExceeded { .. }));
+        parser.read(&mut &INPUT[..]).unwrap();
+    }
+
+    #[test]
+    fn test_pool () {
+        let pool = Pool::new(ReadOptions::default());
+        {
+            let mut a = pool.get();
+            let b = pool.get();
+            let tag = a.read(&mut &INPUT[..]).unwrap();
+            a.recycle(tag);
+            assert_eq!(b.spares(), 0);
+        }
+        assert_eq!(pool.idle(), 2);
+
+        // one of the returned parsers still has its buffers
+        let (a, b) = (pool.get(), pool.get());
+        assert_eq!(a.spares() + b.spares(), 4);
+        assert_eq!(pool.idle(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+//! REAL content octets (X.690 §8.5): binary and decimal encodings plus the special values.
+
+use crate::err;
+use crate::integer::encode_i64;
+use crate::tag::{Number, Tag, Type};
+
+const PLUS_INFINITY: u8 = 0x40;
+const MINUS_INFINITY: u8 = 0x41;
+const NOT_A_NUMBER: u8 = 0x42;
+const MINUS_ZERO: u8 = 0x43;
+
+/// `m * 2^e`, without overflowing or underflowing the intermediate powers of two.
+fn scale (mut m: f64, e: i64) -> f64 {
+    // anything past this is infinite or zero for every f64 mantissa
+    let mut e = e.clamp(-2200, 2200);
+    while e > 1000 {
+        m *= 2f64.powi(1000);
+        e -= 1000;
+    }
+    while e < -1000 {
+        m *= 2f64.powi(-1000);
+        e += 1000;
+    }
+    m * 2f64.powi(e as i32)
+}
+
+fn decode_binary (first: u8, rest: &[u8]) -> Option<f64> {
+    let log2_base = match (first >> 4) & 0x03 {
+        0 => 1,
+        1 => 3,
+        2 => 4,
+        _ => return None,
+    };
+    let f = ((first >> 2) & 0x03) as i64;
+
+    let (exponent, mantissa) = match first & 0x03 {
+        3 => {
+            let (&len, rest) = rest.split_first()?;
+            if len == 0 || rest.len() < len as usize {
+                return None;
+            }
+            rest.split_at(len as usize)
+        },
+        n if rest.len() > n as usize => rest.split_at(n as usize + 1),
+        _ => return None,
+    };
+    if exponent.len() > 8 || mantissa.is_empty() {
+        return None;
+    }
+    let init = if exponent[0] & 0x80 != 0 { -1i64 } else { 0 };
+    let exponent = exponent.iter().fold(init, |acc, &b| (acc << 8) | b as i64);
+
+    // keep the top eight significant octets; anything below only matters for rounding,
+    // so it's folded into a sticky low bit
+    let start = mantissa.iter().position(|&b| b != 0).unwrap_or(mantissa.len());
+    let mantissa = &mantissa[start..];
+    let (top, low) = mantissa.split_at(mantissa.len().min(8));
+    let mut n = top.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    if low.iter().any(|&b| b != 0) {
+        n |= 1;
+    }
+
+    let e = exponent.saturating_mul(log2_base).saturating_add(f).saturating_add(8 * low.len() as i64);
+    let v = scale(n as f64, e);
+    Some(if first & 0x40 != 0 { -v } else { v })
+}
+
+fn decode_decimal (first: u8, rest: &[u8]) -> Option<f64> {
+    // NR1, NR2 and NR3 (ISO 6093) all parse as f64 once the decimal comma is normalised
+    if !(1..=3).contains(&(first & 0x3F)) {
+        return None;
+    }
+    if !rest.iter().all(|&c| c.is_ascii_digit() || b" +-.,Ee".contains(&c)) {
+        return None;
+    }
+    let text: String = rest.iter().map(|&c| if c == b',' { '.' } else { c as char }).collect();
+    text.trim_start_matches(' ').parse().ok()
+}
+
+impl Tag {
+    /// A universal REAL in the canonical DER form: base 2 with an odd mantissa.
+    pub fn real (v: f64) -> Tag {
+        let content = if v.is_nan() {
+            vec![NOT_A_NUMBER]
+        } else if v.is_infinite() {
+            vec![if v > 0.0 { PLUS_INFINITY } else { MINUS_INFINITY }]
+        } else if v == 0.0 {
+            if v.is_sign_negative() { vec![MINUS_ZERO] } else { vec![] }
+        } else {
+            let bits = v.to_bits();
+            let biased = ((bits >> 52) & 0x7FF) as i64;
+            let fraction = bits & ((1 << 52) - 1);
+            let (mut m, mut e) = if biased == 0 {
+                (fraction, -1074)
+            } else {
+                (fraction | (1 << 52), biased - 1075)
+            };
+            let zeros = m.trailing_zeros();
+            m >>= zeros;
+            e += zeros as i64;
+
+            let exponent = encode_i64(e);
+            let mut first = 0x80 | if v < 0.0 { 0x40 } else { 0 };
+            let mut content = Vec::new();
+            if exponent.len() <= 3 {
+                first |= exponent.len() as u8 - 1;
+                content.push(first);
+            } else {
+                content.push(first | 0x03);
+                content.push(exponent.len() as u8);
+            }
+            content.extend_from_slice(&exponent);
+
+            let mantissa = m.to_be_bytes();
+            let start = mantissa.iter().position(|&b| b != 0).unwrap_or(7);
+            content.extend_from_slice(&mantissa[start..]);
+            content
+        };
+
+        Tag::primitive(Number::Universal(Type::Real), content)
+    }
+
+    /// Interprets the content as a REAL in any of its binary, decimal or special forms.
+    /// Like `as_i64`, the tag number is not checked.
+    pub fn as_f64 (&self) -> Result<f64, err::Error> {
+        let v = self.content()?;
+        let (&first, rest) = match v.split_first() {
+            Some(x) => x,
+            None => return Ok(0.0),
+        };
+
+        let value = if first & 0x80 != 0 {
+            decode_binary(first, rest)
+        } else if first & 0x40 == 0 {
+            decode_decimal(first, rest)
+        } else if rest.is_empty() {
+            match first {
+                PLUS_INFINITY => Some(f64::INFINITY),
+                MINUS_INFINITY => Some(f64::NEG_INFINITY),
+                NOT_A_NUMBER => Some(f64::NAN),
+                MINUS_ZERO => Some(-0.0),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        value.ok_or_else(|| self.error(err::Kind::InvalidValue))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tag::{Number, Payload, Tag, Type};
+
+    fn real (v: &[u8]) -> Tag {
+        Tag::primitive(Number::Universal(Type::Real), v.to_vec())
+    }
+
+    #[test]
+    fn test_binary () {
+        assert_eq!(real(&[]).as_f64().unwrap(), 0.0);
+        assert_eq!(real(&[0x80, 0x00, 0x01]).as_f64().unwrap(), 1.0);
+        assert_eq!(real(&[0xC0, 0xFF, 0x03]).as_f64().unwrap(), -1.5);
+        // base 8, base 16 and a scale factor
+        assert_eq!(real(&[0x90, 0x01, 0x01]).as_f64().unwrap(), 8.0);
+        assert_eq!(real(&[0xA0, 0x01, 0x01]).as_f64().unwrap(), 16.0);
+        assert_eq!(real(&[0x84, 0x00, 0x03]).as_f64().unwrap(), 6.0);
+        // long form exponent
+        assert_eq!(real(&[0x83, 0x01, 0x02, 0x01]).as_f64().unwrap(), 4.0);
+        // mantissa wider than 64 bits
+        assert_eq!(real(&[0x81, 0xFF, 0xC0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]).as_f64().unwrap(), 1.0);
+
+        assert!(real(&[0xB0, 0x00, 0x01]).as_f64().is_err());
+        assert!(real(&[0x80, 0x00]).as_f64().is_err());
+        assert!(real(&[0x83, 0x00, 0x01]).as_f64().is_err());
+    }
+
+    #[test]
+    fn test_decimal_and_special () {
+        assert_eq!(real(b"\x01  -12").as_f64().unwrap(), -12.0);
+        assert_eq!(real(b"\x021,5").as_f64().unwrap(), 1.5);
+        assert_eq!(real(b"\x03+25E-1").as_f64().unwrap(), 2.5);
+        assert!(real(b"\x03inf").as_f64().is_err());
+        assert!(real(b"\x041").as_f64().is_err());
+
+        assert_eq!(real(&[0x40]).as_f64().unwrap(), f64::INFINITY);
+        assert_eq!(real(&[0x41]).as_f64().unwrap(), f64::NEG_INFINITY);
+        assert!(real(&[0x42]).as_f64().unwrap().is_nan());
+        assert!(real(&[0x43]).as_f64().unwrap().is_sign_negative());
+        assert!(real(&[0x44]).as_f64().is_err());
+    }
+
+    #[test]
+    fn test_roundtrip () {
+        assert_eq!(Tag::real(1.0).payload, Payload::Primitive(vec![0x80, 0x00, 0x01]));
+        assert_eq!(Tag::real(0.0).payload, Payload::Primitive(vec![]));
+        assert!(Tag::real(f64::NAN).as_f64().unwrap().is_nan());
+
+        for &v in &[
+            -0.0, 0.5, -3.25, 1e300, -1e-300, 0.1,
+            f64::MAX, f64::MIN_POSITIVE, 5e-324, f64::INFINITY, f64::NEG_INFINITY,
+        ] {
+            let back = Tag::real(v).as_f64().unwrap();
+            assert_eq!(back.to_bits(), v.to_bits(), "{}", v);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Best-effort parsing of broken BER, for forensic work on damaged or hostile files.
+//!
+//! `parse` reads what it can of a message instead of giving up at the first problem.
+//! Subtrees that read cleanly come back as ordinary `Tag`s. A constructed tag with a
+//! malformed child keeps its other children, and the bad child becomes an `Opaque`
+//! node holding its raw bytes. Every problem found goes in `Recovered::errors`, with
+//! offsets into the input and the path down to the tag that failed.
+
+use crate::err;
+use crate::tag::{self, Flavor, Length, Number, Payload, ReadOptions, Tag, Type};
+
+// Recovery recurses once per broken level; anything nested deeper stays opaque.
+const MAX_DEPTH: usize = 128;
+
+/// A node of a recovered tree.
+#[derive(Debug)]
+pub enum Node {
+    /// A subtree that read without errors.
+    Tag(Tag),
+    /// A constructed tag with something wrong inside it.
+    Constructed {
+        number:   Number,
+        offset:   usize,
+        children: Vec<Node>,
+    },
+    /// Bytes that couldn't be read as a tag, and the index in `Recovered::errors` of
+    /// the error saying why.
+    Opaque {
+        offset: usize,
+        bytes:  Vec<u8>,
+        error:  usize,
+    },
+}
+
+impl Node {
+    /// Offset of the node's first byte in the input.
+    pub fn offset (&self) -> usize {
+        match *self {
+            Node::Tag(ref tag) => tag.offset.unwrap_or(0),
+            Node::Constructed { offset, .. } | Node::Opaque { offset, .. } => offset,
+        }
+    }
+}
+
+/// What `parse` made of its input.
+#[derive(Debug)]
+pub struct Recovered {
+    pub root:   Node,
+    /// Every problem found, in the order they were met. Empty when the whole message
+    /// read cleanly, in which case `root` is a `Node::Tag`.
+    pub errors: Vec<err::Error>,
+}
+
+/// Reads one message from the front of `input`, recovering from whatever it can.
+pub fn parse (input: &[u8]) -> Recovered {
+    parse_with(input, &ReadOptions::default())
+}
+
+/// `parse` reading the clean subtrees with `opts`. A limit a subtree breaks is an
+/// error like any other, and the subtree is recovered around it.
+pub fn parse_with (input: &[u8], opts: &ReadOptions) -> Recovered {
+    let base = opts.base_offset;
+    let opts = ReadOptions { base_offset: 0, ..opts.clone() };
+    let mut p = Parser { input, opts: &opts, base, errors: Vec::new(), steps: Vec::new() };
+    let (root, _) = p.node(0, input.len());
+    Recovered { root, errors: p.errors }
+}
+
+struct Parser<'a> {
+    input:  &'a [u8],
+    opts:   &'a ReadOptions,
+    // added to every offset on the way out
+    base:   usize,
+    errors: Vec<err::Error>,
+    // the way down to the node being read
+    steps:  Vec<err::Step>,
+}
+
+impl<'a> Parser<'a> {
+    // The node starting at `pos`, which mustn't reach past `limit`, and where it ends.
+    fn node (&mut self, pos: usize, limit: usize) -> (Node, usize) {
+        let depth = self.steps.len();
+        let opts = ReadOptions { max_depth: self.opts.max_depth.saturating_sub(depth), ..self.opts.clone() };
+        let mut r = &self.input[pos..limit];
+        let e = match Tag::read_with(&mut r, &opts) {
+            Ok(mut tag) => {
+                shift(&mut tag, self.base + pos);
+                return (Node::Tag(tag), limit - r.len());
+            },
+            Err(e) => e,
+        };
+        let e = match e.kind {
+            err::Kind::CleanEof => err::Error::new(err::Kind::Truncated { needed: 1 }, 0, None),
+            _ => e,
+        };
+
+        // read again by hand, far enough to tell where the bad tag ends
+        let mut r = &self.input[pos..limit];
+        let header = tag::read_identifiers(&mut r).and_then(|(_, flavor, number)| Ok((flavor, number, tag::read_length(&mut r)?)));
+        let start = limit - r.len();
+        let (flavor, number, length) = match header {
+            Ok(header) if depth < MAX_DEPTH => header,
+            _ => return self.opaque(pos, limit, e),
+        };
+
+        let end = match length {
+            Length::Some(l) => (start as u64).saturating_add(l).min(limit as u64) as usize,
+            Length::Indefinite => limit,
+        };
+        if flavor == Flavor::Primitive {
+            return self.opaque(pos, end, e);
+        }
+
+        let errors = self.errors.len();
+        let mut children = Vec::new();
+        let mut at = start;
+        loop {
+            if at >= end {
+                if length == Length::Indefinite {
+                    // the input ended before the EOC
+                    self.error(err::Error::new(err::Kind::Truncated { needed: 2 }, end, None));
+                }
+                break;
+            }
+
+            self.steps.push(err::Step { number, index: children.len() });
+            let (child, next) = self.node(at, end);
+            self.steps.pop();
+
+            let eoc = matches!(child, Node::Tag(ref tag) if tag.number == Number::Universal(Type::Eoc));
+            if eoc && length == Length::Indefinite {
+                at = next;
+                break;
+            }
+            let child = match child {
+                Node::Tag(_) if eoc => {
+                    let e = err::Error::new(err::Kind::UnexpectedEoc, 0, None);
+                    self.opaque(at, next, e).0
+                },
+                child => child,
+            };
+            children.push(child);
+            at = next;
+        }
+
+        // the children all read, so the trouble was with the tag as a whole
+        if self.errors.len() == errors {
+            self.error(err::Error { offset: pos + e.offset, ..e });
+        }
+        let next = match length {
+            Length::Some(_) => end,
+            Length::Indefinite => at,
+        };
+        (Node::Constructed { number, offset: self.base + pos, children }, next)
+    }
+
+    // `input[from..to]` as an opaque node, failing with `e`, whose offset is relative
+    // to `from`.
+    fn opaque (&mut self, from: usize, to: usize, e: err::Error) -> (Node, usize) {
+        let to = to.max(from);
+        let error = self.error(err::Error { offset: from + e.offset, ..e });
+        (Node::Opaque { offset: self.base + from, bytes: self.input[from..to].to_vec(), error }, to)
+    }
+
+    fn error (&mut self, mut e: err::Error) -> usize {
+        let mut path = self.steps.clone();
+        path.append(&mut e.path);
+        e.path = path;
+        e.offset += self.base;
+        self.errors.push(e);
+        self.errors.len() - 1
+    }
+}
+
+// Moves every offset in `tag` from relative to its start to relative to the input.
+fn shift (tag: &mut Tag, by: usize) {
+    let mut stack = vec![tag];
+    while let Some(tag) = stack.pop() {
+        tag.offset = tag.offset.map(|o| o + by);
+        if let Payload::Constructed(ref mut children) = tag.payload {
+            stack.extend(children.iter_mut());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recovers_siblings () {
+        // SEQUENCE { INTEGER 1, [1] { OCTET STRING claiming 5 bytes but holding 1 }, NULL }
+        let input = [0x30, 0x0A, 0x02, 0x01, 0x01, 0xA1, 0x03, 0x04, 0x05, 0xFF, 0x05, 0x00];
+        let recovered = parse(&input);
+        assert_eq!(recovered.errors.len(), 1);
+        let e = &recovered.errors[0];
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+        assert_eq!(e.path.iter().map(|s| s.index).collect::<Vec<_>>(), vec![1, 0]);
+
+        let children = match recovered.root {
+            Node::Constructed { ref children, .. } => children,
+            ref root => panic!("{:?}", root),
+        };
+        assert!(matches!(children[0], Node::Tag(ref t) if t.offset == Some(2)));
+        assert!(matches!(children[2], Node::Tag(ref t) if t.number == Number::Universal(Type::Null)));
+        match children[1] {
+            Node::Constructed { children: ref inner, offset: 5, .. } => match inner[..] {
+                [Node::Opaque { offset: 7, ref bytes, error: 0 }] => assert_eq!(bytes, &[0x04, 0x05, 0xFF]),
+                ref inner => panic!("{:?}", inner),
+            },
+            ref child => panic!("{:?}", child),
+        }
+
+        let clean = parse(&[0x30, 0x03, 0x02, 0x01, 0x01]);
+        assert!(clean.errors.is_empty());
+        assert!(matches!(clean.root, Node::Tag(_)));
+    }
+
+    #[test]
+    fn test_missing_eoc () {
+        // SEQUENCE (indefinite) { NULL, then garbage where a header should be
+        let input = [0x30, 0x80, 0x05, 0x00, 0x1F];
+        let recovered = parse(&input);
+        let kinds: Vec<_> = recovered.errors.iter().map(|e| (e.code(), e.offset)).collect();
+        assert_eq!(kinds.len(), 2, "{:?}", recovered.errors);
+        assert_eq!(kinds[1], (err::Kind::Truncated { needed: 2 }.code(), 5));
+        match recovered.root {
+            Node::Constructed { ref children, .. } => {
+                assert!(matches!(children[..], [Node::Tag(_), Node::Opaque { offset: 4, .. }]), "{:?}", children);
+            },
+            ref root => panic!("{:?}", root),
+        }
+    }
+
+    #[test]
+    fn test_stray_eoc () {
+        // SEQUENCE { EOC, NULL }, the EOC out of place in a definite length
+        let recovered = parse(&[0x30, 0x04, 0x00, 0x00, 0x05, 0x00]);
+        assert_eq!(recovered.errors.len(), 1);
+        let e = &recovered.errors[0];
+        assert!(matches!(e.kind, err::Kind::UnexpectedEoc), "{}", e);
+        assert_eq!(e.offset, 2);
+        match recovered.root {
+            Node::Constructed { ref children, .. } => {
+                assert!(matches!(children[..], [Node::Opaque { offset: 2, .. }, Node::Tag(_)]), "{:?}", children);
+            },
+            ref root => panic!("{:?}", root),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Registration of richer decoders for tags the generic tooling only sees as bytes.
+//!
+//! Protocol crates register a `Decoder` for an application or context specific tag
+//! number, or for an OBJECT IDENTIFIER. Generic consumers, `dump` and `json::write`, look up
+//! the registry to describe tags they would otherwise print raw. A process wide registry
+//! is available through `global`; separate `Registry` values can be used where global
+//! state is unwelcome.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use crate::oid::Oid;
+use crate::tag::{Number, Payload, Tag, Type};
+
+/// Something that can describe a tag in richer terms than its raw encoding.
+pub trait Decoder: Send + Sync {
+    /// Describes `tag`, or returns `None` if it is not something this decoder handles.
+    fn describe (&self, tag: &Tag) -> Option<String>;
+}
+
+impl<F> Decoder for F where F: Fn(&Tag) -> Option<String> + Send + Sync {
+    fn describe (&self, tag: &Tag) -> Option<String> {
+        self(tag)
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    by_number: HashMap<Number, Box<dyn Decoder>>,
+    by_oid:    HashMap<Vec<u8>, Box<dyn Decoder>>,
+}
+
+impl Registry {
+    pub fn new () -> Registry {
+        Registry::default()
+    }
+
+    /// Registers `decoder` for every tag with the given number, replacing any decoder
+    /// already registered for it.
+    pub fn register_number<D: Decoder + 'static> (&mut self, number: Number, decoder: D) {
+        self.by_number.insert(number, Box::new(decoder));
+    }
+
+    /// Registers `decoder` for constructed tags whose first child is the given OBJECT
+    /// IDENTIFIER, the shape of AlgorithmIdentifier, Extension, ContentInfo and friends.
+    pub fn register_oid<D: Decoder + 'static> (&mut self, oid: &Oid, decoder: D) {
+        // keyed by content octets so lookups don't have to decode every OID they meet
+        self.by_oid.insert(oid.to_content(), Box::new(decoder));
+    }
+
+    /// Finds the decoder for `tag`, preferring one registered by OID.
+    pub fn lookup (&self, tag: &Tag) -> Option<&dyn Decoder> {
+        let by_oid = match tag.payload {
+            Payload::Constructed(ref children) => match children.first() {
+                Some(&Tag { number: Number::Universal(Type::ObjectIdentifier), payload: Payload::Primitive(ref oid), .. }) =>
+                    self.by_oid.get(oid),
+                _ => None,
+            },
+            Payload::Primitive(_) => None,
+        };
+
+        by_oid.or_else(|| self.by_number.get(&tag.number)).map(|d| &**d)
+    }
+
+    /// Describes `tag` using whichever decoder is registered for it.
+    pub fn describe (&self, tag: &Tag) -> Option<String> {
+        self.lookup(tag).and_then(|d| d.describe(tag))
+    }
+}
+
+/// The process wide registry.
+pub fn global () -> &'static RwLock<Registry> {
+    static GLOBAL: OnceLock<RwLock<Registry>> = OnceLock::new();
+    GLOBAL.get_or_init(|| RwLock::new(Registry::new()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::TagNumber;
+
+    #[test]
+    fn test_lookup () {
+        let mut registry = Registry::new();
+        registry.register_number(Number::Application(TagNumber::new(3)), |_: &Tag| Some("bind request".to_string()));
+        registry.register_oid(&"1.2.840.113549.1.1.1".parse().unwrap(), |_: &Tag| Some("rsaEncryption".to_string()));
+
+        let bind = Tag {
+            number: Number::Application(TagNumber::new(3)),
+            offset: None,
+            payload: Payload::Constructed(vec![]),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        };
+        assert_eq!(registry.describe(&bind).as_deref(), Some("bind request"));
+
+        let algorithm = Tag {
+            number: Number::Universal(Type::Sequence),
+            offset: None,
+            payload: Payload::Constructed(vec![
+                Tag {
+                    number: Number::Universal(Type::ObjectIdentifier),
+                    offset: None,
+                    payload: Payload::Primitive(vec![0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01]),
+                    repaired: None,
+                    length_form: None,
+                    layout: None,
+                },
+            ]),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        };
+        assert_eq!(registry.describe(&algorithm).as_deref(), Some("rsaEncryption"));
+
+        let other = Tag {
+            number: Number::Application(TagNumber::new(4)),
+            offset: None,
+            payload: Payload::Primitive(vec![]),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        };
+        assert!(registry.lookup(&other).is_none());
+    }
+}
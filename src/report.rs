@@ -0,0 +1,184 @@
+//! Self-contained diagnostics for malformed input, to attach to bug reports.
+//!
+//! A `Report` holds everything needed to triage a read error without the original file:
+//! the error, the bytes around it, the outline of what parsed before it, the options the
+//! read used and the crate version. Its `Display` output is plain text meant to be pasted
+//! as is.
+
+use std::fmt;
+
+use crate::decoder::{Decoder, Event};
+use crate::err;
+use crate::format::{FormatOptions, Labels};
+use crate::tag::ReadOptions;
+
+/// How many bytes of input a report shows on each side of the error offset.
+pub const CONTEXT_BYTES: usize = 32;
+
+/// A diagnostic snapshot of a read error, built by `Error::to_report`.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The version of this crate that produced the error.
+    pub version: &'static str,
+    /// The error as `Display` renders it, including its code.
+    pub error: String,
+    /// Where the error is, counted from `ReadOptions::base_offset` as the error's own
+    /// offset is, as are `context_start` and the offsets in `outline`.
+    pub offset: usize,
+    pub input_len: usize,
+    /// Input bytes around the error, starting at `context_start`.
+    pub context: Vec<u8>,
+    pub context_start: usize,
+    /// One line per tag that started before the error, indented by depth.
+    pub outline: Vec<String>,
+    /// The options the failed read used, when the caller supplied them.
+    pub options: Option<ReadOptions>,
+}
+
+impl Report {
+    // `input` starts `base` bytes into the source the error's offset counts in.
+    pub(crate) fn new (e: &err::Error, input: &[u8], base: usize) -> Report {
+        let offset = e.offset.saturating_sub(base).min(input.len());
+        let context_start = offset.saturating_sub(CONTEXT_BYTES);
+        let context_end = offset.saturating_add(CONTEXT_BYTES).min(input.len());
+
+        Report {
+            version: env!("CARGO_PKG_VERSION"),
+            error: e.to_string(),
+            offset: e.offset,
+            input_len: input.len(),
+            context: input[context_start..context_end].to_vec(),
+            context_start: base + context_start,
+            outline: outline(&input[..offset], base),
+            options: None,
+        }
+    }
+
+    /// Records the options the failed read used.
+    pub fn with_options (mut self, opts: &ReadOptions) -> Report {
+        self.options = Some(opts.clone());
+        self
+    }
+}
+
+// The tags that start in `input`, as far as a decoder gets through it, with offsets
+// from `base`.
+fn outline (input: &[u8], base: usize) -> Vec<String> {
+    let opts = FormatOptions { labels: Labels::Asn1, ..FormatOptions::default() };
+    let mut decoder = Decoder::new();
+    let mut events = Vec::new();
+    let mut stopped = None;
+    // a byte at a time, so everything up to a decoder error is kept
+    for b in input {
+        match decoder.feed(&[*b]) {
+            Ok(more) => events.extend(more),
+            Err(e) => {
+                stopped = Some(e);
+                break;
+            },
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut depth = 0;
+    for event in events {
+        match event {
+            Event::Start { offset, number, constructed, length, .. } => {
+                let length = match length {
+                    Some(l) => l.to_string(),
+                    None => "indefinite".to_string(),
+                };
+                let flavor = if constructed { "constructed" } else { "primitive" };
+                lines.push(format!("{:width$}{} at {}, {}, length {}", "", opts.number(&number), base + offset, flavor, length, width = depth * 2));
+                depth += 1;
+            },
+            Event::End { .. } => depth -= 1,
+            Event::Content(_) => {},
+        }
+    }
+    if let Some(e) = stopped {
+        lines.push(format!("outline stopped: {}", e));
+    }
+    lines
+}
+
+impl fmt::Display for Report {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ber {} read failure", self.version)?;
+        writeln!(f, "{}", self.error)?;
+        writeln!(f, "input: {} bytes", self.input_len)?;
+        match self.options {
+            Some(ref opts) => writeln!(f, "options: {:?}", opts)?,
+            None => writeln!(f, "options: not recorded")?,
+        }
+
+        writeln!(f, "context:")?;
+        for (i, row) in self.context.chunks(16).enumerate() {
+            let start = self.context_start + i * 16;
+            write!(f, "  {:08x} ", start)?;
+            for (j, b) in row.iter().enumerate() {
+                // bracket the byte the error points at
+                if start + j == self.offset {
+                    write!(f, "[{:02x}]", b)?;
+                } else {
+                    write!(f, " {:02x} ", b)?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "parsed before the error:")?;
+        if self.outline.is_empty() {
+            writeln!(f, "  nothing")?;
+        }
+        for line in &self.outline {
+            writeln!(f, "  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tag::{ReadOptions, Tag};
+
+    #[test]
+    fn test_report () {
+        // SEQUENCE { INTEGER 1, OCTET STRING claiming 5 bytes but holding 2 }
+        let input = [0x30, 0x07, 0x02, 0x01, 0x01, 0x04, 0x05, 0x61, 0x62];
+        let opts = ReadOptions::default();
+        let e = Tag::read_with(&mut &input[..], &opts).unwrap_err();
+        let report = e.to_report(&input).with_options(&opts);
+
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report.context, input[..report.input_len].to_vec());
+        assert_eq!(report.outline, vec![
+            "SEQUENCE at 0, constructed, length 7",
+            "  INTEGER at 2, primitive, length 1",
+            "outline stopped: error at 5 (E27): tag runs past the end of its parent",
+        ]);
+
+        let text = report.to_string();
+        assert!(text.starts_with(&format!("ber {} read failure\n{}\n", report.version, e)), "{}", text);
+        assert!(text.contains("options: ReadOptions {"), "{}", text);
+        assert!(text.contains("  00000000  30  07 "), "{}", text);
+    }
+
+    #[test]
+    fn test_report_with_base () {
+        // the same input with more of the capture after it, found 100 bytes in
+        let mut input = vec![0x30, 0x07, 0x02, 0x01, 0x01, 0x04, 0x05, 0x61, 0x62];
+        input.extend_from_slice(&[0xEE; 64]);
+        let opts = ReadOptions::builder().base_offset(100).build();
+        let e = Tag::read_with(&mut &input[..], &opts).unwrap_err();
+        let relative = Tag::read(&mut &input[..]).unwrap_err().offset;
+        assert_eq!(e.offset, 100 + relative);
+        let report = e.to_report_with(&input, &opts);
+
+        assert_eq!((report.offset, report.context_start), (100 + relative, 100));
+        assert_eq!(report.context, input[..relative + 32].to_vec());
+        assert_eq!(report.outline[..2], ["SEQUENCE at 100, constructed, length 7", "  INTEGER at 102, primitive, length 1"]);
+        assert!(report.options.is_some());
+        assert!(report.to_string().contains("  00000064  30  07 "), "{}", report);
+    }
+}
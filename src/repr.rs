@@ -0,0 +1,459 @@
+//! A common trait for domain types with a BER encoding.
+//!
+//! Protocol crates implement `BerRepr` on their own types to convert them to and from
+//! this crate's `Tag` tree, so that one protocol's types can be nested inside another's
+//! and handed to generic tooling without either crate knowing about the other. The
+//! `Context` passed along says how the surrounding definition tags the value and how
+//! strictly to decode it; `Fields` walks the children of a SEQUENCE in order. See
+//! `examples/protocol.rs` for a complete message type.
+//!
+//! Decoding with `from_tag_with_spans` also gives a `Spans` naming where each field lay
+//! in the input, so a check made after decoding ("expiry date invalid") can still point
+//! at the bytes it objects to.
+//!
+//! The trait is implemented here for the simple universal types and for `Vec<T>` as a
+//! SEQUENCE OF. Wrap foreign types in a local newtype to implement it for them.
+
+use crate::encoder;
+use crate::err;
+use crate::oid::Oid;
+use crate::tag::{LengthPolicy, Number, Payload, Tag, Type, WriteOptions};
+
+/// How the surrounding definition encodes a value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Context {
+    /// The number the value is implicitly tagged with, replacing its own.
+    pub implicit: Option<Number>,
+    /// Reject encodings DER forbids where BER offers a choice, such as a TRUE other
+    /// than 0xFF.
+    pub der: bool,
+}
+
+impl Context {
+    /// This context with the value implicitly tagged as `number`.
+    pub fn implicit (self, number: Number) -> Context {
+        Context { implicit: Some(number), ..self }
+    }
+
+    /// The context for the parts of a value: strictness carries over, tagging doesn't.
+    pub fn inner (&self) -> Context {
+        Context { implicit: None, der: self.der }
+    }
+
+    /// The number to encode with, given the number the type uses by default.
+    pub fn number (&self, default: Number) -> Number {
+        self.implicit.unwrap_or(default)
+    }
+
+    /// `tag` renumbered as this context requires.
+    pub fn tag (&self, mut tag: Tag) -> Tag {
+        tag.number = self.number(tag.number);
+        tag
+    }
+
+    /// Fails with `Kind::InvalidTypeAndFlavor` unless `tag` carries the number this
+    /// context expects of a type that uses `default`.
+    pub fn check (&self, tag: &Tag, default: Number) -> Result<(), err::Error> {
+        if tag.number != self.number(default) {
+            return Err(tag.error(err::Kind::InvalidTypeAndFlavor));
+        }
+        Ok(())
+    }
+}
+
+/// Where an encoding lies in the input it was read from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl Span {
+    /// The span of `tag`'s whole encoding, or `None` if it wasn't read from anywhere.
+    /// The length is that of the tree written back with `LengthPolicy::Preserve`, which
+    /// is the length read unless a lenient read repaired it.
+    pub fn of (tag: &Tag) -> Option<Span> {
+        let opts = WriteOptions { length_policy: LengthPolicy::Preserve, ..WriteOptions::default() };
+        tag.offset.map(|offset| Span { offset, length: encoder::encoded_len(tag, &opts) })
+    }
+
+    pub fn end (&self) -> usize {
+        self.offset + self.length
+    }
+
+    /// The bytes of `input` this span covers, or `None` if it runs past the end.
+    pub fn bytes<'a> (&self, input: &'a [u8]) -> Option<&'a [u8]> {
+        input.get(self.offset..self.end())
+    }
+}
+
+/// The spans of a decoded value and of its fields, by name. Fields of fields are named
+/// with dots, such as `validity.not_after`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Spans {
+    whole:  Option<Span>,
+    fields: Vec<(String, Span)>,
+}
+
+impl Spans {
+    /// Spans for a value at `whole` with no fields recorded yet.
+    pub fn new (whole: Option<Span>) -> Spans {
+        Spans { whole, fields: Vec::new() }
+    }
+
+    /// The span of the whole value.
+    pub fn whole (&self) -> Option<Span> {
+        self.whole
+    }
+
+    /// The span of the field called `name`.
+    pub fn get (&self, name: &str) -> Option<Span> {
+        self.fields.iter().find(|(n, _)| n == name).map(|&(_, span)| span)
+    }
+
+    /// Every field's span, in the order they were recorded.
+    pub fn iter (&self) -> impl Iterator<Item = (&str, Span)> + '_ {
+        self.fields.iter().map(|(n, span)| (n.as_str(), *span))
+    }
+
+    /// Records `spans` as the field `name`, along with its own fields.
+    pub fn insert (&mut self, name: &str, spans: Spans) {
+        if let Some(whole) = spans.whole {
+            self.fields.push((name.to_string(), whole));
+        }
+        for (n, span) in spans.fields {
+            self.fields.push((format!("{}.{}", name, n), span));
+        }
+    }
+}
+
+/// A type that converts to and from a `Tag` tree.
+pub trait BerRepr: Sized {
+    /// The tag for this value, numbered as `cx` requires.
+    fn to_tag (&self, cx: &Context) -> Tag;
+
+    /// Decodes a value from `tag`, failing if its number isn't the one `cx` expects.
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Self, err::Error>;
+
+    /// `from_tag`, also giving the spans of the value and its fields. Types record
+    /// their fields by decoding them with `Fields::spanned`; by default only the span of
+    /// the whole value is known.
+    fn from_tag_with_spans (tag: &Tag, cx: &Context) -> Result<(Self, Spans), err::Error> {
+        Ok((Self::from_tag(tag, cx)?, Spans::new(Span::of(tag))))
+    }
+}
+
+/// `value` encoded in the default context.
+pub fn to_tag<T: BerRepr> (value: &T) -> Tag {
+    value.to_tag(&Context::default())
+}
+
+/// A `T` decoded in the default context.
+pub fn from_tag<T: BerRepr> (tag: &Tag) -> Result<T, err::Error> {
+    T::from_tag(tag, &Context::default())
+}
+
+/// `tag` wrapped in a constructed tag numbered `number`, as `[n] EXPLICIT` gives.
+pub fn explicit (number: Number, tag: Tag) -> Tag {
+    Tag::constructed(number, vec![tag])
+}
+
+/// The children of a SEQUENCE, decoded in order.
+pub struct Fields<'a> {
+    parent:   &'a Tag,
+    children: &'a [Tag],
+    cx:       Context,
+}
+
+impl<'a> Fields<'a> {
+    /// The fields of `tag`, which must be a SEQUENCE or carry the number `cx` expects.
+    pub fn of (tag: &'a Tag, cx: &Context) -> Result<Fields<'a>, err::Error> {
+        cx.check(tag, Number::Universal(Type::Sequence))?;
+        match tag.payload {
+            Payload::Constructed(ref children) => Ok(Fields { parent: tag, children, cx: cx.inner() }),
+            Payload::Primitive(_) => Err(tag.error(err::Kind::InvalidTypeAndFlavor)),
+        }
+    }
+
+    /// Decodes the next field. Running out of fields is `Kind::InvalidValue`.
+    pub fn field<T: BerRepr> (&mut self) -> Result<T, err::Error> {
+        let cx = self.cx;
+        self.field_with(&cx)
+    }
+
+    /// Decodes the next field in context `cx`, e.g. an implicitly tagged one.
+    pub fn field_with<T: BerRepr> (&mut self, cx: &Context) -> Result<T, err::Error> {
+        match self.children.split_first() {
+            Some((first, rest)) => {
+                self.children = rest;
+                T::from_tag(first, cx)
+            },
+            None => Err(self.parent.error(err::Kind::InvalidValue)),
+        }
+    }
+
+    /// Decodes the next field, recording its spans in `spans` as `name`.
+    pub fn spanned<T: BerRepr> (&mut self, name: &str, spans: &mut Spans) -> Result<T, err::Error> {
+        let cx = self.cx;
+        match self.children.split_first() {
+            Some((first, rest)) => {
+                self.children = rest;
+                let (value, field) = T::from_tag_with_spans(first, &cx)?;
+                spans.insert(name, field);
+                Ok(value)
+            },
+            None => Err(self.parent.error(err::Kind::InvalidValue)),
+        }
+    }
+
+    /// Decodes the next field if it is implicitly tagged `number`, for OPTIONAL fields
+    /// told apart by their tags.
+    pub fn optional<T: BerRepr> (&mut self, number: Number) -> Result<Option<T>, err::Error> {
+        match self.children.first() {
+            Some(tag) if tag.number == number => self.field_with(&self.cx.implicit(number)).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Decodes the next field if it is explicitly tagged `number`.
+    pub fn explicit<T: BerRepr> (&mut self, number: Number) -> Result<Option<T>, err::Error> {
+        let wrapper = match self.children.first() {
+            Some(tag) if tag.number == number => tag,
+            _ => return Ok(None),
+        };
+        self.children = &self.children[1..];
+        match wrapper.payload {
+            Payload::Constructed(ref inner) if inner.len() == 1 => T::from_tag(&inner[0], &self.cx).map(Some),
+            _ => Err(wrapper.error(err::Kind::InvalidValue)),
+        }
+    }
+
+    /// Fails with `Kind::InvalidValue` at the first field nothing decoded.
+    pub fn finish (self) -> Result<(), err::Error> {
+        match self.children.first() {
+            Some(extra) => Err(extra.error(err::Kind::InvalidValue)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl BerRepr for bool {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        cx.tag(Tag::boolean(*self))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<bool, err::Error> {
+        cx.check(tag, Number::Universal(Type::Boolean))?;
+        if cx.der { tag.as_bool_der() } else { tag.as_bool() }
+    }
+}
+
+impl BerRepr for i64 {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        cx.tag(Tag::integer(*self))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<i64, err::Error> {
+        cx.check(tag, Number::Universal(Type::Integer))?;
+        tag.as_i64()
+    }
+}
+
+impl BerRepr for u64 {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        cx.tag(Tag::unsigned(*self))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<u64, err::Error> {
+        cx.check(tag, Number::Universal(Type::Integer))?;
+        tag.as_u64()
+    }
+}
+
+impl BerRepr for String {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        cx.tag(Tag::utf8_string(self))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<String, err::Error> {
+        cx.check(tag, Number::Universal(Type::Utf8String))?;
+        tag.as_str_of(Type::Utf8String).map(|s| s.into_owned())
+    }
+}
+
+impl BerRepr for Oid {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        cx.tag(Tag::oid(self))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Oid, err::Error> {
+        cx.check(tag, Number::Universal(Type::ObjectIdentifier))?;
+        tag.as_oid()
+    }
+}
+
+/// SEQUENCE OF `T`.
+impl<T: BerRepr> BerRepr for Vec<T> {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        let inner = cx.inner();
+        cx.tag(Tag::sequence(self.iter().map(|v| v.to_tag(&inner)).collect()))
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Vec<T>, err::Error> {
+        let mut fields = Fields::of(tag, cx)?;
+        let mut values = Vec::with_capacity(fields.children.len());
+        while !fields.children.is_empty() {
+            values.push(fields.field()?);
+        }
+        Ok(values)
+    }
+}
+
+impl<T: BerRepr> BerRepr for Box<T> {
+    fn to_tag (&self, cx: &Context) -> Tag {
+        (**self).to_tag(cx)
+    }
+
+    fn from_tag (tag: &Tag, cx: &Context) -> Result<Box<T>, err::Error> {
+        T::from_tag(tag, cx).map(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::TagNumber;
+
+    fn context (n: u32) -> Number {
+        Number::ContextSpecific(TagNumber::new(n))
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        serial:  u64,
+        names:   Vec<String>,
+        expired: Option<bool>,
+    }
+
+    impl BerRepr for Entry {
+        fn to_tag (&self, cx: &Context) -> Tag {
+            let inner = cx.inner();
+            let mut fields = vec![self.serial.to_tag(&inner), self.names.to_tag(&inner)];
+            if let Some(ref expired) = self.expired {
+                fields.push(expired.to_tag(&inner.implicit(context(0))));
+            }
+            cx.tag(Tag::sequence(fields))
+        }
+
+        fn from_tag (tag: &Tag, cx: &Context) -> Result<Entry, err::Error> {
+            let mut fields = Fields::of(tag, cx)?;
+            let entry = Entry {
+                serial: fields.field()?,
+                names: fields.field()?,
+                expired: fields.optional(context(0))?,
+            };
+            fields.finish()?;
+            Ok(entry)
+        }
+    }
+
+    #[test]
+    fn test_round_trip () {
+        let entries = vec![
+            Entry { serial: 1, names: vec!["a".into(), "b".into()], expired: Some(true) },
+            Entry { serial: u64::MAX, names: vec![], expired: None },
+        ];
+        let tag = to_tag(&entries);
+
+        let mut buf = Vec::new();
+        tag.write(&mut buf).unwrap();
+        let read = Tag::read(&mut &buf[..]).unwrap();
+        assert_eq!(from_tag::<Vec<Entry>>(&read).unwrap(), entries);
+
+        // the same value implicitly tagged
+        let implicit = Context::default().implicit(context(2));
+        let tagged = entries[0].to_tag(&implicit);
+        assert_eq!(tagged.number, context(2));
+        assert_eq!(Entry::from_tag(&tagged, &implicit).unwrap(), entries[0]);
+        assert!(from_tag::<Entry>(&tagged).is_err());
+
+        // and explicitly tagged, as a field of another SEQUENCE
+        let wrapper = Tag::sequence(vec![explicit(context(1), to_tag(&entries[1]))]);
+        let mut fields = Fields::of(&wrapper, &Context::default()).unwrap();
+        assert_eq!(fields.explicit::<Entry>(context(0)).unwrap(), None);
+        assert_eq!(fields.explicit::<Entry>(context(1)).unwrap().as_ref(), Some(&entries[1]));
+        fields.finish().unwrap();
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Holder {
+        id:    i64,
+        entry: Entry,
+    }
+
+    impl BerRepr for Holder {
+        fn to_tag (&self, cx: &Context) -> Tag {
+            let inner = cx.inner();
+            cx.tag(Tag::sequence(vec![self.id.to_tag(&inner), self.entry.to_tag(&inner)]))
+        }
+
+        fn from_tag (tag: &Tag, cx: &Context) -> Result<Holder, err::Error> {
+            Holder::from_tag_with_spans(tag, cx).map(|(holder, _)| holder)
+        }
+
+        fn from_tag_with_spans (tag: &Tag, cx: &Context) -> Result<(Holder, Spans), err::Error> {
+            let mut spans = Spans::new(Span::of(tag));
+            let mut fields = Fields::of(tag, cx)?;
+            let holder = Holder {
+                id: fields.spanned("id", &mut spans)?,
+                entry: fields.spanned("entry", &mut spans)?,
+            };
+            fields.finish()?;
+            Ok((holder, spans))
+        }
+    }
+
+    #[test]
+    fn test_spans () {
+        let holder = Holder { id: 300, entry: Entry { serial: 9, names: vec!["x".into()], expired: None } };
+        let mut buf = Vec::new();
+        to_tag(&holder).write(&mut buf).unwrap();
+
+        let tag = Tag::read(&mut &buf[..]).unwrap();
+        let (decoded, spans) = Holder::from_tag_with_spans(&tag, &Context::default()).unwrap();
+        assert_eq!(decoded, holder);
+        assert_eq!(spans.whole(), Some(Span { offset: 0, length: buf.len() }));
+        assert_eq!(spans.get("id").unwrap().bytes(&buf), Some(&[0x02, 0x02, 0x01, 0x2C][..]));
+        assert_eq!(spans.get("entry").unwrap().end(), buf.len() - 2);
+        assert_eq!(spans.iter().map(|(n, _)| n).collect::<Vec<_>>(), ["id", "entry"]);
+
+        // a field's own fields are named under it
+        let mut outer = Spans::new(None);
+        outer.insert("holder", spans);
+        assert_eq!(outer.get("holder.id"), Some(Span { offset: 2, length: 4 }));
+
+        // built in memory, nothing has a span
+        let (_, spans) = Holder::from_tag_with_spans(&to_tag(&holder), &Context::default()).unwrap();
+        assert_eq!(spans.whole(), None);
+        assert_eq!(spans.get("id"), None);
+    }
+
+    #[test]
+    fn test_errors () {
+        assert!(matches!(from_tag::<bool>(&Tag::integer(1)).unwrap_err().kind, err::Kind::InvalidTypeAndFlavor));
+
+        let loose = Tag::primitive(Number::Universal(Type::Boolean), vec![0x01]);
+        assert!(from_tag::<bool>(&loose).unwrap());
+        assert!(bool::from_tag(&loose, &Context { der: true, ..Context::default() }).is_err());
+
+        // a missing field, then one too many
+        let short = Tag::sequence(vec![Tag::integer(1)]);
+        assert!(matches!(from_tag::<Entry>(&short).unwrap_err().kind, err::Kind::InvalidValue));
+        let long = Tag::sequence(vec![Tag::integer(1), Tag::sequence(vec![]), Tag::null()]);
+        assert!(matches!(from_tag::<Entry>(&long).unwrap_err().kind, err::Kind::InvalidValue));
+
+        let implicit = Context::default().implicit(Number::Application(TagNumber::new(3)));
+        assert_eq!(7i64.to_tag(&implicit).number, Number::Application(TagNumber::new(3)));
+        assert!(i64::from_tag(&Tag::integer(7), &implicit).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Declarative re-tagging, for gateways translating between two dialects of the same
+//! ASN.1 module that differ only in the tags they assign.
+
+use crate::tag::{Class, Number, Payload, Tag};
+
+/// What a rule applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Tags reached through exactly these numbers, starting at the root.
+    Path(Vec<Number>),
+    /// Tags with this number, wherever they are.
+    Number(Number),
+    /// Every tag of this class.
+    Class(Class),
+}
+
+/// What a matching tag becomes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Number(Number),
+    /// The same number in another class.
+    Class(Class),
+}
+
+/// A tag no rule applied to, or whose target could not be built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unmapped {
+    /// Child indices from the root to the tag.
+    pub path:   Vec<usize>,
+    pub number: Number,
+}
+
+/// The outcome of applying a `Mapping`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub mapped:   usize,
+    pub unmapped: Vec<Unmapped>,
+}
+
+/// An ordered set of re-tagging rules. Path rules win over number rules, which win
+/// over class rules; within a kind the rule added first wins.
+#[derive(Debug, Clone, Default)]
+pub struct Mapping {
+    rules:            Vec<(Source, Target)>,
+    report_universal: bool,
+}
+
+impl Mapping {
+    pub fn new () -> Mapping {
+        Mapping::default()
+    }
+
+    pub fn rule (mut self, source: Source, target: Target) -> Mapping {
+        self.rules.push((source, target));
+        self
+    }
+
+    /// Whether universal tags without a rule are reported as unmapped. Off by default,
+    /// since dialects normally share their universal types.
+    pub fn report_universal (mut self, report: bool) -> Mapping {
+        self.report_universal = report;
+        self
+    }
+
+    /// Re-tags `tag` and its descendants in place.
+    pub fn apply (&self, tag: &mut Tag) -> Report {
+        let mut report = Report::default();
+        self.apply_at(tag, &mut Vec::new(), &mut Vec::new(), &mut report);
+        report
+    }
+
+    fn find (&self, numbers: &[Number], number: &Number) -> Option<&Target> {
+        let by = |f: &dyn Fn(&Source) -> bool| self.rules.iter().find(|r| f(&r.0)).map(|r| &r.1);
+
+        by(&|s| matches!(*s, Source::Path(ref p) if p[..] == *numbers))
+            .or_else(|| by(&|s| matches!(*s, Source::Number(ref n) if n == number)))
+            .or_else(|| by(&|s| matches!(*s, Source::Class(c) if c == number.class())))
+    }
+
+    // `numbers` holds the original numbers from the root down to and including `tag`,
+    // so rules match the source dialect even after ancestors were re-tagged.
+    fn apply_at (&self, tag: &mut Tag, path: &mut Vec<usize>, numbers: &mut Vec<Number>, report: &mut Report) {
+        numbers.push(tag.number);
+
+        let target = self.find(numbers, &tag.number).and_then(|t| match *t {
+            Target::Number(ref n) => Some(*n),
+            Target::Class(c) => Number::from_parts(c, tag.number.value()),
+        });
+
+        match target {
+            Some(n) => {
+                tag.number = n;
+                report.mapped += 1;
+            },
+            None if self.report_universal || tag.number.class() != Class::Universal =>
+                report.unmapped.push(Unmapped {
+                    path: path.clone(),
+                    number: tag.number,
+                }),
+            None => {},
+        }
+
+        if let Payload::Constructed(ref mut children) = tag.payload {
+            for (i, child) in children.iter_mut().enumerate() {
+                path.push(i);
+                self.apply_at(child, path, numbers, report);
+                path.pop();
+            }
+        }
+
+        numbers.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::{TagNumber, Type};
+
+    fn tag (number: Number, children: Vec<Tag>) -> Tag {
+        Tag {
+            number,
+            offset: None,
+            payload: Payload::Constructed(children),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn test_apply () {
+        let mut message = tag(Number::Application(TagNumber::new(2)), vec![
+            tag(Number::ContextSpecific(TagNumber::new(0)), vec![]),
+            tag(Number::Universal(Type::Sequence), vec![
+                tag(Number::ContextSpecific(TagNumber::new(0)), vec![]),
+                tag(Number::Private(TagNumber::new(9)), vec![]),
+            ]),
+        ]);
+
+        let mapping = Mapping::new()
+            .rule(Source::Path(vec![Number::Application(TagNumber::new(2)), Number::Universal(Type::Sequence), Number::ContextSpecific(TagNumber::new(0))]),
+                  Target::Number(Number::ContextSpecific(TagNumber::new(5))))
+            .rule(Source::Number(Number::Application(TagNumber::new(2))), Target::Number(Number::Application(TagNumber::new(12))))
+            .rule(Source::Class(Class::ContextSpecific), Target::Class(Class::Application));
+
+        let report = mapping.apply(&mut message);
+        assert_eq!(report.mapped, 3);
+        assert_eq!(report.unmapped, vec![Unmapped { path: vec![1, 1], number: Number::Private(TagNumber::new(9)) }]);
+
+        assert_eq!(message.number, Number::Application(TagNumber::new(12)));
+        match message.payload {
+            Payload::Constructed(ref children) => {
+                assert_eq!(children[0].number, Number::Application(TagNumber::new(0)));
+                match children[1].payload {
+                    Payload::Constructed(ref inner) => assert_eq!(inner[0].number, Number::ContextSpecific(TagNumber::new(5))),
+                    _ => unreachable!(),
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Helpers for getting from a raw S/MIME message part to the CMS blob inside it.
+//!
+//! Mail tooling usually hands over a MIME entity: a header block, a blank line and then
+//! a base64 encoded `application/pkcs7-mime` body. The functions here peel those layers
+//! off one at a time until binary BER remains.
+
+use crate::err;
+use crate::tag::Tag;
+use crate::util;
+
+/// The outermost layer found in front of a CMS blob.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Layer {
+    /// MIME headers followed by a blank line.
+    Headers,
+    /// Base64 transfer encoding.
+    Base64,
+    /// Binary BER, nothing left to strip.
+    Binary,
+}
+
+/// Determines the outermost layer of `buf`.
+pub fn detect (buf: &[u8]) -> Layer {
+    let buf = trim_start(buf);
+
+    if buf.first() == Some(&0x30) {
+        // a ContentInfo is always a SEQUENCE, and '0' never starts a header or base64 text
+        Layer::Binary
+    } else if header_end(buf).is_some() {
+        Layer::Headers
+    } else {
+        Layer::Base64
+    }
+}
+
+/// Returns the body following the MIME header block, or `None` if `buf` does not start
+/// with one.
+pub fn strip_headers (buf: &[u8]) -> Option<&[u8]> {
+    let buf = trim_start(buf);
+    header_end(buf).map(|end| &buf[end..])
+}
+
+/// Strips every MIME and transfer-encoding layer from `buf`, returning the binary CMS blob.
+pub fn unwrap (buf: &[u8]) -> Result<Vec<u8>, err::Error> {
+    let mut current = buf.to_vec();
+
+    loop {
+        current = match detect(&current) {
+            Layer::Binary => return Ok(trim_start(&current).to_vec()),
+            Layer::Headers => strip_headers(&current).unwrap_or(&[]).to_vec(),
+            Layer::Base64 => match util::base64_decode(&current) {
+                // an empty body would otherwise decode to itself forever
+                Some(ref decoded) if decoded.is_empty() =>
+                    return Err(err::Error::new(err::Kind::InvalidBase64, 0, None)),
+                Some(decoded) => decoded,
+                None => return Err(err::Error::new(err::Kind::InvalidBase64, 0, None)),
+            },
+        };
+    }
+}
+
+/// Strips every S/MIME layer from `buf` and parses the resulting CMS blob.
+pub fn read (buf: &[u8]) -> Result<Tag, err::Error> {
+    let der = unwrap(buf)?;
+    Tag::read(&mut &der[..])
+}
+
+fn trim_start (buf: &[u8]) -> &[u8] {
+    let start = buf.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(buf.len());
+    &buf[start..]
+}
+
+// Returns the offset of the body if `buf` begins with `Name: value` header lines.
+fn header_end (buf: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    let mut lines = 0;
+
+    while pos < buf.len() {
+        let end = buf[pos..].iter().position(|&b| b == b'\n').map(|i| pos + i + 1).unwrap_or(buf.len());
+        let line = &buf[pos..end];
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+
+        if content.is_empty() {
+            return if lines > 0 { Some(end) } else { None };
+        }
+
+        let folded = content[0] == b' ' || content[0] == b'\t';
+        if !folded {
+            let colon = content.iter().position(|&b| b == b':')?;
+            let name = &content[..colon];
+            if name.is_empty() || !name.iter().all(|&b| b.is_ascii_alphanumeric() || b == b'-') {
+                return None;
+            }
+        } else if lines == 0 {
+            return None;
+        }
+
+        lines += 1;
+        pos = end;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::{LengthForm, Number, Payload, Type};
+
+    const DER: [u8; 9] = [0x30, 0x80, 0x0C, 0x03, 0x64, 0x65, 0x66, 0x00, 0x00];
+
+    fn expected () -> Tag {
+        Tag {
+            number: Number::Universal(Type::Sequence),
+            offset: Some(0),
+            payload: Payload::Constructed(vec![ Tag {
+                number: Number::Universal(Type::Utf8String),
+                offset: Some(2),
+                payload: Payload::Primitive(vec![0x64, 0x65, 0x66]),
+                repaired: None,
+                length_form: None,
+                layout: None,
+            } ]),
+            repaired: None,
+            length_form: Some(LengthForm::Indefinite),
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn test_detect () {
+        assert_eq!(detect(&DER), Layer::Binary);
+        assert_eq!(detect(b"MIAMA2RlZgAA"), Layer::Base64);
+        assert_eq!(detect(b"Content-Type: application/pkcs7-mime\r\n\r\nMIAMA2RlZgAA"), Layer::Headers);
+    }
+
+    #[test]
+    fn test_read_binary () {
+        assert_eq!(read(&DER).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_read_mime_part () {
+        let part = b"Content-Type: application/pkcs7-mime; smime-type=enveloped-data;\r\n\
+                     \tname=smime.p7m\r\n\
+                     Content-Transfer-Encoding: base64\r\n\
+                     \r\n\
+                     MIAM\r\nA2RlZgAA\r\n";
+        assert_eq!(read(part).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_invalid_base64 () {
+        assert!(unwrap(b"not base64 at all!").is_err());
+        assert!(unwrap(b"Content-Type: application/pkcs7-mime\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Summary counts over trees, for getting a feel for a corpus before writing anything
+//! that handles it, and for checking that two corpora are shaped alike: traffic from a
+//! new encoder against the legacy system's, say.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::container;
+use crate::encoder;
+use crate::err;
+use crate::tag::{LengthPolicy, Number, Payload, Tag, WriteOptions};
+use crate::util;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Stats {
+    /// The number of trees added.
+    pub messages:      usize,
+    pub primitives:    usize,
+    pub constructed:   usize,
+    /// The deepest nesting seen; a lone primitive has depth 0.
+    pub max_depth:     usize,
+    /// Content octets across all primitives, not counting identifiers and lengths.
+    pub content_bytes: usize,
+    /// How many tags carry each number.
+    pub numbers:       BTreeMap<Number, usize>,
+    /// How many messages reach each depth at their deepest.
+    pub depths:        BTreeMap<usize, usize>,
+    /// How many messages have encodings of each size, rounded up to a power of two, as
+    /// they were read.
+    pub sizes:         BTreeMap<usize, usize>,
+}
+
+impl Stats {
+    pub fn new () -> Stats {
+        Stats::default()
+    }
+
+    /// The counts for a single tree.
+    pub fn of (tag: &Tag) -> Stats {
+        let mut stats = Stats::new();
+        stats.add(tag);
+        stats
+    }
+
+    /// Adds a tree's counts to these.
+    pub fn add (&mut self, tag: &Tag) {
+        self.messages += 1;
+        let opts = WriteOptions { length_policy: LengthPolicy::Preserve, ..WriteOptions::default() };
+        *self.sizes.entry(encoder::encoded_len(tag, &opts).next_power_of_two()).or_insert(0) += 1;
+
+        let mut deepest = 0;
+        let mut stack = vec![(tag, 0)];
+        while let Some((tag, depth)) = stack.pop() {
+            deepest = deepest.max(depth);
+            *self.numbers.entry(tag.number).or_insert(0) += 1;
+
+            match tag.payload {
+                Payload::Primitive(ref v) => {
+                    self.primitives += 1;
+                    self.content_bytes += v.len();
+                },
+                Payload::Constructed(ref children) => {
+                    self.constructed += 1;
+                    stack.extend(children.iter().map(|c| (c, depth + 1)));
+                },
+            }
+        }
+        self.max_depth = self.max_depth.max(deepest);
+        *self.depths.entry(deepest).or_insert(0) += 1;
+    }
+
+    /// Adds the trees in every file under `dir`, each read by `container::read_any`. A
+    /// file that doesn't read is handed to `skipped` with its error and left out.
+    pub fn add_dir (&mut self, dir: &Path, skipped: &mut dyn FnMut(&Path, err::Error)) -> io::Result<()> {
+        for file in util::files_under(dir)? {
+            let path = dir.join(&file);
+            match container::read_any(&fs::read(&path)?) {
+                Ok(tags) => tags.iter().for_each(|tag| self.add(tag)),
+                Err(e) => skipped(&path, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets these counts beside `other`'s; see `Comparison`.
+    pub fn compare<'a> (&'a self, other: &'a Stats) -> Comparison<'a> {
+        Comparison { a: self, b: other }
+    }
+
+    fn tags (&self) -> usize {
+        self.primitives + self.constructed
+    }
+}
+
+/// Two sets of counts side by side, from `Stats::compare`. Its `Display` is a table with
+/// a column for each: totals, then how tags spread over numbers and messages over depths
+/// and sizes. The spreads are percentages, so corpora of different sizes compare.
+pub struct Comparison<'a> {
+    pub a: &'a Stats,
+    pub b: &'a Stats,
+}
+
+impl<'a> Comparison<'a> {
+    fn row (&self, f: &mut fmt::Formatter, label: &str, a: f64, b: f64) -> fmt::Result {
+        writeln!(f, "  {:<28} {:>10.1} {:>10.1} {:>+10.1}", label, a, b, b - a)
+    }
+
+    fn spread<K: Ord + Clone> (&self, f: &mut fmt::Formatter, title: &str, a: (&BTreeMap<K, usize>, usize), b: (&BTreeMap<K, usize>, usize), label: impl Fn(&K) -> String) -> fmt::Result {
+        writeln!(f, "{}", title)?;
+        let mut keys: Vec<&K> = a.0.keys().chain(b.0.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            self.row(f, &label(key), percent(a.0.get(key), a.1), percent(b.0.get(key), b.1))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Comparison<'a> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (a, b) = (self.a, self.b);
+        writeln!(f, "  {:<28} {:>10} {:>10} {:>10}", "", "A", "B", "B - A")?;
+        self.row(f, "messages", a.messages as f64, b.messages as f64)?;
+        self.row(f, "tags per message", per(a.tags(), a.messages), per(b.tags(), b.messages))?;
+        self.row(f, "content bytes per message", per(a.content_bytes, a.messages), per(b.content_bytes, b.messages))?;
+        self.row(f, "max depth", a.max_depth as f64, b.max_depth as f64)?;
+        self.spread(f, "numbers, % of tags", (&a.numbers, a.tags()), (&b.numbers, b.tags()), |n| format!("{:?}", n))?;
+        self.spread(f, "depth, % of messages", (&a.depths, a.messages), (&b.depths, b.messages), |d| d.to_string())?;
+        self.spread(f, "size, % of messages", (&a.sizes, a.messages), (&b.sizes, b.messages), |s| format!("<= {} bytes", s))
+    }
+}
+
+fn per (n: usize, of: usize) -> f64 {
+    if of == 0 { 0.0 } else { n as f64 / of as f64 }
+}
+
+fn percent (n: Option<&usize>, of: usize) -> f64 {
+    per(n.copied().unwrap_or(0), of) * 100.0
+}
+
+impl fmt::Display for Stats {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "messages:      {}", self.messages)?;
+        writeln!(f, "primitives:    {}", self.primitives)?;
+        writeln!(f, "constructed:   {}", self.constructed)?;
+        writeln!(f, "max depth:     {}", self.max_depth)?;
+        writeln!(f, "content bytes: {}", self.content_bytes)?;
+        for (number, count) in &self.numbers {
+            writeln!(f, "{:>8} {:?}", count, number)?;
+        }
+        writeln!(f, "depths:")?;
+        for (depth, count) in &self.depths {
+            writeln!(f, "{:>8} {}", count, depth)?;
+        }
+        writeln!(f, "sizes:")?;
+        for (size, count) in &self.sizes {
+            writeln!(f, "{:>8} <= {} bytes", count, size)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tag::Type;
+
+    #[test]
+    fn test_stats () {
+        let mut stats = Stats::of(&Tag::sequence(vec![
+            Tag::integer(300),
+            Tag::sequence(vec![Tag::null(), Tag::integer(1)]),
+        ]));
+        stats.add(&Tag::null());
+
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.primitives, 4);
+        assert_eq!(stats.constructed, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.content_bytes, 3);
+        assert_eq!(stats.numbers.get(&Number::Universal(Type::Null)), Some(&2));
+        assert_eq!(stats.numbers.keys().next(), Some(&Number::Universal(Type::Integer)));
+        assert_eq!(stats.depths, BTreeMap::from([(0, 1), (2, 1)]));
+        assert_eq!(stats.sizes, BTreeMap::from([(2, 1), (32, 1)]));
+    }
+
+    #[test]
+    fn test_compare () {
+        let a = Stats::of(&Tag::sequence(vec![Tag::integer(1), Tag::null()]));
+        let mut b = Stats::of(&Tag::sequence(vec![Tag::integer(1)]));
+        b.add(&Tag::sequence(vec![Tag::integer(2)]));
+
+        let table = a.compare(&b).to_string();
+        let line = |label: &str| table.lines().find(|l| l.trim_start().starts_with(label)).unwrap().split_whitespace().rev().take(3).collect::<Vec<_>>();
+        assert_eq!(line("messages"), ["+1.0", "2.0", "1.0"]);
+        assert_eq!(line("Universal(Null)"), ["-33.3", "0.0", "33.3"]);
+        assert_eq!(line("<= 8 bytes"), ["+100.0", "100.0", "0.0"]);
+    }
+
+    #[test]
+    fn test_add_dir () {
+        let root = std::env::temp_dir().join(format!("ber-stats-{}", std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+
+        // two messages back to back, a container of one, and not BER at all
+        let mut container = Vec::new();
+        container::write(&mut container, &[Tag::null()]).unwrap();
+        fs::write(root.join("pair.ber"), [0x05, 0x00, 0x30, 0x03, 0x02, 0x01, 0x05]).unwrap();
+        fs::write(root.join("nested/one.berc"), container).unwrap();
+        fs::write(root.join("notes.txt"), b"hello").unwrap();
+
+        let mut stats = Stats::new();
+        let mut skipped = Vec::new();
+        let result = stats.add_dir(&root, &mut |path, _| skipped.push(path.to_path_buf()));
+        fs::remove_dir_all(&root).ok();
+        result.unwrap();
+
+        assert_eq!(skipped, [root.join("notes.txt")]);
+        assert_eq!((stats.messages, stats.constructed), (3, 1));
+        assert_eq!(stats.numbers[&Number::Universal(Type::Null)], 2);
+    }
+}
@@ -0,0 +1,259 @@
+//! Pull-based decoding that hands out primitive content as a reader.
+//!
+//! `Tag::read` holds every primitive's content in memory, which is the wrong shape for a
+//! signed firmware image or the encapsulated content of a large PKCS#7 message. A
+//! `Stream` walks a message header by header, reporting the same `Start` and `End`
+//! events as a `decoder::Decoder`; after a primitive's `Start`, `Stream::content` reads
+//! its content straight from the input, bounded by the declared length, so it can be
+//! copied to disk or a hasher a buffer at a time. Content left unread is skipped.
+
+use std::io::{self, Read};
+
+use crate::decoder::Event;
+use crate::err;
+use crate::layer::{ReadLayer, Tracked};
+use crate::tag::{self, Flavor, HeaderLayout, Length, Number, Type};
+
+// An open constructed tag: where its content ends, or `None` for the indefinite form.
+struct Frame {
+    end: Option<usize>,
+}
+
+/// The events of the messages read from `R`, with primitive content left in the input
+/// until asked for.
+///
+/// After an error the stream's state is unspecified.
+pub struct Stream<R> {
+    r:         Tracked<R>,
+    frames:    Vec<Frame>,
+    // content of the current primitive not yet read, and whether its `End` is due
+    unread:    u64,
+    primitive: bool,
+}
+
+impl<R: Read> Stream<R> {
+    pub fn new (r: R) -> Stream<R> {
+        Stream {
+            r: Tracked::new(r),
+            frames: Vec::new(),
+            unread: 0,
+            primitive: false,
+        }
+    }
+
+    /// Stream offset of the next byte to be read.
+    pub fn offset (&self) -> usize {
+        self.r.tell()
+    }
+
+    /// The next event, or `None` once the input ends between messages. Never
+    /// `Event::Content`: read that through `content` instead.
+    pub fn next_event (&mut self) -> Result<Option<Event>, err::Error> {
+        if self.primitive {
+            self.primitive = false;
+            let unread = self.unread;
+            let skipped = io::copy(&mut self.content(), &mut io::sink()).map_err(|e| self.error(e.into()))?;
+            debug_assert_eq!(skipped, unread);
+            return Ok(Some(Event::End { offset: self.offset() }));
+        }
+
+        if let Some(&Frame { end: Some(end) }) = self.frames.last() {
+            if self.offset() >= end {
+                if self.offset() > end {
+                    // the last child ran past the end of its parent
+                    return Err(self.error(err::Kind::LengthExceedsContainer));
+                }
+                self.frames.pop();
+                return Ok(Some(Event::End { offset: end }));
+            }
+        }
+
+        let offset = self.offset();
+        let identifiers = tag::read_identifiers(&mut self.r);
+        let number_octets = self.offset() - offset;
+        let header = identifiers.and_then(|x| tag::read_length(&mut self.r).map(|l| (x, l)));
+        let ((_class, flavor, number), length) = match header {
+            Ok(header) => header,
+            Err(e) => {
+                let e = tag::truncation(err::Error { offset: self.offset(), ..e });
+                return match e.kind {
+                    err::Kind::Truncated { .. } if self.frames.is_empty() && self.offset() == offset => Ok(None),
+                    _ => Err(e),
+                };
+            },
+        };
+        let layout = HeaderLayout::observed(&number, &length, number_octets - 1, self.offset() - offset - number_octets - 1);
+
+        let indefinite_parent = matches!(self.frames.last(), Some(&Frame { end: None }));
+        if indefinite_parent && number == Number::Universal(Type::Eoc) && flavor == Flavor::Primitive && length == Length::Some(0) {
+            self.frames.pop();
+            return Ok(Some(Event::End { offset: self.offset() }));
+        }
+        if number == Number::Universal(Type::Eoc) && matches!(self.frames.last(), Some(&Frame { end: Some(_) })) {
+            return Err(err::Error::new(err::Kind::UnexpectedEoc, offset, None));
+        }
+
+        let length = match (flavor, length) {
+            (Flavor::Primitive, Length::Indefinite) => return Err(self.error(err::Kind::InvalidLength)),
+            (_, Length::Indefinite) => None,
+            (_, Length::Some(l)) => Some(l),
+        };
+        let event = Event::Start {
+            offset,
+            number,
+            constructed: flavor == Flavor::Constructed,
+            length: length.map(|l| usize::try_from(l).unwrap_or(usize::MAX)),
+            layout,
+        };
+
+        match flavor {
+            Flavor::Constructed => {
+                let end = length.map(|l| self.offset().saturating_add(usize::try_from(l).unwrap_or(usize::MAX)));
+                self.frames.push(Frame { end });
+            },
+            Flavor::Primitive => {
+                self.unread = length.unwrap_or(0);
+                self.primitive = true;
+            },
+        }
+        Ok(Some(event))
+    }
+
+    /// The content of the primitive most recently started, as far as it hasn't been read
+    /// already; empty anywhere else. Input ending before the declared length is an
+    /// `io::ErrorKind::UnexpectedEof` error.
+    pub fn content (&mut self) -> Content<'_, R> {
+        Content { stream: self }
+    }
+
+    pub fn into_inner (self) -> R {
+        self.r.into_inner()
+    }
+
+    fn error (&self, kind: err::Kind) -> err::Error {
+        tag::truncation(err::Error::new(kind, self.offset(), None))
+    }
+}
+
+/// The unread content of a primitive, from `Stream::content`.
+pub struct Content<'a, R> {
+    stream: &'a mut Stream<R>,
+}
+
+impl<'a, R> Content<'a, R> {
+    /// Content bytes not yet read.
+    pub fn remaining (&self) -> u64 {
+        self.stream.unread
+    }
+}
+
+impl<'a, R: Read> Read for Content<'a, R> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stream.unread == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = usize::try_from(self.stream.unread).unwrap_or(usize::MAX).min(buf.len());
+        let n = self.stream.r.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.stream.unread -= n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // SEQUENCE (indefinite) { OCTET STRING (100000 bytes), [1] { NULL } }, then NULL
+    fn input () -> Vec<u8> {
+        let mut v = vec![0x30, 0x80, 0x04, 0x83, 0x01, 0x86, 0xA0];
+        v.extend((0..100_000u32).map(|i| i as u8));
+        v.extend_from_slice(&[0xA1, 0x02, 0x05, 0x00, 0x00, 0x00, 0x05, 0x00]);
+        v
+    }
+
+    fn start (offset: usize, number: Number, constructed: bool, length: Option<usize>) -> Event {
+        Event::Start { offset, number, constructed, length, layout: None }
+    }
+
+    #[test]
+    fn test_content_as_reader () {
+        let input = input();
+        let mut s = Stream::new(&input[..]);
+
+        assert_eq!(s.next_event().unwrap(), Some(start(0, Number::Universal(Type::Sequence), true, None)));
+        assert_eq!(s.next_event().unwrap(), Some(start(2, Number::Universal(Type::OctetString), false, Some(100_000))));
+
+        // copied out in pieces no larger than the buffer handed to `read`
+        let mut content = s.content();
+        assert_eq!(content.remaining(), 100_000);
+        let mut copied = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = content.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            copied.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(copied, &input[7..100_007]);
+
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_007 }));
+        assert!(matches!(s.next_event().unwrap(), Some(Event::Start { constructed: true, length: Some(2), .. })));
+        assert!(matches!(s.next_event().unwrap(), Some(Event::Start { constructed: false, length: Some(0), .. })));
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_011 }));
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_011 }));
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_013 }));
+
+        // the next message, then a clean end
+        assert!(matches!(s.next_event().unwrap(), Some(Event::Start { offset: 100_013, .. })));
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_015 }));
+        assert_eq!(s.next_event().unwrap(), None);
+    }
+
+    #[test]
+    fn test_unread_content_is_skipped () {
+        let input = input();
+        let mut s = Stream::new(crate::layer::Tracked::new(&input[..]));
+        s.next_event().unwrap();
+        s.next_event().unwrap();
+
+        let mut head = [0; 10];
+        s.content().read_exact(&mut head).unwrap();
+        assert_eq!(s.next_event().unwrap(), Some(Event::End { offset: 100_007 }));
+        assert_eq!(s.into_inner().tell(), 100_007);
+    }
+
+    #[test]
+    fn test_errors () {
+        // content cut short
+        let input = input();
+        let mut s = Stream::new(&input[..5000]);
+        s.next_event().unwrap();
+        s.next_event().unwrap();
+        let e = io::copy(&mut s.content(), &mut io::sink()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        // skipping it instead
+        let mut s = Stream::new(&input[..5000]);
+        s.next_event().unwrap();
+        s.next_event().unwrap();
+        let e = s.next_event().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+        assert_eq!(e.offset, 5000);
+
+        // a child running past its parent
+        let mut s = Stream::new(&[0x30, 0x01, 0x05, 0x00][..]);
+        s.next_event().unwrap();
+        s.next_event().unwrap();
+        s.next_event().unwrap();
+        assert!(matches!(s.next_event().unwrap_err().kind, err::Kind::LengthExceedsContainer));
+
+        // a header cut short inside a message
+        let mut s = Stream::new(&[0x30, 0x80, 0x04][..]);
+        s.next_event().unwrap();
+        assert!(matches!(s.next_event().unwrap_err().kind, err::Kind::Truncated { .. }));
+    }
+}
@@ -0,0 +1,113 @@
+//! Character string content, checked against the repertoire of each string type.
+
+use std::borrow::Cow;
+use std::str;
+
+use crate::err;
+use crate::tag::{Number, Tag, Type};
+
+fn printable (c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b" '()+,-./:=?".contains(&c)
+}
+
+fn ascii_with (v: &[u8], allowed: fn (u8) -> bool) -> Option<Cow<'_, str>> {
+    if v.iter().all(|&c| allowed(c)) {
+        // every allowed byte is ASCII, so this can't fail
+        str::from_utf8(v).ok().map(Cow::Borrowed)
+    } else {
+        None
+    }
+}
+
+fn decode_units<const N: usize> (v: &[u8]) -> Option<Cow<'static, str>> {
+    if !v.len().is_multiple_of(N) {
+        return None;
+    }
+    // surrogates aren't characters, so they're rejected along with out of range values
+    v.chunks(N)
+        .map(|unit| char::from_u32(unit.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32)))
+        .collect::<Option<String>>()
+        .map(Cow::Owned)
+}
+
+impl Tag {
+    /// Decodes a universal character string, checking its content against the type:
+    /// UTF-8 for Utf8String, the PrintableString, NumericString and VisibleString
+    /// alphabets, ASCII for IA5String, UTF-16BE without surrogates for BmpString and
+    /// UCS-4 for UniversalString. Violations are a `Kind::InvalidString` error naming the
+    /// type, other numbers an `InvalidValue` error.
+    pub fn as_str (&self) -> Result<Cow<'_, str>, err::Error> {
+        let t = match self.number {
+            Number::Universal(t) => t,
+            _ => return Err(self.error(err::Kind::InvalidValue)),
+        };
+        self.as_str_of(t)
+    }
+
+    /// Like `as_str`, but decodes the content as string type `t` whatever the tag number,
+    /// for implicitly tagged strings. Both decode constructed strings, reassembling them
+    /// as `as_bytes` does.
+    pub fn as_str_of (&self, t: Type) -> Result<Cow<'_, str>, err::Error> {
+        let decoded = match self.as_bytes()? {
+            Cow::Borrowed(v) => self.decode_str(t, v)?,
+            Cow::Owned(v) => Cow::Owned(self.decode_str(t, &v)?.into_owned()),
+        };
+        Ok(decoded)
+    }
+
+    fn decode_str<'a> (&self, t: Type, v: &'a [u8]) -> Result<Cow<'a, str>, err::Error> {
+        let decoded = match t {
+            Type::Utf8String => str::from_utf8(v).ok().map(Cow::Borrowed),
+            Type::PrintableString => ascii_with(v, printable),
+            Type::NumericString => ascii_with(v, |c| c.is_ascii_digit() || c == b' '),
+            Type::VisibleString => ascii_with(v, |c| (0x20..0x7F).contains(&c)),
+            Type::Ia5String => ascii_with(v, |c| c.is_ascii()),
+            Type::BmpString => decode_units::<2>(v),
+            Type::UniversalString => decode_units::<4>(v),
+            _ => return Err(self.error(err::Kind::InvalidValue)),
+        };
+        decoded.ok_or_else(|| self.error(err::Kind::InvalidString(t)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::err;
+    use crate::tag::{Number, Tag, TagNumber, Type};
+
+    fn string (t: Type, v: &[u8]) -> Tag {
+        Tag::primitive(Number::Universal(t), v.to_vec())
+    }
+
+    fn invalid (t: Type, v: &[u8]) -> bool {
+        matches!(string(t, v).as_str().unwrap_err().kind, err::Kind::InvalidString(k) if k == t)
+    }
+
+    #[test]
+    fn test_as_str () {
+        assert_eq!(string(Type::Utf8String, "häh".as_bytes()).as_str().unwrap(), "häh");
+        assert_eq!(string(Type::PrintableString, b"Acme, Inc. (US)").as_str().unwrap(), "Acme, Inc. (US)");
+        assert_eq!(string(Type::Ia5String, b"a@b.example").as_str().unwrap(), "a@b.example");
+        assert_eq!(string(Type::NumericString, b"12 34").as_str().unwrap(), "12 34");
+        assert_eq!(string(Type::BmpString, &[0x00, 0x41, 0x20, 0xAC]).as_str().unwrap(), "A€");
+        assert_eq!(string(Type::UniversalString, &[0x00, 0x01, 0xF6, 0x00]).as_str().unwrap(), "😀");
+
+        assert!(invalid(Type::Utf8String, &[0xC3]));
+        assert!(invalid(Type::PrintableString, b"a@b"));
+        assert!(invalid(Type::Ia5String, &[0x80]));
+        assert!(invalid(Type::NumericString, b"12a"));
+        assert!(invalid(Type::VisibleString, b"tab\there"));
+        assert!(invalid(Type::BmpString, &[0x00]));
+        assert!(invalid(Type::BmpString, &[0xD8, 0x3D, 0xDE, 0x00]));
+        assert!(invalid(Type::UniversalString, &[0x00, 0x11, 0x00, 0x00]));
+
+        assert!(string(Type::OctetString, b"abc").as_str().is_err());
+
+        let segmented = Tag::constructed(Number::Universal(Type::BmpString), vec![
+            string(Type::OctetString, &[0x00, 0x41, 0x20]),
+            string(Type::OctetString, &[0xAC]),
+        ]);
+        assert_eq!(segmented.as_str().unwrap(), "A€");
+        assert_eq!(Tag::primitive(Number::ContextSpecific(TagNumber::new(1)), b"x".to_vec()).as_str_of(Type::Ia5String).unwrap(), "x");
+    }
+}
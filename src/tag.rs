@@ -1,11 +1,18 @@
+use std::fmt;
 use std::io::{self, Write, Read};
+use std::iter::FusedIterator;
+use std::mem;
+use std::str;
+use std::time::Instant;
 
-use byteorder::{self, ReadBytesExt, WriteBytesExt};
+use byteorder::{ReadBytesExt, WriteBytesExt};
 
-use err;
-use util::TrackedRead;
+use crate::encoder;
+use crate::der;
+use crate::err;
+use crate::util::TrackedRead;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum Type {
     Eoc              = 0,
     Boolean          = 1,
@@ -39,8 +46,8 @@ pub enum Type {
 }
 
 impl Type {
-    fn from_i8 (v: i8) -> Type {
-        match v {
+    fn from_i8 (v: i8) -> Option<Type> {
+        Some(match v {
             0 =>  Type::Eoc,
             1 =>  Type::Boolean,
             2 =>  Type::Integer,
@@ -70,21 +77,115 @@ impl Type {
             28 => Type::UniversalString,
             29 => Type::CharacterString,
             30 => Type::BmpString,
-            _  => unreachable!(),
-        }
+            _  => return None,
+        })
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// A tag number outside the universal class. Numbers are bounded to `u32`, so every
+/// one fits in five octets of the long form.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct TagNumber(u32);
+
+impl TagNumber {
+    pub const MAX: TagNumber = TagNumber(u32::MAX);
+
+    pub const fn new (n: u32) -> TagNumber {
+        TagNumber(n)
+    }
+
+    pub const fn get (self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for TagNumber {
+    fn from (n: u32) -> TagNumber {
+        TagNumber(n)
+    }
+}
+
+impl From<TagNumber> for u32 {
+    fn from (n: TagNumber) -> u32 {
+        n.0
+    }
+}
+
+impl TryFrom<i64> for TagNumber {
+    type Error = err::Error;
+
+    /// Fails with `NumberOverflow` for negative numbers and those above `TagNumber::MAX`.
+    fn try_from (n: i64) -> Result<TagNumber, err::Error> {
+        u32::try_from(n)
+            .map(TagNumber)
+            .map_err(|_| err::Error::new(err::Kind::NumberOverflow, 0, None))
+    }
+}
+
+impl TryFrom<u64> for TagNumber {
+    type Error = err::Error;
+
+    fn try_from (n: u64) -> Result<TagNumber, err::Error> {
+        u32::try_from(n)
+            .map(TagNumber)
+            .map_err(|_| err::Error::new(err::Kind::NumberOverflow, 0, None))
+    }
+}
+
+impl fmt::Display for TagNumber {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for TagNumber {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// The class and number of a tag. Variants order by class, then number.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
 pub enum Number {
     Universal(Type),
-    Application(i64),
-    ContextSpecific(i64),
-    Private(i64),
+    Application(TagNumber),
+    ContextSpecific(TagNumber),
+    Private(TagNumber),
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Class {
+impl Number {
+    pub fn class (&self) -> Class {
+        match *self {
+            Number::Universal(_) => Class::Universal,
+            Number::Application(_) => Class::Application,
+            Number::ContextSpecific(_) => Class::ContextSpecific,
+            Number::Private(_) => Class::Private,
+        }
+    }
+
+    /// The tag number within its class.
+    pub fn value (&self) -> u32 {
+        match *self {
+            Number::Universal(t) => t as u32,
+            Number::Application(n) | Number::ContextSpecific(n) | Number::Private(n) => n.get(),
+        }
+    }
+
+    /// Builds a number from its class and value, or returns `None` for a universal value
+    /// with no corresponding `Type`.
+    pub fn from_parts (class: Class, value: u32) -> Option<Number> {
+        let n = TagNumber::new(value);
+        Some(match class {
+            Class::Universal => Number::Universal(i8::try_from(value).ok().and_then(Type::from_i8)?),
+            Class::Application => Number::Application(n),
+            Class::ContextSpecific => Number::ContextSpecific(n),
+            Class::Private => Number::Private(n),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Class {
     Universal       = 0,
     Application     = 1,
     ContextSpecific = 2,
@@ -92,29 +193,29 @@ enum Class {
 }
 
 impl Class {
+    // The class in the low two bits of `v`.
     fn from_u8 (v: u8) -> Class {
-        match v {
+        match v & 0x03 {
             0 => Class::Universal,
             1 => Class::Application,
             2 => Class::ContextSpecific,
-            3 => Class::Private,
-            _ => unreachable!(),
+            _ => Class::Private,
         }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum Flavor {
+pub(crate) enum Flavor {
     Primitive   = 0,
     Constructed = 1,
 }
 
 impl Flavor {
+    // The flavor in the low bit of `v`.
     fn from_u8 (v: u8) -> Flavor {
-        match v {
+        match v & 0x01 {
             0 => Flavor::Primitive,
-            1 => Flavor::Constructed,
-            _ => unreachable!(),
+            _ => Flavor::Constructed,
         }
     }
 }
@@ -125,51 +226,208 @@ pub enum Payload {
     Constructed(Vec<Tag>),
 }
 
-#[derive(PartialEq, Eq, Debug)]
-enum Length {
+impl Payload {
+    pub fn is_constructed (&self) -> bool {
+        matches!(*self, Payload::Constructed(_))
+    }
+
+    /// The content, `None` if constructed.
+    pub fn as_primitive (&self) -> Option<&[u8]> {
+        match *self {
+            Payload::Primitive(ref v) => Some(v),
+            Payload::Constructed(_) => None,
+        }
+    }
+
+    /// The children, `None` if primitive.
+    pub fn children (&self) -> Option<&[Tag]> {
+        match *self {
+            Payload::Primitive(_) => None,
+            Payload::Constructed(ref children) => Some(children),
+        }
+    }
+
+    /// Octets of content if primitive, children if constructed.
+    pub fn len (&self) -> usize {
+        match *self {
+            Payload::Primitive(ref v) => v.len(),
+            Payload::Constructed(ref children) => children.len(),
+        }
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub(crate) enum Length {
     Indefinite,
     Some(u64),
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Tag {
-    pub number:  Number,
-    pub offset:  Option<usize>,
-    pub payload: Payload,
+    pub number:   Number,
+    pub offset:   Option<usize>,
+    pub payload:  Payload,
+    /// Set when a lenient read had to accept content whose size disagreed with the
+    /// declared length.
+    pub repaired: Option<LengthRepair>,
+    /// The length form a constructed tag was read with, or should be written with under
+    /// `LengthPolicy::Preserve`. Reads leave it `None` on primitives, which are always
+    /// definite.
+    pub length_form: Option<LengthForm>,
+    /// How a read found the identifier and length octets laid out, when that was longer
+    /// than needed. `None` for headers already in their shortest form.
+    pub layout: Option<HeaderLayout>,
 }
 
-fn read_extended_number (mut r: &mut Read) -> Result<i64, err::Error> {
-    // 
-    let mut count = 0usize;
-    let mut ret = 0i64;
+// Dropping a tree takes each tag's children out before the tag itself goes, so however
+// deep the tree, no drop recurses more than one level.
+impl Drop for Tag {
+    fn drop (&mut self) {
+        let mut stack = match self.payload {
+            Payload::Constructed(ref mut children) => mem::take(children),
+            Payload::Primitive(_) => return,
+        };
+        while let Some(mut tag) = stack.pop() {
+            if let Payload::Constructed(ref mut children) = tag.payload {
+                stack.append(children);
+            }
+        }
+    }
+}
+
+/// Identifier and length octets in a longer form than needed. Writes under
+/// `LengthPolicy::Preserve` lay headers out the same way, so a tree that was read writes
+/// back byte for byte.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct HeaderLayout {
+    /// The number of octets after the first identifier octet; `None` for the short form.
+    pub number_octets: Option<u8>,
+    /// The number of octets after the first length octet of a definite length; `None`
+    /// for the short form.
+    pub length_octets: Option<u8>,
+}
+
+impl HeaderLayout {
+    // The layout of the shortest header for `number` and `length`.
+    fn shortest (number: &Number, length: &Length) -> HeaderLayout {
+        HeaderLayout {
+            number_octets: match *number {
+                Number::Universal(_) => None,
+                Number::Application(n) | Number::ContextSpecific(n) | Number::Private(n) =>
+                    Some(number_groups(n)).filter(|_| n.get() >= 0x1F),
+            },
+            length_octets: match *length {
+                Length::Some(l) if l >= 0x80 => Some(length_bytes(l)),
+                _ => None,
+            },
+        }
+    }
+
+    // The layout of a header read with `number_octets` octets after the first identifier
+    // octet and `length_octets` after the first length octet, or `None` if it was the
+    // shortest.
+    pub(crate) fn observed (number: &Number, length: &Length, number_octets: usize, length_octets: usize) -> Option<HeaderLayout> {
+        let read = HeaderLayout {
+            number_octets: Some(number_octets as u8).filter(|&n| n > 0),
+            length_octets: match *length {
+                Length::Some(_) => Some(length_octets as u8).filter(|&n| n > 0),
+                Length::Indefinite => None,
+            },
+        };
+        Some(read).filter(|l| *l != HeaderLayout::shortest(number, length))
+    }
+}
+
+/// The two ways a constructed tag's length can be encoded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LengthForm {
+    Definite,
+    Indefinite,
+}
+
+/// How `Tag::write_with` picks the length form of each constructed tag.
+#[derive(Debug, Clone, Copy)]
+pub enum LengthPolicy {
+    /// Every constructed tag uses this form.
+    Always(LengthForm),
+    /// Each tag uses its own `length_form`, so trees that were read keep their forms and
+    /// callers can pick per node. Tags with none are indefinite. Headers with a `layout`
+    /// are laid out as it says, wherever the values still fit.
+    Preserve,
+    /// Each tag gets the form and layout the function picks for it, primitives
+    /// ignoring the form, to reproduce a peer's quirks or exercise a receiver's length
+    /// handling. Layouts apply wherever the values still fit, so four octet lengths
+    /// everywhere are `|_| (LengthForm::Definite, HeaderLayout { number_octets: None,
+    /// length_octets: Some(4) })`.
+    Custom(fn(&Tag) -> (LengthForm, HeaderLayout)),
+}
+
+/// Options controlling how `Tag::write_with` encodes a tree.
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub length_policy: LengthPolicy,
+    /// How deeply tags may nest, counting the root as depth 1, as with
+    /// `ReadOptions::max_depth`. A deeper tree is an `io::ErrorKind::InvalidInput` error
+    /// before anything is written. No writer recurses, `write_der`, `write_cer`,
+    /// `canonicalize`, `dump` and `json::write` included, and neither do `redact` or
+    /// dropping a tree, so this is a policy for trees built from untrusted input rather
+    /// than a guard for the stack. Comparing trees with `==` and formatting them with
+    /// `{:?}` still recurse.
+    pub max_depth: usize,
+}
+
+impl Default for WriteOptions {
+    fn default () -> WriteOptions {
+        WriteOptions {
+            length_policy: LengthPolicy::Always(LengthForm::Indefinite),
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+/// A declared length a lenient read overrode with the number of content bytes actually
+/// consumed, or with the one octet `EmptyValues::Repair` filled an empty value in with.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct LengthRepair {
+    pub declared: u64,
+    pub actual:   u64,
+}
+
+// 5 groups of 7 bits cover every u32
+const MAX_NUMBER_OCTETS: usize = 5;
 
-    while count < 8 {
-        let b = try!(r.read_u8());
-        let bits = (b & 0x7F) as i64;
+// The long form is base 128, most significant group first.
+fn read_extended_number (r: &mut dyn Read) -> Result<TagNumber, err::Error> {
+    let mut ret = 0u64;
 
-        ret |= bits << (7 * count);
+    for _ in 0..MAX_NUMBER_OCTETS {
+        let b = r.read_u8()?;
+
+        ret = ret << 7 | (b & 0x7F) as u64;
 
         if b & 0x80 == 0 {
-            break;
+            return TagNumber::try_from(ret);
         }
-        count += 1;
     }
 
-    Ok(ret)
+    Err(err::Error::new(err::Kind::NumberOverflow, 0, None))
 }
 
-fn maybe_read_extended_number (b: i8, r: &mut Read) -> Result<i64, err::Error> {
+fn maybe_read_extended_number (b: i8, r: &mut dyn Read) -> Result<TagNumber, err::Error> {
     if b == 0x1F {
         read_extended_number(r)
     } else {
-        Ok(b as i64)
+        Ok(TagNumber::new(b as u32))
     }
 }
 
-fn read_identifiers (mut r: &mut Read) -> Result<(Class, Flavor, Number), err::Error> {
-    let b = try!(r.read_u8());
+pub(crate) fn read_identifiers (r: &mut dyn Read) -> Result<(Class, Flavor, Number), err::Error> {
+    let b = r.read_u8()?;
 
-    // these are unwrappable because they are comprehensive within their ranges
     let class:  Class  = Class::from_u8((b & 0xC0) >> 6);
     let flavor: Flavor = Flavor::from_u8((b & 0x20) >> 5);
     let number = (b & 0x1F) as i8;
@@ -180,21 +438,24 @@ fn read_identifiers (mut r: &mut Read) -> Result<(Class, Flavor, Number), err::E
                 // this is only valid in non-universal classes
                 return Err(err::Error::new(err::Kind::InvalidTypeAndFlavor, 0, None));
             }
-            Number::Universal(Type::from_i8(number))
+            match Type::from_i8(number) {
+                Some(t) => Number::Universal(t),
+                None => return Err(err::Error::new(err::Kind::UnknownType, 0, None)),
+            }
         },
         Class::Application =>
-            Number::Application(try!(maybe_read_extended_number(number, r))),
+            Number::Application(maybe_read_extended_number(number, r)?),
         Class::ContextSpecific =>
-            Number::ContextSpecific(try!(maybe_read_extended_number(number, r))),
+            Number::ContextSpecific(maybe_read_extended_number(number, r)?),
         Class::Private =>
-            Number::Private(try!(maybe_read_extended_number(number, r))),
+            Number::Private(maybe_read_extended_number(number, r)?),
     };
 
     Ok((class, flavor, number))
 }
 
-fn read_length (mut r: &mut Read) -> Result<Length, err::Error> {
-    let b = try!(r.read_u8());
+pub(crate) fn read_length (r: &mut dyn Read) -> Result<Length, err::Error> {
+    let b = r.read_u8()?;
 
     if b == 0x80 {
         Ok(Length::Indefinite)
@@ -210,7 +471,7 @@ fn read_length (mut r: &mut Read) -> Result<Length, err::Error> {
         }
 
         for i in 0..count {
-            let b = try!(r.read_u8());
+            let b = r.read_u8()?;
             ret |= (b as u64) << ((count - i -1) * 8);
         }
 
@@ -220,99 +481,231 @@ fn read_length (mut r: &mut Read) -> Result<Length, err::Error> {
     }
 }
 
-fn read_payload(length: &Length, flavor: &Flavor, mut r: &mut TrackedRead) -> Result<Payload, err::Error> {
+fn read_payload(length: &Length, flavor: &Flavor, r: &mut TrackedRead, opts: &ReadOptions, state: &mut ReadState) -> Result<(Payload, Option<LengthRepair>), err::Error> {
     if let &Flavor::Primitive = flavor {
-        if let Length::Some(ref l) = *length {
-            let mut buf = vec![0; *l as usize];
-            //TODO: handle partial reads?
-            try!(r.read(&mut buf));
-            Ok(Payload::Primitive(buf))
-        } else {
-            unreachable!()
+        // inner_read turns indefinite primitives away before this; say so rather than panic
+        let l = match *length {
+            Length::Some(ref l) => l,
+            Length::Indefinite => return Err(err::Error::new(err::Kind::InvalidLength, 0, None)),
+        };
+        if *l > opts.max_element_bytes as u64 {
+            let kind = err::Kind::BudgetExceeded { limit: opts.max_element_bytes, path: state.path.clone() };
+            return Err(err::Error::new(kind, 0, None));
+        }
+
+        // charge the declared length up front so the budget holds before anything
+        // is allocated
+        state.payload_bytes = state.payload_bytes.saturating_add(*l);
+        if state.payload_bytes > opts.max_total_payload_bytes as u64 {
+            let kind = err::Kind::BudgetExceeded { limit: opts.max_total_payload_bytes, path: state.path.clone() };
+            return Err(err::Error::new(kind, 0, None));
         }
+
+        // the declared length is untrusted: reserve at most the cap and let the
+        // buffer grow only as content actually arrives
+        let prealloc = (*l).min(opts.prealloc_cap as u64) as usize;
+        let mut buf = state.spare.pop().unwrap_or_default();
+        buf.reserve(prealloc);
+        r.by_ref().take(*l).read_to_end(&mut buf)?;
+        let mut repaired = None;
+        if (buf.len() as u64) < *l {
+            if !opts.lenient {
+                let needed = usize::try_from(*l - buf.len() as u64).unwrap_or(usize::MAX);
+                return Err(err::Error::new(err::Kind::Truncated { needed }, 0, None));
+            }
+            repaired = Some(LengthRepair { declared: *l, actual: buf.len() as u64 });
+        }
+        Ok((Payload::Primitive(buf), repaired))
     } else {
         let start = r.tell();
-        let mut children = Vec::new();
+        let mut children = state.lists.pop().unwrap_or_default();
+
+        loop {
+            let consumed = (r.tell() - start) as u64;
+            if let Length::Some(l) = *length {
+                if consumed >= l {
+                    if consumed == l {
+                        return Ok((Payload::Constructed(children), None));
+                    }
+                    // the last child ran past the end of this tag
+                    if !opts.lenient {
+                        return Err(err::Error::new(err::Kind::LengthExceedsContainer, 0, None));
+                    }
+                    return Ok((Payload::Constructed(children), Some(LengthRepair { declared: l, actual: consumed })));
+                }
+            }
+
+            state.path.push(children.len());
+            let child = Tag::inner_read(r, opts, state).map_err(|e| locate(e, state));
+            state.path.pop();
+
+            let child = match child {
+                Ok(child) => child,
+                Err(e) => match (*length, &e.kind) {
+                    // the input ended between children before the declared length was reached
+                    (Length::Some(l), &err::Kind::Truncated { .. }) if opts.lenient && (r.tell() - start) as u64 == consumed =>
+                        return Ok((Payload::Constructed(children), Some(LengthRepair { declared: l, actual: consumed }))),
+                    _ => return Err(e),
+                },
+            };
+
+            if child.number == Number::Universal(Type::Eoc) {
+                if *length == Length::Indefinite {
+                    // this is the end of the indefinite constructed payload
+                    return Ok((Payload::Constructed(children), None));
+                }
+                return Err(err::Error::new(err::Kind::UnexpectedEoc, 0, None));
+            }
+            if children.len() >= opts.max_children_per_node {
+                let kind = err::Kind::TooManyChildren { limit: opts.max_children_per_node, path: state.path.clone() };
+                return Err(err::Error::new(kind, 0, None));
+            }
+            children.push(child);
+        }
+    }
+}
 
-        while {
-            let child = try!(Tag::inner_read(r));
+/// Reads a path of child indices written with dots between them, as `0.2.1`. It may
+/// start with `root`, as `root.0.2.1`, and `root` or the empty string alone is the
+/// empty path. `None` if any index isn't a number.
+pub fn parse_path (s: &str) -> Option<Vec<usize>> {
+    let s = s.strip_prefix("root").unwrap_or(s).trim_start_matches('.');
+    if s.is_empty() {
+        return Some(Vec::new());
+    }
+    s.split('.').map(|i| i.parse().ok()).collect()
+}
 
-            if child.number == Number::Universal(Type::Eoc) && *length == Length::Indefinite {
-                // this is the end of the indefinite constructed payload
-                false
-            } else {
-                children.push(child);
-                if let Length::Some(ref l) = *length {
-                    if r.tell() - start >= *l as usize {
-                        false
-                    } else {
-                        true
-                    }
-                } else {
-                    true
+// Records where in the tree the child being read failed, unless a tag further down
+// already did.
+fn locate (mut e: err::Error, state: &ReadState) -> err::Error {
+    if e.path.is_empty() {
+        e.path = state.numbers.iter().zip(&state.path).map(|(&number, &index)| err::Step { number, index }).collect();
+    }
+    e
+}
+
+// Deep enough for any real structure, shallow enough to keep measure's recursion safe.
+const MAX_MEASURE_DEPTH: usize = 128;
+
+/// Returns the total encoded size of the tag at the start of `buf` without allocating
+/// anything, or `None` if it is malformed or does not fit inside `buf`. Useful to vet
+/// untrusted bytes before handing them to `Tag::read`, which trusts declared lengths.
+pub fn measure (buf: &[u8]) -> Option<usize> {
+    measure_at(buf, 0)
+}
+
+fn measure_at (buf: &[u8], depth: usize) -> Option<usize> {
+    if depth > MAX_MEASURE_DEPTH {
+        return None;
+    }
+
+    let mut r = buf;
+    let (_, flavor, _) = read_identifiers(&mut r).ok()?;
+    let length = read_length(&mut r).ok()?;
+    let header = buf.len() - r.len();
+
+    match length {
+        Length::Some(l) => {
+            let l = usize::try_from(l).ok()?;
+            if l > r.len() {
+                return None;
+            }
+
+            if flavor == Flavor::Constructed {
+                // children must exactly fill the declared length
+                let mut pos = 0;
+                while pos < l {
+                    pos += measure_at(&r[pos..l], depth + 1)?;
                 }
             }
-        } {}
 
-        Ok(Payload::Constructed(children))
+            Some(header + l)
+        },
+        Length::Indefinite => {
+            if flavor == Flavor::Primitive {
+                return None;
+            }
+
+            let mut pos = 0;
+            loop {
+                if r[pos..].starts_with(&[0x00, 0x00]) {
+                    return Some(header + pos + 2);
+                }
+                pos += measure_at(&r[pos..], depth + 1)?;
+            }
+        },
     }
 }
 
-fn write_extended_number (mut w: &mut Write, mut num: i64) -> io::Result<()> {
-    let mask = 0x7F;
+// The number of base 128 groups in `num`.
+fn number_groups (num: TagNumber) -> u8 {
+    (32 - (num.get() | 1).leading_zeros()).div_ceil(7) as u8
+}
+
+// The number of octets in `l`.
+fn length_bytes (l: u64) -> u8 {
+    (64 - (l | 1).leading_zeros()).div_ceil(8) as u8
+}
+
+fn write_extended_number (w: &mut dyn Write, num: TagNumber) -> io::Result<()> {
+    write_padded_number(w, num, number_groups(num))
+}
 
-    while num > 0 {
-        let mut b = (num & mask) as u8;
+// Writes `num` in `groups` groups, padding with leading zero groups.
+fn write_padded_number (w: &mut dyn Write, num: TagNumber, groups: u8) -> io::Result<()> {
+    let num = num.get() as u64;
 
-        num >>= 7;
-        if num != 0 {
+    for i in (0..groups as u32).rev() {
+        let mut b = num.checked_shr(7 * i).unwrap_or(0) as u8 & 0x7F;
+        if i != 0 {
             b |= 0x80;
         }
 
-        try!(w.write_u8(b));
+        w.write_u8(b)?;
     }
     Ok(())
 }
 
-fn maybe_write_extended_number (w: &mut Write, num: i64) -> io::Result<()> {
-    if num >= 0x1F {
+fn maybe_write_extended_number (w: &mut dyn Write, num: TagNumber) -> io::Result<()> {
+    if num.get() >= 0x1F {
         write_extended_number(w, num)
     } else {
         Ok(())
     }
 }
 
-fn write_identifiers (mut w: &mut Write, class: &Class, flavor: &Flavor, number: &Number) -> io::Result<()> {
+pub(crate) fn write_identifiers (w: &mut dyn Write, class: &Class, flavor: &Flavor, number: &Number) -> io::Result<()> {
     let b: u8 = 
         (*class as u8)  << 6 |
         (*flavor as u8) << 5 |
         match *number {
-            Number::Universal(ref t) => (*t as u8),
+            Number::Universal(ref t) => *t as u8,
             Number::Application(ref n) |
                 Number::ContextSpecific(ref n) |
-                Number::Private(ref n) => if *n >= 0x1F {
+                Number::Private(ref n) => if n.get() >= 0x1F {
                     0x1F
                 } else {
-                    *n as u8
+                    n.get() as u8
                 }
         };
 
-    try!(w.write_u8(b));
+    w.write_u8(b)?;
     match *number {
         Number::Application(ref num) |
             Number::ContextSpecific(ref num) |
-            Number::Private(ref num) => try!(maybe_write_extended_number(w, *num)),
+            Number::Private(ref num) => maybe_write_extended_number(w, *num)?,
             _ => {},
     }
 
     Ok(())
 }
 
-fn write_length (mut w: &mut Write, length: &Length) -> byteorder::Result<()> {
-    match length {
-        &Length::Indefinite => w.write_u8(0x80),
-        &Length::Some(ref l) => {
-            if *l < 0x1F {
+pub(crate) fn write_length (w: &mut dyn Write, length: &Length) -> io::Result<()> {
+    match *length {
+        Length::Indefinite => w.write_u8(0x80),
+        Length::Some(ref l) => {
+            if *l < 0x80 {
                 w.write_u8(*l as u8)
             } else {
                 let count = {
@@ -327,12 +720,12 @@ fn write_length (mut w: &mut Write, length: &Length) -> byteorder::Result<()> {
                     count
                 } as u8;
 
-                try!(w.write_u8(count | 0x80));
+                w.write_u8(count | 0x80)?;
 
                 for i in (0..count).rev() {
                     // start with the largest bytes first
-                    let byte = ((*l & (0xFF << i * 8)) >> i * 8) as u8;
-                    try!(w.write_u8(byte));
+                    let byte = ((*l & (0xFF << (i * 8))) >> (i * 8)) as u8;
+                    w.write_u8(byte)?;
                 }
 
                 Ok(())
@@ -341,86 +734,783 @@ fn write_length (mut w: &mut Write, length: &Length) -> byteorder::Result<()> {
     }
 }
 
-fn write_payload (mut w: &mut Write, payload: &Payload) -> io::Result<()> {
-    match payload {
-        &Payload::Primitive(ref v) => {
-            w.write_all(v)
+// Writes identifier and length octets in `layout`, falling back to the shortest form
+// for any part whose value doesn't fit the layout.
+pub(crate) fn write_header (w: &mut dyn Write, class: &Class, flavor: &Flavor, number: &Number, length: &Length, layout: &HeaderLayout) -> io::Result<()> {
+    match (*number, layout.number_octets) {
+        (Number::Application(n), Some(octets)) |
+            (Number::ContextSpecific(n), Some(octets)) |
+            (Number::Private(n), Some(octets)) if octets >= number_groups(n) => {
+            w.write_u8((*class as u8) << 6 | (*flavor as u8) << 5 | 0x1F)?;
+            write_padded_number(w, n, octets)?;
         },
-        &Payload::Constructed(ref v) => {
-            for tag in v {
-                try!(tag.write(w));
-            }
-            Ok(())
+        _ => write_identifiers(w, class, flavor, number)?,
+    }
+
+    match (*length, layout.length_octets) {
+        (Length::Some(l), Some(octets)) if (1..=8).contains(&octets) && octets >= length_bytes(l) => {
+            w.write_u8(0x80 | octets)?;
+            w.write_all(&l.to_be_bytes()[8 - octets as usize..])
         },
+        _ => write_length(w, length),
+    }
+}
+
+// Running out of input in a header means at least one more byte is needed.
+pub(crate) fn truncation (e: err::Error) -> err::Error {
+    match e.kind {
+        err::Kind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof =>
+            err::Error::new(err::Kind::Truncated { needed: 1 }, e.offset, None),
+        _ => e,
+    }
+}
+
+/// The messages of a stream, from `Tag::read_all`. Errors carry stream offsets.
+pub struct Messages<'a> {
+    r:    TrackedRead<'a>,
+    opts: ReadOptions,
+    done: bool,
+}
+
+impl<'a> Messages<'a> {
+    /// Stream offset of the next message.
+    pub fn offset (&self) -> usize {
+        self.r.tell()
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<(usize, Tag), err::Error>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let offset = self.r.tell();
+        match Tag::read_with(&mut self.r, &self.opts) {
+            Ok(tag) => Some(Ok((offset, tag))),
+            Err(e) => {
+                self.done = true;
+                match e.kind {
+                    err::Kind::CleanEof => None,
+                    _ => Some(Err(err::Error { offset: offset + e.offset, ..e })),
+                }
+            },
+        }
+    }
+}
+
+impl<'a> FusedIterator for Messages<'a> {}
+
+/// The children of one constructed tag, read off a stream one at a time, from
+/// `Tag::children_stream`. Errors carry stream offsets.
+pub struct Children<'a> {
+    r:      TrackedRead<'a>,
+    opts:   ReadOptions,
+    number: Number,
+    // stream offset where a definite-length parent ends; `None` reads up to its EOC
+    end:    Option<u64>,
+    index:  usize,
+    done:   bool,
+}
+
+impl<'a> Children<'a> {
+    /// The number of the tag whose children these are.
+    pub fn number (&self) -> Number {
+        self.number
+    }
+
+    /// Stream offset of the next child.
+    pub fn offset (&self) -> usize {
+        self.r.tell()
+    }
+
+    fn fail (&mut self, e: err::Error) -> Option<<Self as Iterator>::Item> {
+        self.done = true;
+        Some(Err(e))
+    }
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Result<(usize, Tag), err::Error>;
+
+    fn next (&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let offset = self.r.tell();
+        if self.end.is_some_and(|end| offset as u64 >= end) {
+            self.done = true;
+            return None;
+        }
+
+        let tag = match Tag::read_with(&mut self.r, &self.opts) {
+            Ok(tag) => tag,
+            Err(e) => {
+                let kind = match e.kind {
+                    // the stream ended before the parent did
+                    err::Kind::CleanEof => err::Kind::Truncated { needed: 1 },
+                    kind => kind,
+                };
+                let mut path = vec![err::Step { number: self.number, index: self.index }];
+                path.extend(e.path);
+                return self.fail(err::Error { kind, offset: offset + e.offset, path, ..e });
+            },
+        };
+
+        if tag.number == Number::Universal(Type::Eoc) {
+            return match self.end {
+                None => {
+                    self.done = true;
+                    None
+                },
+                Some(_) => self.fail(err::Error::new(err::Kind::UnexpectedEoc, self.r.tell(), None)),
+            };
+        }
+        if self.end.is_some_and(|end| self.r.tell() as u64 > end) {
+            // the child ran past the end of its parent
+            return self.fail(err::Error::new(err::Kind::LengthExceedsContainer, self.r.tell(), None));
+        }
+
+        self.index += 1;
+        Some(Ok((offset, tag)))
+    }
+}
+
+impl<'a> FusedIterator for Children<'a> {}
+
+/// Options controlling how `Tag::read_with` decodes its input.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// The most bytes reserved up front for a primitive's content, whatever length it
+    /// declares. Content longer than this is still read, the buffer just grows as the
+    /// bytes arrive, so a lying length field can't force a huge allocation.
+    pub prealloc_cap: usize,
+    /// Accept lengths that disagree with the content actually present instead of failing:
+    /// a primitive cut short by the end of input, a constructed tag whose children run past
+    /// its declared length or stop at the end of input before reaching it. Each affected
+    /// tag records the discrepancy in `Tag::repaired`.
+    pub lenient: bool,
+    /// The most primitive content bytes a whole tree may hold, counted by declared
+    /// length as each primitive is reached. Going over is a `Kind::BudgetExceeded`
+    /// error.
+    pub max_total_payload_bytes: usize,
+    /// The most content bytes any one primitive may declare. Going over is a
+    /// `Kind::BudgetExceeded` error naming this limit, raised from the header alone.
+    pub max_element_bytes: usize,
+    /// Check the content of every primitive UTF8String as it is read, failing with
+    /// `Kind::InvalidString` at the first byte that isn't valid UTF-8. Constructed
+    /// strings are not checked.
+    pub validate_utf8: bool,
+    /// Reject anything DER forbids, as `der::Validator` checks it: indefinite or
+    /// non-minimal lengths, constructed strings, non-canonical BOOLEANs and INTEGERs and
+    /// so on. The error is `Kind::NotDer` naming the violation.
+    pub strict_der: bool,
+    /// The most children any one constructed tag may have. Zero length children cost
+    /// nothing against `max_total_payload_bytes`, so this is what bounds a message made
+    /// of millions of them. Going over is a `Kind::TooManyChildren` error.
+    pub max_children_per_node: usize,
+    /// Give up once this moment passes, checked before each tag. Reading one long
+    /// primitive isn't interrupted, so pair this with a payload budget.
+    pub deadline: Option<Instant>,
+    /// The most tags a read may start, counting end-of-contents markers: a deadline
+    /// that doesn't depend on the clock. Both give `Kind::DeadlineExceeded`.
+    pub max_tags: usize,
+    /// How deeply tags may nest, counting the root as depth 1. Going deeper is a
+    /// `Kind::TooDeep` error. Reading recurses once per level, so the default,
+    /// `DEFAULT_MAX_DEPTH`, is what keeps a hostile input from exhausting the stack;
+    /// raise it only as far as the reading thread's stack allows.
+    pub max_depth: usize,
+    /// Where the input starts in some larger source, such as a capture file or a TCP
+    /// stream. It's added to the offsets in errors and in the tree `read_with` returns,
+    /// so they point into the source rather than the input. Readers of several
+    /// messages, `read_all_with`, `children_stream_with` and `ingest::Reassembler`, add
+    /// it to the stream offsets they report and to errors, but offsets within each tag
+    /// they return stay relative to its message, as they are without a base.
+    pub base_offset: usize,
+    /// What to do with a universal BOOLEAN or INTEGER that has no content octets, which
+    /// X.690 forbids but some legacy SNMP agents send anyway.
+    pub empty_values: EmptyValues,
+}
+
+/// How a read treats a BOOLEAN or INTEGER with no content octets, per
+/// `ReadOptions::empty_values`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EmptyValues {
+    /// Read it as it is. The accessors such as `Tag::as_i64` fail on it with
+    /// `Kind::InvalidValue`.
+    Accept,
+    /// Fail the read with `Kind::InvalidValue`.
+    Reject,
+    /// Read it as FALSE or 0, giving it a single zero content octet, and record the
+    /// substitution in `Tag::repaired` so `Tag::repaired_nodes` can report it.
+    Repair,
+}
+
+/// The default `ReadOptions::max_depth`: deeper than any real protocol nests, and a
+/// fraction of the stack a spawned thread gets even in an unoptimized build.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// The encoding rules a read holds its input to. CER input is read as BER: it has a
+/// writer, `Tag::write_cer`, but no validator.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Rules {
+    Ber,
+    Der,
+}
+
+impl ReadOptions {
+    /// Starts from the defaults, which read any BER with no limits beyond
+    /// `prealloc_cap` and `max_depth`.
+    pub fn builder () -> ReadOptionsBuilder {
+        ReadOptionsBuilder { opts: ReadOptions::default() }
+    }
+
+    pub fn rules (&self) -> Rules {
+        if self.strict_der { Rules::Der } else { Rules::Ber }
+    }
+}
+
+/// Builds `ReadOptions` a setting at a time; each method sets the field of the same
+/// name.
+#[derive(Debug, Clone)]
+pub struct ReadOptionsBuilder {
+    opts: ReadOptions,
+}
+
+impl ReadOptionsBuilder {
+    pub fn prealloc_cap (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.prealloc_cap = n;
+        self
+    }
+
+    pub fn lenient (mut self, lenient: bool) -> ReadOptionsBuilder {
+        self.opts.lenient = lenient;
+        self
+    }
+
+    pub fn max_total_payload_bytes (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.max_total_payload_bytes = n;
+        self
+    }
+
+    pub fn max_element_bytes (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.max_element_bytes = n;
+        self
+    }
+
+    pub fn validate_utf8 (mut self, validate: bool) -> ReadOptionsBuilder {
+        self.opts.validate_utf8 = validate;
+        self
+    }
+
+    pub fn empty_values (mut self, empty: EmptyValues) -> ReadOptionsBuilder {
+        self.opts.empty_values = empty;
+        self
+    }
+
+    /// Sets `strict_der` for `Rules::Der`.
+    pub fn rules (mut self, rules: Rules) -> ReadOptionsBuilder {
+        self.opts.strict_der = rules == Rules::Der;
+        self
+    }
+
+    pub fn max_children_per_node (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.max_children_per_node = n;
+        self
+    }
+
+    pub fn deadline (mut self, deadline: Instant) -> ReadOptionsBuilder {
+        self.opts.deadline = Some(deadline);
+        self
+    }
+
+    pub fn max_tags (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.max_tags = n;
+        self
+    }
+
+    pub fn max_depth (mut self, n: usize) -> ReadOptionsBuilder {
+        self.opts.max_depth = n;
+        self
+    }
+
+    pub fn base_offset (mut self, offset: usize) -> ReadOptionsBuilder {
+        self.opts.base_offset = offset;
+        self
+    }
+
+    pub fn build (self) -> ReadOptions {
+        self.opts
+    }
+}
+
+fn not_der (v: der::Violation, base: usize) -> err::Error {
+    err::Error::new(err::Kind::NotDer(v.kind), base + v.offset, None)
+}
+
+struct DerCheck<'a> {
+    inner:     &'a mut dyn Read,
+    validator: der::Validator,
+    violation: Option<der::Violation>,
+}
+
+impl<'a> Read for DerCheck<'a> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Err(v) = self.validator.feed(&buf[..n]) {
+            self.violation = Some(v);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, v));
+        }
+        Ok(n)
+    }
+}
+
+// The most recycled buffers of each kind a `ReadState` holds on to.
+const MAX_SPARE: usize = 1024;
+
+// What a single read has used so far, plus emptied buffers from earlier trees to fill
+// before allocating new ones.
+#[derive(Default)]
+pub(crate) struct ReadState {
+    payload_bytes: u64,
+    tags:          usize,
+    // child indices from the root down to the tag being read, and the numbers of the
+    // constructed tags those indices are into
+    path:          Vec<usize>,
+    numbers:       Vec<Number>,
+    spare:         Vec<Vec<u8>>,
+    lists:         Vec<Vec<Tag>>,
+}
+
+impl ReadState {
+    // Forgets the last read, keeping every buffer.
+    pub(crate) fn reset (&mut self) {
+        self.payload_bytes = 0;
+        self.tags = 0;
+        self.path.clear();
+        self.numbers.clear();
+    }
+
+    // Empties the buffers of `tag` into the spares for later reads.
+    pub(crate) fn recycle (&mut self, tag: Tag) {
+        let mut stack = vec![tag];
+        while let Some(mut tag) = stack.pop() {
+            match tag.payload {
+                Payload::Primitive(ref mut v) => if self.spare.len() < MAX_SPARE {
+                    let mut v = mem::take(v);
+                    v.clear();
+                    self.spare.push(v);
+                },
+                Payload::Constructed(ref mut children) => {
+                    let mut children = mem::take(children);
+                    stack.append(&mut children);
+                    if self.lists.len() < MAX_SPARE {
+                        self.lists.push(children);
+                    }
+                },
+            }
+        }
+    }
+
+    pub(crate) fn spares (&self) -> usize {
+        self.spare.len() + self.lists.len()
+    }
+}
+
+impl Default for ReadOptions {
+    fn default () -> ReadOptions {
+        ReadOptions {
+            prealloc_cap: 64 * 1024,
+            lenient: false,
+            max_total_payload_bytes: usize::MAX,
+            max_element_bytes: usize::MAX,
+            validate_utf8: false,
+            strict_der: false,
+            max_children_per_node: usize::MAX,
+            deadline: None,
+            max_tags: usize::MAX,
+            max_depth: DEFAULT_MAX_DEPTH,
+            base_offset: 0,
+            empty_values: EmptyValues::Accept,
+        }
     }
 }
 
 impl Tag {
-    fn inner_read (r: &mut TrackedRead) -> Result<Tag, err::Error> {
+    fn inner_read (r: &mut TrackedRead, opts: &ReadOptions, state: &mut ReadState) -> Result<Tag, err::Error> {
         let offset = r.tell();
 
+        if state.tags >= opts.max_tags || opts.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(err::Error::new(err::Kind::DeadlineExceeded { tags: state.tags }, offset, None));
+        }
+        state.tags += 1;
+        if state.path.len() >= opts.max_depth {
+            return Err(err::Error::new(err::Kind::TooDeep { limit: opts.max_depth }, offset, None));
+        }
+
         let (_class, flavor, number) = match read_identifiers(r) {
             Ok(x) => x,
             Err(mut e) => {
                 e.offset = r.tell();
-                return Err(e);
+                return Err(truncation(e));
             },
         };
 
+        let identifier_end = r.tell();
+
         let length = match read_length(r) {
             Ok(x) => x,
             Err(mut e) => {
                 e.offset = r.tell();
-                return Err(e);
+                return Err(truncation(e));
             },
         };
 
+        let layout = HeaderLayout::observed(&number, &length, identifier_end - offset - 1, r.tell() - identifier_end - 1);
+
         if length == Length::Indefinite  && flavor == Flavor::Primitive {
             return Err(err::Error::new(err::Kind::InvalidLength, r.tell(), None));
         }
 
-        let payload = match read_payload(&length, &flavor, r) {
+        let content_start = r.tell();
+        state.numbers.push(number);
+        let payload = read_payload(&length, &flavor, r, opts, state);
+        state.numbers.pop();
+        let (mut payload, mut repaired) = match payload {
             Ok(x) => x,
             Err(mut e) => {
-                e.offset = r.tell();
-                return Err(e);
+                // a bad string in a child already points at the offending byte
+                if !matches!(e.kind, err::Kind::InvalidString(_)) {
+                    e.offset = r.tell();
+                }
+                return Err(truncation(e));
             },
         };
 
+        if let (true, Number::Universal(Type::Utf8String), Payload::Primitive(ref v)) = (opts.validate_utf8, number, &payload) {
+            if let Err(e) = str::from_utf8(v) {
+                return Err(err::Error::new(err::Kind::InvalidString(Type::Utf8String), content_start + e.valid_up_to(), None));
+            }
+        }
+
+        if let (Number::Universal(Type::Boolean | Type::Integer), Payload::Primitive(ref mut v)) = (number, &mut payload) {
+            if v.is_empty() {
+                match opts.empty_values {
+                    EmptyValues::Accept => {},
+                    EmptyValues::Reject => return Err(err::Error::new(err::Kind::InvalidValue, content_start, None)),
+                    EmptyValues::Repair => {
+                        v.push(0);
+                        repaired = Some(LengthRepair { declared: repaired.map_or(0, |r| r.declared), actual: 1 });
+                    },
+                }
+            }
+        }
+
+        let length_form = match (flavor, length) {
+            (Flavor::Primitive, _) => None,
+            (Flavor::Constructed, Length::Indefinite) => Some(LengthForm::Indefinite),
+            (Flavor::Constructed, Length::Some(_)) => Some(LengthForm::Definite),
+        };
+
         Ok(Tag {
-            number: number,
+            number,
             offset: Some(offset),
-            payload: payload,
+            payload,
+            repaired,
+            length_form,
+            layout,
         })
     }
-    pub fn read (r: &mut Read) -> Result<Tag, err::Error> {
-        Tag::inner_read(&mut TrackedRead::new(r))
+
+    /// Reads one tag with the default options.
+    ///
+    /// This never panics, whatever `r` yields: malformed, truncated or hostile input is
+    /// an error, never a panic or a blown stack. `read_with` keeps the same promise for
+    /// any options whose `max_depth` the reading thread's stack can take.
+    /// `tests/no_panic.rs` holds the reading code to it.
+    pub fn read (r: &mut dyn Read) -> Result<Tag, err::Error> {
+        Tag::read_with(r, &ReadOptions::default())
     }
 
-    pub fn write (&self, mut w: &mut Write) -> io::Result<()> {
-        let class = match self.number {
-            Number::Universal(_) => Class::Universal,
-            Number::Application(_) => Class::Application,
-            Number::ContextSpecific(_) => Class::ContextSpecific,
-            Number::Private(_) => Class::Private,
+    /// Reads one tag using `opts`.
+    ///
+    /// If the input ends before the first byte of the tag the error is `Kind::CleanEof`,
+    /// meaning there simply was no message; running out anywhere later is
+    /// `Kind::Truncated`.
+    pub fn read_with (r: &mut dyn Read, opts: &ReadOptions) -> Result<Tag, err::Error> {
+        Tag::read_in(r, opts, &mut ReadState::default())
+    }
+
+    // `read_with`, drawing buffers from `state`, which must have been reset.
+    pub(crate) fn read_in (r: &mut dyn Read, opts: &ReadOptions, state: &mut ReadState) -> Result<Tag, err::Error> {
+        if opts.strict_der {
+            return Tag::read_der(r, opts, state);
+        }
+
+        let mut r = TrackedRead::starting_at(r, opts.base_offset);
+
+        Tag::inner_read(&mut r, opts, state).map_err(|mut e| {
+            if let err::Kind::Truncated { .. } = e.kind {
+                if r.tell() == opts.base_offset {
+                    e.kind = err::Kind::CleanEof;
+                }
+            }
+            e
+        })
+    }
+
+    /// Reads one tag and checks that the input ends with it, as parsers of certificates
+    /// and keys need to: anything after the tag is a `Kind::TrailingData` error at the
+    /// offset where it starts, rather than data smuggled past the parser.
+    pub fn read_exact_one (r: &mut dyn Read) -> Result<Tag, err::Error> {
+        Tag::read_exact_one_with(r, &ReadOptions::default())
+    }
+
+    /// `read_exact_one` using `opts`.
+    pub fn read_exact_one_with (r: &mut dyn Read, opts: &ReadOptions) -> Result<Tag, err::Error> {
+        let mut r = TrackedRead::starting_at(r, opts.base_offset);
+        let tag = Tag::read_with(&mut r, opts)?;
+
+        let offset = r.tell();
+        match r.read(&mut [0]) {
+            Ok(0) => Ok(tag),
+            Ok(_) => Err(err::Error::new(err::Kind::TrailingData, offset, None)),
+            Err(e) => Err(err::Error { offset, ..e.into() }),
+        }
+    }
+
+    /// Reads back-to-back messages until the input ends between two of them. Each item
+    /// is a message and its offset in the stream; offsets within the tag are relative to
+    /// the message, as with `Tag::read`. Input ending inside a message yields a
+    /// `Kind::Truncated` error, after which the iterator is done.
+    pub fn read_all (r: &mut dyn Read) -> Messages<'_> {
+        Tag::read_all_with(r, &ReadOptions::default())
+    }
+
+    /// `read_all` using `opts` for every message.
+    pub fn read_all_with<'a> (r: &'a mut dyn Read, opts: &ReadOptions) -> Messages<'a> {
+        // stream offsets start from the base, offsets in each message from nothing
+        Messages {
+            r: TrackedRead::starting_at(r, opts.base_offset),
+            opts: ReadOptions { base_offset: 0, ..opts.clone() },
+            done: false,
+        }
+    }
+
+    /// Reads the header of a constructed tag and returns an iterator over its children,
+    /// each read only when asked for, so the elements of a SEQUENCE too big to hold in
+    /// memory can be handled and dropped one at a time. Each item is a child and its
+    /// offset in the stream. The iterator stops after the parent's EOC, or at its end
+    /// for a definite length, leaving `r` just past the parent.
+    ///
+    /// A primitive tag is a `Kind::InvalidTypeAndFlavor` error; input ending before the
+    /// header is `Kind::CleanEof`, as with `Tag::read`.
+    pub fn children_stream (r: &mut dyn Read) -> Result<Children<'_>, err::Error> {
+        Tag::children_stream_with(r, &ReadOptions::default())
+    }
+
+    /// `children_stream` using `opts`. The parent counts towards `max_depth`; the other
+    /// limits apply to each child on its own, and `strict_der` to the children but not
+    /// the parent's header.
+    pub fn children_stream_with<'a> (r: &'a mut dyn Read, opts: &ReadOptions) -> Result<Children<'a>, err::Error> {
+        let base = opts.base_offset;
+        let mut r = TrackedRead::starting_at(r, base);
+        if opts.max_depth == 0 {
+            return Err(err::Error::new(err::Kind::TooDeep { limit: 0 }, base, None));
+        }
+
+        let header = read_identifiers(&mut r).and_then(|(_, flavor, number)| Ok((flavor, number, read_length(&mut r)?)));
+        let (flavor, number, length) = match header {
+            Ok(x) => x,
+            Err(mut e) => {
+                e.offset = r.tell();
+                let e = truncation(e);
+                return Err(match e.kind {
+                    err::Kind::Truncated { .. } if r.tell() == base => err::Error::new(err::Kind::CleanEof, base, None),
+                    _ => e,
+                });
+            },
         };
+        if flavor != Flavor::Constructed {
+            return Err(err::Error::new(err::Kind::InvalidTypeAndFlavor, base, None));
+        }
 
-        let (flavor, length) = match self.payload {
-            Payload::Primitive(ref v) => (Flavor::Primitive, Length::Some(v.len() as u64)),
-            Payload::Constructed(_) => (Flavor::Constructed, Length::Indefinite),
+        let end = match length {
+            Length::Some(l) => Some((r.tell() as u64).saturating_add(l)),
+            Length::Indefinite => None,
         };
+        Ok(Children {
+            r,
+            opts: ReadOptions { max_depth: opts.max_depth - 1, base_offset: 0, ..opts.clone() },
+            number,
+            end,
+            index: 0,
+            done: false,
+        })
+    }
+
+    // Runs every byte the reader consumes through a DER validator on the way in.
+    fn read_der (r: &mut dyn Read, opts: &ReadOptions, state: &mut ReadState) -> Result<Tag, err::Error> {
+        let mut checked = DerCheck { inner: r, validator: der::Validator::new(), violation: None };
+        let opts = ReadOptions { strict_der: false, ..opts.clone() };
+
+        let result = Tag::read_in(&mut checked, &opts, state)
+            .and_then(|tag| match checked.validator.finish() {
+                Ok(()) => Ok(tag),
+                Err(v) => Err(not_der(v, opts.base_offset)),
+            });
+
+        match checked.violation.take() {
+            Some(v) => Err(not_der(v, opts.base_offset)),
+            None => result,
+        }
+    }
+
+    /// Every tag in this tree, in document order, whose length a lenient read repaired.
+    pub fn repaired_nodes (&self) -> Vec<&Tag> {
+        let mut out = Vec::new();
+        let mut stack = vec![self];
+        while let Some(tag) = stack.pop() {
+            if tag.repaired.is_some() {
+                out.push(tag);
+            }
+            if let Payload::Constructed(ref children) = tag.payload {
+                stack.extend(children.iter().rev());
+            }
+        }
+        out
+    }
+
+    pub fn is_constructed (&self) -> bool {
+        self.payload.is_constructed()
+    }
+
+    /// As `Payload::as_primitive`.
+    pub fn as_primitive (&self) -> Option<&[u8]> {
+        self.payload.as_primitive()
+    }
+
+    /// As `Payload::children`.
+    pub fn children (&self) -> Option<&[Tag]> {
+        self.payload.children()
+    }
+
+    /// The `n`th child, `None` if there isn't one or this tag is primitive.
+    pub fn child (&self, n: usize) -> Option<&Tag> {
+        self.children()?.get(n)
+    }
+
+    /// As `Payload::len`.
+    pub fn len (&self) -> usize {
+        self.payload.len()
+    }
+
+    pub fn is_empty (&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// The tag reached by following `path`, a list of child indices from this tag down.
+    /// The empty path is this tag.
+    pub fn at_path (&self, path: &[usize]) -> Option<&Tag> {
+        path.iter().try_fold(self, |tag, &i| match tag.payload {
+            Payload::Constructed(ref children) => children.get(i),
+            Payload::Primitive(_) => None,
+        })
+    }
+
+    pub fn at_path_mut (&mut self, path: &[usize]) -> Option<&mut Tag> {
+        path.iter().try_fold(self, |tag, &i| match tag.payload {
+            Payload::Constructed(ref mut children) => children.get_mut(i),
+            Payload::Primitive(_) => None,
+        })
+    }
 
-        try!(write_identifiers(w, &class, &flavor, &self.number));
+    /// `at_path`, for short: `tag.at(&[0, 2])` is the third child of the first child.
+    pub fn at (&self, path: &[usize]) -> Option<&Tag> {
+        self.at_path(path)
+    }
 
-        try!(write_length(w, &length));
+    /// The tag at `query`, a path written as `parse_path` reads it, such as `"0.2.1"`.
+    /// A query that doesn't parse finds nothing.
+    pub fn get (&self, query: &str) -> Option<&Tag> {
+        self.at_path(&parse_path(query)?)
+    }
 
-        try!(write_payload(w, &self.payload));
+    pub fn get_mut (&mut self, query: &str) -> Option<&mut Tag> {
+        self.at_path_mut(&parse_path(query)?)
+    }
 
-        match length {
-            Length::Indefinite => w.write_all(&[0x00, 0x00]),
-            _ => Ok(()),
+    /// Overwrites the content of every primitive in this subtree with zeros, keeping
+    /// lengths and structure so the redacted tree still lines up with the original.
+    pub fn redact (&mut self) {
+        for (_, t) in self.iter_mut() {
+            if let Some(v) = t.content {
+                v.iter_mut().for_each(|b| *b = 0);
+            }
         }
     }
+
+    /// Writes the tag with every constructed length indefinite.
+    pub fn write (&self, w: &mut dyn Write) -> io::Result<()> {
+        self.write_with(w, &WriteOptions::default())
+    }
+
+    // The length form and header layout `opts` gives this tag.
+    pub(crate) fn header_form (&self, opts: &WriteOptions) -> (LengthForm, HeaderLayout) {
+        match opts.length_policy {
+            LengthPolicy::Always(form) => (form, HeaderLayout::default()),
+            LengthPolicy::Preserve =>
+                (self.length_form.unwrap_or(LengthForm::Indefinite), self.layout.unwrap_or_default()),
+            LengthPolicy::Custom(pick) => pick(self),
+        }
+    }
+
+    /// Writes the tag using `opts`. The tree is measured first to find the definite
+    /// lengths, so nothing is buffered, and neither pass recurses.
+    pub fn write_with (&self, w: &mut dyn Write, opts: &WriteOptions) -> io::Result<()> {
+        encoder::write_tree(w, self, opts)
+    }
+}
+
+#[cfg(kani)]
+mod verification {
+    use super::*;
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn read_length_never_panics () {
+        let bytes: [u8; 9] = kani::any();
+        let _ = read_length(&mut &bytes[..]);
+    }
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn read_extended_number_never_panics () {
+        let bytes: [u8; 6] = kani::any();
+        let _ = read_extended_number(&mut &bytes[..]);
+    }
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn length_round_trips () {
+        let l: u64 = kani::any();
+        let mut buf = Vec::new();
+        write_length(&mut buf, &Length::Some(l)).unwrap();
+        assert!(read_length(&mut &buf[..]).unwrap() == Length::Some(l));
+    }
+
+    #[kani::proof]
+    #[kani::unwind(10)]
+    fn extended_number_round_trips () {
+        let n = TagNumber::new(kani::any());
+        kani::assume(n.get() >= 0x1F);
+        let mut buf = Vec::new();
+        write_extended_number(&mut buf, n).unwrap();
+        assert!(read_extended_number(&mut &buf[..]).unwrap() == n);
+    }
 }
 
 #[cfg(test)]
@@ -443,7 +1533,13 @@ mod test {
                     number: Number::Universal(Type::Utf8String),
                     offset: Some(2),
                     payload: Payload::Primitive(vec![0x64, 0x65, 0x66]),
+                    repaired: None,
+                    length_form: None,
+                    layout: None,
                 } ]),
+                repaired: None,
+                length_form: Some(LengthForm::Indefinite),
+                layout: None,
             }
             );
     }
@@ -474,7 +1570,13 @@ mod test {
                     number: Number::Universal(Type::Utf8String),
                     offset: Some(2),
                     payload: Payload::Primitive(vec![0x64, 0x65, 0x66]),
+                    repaired: None,
+                    length_form: None,
+                    layout: Some(HeaderLayout { number_octets: None, length_octets: Some(2) }),
                 } ]),
+                repaired: None,
+                length_form: Some(LengthForm::Indefinite),
+                layout: None,
             }
             );
     }
@@ -502,10 +1604,16 @@ mod test {
                 number: Number::Universal(Type::Sequence),
                 offset: Some(0),
                 payload: Payload::Constructed(vec![ Tag {
-                    number: Number::ContextSpecific(0x7F),
+                    number: Number::ContextSpecific(TagNumber::new(0x7F)),
                     offset: Some(2),
                     payload: Payload::Primitive(vec![0x64, 0x65, 0x66]),
+                    repaired: None,
+                    length_form: None,
+                    layout: Some(HeaderLayout { number_octets: Some(1), length_octets: Some(1) }),
                 } ]),
+                repaired: None,
+                length_form: Some(LengthForm::Indefinite),
+                layout: None,
             }
             );
     }
@@ -521,6 +1629,599 @@ mod test {
         assert!(buf == payload);
     }
 
+    #[test]
+    fn test_length_boundaries () {
+        for &l in &[0u64, 0x1E, 0x1F, 0x7F, 0x80, 0xFF, 0x100, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_length(&mut buf, &Length::Some(l)).unwrap();
+            assert_eq!(read_length(&mut &buf[..]).unwrap(), Length::Some(l));
+            // the short form covers everything below 0x80
+            assert_eq!(buf.len() == 1, l < 0x80);
+        }
+    }
+
+    #[test]
+    fn test_extended_number_boundaries () {
+        for &n in &[0x1Fu32, 0x7F, 0x80, 0x3FFF, 0x4000, 1 << 28, u32::MAX] {
+            let mut buf = Vec::new();
+            write_extended_number(&mut buf, TagNumber::new(n)).unwrap();
+            assert_eq!(read_extended_number(&mut &buf[..]).unwrap(), TagNumber::new(n));
+        }
+
+        // most significant group first
+        let mut buf = Vec::new();
+        write_extended_number(&mut buf, TagNumber::new(0x80)).unwrap();
+        assert_eq!(buf, vec![0x81, 0x00]);
+        let mut buf = Vec::new();
+        write_extended_number(&mut buf, TagNumber::new(201)).unwrap();
+        assert_eq!(buf, vec![0x81, 0x49]);
+
+        let too_long = [0xFF; 6];
+        assert!(read_extended_number(&mut &too_long[..]).is_err());
+        let too_big = [0x90, 0x80, 0x80, 0x80, 0x00];
+        assert!(read_extended_number(&mut &too_big[..]).is_err());
+    }
+
+    #[test]
+    fn test_tag_number_conversions () {
+        assert_eq!(TagNumber::try_from(7i64).unwrap(), TagNumber::new(7));
+        assert!(TagNumber::try_from(-1i64).is_err());
+        assert!(TagNumber::try_from(1u64 << 32).is_err());
+
+        const BIND: Number = Number::Application(TagNumber::new(0));
+        assert!(Number::Universal(Type::Null) < BIND);
+        assert!(BIND < Number::ContextSpecific(TagNumber::new(0)));
+        assert_eq!(Number::from_parts(Class::Private, 9), Some(Number::Private(TagNumber::new(9))));
+    }
+
+    #[test]
+    fn test_lying_length () {
+        // claims almost 2^64 bytes of content but carries 3
+        let payload = vec![0x04, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0, 0x64, 0x65, 0x66];
+        assert!(Tag::read(&mut Cursor::new(payload)).is_err());
+    }
+
+    #[test]
+    fn test_clean_eof_and_truncation () {
+        let e = Tag::read(&mut Cursor::new(vec![])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::CleanEof));
+
+        let e = Tag::read(&mut Cursor::new(vec![0x30])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 1 }));
+
+        let e = Tag::read(&mut Cursor::new(vec![0x30, 0x80, 0x0C, 0x05, 0x64])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 4 }));
+
+        let e = Tag::read(&mut Cursor::new(vec![0x30, 0x80, 0x0C, 0x01, 0x64])).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 1 }));
+    }
+
+    #[test]
+    fn test_read_all () {
+        // NULL, SEQUENCE { NULL }, then the start of a truncated OCTET STRING
+        let stream = vec![0x05, 0x00, 0x30, 0x02, 0x05, 0x00, 0x04, 0x03, 0x61];
+
+        let mut r = Cursor::new(stream[..6].to_vec());
+        let messages: Vec<_> = Tag::read_all(&mut r).map(|m| m.unwrap()).collect();
+        assert_eq!(messages.iter().map(|m| m.0).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(messages[1].1, Tag::read(&mut Cursor::new(stream[2..6].to_vec())).unwrap());
+
+        let mut r = Cursor::new(stream);
+        let mut messages = Tag::read_all(&mut r);
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_ok());
+        assert_eq!(messages.offset(), 6);
+        let e = messages.next().unwrap().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 2 }));
+        assert_eq!(e.offset, 9);
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn test_children_stream () {
+        // SEQUENCE (indefinite) { INTEGER 1, NULL }, then a NULL after it
+        let stream = [0x30, 0x80, 0x02, 0x01, 0x01, 0x05, 0x00, 0x00, 0x00, 0x05, 0x00];
+
+        let mut r = &stream[..];
+        let children: Vec<_> = Tag::children_stream(&mut r).unwrap().map(|c| c.unwrap()).collect();
+        assert_eq!(children.iter().map(|c| c.0).collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(children[0].1, Tag::read(&mut &stream[2..5]).unwrap());
+        assert_eq!(r, [0x05, 0x00]);
+
+        // definite lengths stop at the end; a missing EOC is truncation inside the parent
+        let definite = [0x30, 0x05, 0x02, 0x01, 0x01, 0x05, 0x00, 0x05, 0x00];
+        assert_eq!(Tag::children_stream(&mut &definite[..]).unwrap().count(), 2);
+        let e = Tag::children_stream(&mut &stream[..7]).unwrap().last().unwrap().unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+        assert_eq!((e.offset, e.path[0].index), (7, 2));
+
+        let e = Tag::children_stream(&mut &stream[9..]).err().unwrap();
+        assert!(matches!(e.kind, err::Kind::InvalidTypeAndFlavor), "{}", e);
+    }
+
+    #[test]
+    fn test_base_offset () {
+        // SEQUENCE { NULL } found 100 bytes into a capture
+        let input = [0x30, 0x02, 0x05, 0x00];
+        let opts = ReadOptions::builder().base_offset(100).build();
+
+        let tag = Tag::read_with(&mut &input[..], &opts).unwrap();
+        assert_eq!(tag.offset, Some(100));
+        assert_eq!(tag.at_path(&[0]).unwrap().offset, Some(102));
+
+        let e = Tag::read_with(&mut &input[..3], &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }), "{}", e);
+        assert_eq!(e.offset, 103);
+        assert!(matches!(Tag::read_with(&mut &input[..0], &opts).unwrap_err().kind, err::Kind::CleanEof));
+        assert_eq!(Tag::read_exact_one_with(&mut &[0x05, 0x00, 0x00][..], &opts).unwrap_err().offset, 102);
+
+        // streams count from the base, messages within themselves as before, and an
+        // error lands where the message offset plus its offset in the message puts it
+        let relative = Tag::read(&mut &input[..3]).unwrap_err().offset;
+        let stream = [&input[..], &input[..], &input[..3]].concat();
+        let mut r = &stream[..];
+        let mut messages = Tag::read_all_with(&mut r, &opts);
+        for expected in [100, 104] {
+            let (offset, tag) = messages.next().unwrap().unwrap();
+            assert_eq!(offset, expected);
+            assert_eq!((tag.offset, tag.at_path(&[0]).unwrap().offset), (Some(0), Some(2)));
+        }
+        assert_eq!(messages.next().unwrap().unwrap_err().offset, 108 + relative);
+
+        // SEQUENCE (indefinite) { SEQUENCE { NULL }, a truncated SEQUENCE { NULL }
+        let stream = [&[0x30, 0x80][..], &input[..], &input[..3]].concat();
+        let mut r = &stream[..];
+        let mut children = Tag::children_stream_with(&mut r, &opts).unwrap();
+        let (offset, child) = children.next().unwrap().unwrap();
+        assert_eq!((offset, child.offset, child.at_path(&[0]).unwrap().offset), (102, Some(0), Some(2)));
+        assert_eq!(children.next().unwrap().unwrap_err().offset, 106 + relative);
+    }
+
+    #[test]
+    fn test_get () {
+        let tag = Tag::sequence(vec![Tag::integer(1), Tag::sequence(vec![Tag::null(), Tag::integer(7)])]);
+        assert_eq!(tag.get("1.1"), tag.at(&[1, 1]));
+        assert_eq!(tag.get("root.1.1").unwrap().content().unwrap(), &[7]);
+        assert!(std::ptr::eq(tag.get("").unwrap(), &tag));
+        assert_eq!(tag.get("1.2"), None);
+        assert_eq!(tag.get("0.0"), None);
+        assert_eq!(tag.get("1.x"), None);
+        assert_eq!(parse_path("root"), Some(vec![]));
+    }
+
+    #[test]
+    fn test_accessors () {
+        let tag = Tag::sequence(vec![Tag::integer(1), Tag::null()]);
+        assert!(tag.is_constructed());
+        assert_eq!((tag.len(), tag.as_primitive()), (2, None));
+        assert_eq!(tag.children().map(<[Tag]>::len), Some(2));
+
+        let int = tag.child(0).unwrap();
+        assert!(!int.is_constructed());
+        assert_eq!(int.as_primitive(), Some(&[1][..]));
+        assert_eq!((int.children(), int.child(0)), (None, None));
+        assert!(tag.child(1).unwrap().is_empty());
+        assert_eq!(tag.child(2), None);
+    }
+
+    #[test]
+    fn test_custom_length_policy () {
+        let long = |_: &Tag| (LengthForm::Definite, HeaderLayout { number_octets: None, length_octets: Some(4) });
+        let opts = WriteOptions { length_policy: LengthPolicy::Custom(long), ..WriteOptions::default() };
+
+        let tag = Tag::sequence(vec![Tag::integer(1)]);
+        let mut buf = Vec::new();
+        tag.write_with(&mut buf, &opts).unwrap();
+        assert_eq!(buf, [0x30, 0x84, 0x00, 0x00, 0x00, 0x07, 0x02, 0x84, 0x00, 0x00, 0x00, 0x01, 0x01]);
+        assert_eq!(encoder::encoded_len(&tag, &opts), buf.len());
+        // a depth limit is for writing, and doesn't stop the tree being measured
+        let shallow = WriteOptions { max_depth: 1, ..opts.clone() };
+        assert_eq!(encoder::encoded_len(&tag, &shallow), buf.len());
+
+        // and it reads back as what it is, layouts and all
+        let back = Tag::read(&mut &buf[..]).unwrap();
+        assert_eq!(back.at(&[0]).unwrap().layout.and_then(|l| l.length_octets), Some(4));
+    }
+
+    #[test]
+    fn test_empty_definite_constructed () {
+        let tag = Tag::read(&mut Cursor::new(vec![0x30, 0x00, 0x05, 0x00])).unwrap();
+        assert_eq!(tag.payload, Payload::Constructed(vec![]));
+    }
+
+    #[test]
+    fn test_length_policy () {
+        // definite outer SEQUENCE around an indefinite inner one
+        let payload = vec![0x30, 0x09, 0x02, 0x01, 0x05, 0x30, 0x80, 0x05, 0x00, 0x00, 0x00];
+        let mut tag = Tag::read(&mut &payload[..]).unwrap();
+        assert_eq!(tag.length_form, Some(LengthForm::Definite));
+        assert_eq!(tag.at_path(&[0]).unwrap().length_form, None);
+
+        let write = |tag: &Tag, length_policy| {
+            let mut buf = Vec::new();
+            tag.write_with(&mut buf, &WriteOptions { length_policy, ..WriteOptions::default() }).unwrap();
+            buf
+        };
+
+        assert_eq!(write(&tag, LengthPolicy::Preserve), payload);
+        assert_eq!(write(&tag, LengthPolicy::Always(LengthForm::Definite)),
+                   vec![0x30, 0x07, 0x02, 0x01, 0x05, 0x30, 0x02, 0x05, 0x00]);
+        assert_eq!(write(&tag, LengthPolicy::Always(LengthForm::Indefinite)),
+                   vec![0x30, 0x80, 0x02, 0x01, 0x05, 0x30, 0x80, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        // a caller's choice for one node
+        tag.length_form = Some(LengthForm::Indefinite);
+        tag.at_path_mut(&[1]).unwrap().length_form = Some(LengthForm::Definite);
+        assert_eq!(write(&tag, LengthPolicy::Preserve),
+                   vec![0x30, 0x80, 0x02, 0x01, 0x05, 0x30, 0x02, 0x05, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_preserve_layout () {
+        // [5] in the long form with a padding group, a zero length in two octets, and
+        // a definite SEQUENCE whose length takes three octets
+        let payload = vec![
+            0x30, 0x83, 0x00, 0x00, 0x0C,
+                0x9F, 0x80, 0x05, 0x82, 0x00, 0x00,
+                0x02, 0x81, 0x01, 0x07,
+                0x05, 0x00,
+        ];
+        let tag = Tag::read(&mut &payload[..]).unwrap();
+        assert_eq!(tag.layout, Some(HeaderLayout { number_octets: None, length_octets: Some(3) }));
+        assert_eq!(tag.at_path(&[0]).unwrap().layout, Some(HeaderLayout { number_octets: Some(2), length_octets: Some(2) }));
+        assert_eq!(tag.at_path(&[2]).unwrap().layout, None);
+
+        let preserve = WriteOptions { length_policy: LengthPolicy::Preserve, ..WriteOptions::default() };
+        let mut buf = Vec::new();
+        tag.write_with(&mut buf, &preserve).unwrap();
+        assert_eq!(buf, payload);
+
+        let mut buf = Vec::new();
+        tag.write_with(&mut buf, &WriteOptions { length_policy: LengthPolicy::Always(LengthForm::Definite), ..WriteOptions::default() }).unwrap();
+        assert_eq!(buf, vec![0x30, 0x07, 0x85, 0x00, 0x02, 0x01, 0x07, 0x05, 0x00]);
+
+        // a layout too short for the value falls back to the shortest form
+        let mut tag = Tag::octet_string(vec![0; 0x100]);
+        tag.layout = Some(HeaderLayout { number_octets: None, length_octets: Some(1) });
+        let mut buf = Vec::new();
+        tag.write_with(&mut buf, &preserve).unwrap();
+        assert_eq!(&buf[..4], &[0x04, 0x82, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_paths_and_redact () {
+        let mut tag = Tag::sequence(vec![
+            Tag::integer(5),
+            Tag::sequence(vec![Tag::octet_string(vec![1, 2, 3])]),
+        ]);
+
+        assert_eq!(tag.at_path(&[]), Some(&tag));
+        assert_eq!(tag.at_path(&[1, 0]).unwrap().content().unwrap(), &[1, 2, 3]);
+        assert!(tag.at_path(&[0, 0]).is_none());
+        assert!(tag.at_path(&[2]).is_none());
+
+        tag.at_path_mut(&[1]).unwrap().redact();
+        assert_eq!(tag.at_path(&[1, 0]).unwrap().content().unwrap(), &[0, 0, 0]);
+        assert_eq!(tag.at_path(&[0]).unwrap().content().unwrap(), &[5]);
+    }
+
+    #[test]
+    fn test_lenient_repairs () {
+        let lenient = ReadOptions { lenient: true, ..ReadOptions::default() };
+
+        // child runs one byte past the SEQUENCE's declared length
+        let overrun = vec![0x30, 0x02, 0x04, 0x01, 0xAA];
+        assert!(matches!(Tag::read(&mut Cursor::new(overrun.clone())).unwrap_err().kind, err::Kind::LengthExceedsContainer));
+        let tag = Tag::read_with(&mut Cursor::new(overrun), &lenient).unwrap();
+        assert_eq!(tag.repaired, Some(LengthRepair { declared: 2, actual: 3 }));
+
+        // input ends between children, then inside a primitive
+        let tag = Tag::read_with(&mut Cursor::new(vec![0x30, 0x06, 0x05, 0x00]), &lenient).unwrap();
+        assert_eq!(tag.repaired, Some(LengthRepair { declared: 6, actual: 2 }));
+
+        // an indefinite length has nothing to reconcile against
+        let e = Tag::read_with(&mut Cursor::new(vec![0x30, 0x80, 0x04, 0x05, 0xAA]), &lenient).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { .. }));
+        let tag = Tag::read_with(&mut Cursor::new(vec![0x30, 0x07, 0x05, 0x00, 0x04, 0x03, 0xAA]), &lenient).unwrap();
+        let repaired = tag.repaired_nodes();
+        assert_eq!(repaired.len(), 2);
+        assert_eq!(repaired[0].repaired, Some(LengthRepair { declared: 7, actual: 5 }));
+        assert_eq!(repaired[1].offset, Some(4));
+        assert_eq!(repaired[1].repaired, Some(LengthRepair { declared: 3, actual: 1 }));
+    }
+
+    #[test]
+    fn test_empty_values () {
+        // SEQUENCE { INTEGER with no content, BOOLEAN with no content, OCTET STRING "" }
+        let input = vec![0x30, 0x06, 0x02, 0x00, 0x01, 0x00, 0x04, 0x00];
+
+        let tag = Tag::read(&mut Cursor::new(input.clone())).unwrap();
+        assert!(matches!(tag.child(0).unwrap().as_i64().unwrap_err().kind, err::Kind::InvalidValue));
+
+        let reject = ReadOptions { empty_values: EmptyValues::Reject, ..ReadOptions::default() };
+        let e = Tag::read_with(&mut Cursor::new(input.clone()), &reject).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidValue), "{}", e);
+        assert_eq!(e.offset, 4);
+
+        let repair = ReadOptions::builder().empty_values(EmptyValues::Repair).build();
+        let tag = Tag::read_with(&mut Cursor::new(input), &repair).unwrap();
+        assert_eq!(tag.child(0).unwrap().as_i64().unwrap(), 0);
+        assert!(!tag.child(1).unwrap().as_bool().unwrap());
+        assert_eq!(tag.child(2).unwrap().as_primitive(), Some(&[][..]));
+        let repaired = tag.repaired_nodes();
+        assert_eq!(repaired.iter().map(|t| t.offset).collect::<Vec<_>>(), [Some(2), Some(4)]);
+        assert_eq!(repaired[0].repaired, Some(LengthRepair { declared: 0, actual: 1 }));
+    }
+
+    #[test]
+    fn test_payload_budget () {
+        // SEQUENCE { OCTET STRING (3), SEQUENCE { NULL, OCTET STRING (3) } }
+        let payload = vec![0x30, 0x0E, 0x04, 0x03, 0xAA, 0xAA, 0xAA, 0x30, 0x07, 0x05, 0x00, 0x04, 0x03, 0xBB, 0xBB, 0xBB];
+        let opts = ReadOptions { max_total_payload_bytes: 5, ..ReadOptions::default() };
+
+        let e = Tag::read_with(&mut Cursor::new(payload.clone()), &opts).unwrap_err();
+        match e.kind {
+            err::Kind::BudgetExceeded { limit, ref path } => {
+                assert_eq!(limit, 5);
+                assert_eq!(*path, vec![1, 1]);
+            },
+            _ => panic!("unexpected {}", e),
+        }
+        assert_eq!(e.offset, 13);
+
+        let opts = ReadOptions { max_total_payload_bytes: 6, ..ReadOptions::default() };
+        assert!(Tag::read_with(&mut Cursor::new(payload), &opts).is_ok());
+    }
+
+    #[test]
+    fn test_max_children () {
+        // SEQUENCE { NULL, SEQUENCE { NULL, NULL, NULL } }
+        let payload = vec![0x30, 0x0A, 0x05, 0x00, 0x30, 0x06, 0x05, 0x00, 0x05, 0x00, 0x05, 0x00];
+        let opts = ReadOptions { max_children_per_node: 2, ..ReadOptions::default() };
+
+        let e = Tag::read_with(&mut Cursor::new(payload.clone()), &opts).unwrap_err();
+        match e.kind {
+            err::Kind::TooManyChildren { limit, ref path } => {
+                assert_eq!(limit, 2);
+                assert_eq!(*path, vec![1]);
+            },
+            _ => panic!("unexpected {}", e),
+        }
+        assert_eq!(e.offset, 12);
+
+        let opts = ReadOptions { max_children_per_node: 3, ..ReadOptions::default() };
+        assert!(Tag::read_with(&mut Cursor::new(payload), &opts).is_ok());
+    }
+
+    #[test]
+    fn test_deadline () {
+        // SEQUENCE { NULL, SEQUENCE { NULL, NULL, NULL } }
+        let payload = vec![0x30, 0x0A, 0x05, 0x00, 0x30, 0x06, 0x05, 0x00, 0x05, 0x00, 0x05, 0x00];
+
+        let opts = ReadOptions { max_tags: 4, ..ReadOptions::default() };
+        let e = Tag::read_with(&mut Cursor::new(payload.clone()), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::DeadlineExceeded { tags: 4 }), "{}", e);
+        assert_eq!(e.offset, 8);
+
+        let opts = ReadOptions { max_tags: 6, ..ReadOptions::default() };
+        assert!(Tag::read_with(&mut Cursor::new(payload.clone()), &opts).is_ok());
+
+        let opts = ReadOptions { deadline: Some(Instant::now()), ..ReadOptions::default() };
+        let e = Tag::read_with(&mut Cursor::new(payload), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::DeadlineExceeded { tags: 0 }), "{}", e);
+    }
+
+    #[test]
+    fn test_strict_der () {
+        let strict = ReadOptions { strict_der: true, ..ReadOptions::default() };
+        let read = |v: &[u8]| Tag::read_with(&mut Cursor::new(v.to_vec()), &strict);
+
+        assert!(read(&[0x30, 0x03, 0x01, 0x01, 0xFF]).is_ok());
+        // a second message after the first is left alone
+        assert!(read(&[0x05, 0x00, 0x05, 0x00]).is_ok());
+
+        for &(input, kind) in &[
+            (&[0x30, 0x80, 0x05, 0x00, 0x00, 0x00][..], der::ViolationKind::IndefiniteLength),
+            (&[0x04, 0x81, 0x01, 0x00][..], der::ViolationKind::NonMinimalLength),
+            (&[0x24, 0x03, 0x04, 0x01, 0x00][..], der::ViolationKind::ConstructedString),
+            (&[0x30, 0x03, 0x01, 0x01, 0x01][..], der::ViolationKind::InvalidBoolean),
+            // a length that runs past the end of any offset
+            (&[0x04, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF][..], der::ViolationKind::Malformed),
+        ] {
+            let e = read(input).unwrap_err();
+            assert!(matches!(e.kind, err::Kind::NotDer(k) if k == kind), "{}", e);
+        }
+        assert_eq!(read(&[0x30, 0x03, 0x01, 0x01, 0x01]).unwrap_err().offset, 2);
+
+        assert!(matches!(read(&[]).unwrap_err().kind, err::Kind::CleanEof));
+    }
+
+    #[test]
+    fn test_content_beyond_prealloc_cap () {
+        let mut payload = vec![0x04, 0x82, 0x01, 0x00];
+        payload.extend_from_slice(&[0xAB; 256]);
+
+        let opts = ReadOptions { prealloc_cap: 16, ..ReadOptions::default() };
+        let tag = Tag::read_with(&mut Cursor::new(payload), &opts).unwrap();
+        assert_eq!(tag.payload, Payload::Primitive(vec![0xAB; 256]));
+    }
+
+    #[test]
+    fn test_absurd_declared_length () {
+        // five bytes claiming 4GB of content: the read runs out of input long before it
+        // has allocated much
+        let payload = vec![0x04, 0x84, 0xFF, 0xFF, 0xFF, 0xFF, 0xAB];
+        let e = Tag::read(&mut Cursor::new(payload.clone())).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Truncated { needed: 0xFFFFFFFE }), "{}", e);
+
+        // with a per element cap it fails on the header alone
+        let opts = ReadOptions { max_element_bytes: 1024, ..ReadOptions::default() };
+        let e = Tag::read_with(&mut Cursor::new(payload), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::BudgetExceeded { limit: 1024, .. }), "{}", e);
+        assert_eq!(e.offset, 6);
+
+        let opts = ReadOptions { max_element_bytes: 256, ..ReadOptions::default() };
+        let mut payload = vec![0x30, 0x84, 0x00, 0x00, 0x01, 0x04, 0x04, 0x82, 0x01, 0x00];
+        payload.extend_from_slice(&[0xAB; 256]);
+        assert!(Tag::read_with(&mut Cursor::new(payload), &opts).is_ok());
+    }
+
+    #[test]
+    fn test_error_path () {
+        // SEQUENCE { INTEGER 5, SET { NULL, NULL, NULL with an indefinite length } }
+        let input = [0x30, 0x80, 0x02, 0x01, 0x05, 0x31, 0x80, 0x05, 0x00, 0x05, 0x00, 0x05, 0x80];
+        let e = Tag::read(&mut &input[..]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidLength), "{}", e);
+        assert_eq!(e.path, [
+            err::Step { number: Number::Universal(Type::Sequence), index: 1 },
+            err::Step { number: Number::Universal(Type::Set), index: 2 },
+        ]);
+
+        // the root itself failing has no path
+        let e = Tag::read(&mut &[0x05, 0x80][..]).unwrap_err();
+        assert!(e.path.is_empty());
+    }
+
+    #[test]
+    fn test_read_exact_one () {
+        // SEQUENCE { NULL }, then a smuggled NULL
+        let input = [0x30, 0x02, 0x05, 0x00, 0x05, 0x00];
+
+        let tag = Tag::read_exact_one(&mut &input[..4]).unwrap();
+        assert_eq!(tag, Tag::read(&mut &input[..]).unwrap());
+
+        let e = Tag::read_exact_one(&mut &input[..]).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::TrailingData), "{}", e);
+        assert_eq!(e.offset, 4);
+
+        // nothing at all is still a clean end, and a cut short tag still truncated
+        assert!(matches!(Tag::read_exact_one(&mut &input[..0]).unwrap_err().kind, err::Kind::CleanEof));
+        assert!(matches!(Tag::read_exact_one(&mut &input[..3]).unwrap_err().kind, err::Kind::Truncated { .. }));
+    }
+
+    #[test]
+    fn test_max_depth () {
+        // SEQUENCE { SEQUENCE { SEQUENCE { NULL } } }
+        let payload = vec![0x30, 0x06, 0x30, 0x04, 0x30, 0x02, 0x05, 0x00];
+
+        let opts = ReadOptions::builder().max_depth(3).build();
+        let e = Tag::read_with(&mut Cursor::new(payload.clone()), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::TooDeep { limit: 3 }), "{}", e);
+        assert_eq!(e.offset, 6);
+
+        let opts = ReadOptions::builder().max_depth(4).build();
+        assert!(Tag::read_with(&mut Cursor::new(payload), &opts).is_ok());
+    }
+
+    // `depth` SEQUENCEs around a NULL
+    fn nested (depth: usize) -> Tag {
+        (1..depth).fold(Tag::null(), |tag, _| Tag::sequence(vec![tag]))
+    }
+
+    #[test]
+    fn test_write_max_depth () {
+        let tag = nested(4);
+        let mut buf = Vec::new();
+        let opts = WriteOptions { max_depth: 3, ..WriteOptions::default() };
+        let e = tag.write_with(&mut buf, &opts).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+
+        let opts = WriteOptions { max_depth: 4, ..WriteOptions::default() };
+        tag.write_with(&mut buf, &opts).unwrap();
+        assert_eq!(buf, [0x30, 0x80, 0x30, 0x80, 0x30, 0x80, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        // far deeper than a recursive writer could go, and redacted and dropped without
+        // recursing either
+        let mut tag = nested(200_000);
+        let mut buf = Vec::new();
+        tag.write(&mut buf).unwrap();
+        assert_eq!(buf.len(), 4 * 199_999 + 2);
+        assert_eq!(buf[199_999 * 2..][..2], [0x05, 0x00]);
+
+        let mut buf = Vec::new();
+        let opts = WriteOptions { length_policy: LengthPolicy::Always(LengthForm::Definite), ..WriteOptions::default() };
+        tag.write_with(&mut buf, &opts).unwrap();
+        assert_eq!(buf[..2], [0x30, 0x83]);
+        assert_eq!(buf[buf.len() - 2..], [0x05, 0x00]);
+        tag.redact();
+    }
+
+    #[test]
+    fn test_options_builder () {
+        let opts = ReadOptions::builder()
+            .lenient(true)
+            .max_element_bytes(16)
+            .rules(Rules::Der)
+            .max_tags(10)
+            .build();
+        assert!(opts.lenient && opts.strict_der);
+        assert_eq!((opts.max_element_bytes, opts.max_tags), (16, 10));
+        assert_eq!(opts.rules(), Rules::Der);
+        assert_eq!(ReadOptions::builder().build().rules(), Rules::Ber);
+
+        // DER input is rejected if it isn't DER
+        let e = Tag::read_with(&mut Cursor::new(vec![0x30, 0x80, 0x00, 0x00]), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::NotDer(_)), "{}", e);
+    }
+
+    #[test]
+    fn test_validate_utf8 () {
+        // SEQUENCE { UTF8String "ab\xC3(", PrintableString "\xFF" }
+        let payload = vec![0x30, 0x09, 0x0C, 0x04, 0x61, 0x62, 0xC3, 0x28, 0x13, 0x01, 0xFF];
+        assert!(Tag::read(&mut Cursor::new(payload.clone())).is_ok());
+
+        let opts = ReadOptions { validate_utf8: true, ..ReadOptions::default() };
+        let e = Tag::read_with(&mut Cursor::new(payload.clone()), &opts).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::InvalidString(Type::Utf8String)), "{}", e);
+        assert_eq!(e.offset, 6);
+
+        // other string types are left alone
+        let mut fixed = payload;
+        fixed[6] = 0x63;
+        assert!(Tag::read_with(&mut Cursor::new(fixed), &opts).is_ok());
+    }
+
+    // Hands out one byte per call, interrupting every other call, and would block once
+    // `ready` bytes are gone.
+    struct Flaky {
+        input: Vec<u8>,
+        pos:   usize,
+        ready: usize,
+        calls: usize,
+    }
+
+    impl Read for Flaky {
+        fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls.is_multiple_of(2) {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            if self.pos == self.ready && self.pos < self.input.len() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(1).min(self.input.len() - self.pos);
+            buf[..n].copy_from_slice(&self.input[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_io_error_kinds () {
+        let input = vec![0x30, 0x80, 0x04, 0x03, 0x61, 0x62, 0x63, 0x00, 0x00];
+        let flaky = |ready| Flaky { input: input.clone(), pos: 0, ready, calls: 0 };
+
+        // interruptions are retried
+        let tag = Tag::read(&mut flaky(input.len())).unwrap();
+        assert_eq!(tag, Tag::read(&mut &input[..]).unwrap());
+
+        let e = Tag::read(&mut flaky(5)).unwrap_err();
+        assert!(matches!(e.kind, err::Kind::Pending), "{}", e);
+        assert!(e.is_retryable());
+        assert_eq!(e.offset, 5);
+    }
+
     #[test]
     #[should_panic]
     fn test_invalid_number () {
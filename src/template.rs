@@ -0,0 +1,222 @@
+//! Structural matching of tags against templates with wildcards, for conformance tests
+//! where some fields legitimately vary from message to message.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::tag::{Number, Payload, Tag, Type};
+
+/// An expected tree shape.
+#[derive(Debug, Clone)]
+pub enum Template {
+    /// Matches any tag.
+    Any,
+    /// Matches any primitive tag.
+    AnyPrimitive,
+    /// Matches any tag with the given universal type.
+    AnyOfType(Type),
+    /// Matches a primitive tag with exactly this number and content.
+    Primitive(Number, Vec<u8>),
+    /// Matches a constructed tag with this number whose children match the templates
+    /// in order.
+    Constructed(Number, Vec<Template>),
+    /// Matches zero or more consecutive children; only meaningful inside `Constructed`.
+    Repeated(Box<Template>),
+    /// Matches what the inner template matches, binding the tag to a name.
+    Capture(String, Box<Template>),
+}
+
+/// Tags bound by `Capture` templates during a successful match.
+#[derive(Debug, Clone, Default)]
+pub struct Bindings<'a> {
+    map: HashMap<String, Vec<&'a Tag>>,
+}
+
+impl<'a> Bindings<'a> {
+    /// The first tag bound to `name`.
+    pub fn get (&self, name: &str) -> Option<&'a Tag> {
+        self.map.get(name).and_then(|v| v.first().cloned())
+    }
+
+    /// Every tag bound to `name`, in document order; captures under `Repeated` can bind
+    /// more than one.
+    pub fn get_all (&self, name: &str) -> &[&'a Tag] {
+        self.map.get(name).map(|v| &v[..]).unwrap_or(&[])
+    }
+}
+
+impl Template {
+    /// A template matching exactly `tag`.
+    pub fn exact (tag: &Tag) -> Template {
+        match tag.payload {
+            Payload::Primitive(ref v) => Template::Primitive(tag.number, v.clone()),
+            Payload::Constructed(ref children) =>
+                Template::Constructed(tag.number, children.iter().map(Template::exact).collect()),
+        }
+    }
+
+    /// Matches `tag` against this template, returning the captured bindings on success.
+    pub fn matches<'a> (&self, tag: &'a Tag) -> Option<Bindings<'a>> {
+        let mut log = Vec::new();
+        if !self.match_into(tag, &mut log) {
+            return None;
+        }
+
+        let mut bindings = Bindings::default();
+        for (name, tag) in log {
+            bindings.map.entry(name.to_string()).or_default().push(tag);
+        }
+        Some(bindings)
+    }
+
+    // On failure `log` may be left with bindings of the failed attempt; the caller
+    // truncates them away.
+    fn match_into<'t, 'a> (&'t self, tag: &'a Tag, log: &mut Log<'t, 'a>) -> bool {
+        match *self {
+            Template::Any => true,
+            Template::AnyPrimitive => matches!(tag.payload, Payload::Primitive(_)),
+            Template::AnyOfType(t) => tag.number == Number::Universal(t),
+            Template::Primitive(ref number, ref content) => *number == tag.number && match tag.payload {
+                Payload::Primitive(ref v) => v == content,
+                Payload::Constructed(_) => false,
+            },
+            Template::Constructed(ref number, ref templates) => *number == tag.number && match tag.payload {
+                Payload::Constructed(ref children) => match_seq(templates, children, log, &mut HashSet::new()),
+                Payload::Primitive(_) => false,
+            },
+            // a lone Repeated stands for a single element
+            Template::Repeated(ref inner) => inner.match_into(tag, log),
+            Template::Capture(ref name, ref inner) => {
+                if inner.match_into(tag, log) {
+                    log.push((name, tag));
+                    true
+                } else {
+                    false
+                }
+            },
+        }
+    }
+}
+
+// Every binding made so far, in the order it was made, so that backtracking undoes a
+// failed attempt by truncating to where the attempt began.
+type Log<'t, 'a> = Vec<(&'t str, &'a Tag)>;
+
+// Matches children against a template sequence, backtracking over how many children
+// each Repeated consumes. Bindings never decide whether a match succeeds, so a suffix
+// of the templates that failed against a suffix of the children always will: `failed`
+// holds those, by how many of each remain, and keeps the backtracking polynomial.
+fn match_seq<'t, 'a> (templates: &'t [Template], children: &'a [Tag], log: &mut Log<'t, 'a>, failed: &mut HashSet<(usize, usize)>) -> bool {
+    let (first, rest) = match templates.split_first() {
+        Some(x) => x,
+        None => return children.is_empty(),
+    };
+    if failed.contains(&(templates.len(), children.len())) {
+        return false;
+    }
+
+    let mark = log.len();
+    let matched = if let Template::Repeated(ref inner) = *first {
+        // greedily take as many children as match, noting where the log stood after
+        // each, then give them back one at a time
+        let mut marks = Vec::new();
+        for child in children {
+            if !inner.match_into(child, log) {
+                break;
+            }
+            marks.push(log.len());
+        }
+
+        (0..=marks.len()).rev().any(|n| {
+            log.truncate(if n == 0 { mark } else { marks[n - 1] });
+            match_seq(rest, &children[n..], log, failed)
+        })
+    } else {
+        match children.split_first() {
+            Some((child, others)) => first.match_into(child, log) && match_seq(rest, others, log, failed),
+            None => false,
+        }
+    };
+
+    if !matched {
+        log.truncate(mark);
+        failed.insert((templates.len(), children.len()));
+    }
+    matched
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn prim (number: Number, v: &[u8]) -> Tag {
+        Tag {
+            number,
+            offset: None,
+            payload: Payload::Primitive(v.to_vec()),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    fn seq (children: Vec<Tag>) -> Tag {
+        Tag {
+            number: Number::Universal(Type::Sequence),
+            offset: None,
+            payload: Payload::Constructed(children),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    #[test]
+    fn test_exact () {
+        let tag = seq(vec![prim(Number::Universal(Type::Integer), &[1])]);
+        assert!(Template::exact(&tag).matches(&tag).is_some());
+        assert!(Template::exact(&tag).matches(&seq(vec![])).is_none());
+    }
+
+    #[test]
+    fn test_wildcards_and_captures () {
+        let template = Template::Constructed(Number::Universal(Type::Sequence), vec![
+            Template::Capture("id".to_string(), Box::new(Template::AnyOfType(Type::Integer))),
+            Template::Repeated(Box::new(Template::Capture("item".to_string(), Box::new(Template::AnyPrimitive)))),
+            Template::Primitive(Number::Universal(Type::Null), vec![]),
+        ]);
+
+        let tag = seq(vec![
+            prim(Number::Universal(Type::Integer), &[7]),
+            prim(Number::Universal(Type::OctetString), b"a"),
+            prim(Number::Universal(Type::OctetString), b"b"),
+            prim(Number::Universal(Type::Null), &[]),
+        ]);
+
+        let bindings = template.matches(&tag).unwrap();
+        assert_eq!(bindings.get("id").unwrap().payload, Payload::Primitive(vec![7]));
+        // the trailing NULL is primitive too, but must be left for the last template
+        assert_eq!(bindings.get_all("item").len(), 2);
+
+        let short = seq(vec![prim(Number::Universal(Type::Integer), &[7])]);
+        assert!(template.matches(&short).is_none());
+    }
+
+    #[test]
+    fn test_large_sequences () {
+        let items = || Template::Repeated(Box::new(Template::Capture("item".to_string(), Box::new(Template::AnyOfType(Type::Integer)))));
+        let null = Template::Primitive(Number::Universal(Type::Null), vec![]);
+        let mut children: Vec<_> = (0..10_000).map(|i| prim(Number::Universal(Type::Integer), &[i as u8])).collect();
+        children.push(prim(Number::Universal(Type::Null), &[]));
+        let tag = seq(children);
+
+        let template = Template::Constructed(Number::Universal(Type::Sequence), vec![items(), null.clone()]);
+        assert_eq!(template.matches(&tag).unwrap().get_all("item").len(), 10_000);
+
+        // adjacent Repeateds that can't finish the match try every split of the children
+        // once, not once per way of reaching it
+        let mut children: Vec<_> = (0..1000).map(|i| prim(Number::Universal(Type::Integer), &[i as u8])).collect();
+        children.push(prim(Number::Universal(Type::Null), &[]));
+        let template = Template::Constructed(Number::Universal(Type::Sequence), vec![items(), items(), items(), null.clone(), null]);
+        assert!(template.matches(&seq(children)).is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! Support for downstream crates testing their own message types: the
+//! `roundtrip_tests!` macro and the checks it runs, and differential checks for rolling
+//! out parser behaviour changes.
+//!
+//! `check_differential` reads a corpus under two profiles side by side. To compare two
+//! versions of this crate instead, `record` what the old one produces for each input,
+//! keep the records, and `compare_records` against what the new one produces.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::diff;
+use crate::json;
+use crate::tag::{ReadOptions, Tag};
+
+/// A way of reading and writing tags that fixtures must round-trip under.
+#[derive(Clone)]
+pub struct Profile {
+    pub name: &'static str,
+    pub read: ReadOptions,
+    pub write: fn (&Tag, &mut dyn Write) -> io::Result<()>,
+}
+
+impl Profile {
+    /// Default `ReadOptions`, written with `Tag::write`.
+    pub fn ber () -> Profile {
+        Profile {
+            name: "ber",
+            read: ReadOptions::default(),
+            write: |tag, w| tag.write(w),
+        }
+    }
+
+    /// Default `ReadOptions`, written with `Tag::write_der`.
+    pub fn der () -> Profile {
+        Profile {
+            name: "der",
+            read: ReadOptions::default(),
+            write: |tag, w| tag.write_der(w),
+        }
+    }
+
+    /// Default `ReadOptions`, written with `Tag::write_cer`.
+    pub fn cer () -> Profile {
+        Profile {
+            name: "cer",
+            read: ReadOptions::default(),
+            write: |tag, w| tag.write_cer(w),
+        }
+    }
+
+    /// Lenient `ReadOptions`, written with `Tag::write`.
+    pub fn lenient () -> Profile {
+        Profile {
+            name: "lenient",
+            read: ReadOptions { lenient: true, ..ReadOptions::default() },
+            write: |tag, w| tag.write(w),
+        }
+    }
+
+    /// `ReadOptions` rejecting anything but DER, written with `Tag::write_der`.
+    pub fn strict () -> Profile {
+        Profile {
+            name: "strict",
+            read: ReadOptions { strict_der: true, ..ReadOptions::default() },
+            write: |tag, w| tag.write_der(w),
+        }
+    }
+
+    /// The built in profile called `name`.
+    pub fn named (name: &str) -> Option<Profile> {
+        match name {
+            "ber" => Some(Profile::ber()),
+            "der" => Some(Profile::der()),
+            "cer" => Some(Profile::cer()),
+            "lenient" => Some(Profile::lenient()),
+            "strict" => Some(Profile::strict()),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that every fixture reads, writes and reads again to the same encoding under
+/// every profile, returning a description of each failure.
+pub fn check_roundtrip (dir: &Path, fixtures: &[&str], profiles: &[Profile]) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    for fixture in fixtures {
+        let input = match fs::read(dir.join(fixture)) {
+            Ok(x) => x,
+            Err(e) => {
+                failures.push(format!("{}: {}", fixture, e));
+                continue;
+            },
+        };
+
+        for profile in profiles {
+            if let Err(e) = roundtrip(&input, profile) {
+                failures.push(format!("{} [{}]: {}", fixture, profile.name, e));
+            }
+        }
+    }
+
+    failures
+}
+
+fn roundtrip (input: &[u8], profile: &Profile) -> Result<(), String> {
+    let once = reencode(input, profile).map_err(|e| format!("first read: {}", e))?;
+    let twice = reencode(&once, profile).map_err(|e| format!("second read: {}", e))?;
+
+    if once != twice {
+        return Err(format!("re-encoding is not stable: {} bytes then {} bytes", once.len(), twice.len()));
+    }
+    Ok(())
+}
+
+fn reencode (input: &[u8], profile: &Profile) -> Result<Vec<u8>, String> {
+    let mut r = input;
+    let tag = Tag::read_with(&mut r, &profile.read).map_err(|e| e.to_string())?;
+    if !r.is_empty() {
+        return Err(format!("{} trailing bytes", r.len()));
+    }
+
+    let mut out = Vec::new();
+    (profile.write)(&tag, &mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// What reading the first message of `input` under `profile` produces, as one line of
+/// text that stays comparable across versions of this crate: the JSON export of the
+/// tree, or `error` and the error's stable code.
+pub fn record (input: &[u8], profile: &Profile) -> String {
+    match Tag::read_with(&mut &input[..], &profile.read) {
+        Ok(tag) => json::to_string(&tag),
+        Err(e) => format!("error {}", e.code()),
+    }
+}
+
+/// Describes how two records of the same input differ, or returns `None` if they match.
+pub fn compare_records (a: &str, b: &str) -> Option<String> {
+    if a == b {
+        return None;
+    }
+
+    let (ta, tb) = match (json::read(a), json::read(b)) {
+        (Ok(ta), Ok(tb)) => (ta, tb),
+        (Ok(_), Err(_)) => return Some(format!("accepted, then rejected with {}", b)),
+        (Err(_), Ok(_)) => return Some(format!("rejected with {}, then accepted", a)),
+        (Err(_), Err(_)) => return Some(format!("rejected with {}, then with {}", a, b)),
+    };
+    Some(match diff::diff(&ta, &tb).first() {
+        Some(d) => format!("trees differ at {}", d),
+        None => "trees differ in offsets or header forms".to_string(),
+    })
+}
+
+/// Reads every fixture under both profiles and describes each one they disagree on:
+/// accepted by one and rejected by the other, rejected with different errors, or
+/// accepted as different trees.
+pub fn check_differential (dir: &Path, fixtures: &[&str], a: &Profile, b: &Profile) -> Vec<String> {
+    let mut divergences = Vec::new();
+
+    for fixture in fixtures {
+        let input = match fs::read(dir.join(fixture)) {
+            Ok(x) => x,
+            Err(e) => {
+                divergences.push(format!("{}: {}", fixture, e));
+                continue;
+            },
+        };
+
+        if let Some(d) = compare_records(&record(&input, a), &record(&input, b)) {
+            divergences.push(format!("{} [{} vs {}]: {}", fixture, a.name, b.name, d));
+        }
+    }
+
+    divergences
+}
+
+/// Expands to a module of tests asserting that each fixture file survives
+/// read → write → read unchanged. Fixture paths are relative to the calling crate's
+/// manifest directory; profiles default to `Profile::ber()`.
+///
+/// ```ignore
+/// roundtrip_tests!(ldap, ["tests/fixtures/bind-request.ber", "tests/fixtures/search.ber"]);
+/// roundtrip_tests!(snmp, ["tests/fixtures/get.ber"], [ber::testing::Profile::ber()]);
+/// ```
+#[macro_export]
+macro_rules! roundtrip_tests {
+    ($module:ident, [$($fixture:expr),* $(,)?]) => {
+        $crate::roundtrip_tests!($module, [$($fixture),*], [$crate::testing::Profile::ber()]);
+    };
+    ($module:ident, [$($fixture:expr),* $(,)?], [$($profile:expr),* $(,)?]) => {
+        #[cfg(test)]
+        mod $module {
+            #[test]
+            fn roundtrip () {
+                let failures = $crate::testing::check_roundtrip(
+                    ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")),
+                    &[$($fixture),*],
+                    &[$($profile),*],
+                );
+                assert!(failures.is_empty(), "round trip failures:\n{}", failures.join("\n"));
+            }
+        }
+    };
+}
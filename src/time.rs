@@ -0,0 +1,232 @@
+//! UTCTime and GeneralizedTime (X.680 §46, §47) as `chrono` date-times.
+//!
+//! Parsing is BER-tolerant: seconds may be left out, offsets other than `Z` are accepted,
+//! GeneralizedTime may carry a fraction of its last field with either a period or a comma,
+//! and a missing zone is read as UTC. Encoding always produces the DER forms.
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+
+use crate::err;
+use crate::tag::{Number, Tag, Type};
+
+struct Cursor<'a> {
+    s: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn digits (&mut self, n: usize) -> Option<u32> {
+        if self.s.len() < n || !self.s[..n].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let v = self.s[..n].iter().fold(0, |acc, &c| acc * 10 + (c - b'0') as u32);
+        self.s = &self.s[n..];
+        Some(v)
+    }
+
+    fn next_is_digit (&self) -> bool {
+        self.s.first().is_some_and(u8::is_ascii_digit)
+    }
+
+    fn eat (&mut self, c: u8) -> bool {
+        if self.s.first() == Some(&c) {
+            self.s = &self.s[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    /// `Z`, `+hh[mm]`, `-hh[mm]` or nothing (UTC), which must end the input.
+    fn zone (&mut self) -> Option<FixedOffset> {
+        let offset = if self.s.is_empty() || self.eat(b'Z') {
+            FixedOffset::east_opt(0)?
+        } else {
+            let sign = if self.eat(b'+') {
+                1
+            } else if self.eat(b'-') {
+                -1
+            } else {
+                return None;
+            };
+            let hours = self.digits(2)?;
+            let minutes = if self.s.is_empty() { 0 } else { self.digits(2)? };
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60) as i32)?
+        };
+
+        if self.s.is_empty() { Some(offset) } else { None }
+    }
+}
+
+fn datetime (date: NaiveDate, h: u32, m: u32, s: u32, extra_nanos: i64, zone: FixedOffset) -> Option<DateTime<FixedOffset>> {
+    // a leap second is represented by chrono as second 59 with an extra second of nanos
+    let time = if s == 60 {
+        NaiveTime::from_hms_nano_opt(h, m, 59, 1_000_000_000)?
+    } else {
+        NaiveTime::from_hms_opt(h, m, s)?
+    };
+    let local = NaiveDateTime::new(date, time).checked_add_signed(TimeDelta::nanoseconds(extra_nanos))?;
+    zone.from_local_datetime(&local).single()
+}
+
+/// Parses UTCTime content: `YYMMDDhhmm[ss]` and a zone. Two digit years below 50 are in
+/// the 2000s, as RFC 5280 specifies.
+pub fn parse_utc_time (content: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let mut c = Cursor { s: content };
+    let yy = c.digits(2)? as i32;
+    let (month, day, hour, minute) = (c.digits(2)?, c.digits(2)?, c.digits(2)?, c.digits(2)?);
+    let second = if c.next_is_digit() { c.digits(2)? } else { 0 };
+    let zone = c.zone()?;
+
+    let year = if yy < 50 { 2000 + yy } else { 1900 + yy };
+    datetime(NaiveDate::from_ymd_opt(year, month, day)?, hour, minute, second, 0, zone)
+}
+
+/// Parses GeneralizedTime content: `YYYYMMDDhh[mm[ss]]`, an optional fraction of the
+/// last field present and an optional zone.
+pub fn parse_generalized_time (content: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let mut c = Cursor { s: content };
+    let year = c.digits(4)? as i32;
+    let (month, day, hour) = (c.digits(2)?, c.digits(2)?, c.digits(2)?);
+
+    // seconds in the last field present, which any fraction is a fraction of
+    let mut unit = 3600;
+    let mut minute = 0;
+    let mut second = 0;
+    if c.next_is_digit() {
+        minute = c.digits(2)?;
+        unit = 60;
+        if c.next_is_digit() {
+            second = c.digits(2)?;
+            unit = 1;
+        }
+    }
+
+    let mut nanos = 0;
+    if c.eat(b'.') || c.eat(b',') {
+        let len = c.s.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        // digits past these can't change the result even for a fraction of an hour
+        let used = len.min(15);
+        let numerator = c.s[..used].iter().fold(0u128, |acc, &d| acc * 10 + (d - b'0') as u128);
+        c.s = &c.s[len..];
+        nanos = (numerator * unit as u128 * 1_000_000_000 / 10u128.pow(used as u32)) as i64;
+    }
+    let zone = c.zone()?;
+
+    datetime(NaiveDate::from_ymd_opt(year, month, day)?, hour, minute, second, nanos, zone)
+}
+
+impl Tag {
+    /// Interprets a universal UTCTime or GeneralizedTime. Implicitly tagged times need
+    /// `parse_utc_time` or `parse_generalized_time`, since only the number tells them apart.
+    pub fn as_datetime (&self) -> Result<DateTime<FixedOffset>, err::Error> {
+        let content = self.content()?;
+        let parsed = match self.number {
+            Number::Universal(Type::UtcTime) => parse_utc_time(content),
+            Number::Universal(Type::GeneralizedTime) => parse_generalized_time(content),
+            _ => None,
+        };
+        parsed.ok_or_else(|| self.error(err::Kind::InvalidValue))
+    }
+
+    /// A universal UTCTime in the DER form `YYMMDDhhmmssZ`. Only years 1950 to 2049 fit.
+    pub fn utc_time<Tz: TimeZone> (dt: &DateTime<Tz>) -> Result<Tag, err::Error> {
+        let dt = dt.with_timezone(&Utc);
+        if !(1950..2050).contains(&dt.year()) {
+            return Err(err::Error::new(err::Kind::InvalidValue, 0, None));
+        }
+
+        let (second, _) = seconds(&dt);
+        let text = format!("{:02}{:02}{:02}{:02}{:02}{:02}Z",
+            dt.year() % 100, dt.month(), dt.day(), dt.hour(), dt.minute(), second);
+        Ok(Tag::primitive(Number::Universal(Type::UtcTime), text.into_bytes()))
+    }
+
+    /// A universal GeneralizedTime in the DER form `YYYYMMDDhhmmss[.f]Z`, with trailing
+    /// zeros of the fraction removed.
+    pub fn generalized_time<Tz: TimeZone> (dt: &DateTime<Tz>) -> Result<Tag, err::Error> {
+        let dt = dt.with_timezone(&Utc);
+        if !(0..=9999).contains(&dt.year()) {
+            return Err(err::Error::new(err::Kind::InvalidValue, 0, None));
+        }
+
+        let (second, nanos) = seconds(&dt);
+        let mut text = format!("{:04}{:02}{:02}{:02}{:02}{:02}",
+            dt.year(), dt.month(), dt.day(), dt.hour(), dt.minute(), second);
+        if nanos != 0 {
+            let fraction = format!("{:09}", nanos);
+            text.push('.');
+            text.push_str(fraction.trim_end_matches('0'));
+        }
+        text.push('Z');
+        Ok(Tag::primitive(Number::Universal(Type::GeneralizedTime), text.into_bytes()))
+    }
+}
+
+// Second of the minute and nanoseconds, with chrono's leap second representation undone.
+fn seconds (dt: &DateTime<Utc>) -> (u32, u32) {
+    let nanos = dt.nanosecond();
+    if nanos >= 1_000_000_000 {
+        (60, nanos - 1_000_000_000)
+    } else {
+        (dt.second(), nanos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+
+    use super::{parse_generalized_time, parse_utc_time};
+    use crate::tag::{Number, Payload, Tag, Type};
+
+    fn utc (s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    #[test]
+    fn test_utc_time () {
+        assert_eq!(parse_utc_time(b"491231235959Z"), Some(utc("2049-12-31T23:59:59Z")));
+        assert_eq!(parse_utc_time(b"500101000000Z"), Some(utc("1950-01-01T00:00:00Z")));
+        assert_eq!(parse_utc_time(b"9912312359-0500"), Some(utc("1999-12-31T23:59:00-05:00")));
+        assert_eq!(parse_utc_time(b"991231235959"), Some(utc("1999-12-31T23:59:59Z")));
+
+        assert_eq!(parse_utc_time(b"991331235959Z"), None);
+        assert_eq!(parse_utc_time(b"9912312359Zjunk"), None);
+        assert_eq!(parse_utc_time(b"99123123"), None);
+    }
+
+    #[test]
+    fn test_generalized_time () {
+        assert_eq!(parse_generalized_time(b"20240229120000Z"), Some(utc("2024-02-29T12:00:00Z")));
+        assert_eq!(parse_generalized_time(b"20240229120000.25Z"), Some(utc("2024-02-29T12:00:00.25Z")));
+        assert_eq!(parse_generalized_time(b"20240229120000,5+0130"), Some(utc("2024-02-29T12:00:00.5+01:30")));
+        assert_eq!(parse_generalized_time(b"2024022912.5"), Some(utc("2024-02-29T12:30:00Z")));
+        assert_eq!(parse_generalized_time(b"202402291230-08"), Some(utc("2024-02-29T12:30:00-08:00")));
+        assert_eq!(parse_generalized_time(b"20161231235960Z").unwrap().timestamp(), utc("2016-12-31T23:59:59Z").timestamp());
+
+        assert_eq!(parse_generalized_time(b"20230229120000Z"), None);
+        assert_eq!(parse_generalized_time(b"20240229120000.Z"), None);
+        assert_eq!(parse_generalized_time(b"20240229120000+2500"), None);
+    }
+
+    #[test]
+    fn test_encode () {
+        let dt = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2001, 2, 3, 5, 5, 6).unwrap();
+        assert_eq!(Tag::utc_time(&dt).unwrap().payload, Payload::Primitive(b"010203040506Z".to_vec()));
+        assert_eq!(Tag::generalized_time(&dt).unwrap().payload, Payload::Primitive(b"20010203040506Z".to_vec()));
+
+        let frac = utc("2001-02-03T04:05:06.120Z");
+        let tag = Tag::generalized_time(&frac).unwrap();
+        assert_eq!(tag.payload, Payload::Primitive(b"20010203040506.12Z".to_vec()));
+        assert_eq!(tag.as_datetime().unwrap(), frac);
+
+        assert!(Tag::utc_time(&Utc.with_ymd_and_hms(2050, 1, 1, 0, 0, 0).unwrap()).is_err());
+        assert!(Tag::primitive(Number::Universal(Type::OctetString), b"010203040506Z".to_vec()).as_datetime().is_err());
+    }
+}
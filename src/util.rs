@@ -1,26 +1,148 @@
+use std::fs;
 use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
-pub struct TrackedRead<'a> {
-    inner:      &'a mut (Read + 'a),
-    read_bytes: usize,
+use crate::layer::Tracked;
+
+pub type TrackedRead<'a> = Tracked<&'a mut (dyn Read + 'a)>;
+
+/// Decodes standard base64 text, skipping ASCII whitespace. Returns `None` on any other
+/// character outside the alphabet or on bad padding.
+pub fn base64_decode (text: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+
+    for &c in text {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            },
+            _ if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+
+        if padding > 0 {
+            // data after padding
+            return None;
+        }
+
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    if padding > 2 || bits >= 6 {
+        return None;
+    }
+
+    Some(out)
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `buf` as standard padded base64, on a single line.
+pub fn base64_encode (buf: &[u8]) -> String {
+    let mut out = String::with_capacity(buf.len().div_ceil(3) * 4);
+
+    for chunk in buf.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
 }
 
-impl<'a> TrackedRead<'a> {
-    pub fn new (inner: &'a mut (Read + 'a)) -> TrackedRead<'a> {
-        TrackedRead {
-            inner: inner,
-            read_bytes: 0,
+// Lookup tables for the reflected forms of the CRC-32 and CRC-32C polynomials.
+const CRC32_TABLE: [u32; 256] = crc_table(0xEDB8_8320);
+const CRC32C_TABLE: [u32; 256] = crc_table(0x82F6_3B78);
+
+const fn crc_table (poly: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 { poly ^ (c >> 1) } else { c >> 1 };
+            bit += 1;
         }
+        table[i] = c;
+        i += 1;
     }
-    pub fn tell (&self) -> usize {
-        self.read_bytes
+    table
+}
+
+fn crc (table: &[u32; 256], buf: &[u8]) -> u32 {
+    !buf.iter().fold(!0u32, |c, &b| table[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8))
+}
+
+/// The CRC-32 of `buf`, as used by Ethernet and zlib.
+pub fn crc32 (buf: &[u8]) -> u32 {
+    crc(&CRC32_TABLE, buf)
+}
+
+/// The CRC-32C (Castagnoli) of `buf`, as used by iSCSI and SCTP.
+pub fn crc32c (buf: &[u8]) -> u32 {
+    crc(&CRC32C_TABLE, buf)
+}
+
+/// The files under `dir`, subdirectories included, relative to it and sorted, so
+/// batches over a corpus run in a stable order.
+pub fn files_under (dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(rel) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&rel))? {
+            let entry = entry?;
+            let path = rel.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
     }
+    files.sort();
+    Ok(files)
 }
 
-impl<'a> Read for TrackedRead<'a> {
-    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let count = try!(self.inner.read(buf));
-        self.read_bytes += count;
-        Ok(count)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base64_round_trip () {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+
+        let buf: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(base64_encode(&buf).as_bytes()).unwrap(), buf);
+    }
+
+    #[test]
+    fn test_crc () {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
     }
 }
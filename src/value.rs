@@ -0,0 +1,170 @@
+//! Constructors and accessors for the simple universal types.
+
+use std::borrow::Cow;
+
+use crate::err;
+use crate::tag::{Number, Payload, Tag, Type};
+
+impl Tag {
+    /// A primitive tag with the given number and content, not yet read from anywhere.
+    pub fn primitive (number: Number, content: Vec<u8>) -> Tag {
+        Tag {
+            number,
+            offset: None,
+            payload: Payload::Primitive(content),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    /// A constructed tag with the given number and children.
+    pub fn constructed (number: Number, children: Vec<Tag>) -> Tag {
+        Tag {
+            number,
+            offset: None,
+            payload: Payload::Constructed(children),
+            repaired: None,
+            length_form: None,
+            layout: None,
+        }
+    }
+
+    /// A universal BOOLEAN, encoded as DER requires (0xFF for TRUE).
+    pub fn boolean (b: bool) -> Tag {
+        Tag::primitive(Number::Universal(Type::Boolean), vec![if b { 0xFF } else { 0x00 }])
+    }
+
+    /// Interprets the content as a BOOLEAN the way BER does: any non-zero octet is TRUE.
+    /// The content must be exactly one octet.
+    pub fn as_bool (&self) -> Result<bool, err::Error> {
+        self.bool_with(false)
+    }
+
+    /// Like `as_bool`, but TRUE must be encoded as 0xFF, as DER requires.
+    pub fn as_bool_der (&self) -> Result<bool, err::Error> {
+        self.bool_with(true)
+    }
+
+    fn bool_with (&self, der: bool) -> Result<bool, err::Error> {
+        match *self.content()? {
+            [0x00] => Ok(false),
+            [0xFF] => Ok(true),
+            [_] if !der => Ok(true),
+            _ => Err(self.error(err::Kind::InvalidValue)),
+        }
+    }
+
+    /// The content octets of an OCTET STRING, or of a character string encoded like one,
+    /// whatever the tag number. A constructed encoding is reassembled from its segments,
+    /// which must be universal OCTET STRINGs; anything else among them is a
+    /// `Kind::InvalidTypeAndFlavor` error.
+    pub fn as_bytes (&self) -> Result<Cow<'_, [u8]>, err::Error> {
+        match self.payload {
+            Payload::Primitive(ref v) => Ok(Cow::Borrowed(v)),
+            Payload::Constructed(_) => {
+                let mut segments = Vec::new();
+                self.segments(Type::OctetString, &mut segments)?;
+                Ok(Cow::Owned(segments.iter().flat_map(|s| s.content().unwrap_or_default()).copied().collect()))
+            },
+        }
+    }
+
+    // Collects the primitive segments of a constructed string in order, descending into
+    // segments that are constructed in turn, with a stack of its own rather than the
+    // real one. Every segment must be a universal `t`.
+    pub(crate) fn segments<'a> (&'a self, t: Type, out: &mut Vec<&'a Tag>) -> Result<(), err::Error> {
+        let mut stack = vec![self.children().unwrap_or_default().iter()];
+        while let Some(siblings) = stack.last_mut() {
+            let segment = match siblings.next() {
+                Some(segment) => segment,
+                None => {
+                    stack.pop();
+                    continue;
+                },
+            };
+            if segment.number != Number::Universal(t) {
+                return Err(segment.error(err::Kind::InvalidTypeAndFlavor));
+            }
+            match segment.payload {
+                Payload::Primitive(_) => out.push(segment),
+                Payload::Constructed(ref children) => stack.push(children.iter()),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn null () -> Tag {
+        Tag::primitive(Number::Universal(Type::Null), vec![])
+    }
+
+    pub fn octet_string (v: Vec<u8>) -> Tag {
+        Tag::primitive(Number::Universal(Type::OctetString), v)
+    }
+
+    pub fn utf8_string (s: &str) -> Tag {
+        Tag::primitive(Number::Universal(Type::Utf8String), s.as_bytes().to_vec())
+    }
+
+    pub fn sequence (children: Vec<Tag>) -> Tag {
+        Tag::constructed(Number::Universal(Type::Sequence), children)
+    }
+
+    pub fn set (children: Vec<Tag>) -> Tag {
+        Tag::constructed(Number::Universal(Type::Set), children)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tag::{Number, Tag, Type};
+
+    #[test]
+    fn test_write_constructed_values () {
+        let tag = Tag::sequence(vec![
+            Tag::boolean(true),
+            Tag::null(),
+            Tag::octet_string(vec![0x01]),
+            Tag::utf8_string("def"),
+            Tag::integer(-1),
+        ]);
+
+        let mut buf = Vec::new();
+        tag.write(&mut buf).unwrap();
+        assert_eq!(buf, vec![
+            0x30, 0x80,
+            0x01, 0x01, 0xFF,
+            0x05, 0x00,
+            0x04, 0x01, 0x01,
+            0x0C, 0x03, 0x64, 0x65, 0x66,
+            0x02, 0x01, 0xFF,
+            0x00, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn test_as_bool () {
+        let loose = Tag::primitive(Number::Universal(Type::Boolean), vec![0x01]);
+        assert!(loose.as_bool().unwrap());
+        assert!(loose.as_bool_der().is_err());
+
+        assert!(Tag::boolean(true).as_bool_der().unwrap());
+        assert!(!Tag::boolean(false).as_bool_der().unwrap());
+
+        assert!(Tag::null().as_bool().is_err());
+        assert!(Tag::primitive(Number::Universal(Type::Boolean), vec![0xFF, 0xFF]).as_bool().is_err());
+        assert!(Tag::sequence(vec![]).as_bool().is_err());
+    }
+
+    #[test]
+    fn test_as_bytes_reassembles_segments () {
+        // OCTET STRING (constructed) { OCTET STRING "ab", OCTET STRING (constructed) { "c" } }
+        let input = [0x24, 0x80, 0x04, 0x02, 0x61, 0x62, 0x24, 0x03, 0x04, 0x01, 0x63, 0x00, 0x00];
+        let tag = Tag::read(&mut &input[..]).unwrap();
+        assert_eq!(&*tag.as_bytes().unwrap(), b"abc");
+        assert_eq!(&*Tag::octet_string(b"abc".to_vec()).as_bytes().unwrap(), b"abc");
+
+        let bad = Tag::constructed(Number::Universal(Type::OctetString), vec![Tag::octet_string(vec![1]), Tag::null()]);
+        assert!(matches!(bad.as_bytes().unwrap_err().kind, crate::err::Kind::InvalidTypeAndFlavor));
+    }
+}
@@ -0,0 +1,164 @@
+//! Walking a tree, for passes such as linting, counting, printing or searching that only
+//! care what each tag is and how deep it sits: with callbacks through `Tag::accept`, or
+//! as a loop over `Tag::iter` and `Tag::iter_mut`.
+
+use std::iter::FusedIterator;
+
+use crate::tag::{HeaderLayout, LengthForm, LengthRepair, Number, Payload, Tag};
+
+/// Callbacks for `Tag::accept`, each given the tag and its depth, the root being at 0.
+/// Every callback does nothing unless overridden.
+pub trait Visitor {
+    /// Called on a constructed tag before any of its children.
+    fn enter_constructed (&mut self, _tag: &Tag, _depth: usize) {}
+
+    fn visit_primitive (&mut self, _tag: &Tag, _content: &[u8], _depth: usize) {}
+
+    /// Called on a constructed tag after all of its children.
+    fn leave_constructed (&mut self, _tag: &Tag, _depth: usize) {}
+}
+
+// A step of the walk still to take.
+enum Step<'a> {
+    Enter(&'a Tag, usize),
+    Leave(&'a Tag, usize),
+}
+
+impl Tag {
+    /// Walks the tree in document order, calling `visitor` on each tag. The walk keeps
+    /// its own stack, so a deep tree can't overflow the real one.
+    pub fn accept<V: Visitor + ?Sized> (&self, visitor: &mut V) {
+        let mut stack = vec![Step::Enter(self, 0)];
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Enter(tag, depth) => match tag.payload {
+                    Payload::Primitive(ref v) => visitor.visit_primitive(tag, v, depth),
+                    Payload::Constructed(ref children) => {
+                        visitor.enter_constructed(tag, depth);
+                        stack.push(Step::Leave(tag, depth));
+                        stack.extend(children.iter().rev().map(|c| Step::Enter(c, depth + 1)));
+                    },
+                },
+                Step::Leave(tag, depth) => visitor.leave_constructed(tag, depth),
+            }
+        }
+    }
+
+    /// Every tag in the tree with its depth, in document order, the root first at 0.
+    pub fn iter (&self) -> Iter<'_> {
+        Iter { stack: vec![(0, self)] }
+    }
+
+    /// As `iter`, with each tag open for editing. Everything but the children is
+    /// editable; the children come next, as tags of their own.
+    pub fn iter_mut (&mut self) -> IterMut<'_> {
+        IterMut { stack: vec![(0, self)] }
+    }
+}
+
+/// The tags of a tree, from `Tag::iter`.
+pub struct Iter<'a> {
+    stack: Vec<(usize, &'a Tag)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (usize, &'a Tag);
+
+    fn next (&mut self) -> Option<Self::Item> {
+        let (depth, tag) = self.stack.pop()?;
+        if let Payload::Constructed(ref children) = tag.payload {
+            self.stack.extend(children.iter().rev().map(|c| (depth + 1, c)));
+        }
+        Some((depth, tag))
+    }
+}
+
+impl<'a> FusedIterator for Iter<'a> {}
+
+/// A tag's fields, borrowed for editing apart from its children, from `Tag::iter_mut`.
+pub struct TagMut<'a> {
+    pub number:      &'a mut Number,
+    pub offset:      &'a mut Option<usize>,
+    /// The content of a primitive, `None` for a constructed tag.
+    pub content:     Option<&'a mut Vec<u8>>,
+    pub repaired:    &'a mut Option<LengthRepair>,
+    pub length_form: &'a mut Option<LengthForm>,
+    pub layout:      &'a mut Option<HeaderLayout>,
+}
+
+/// The tags of a tree open for editing, from `Tag::iter_mut`.
+pub struct IterMut<'a> {
+    stack: Vec<(usize, &'a mut Tag)>,
+}
+
+impl<'a> Iterator for IterMut<'a> {
+    type Item = (usize, TagMut<'a>);
+
+    fn next (&mut self) -> Option<Self::Item> {
+        let (depth, tag) = self.stack.pop()?;
+        let Tag { number, offset, payload, repaired, length_form, layout } = tag;
+        let content = match payload {
+            Payload::Primitive(v) => Some(v),
+            Payload::Constructed(children) => {
+                self.stack.extend(children.iter_mut().rev().map(|c| (depth + 1, c)));
+                None
+            },
+        };
+        Some((depth, TagMut { number, offset, content, repaired, length_form, layout }))
+    }
+}
+
+impl<'a> FusedIterator for IterMut<'a> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Writes the tree out in the shape of an S-expression.
+    struct Outline(String);
+
+    impl Visitor for Outline {
+        fn enter_constructed (&mut self, tag: &Tag, _depth: usize) {
+            self.0 += &format!("({:?}", tag.number);
+        }
+
+        fn visit_primitive (&mut self, _tag: &Tag, content: &[u8], depth: usize) {
+            self.0 += &format!(" {}:{:02x?}", depth, content);
+        }
+
+        fn leave_constructed (&mut self, _tag: &Tag, _depth: usize) {
+            self.0 += ")";
+        }
+    }
+
+    #[test]
+    fn test_accept () {
+        let tag = Tag::sequence(vec![Tag::integer(1), Tag::sequence(vec![]), Tag::sequence(vec![Tag::null()])]);
+        let mut outline = Outline(String::new());
+        tag.accept(&mut outline);
+        assert_eq!(outline.0, "(Universal(Sequence) 1:[01](Universal(Sequence))(Universal(Sequence) 2:[]))");
+    }
+
+    #[test]
+    fn test_iter () {
+        use crate::tag::Type;
+
+        let mut tag = Tag::sequence(vec![Tag::integer(1), Tag::sequence(vec![Tag::integer(2)]), Tag::null()]);
+        let walk: Vec<_> = tag.iter().map(|(depth, t)| (depth, t.number)).collect();
+        assert_eq!(walk, [
+            (0, Number::Universal(Type::Sequence)),
+            (1, Number::Universal(Type::Integer)),
+            (1, Number::Universal(Type::Sequence)),
+            (2, Number::Universal(Type::Integer)),
+            (1, Number::Universal(Type::Null)),
+        ]);
+
+        // bump every INTEGER in place
+        for (_, t) in tag.iter_mut() {
+            if let (Number::Universal(Type::Integer), Some(v)) = (*t.number, t.content) {
+                v[0] += 10;
+            }
+        }
+        assert_eq!(tag, Tag::sequence(vec![Tag::integer(11), Tag::sequence(vec![Tag::integer(12)]), Tag::null()]));
+    }
+}
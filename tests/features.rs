@@ -0,0 +1,11 @@
+extern crate ber;
+
+#[test]
+fn test_features_match_flags () {
+    assert_eq!(ber::features().contains(&"chrono"), ber::feature::CHRONO);
+    assert_eq!(ber::features().contains(&"digest"), ber::feature::DIGEST);
+    assert_eq!(ber::features().contains(&"jks"), ber::feature::JKS);
+    assert_eq!(ber::features().contains(&"mmap"), ber::feature::MMAP);
+    assert_eq!(ber::features().contains(&"tokio"), ber::feature::TOKIO);
+    assert_eq!(ber::features().contains(&"tui"), ber::feature::TUI);
+}
@@ -0,0 +1,172 @@
+//! The contract on `Tag::read`: no input makes it panic or overflow the stack.
+//!
+//! Two checks hold it in place. An audit of the reading code in `src/tag.rs`, and of
+//! the DER validator strict reads run through in `src/der.rs`, fails if anything that
+//! can panic creeps in, and a seeded fuzz loop throws mutated and random
+//! input at every read option. A fuzzer finding goes in `tests/regressions/` as well,
+//! as `tests/regressions.rs` describes.
+
+extern crate ber;
+
+use ber::{Number, ReadOptions, Rules, Tag, TagNumber};
+
+// The functions a read runs through, and the files they're in.
+const READ_PATH: &[(&str, &[&str])] = &[
+    ("src/tag.rs", &[
+        "from_u8", "from_i8", "try_from", "observed", "shortest", "number_groups", "length_bytes",
+        "read_extended_number", "maybe_read_extended_number", "read_identifiers", "read_length",
+        "read_payload", "locate", "truncation", "not_der", "read", "inner_read", "read_with",
+        "read_in", "read_der",
+    ]),
+    ("src/der.rs", &[
+        "read_header", "is_string_type", "feed", "finish", "byte", "violation", "start_element",
+        "close_frames", "header_complete",
+    ]),
+];
+
+// The source of `file`, up to the proofs and tests at the bottom, which may unwrap all
+// they like.
+fn source (file: &str) -> &'static str {
+    let source = match file {
+        "src/tag.rs" => include_str!("../src/tag.rs"),
+        "src/der.rs" => include_str!("../src/der.rs"),
+        _ => panic!("no source for {}", file),
+    };
+    let end = ["#[cfg(kani)]", "#[cfg(test)]"].iter().filter_map(|m| source.find(m)).min();
+    &source[..end.unwrap_or(source.len())]
+}
+
+// Whatever can panic without an index: indexing is bounds-checked arithmetic the fuzz
+// loop covers.
+const FORBIDDEN: &[&str] = &[".unwrap()", ".expect(", "panic!", "unreachable!", "todo!", "unimplemented!", "assert!"];
+
+// The bodies of every function named `name` in `source`.
+fn bodies<'a> (source: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    for (at, _) in source.match_indices(&format!("fn {}", name)) {
+        let rest = &source[at + 3 + name.len()..];
+        if !rest.starts_with([' ', '(', '<']) {
+            continue;
+        }
+        let open = at + rest.find('{').unwrap() + 3 + name.len();
+        let mut depth = 0;
+        for (i, c) in source[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {},
+            }
+            if depth == 0 {
+                found.push(&source[open..=open + i]);
+                break;
+            }
+        }
+    }
+    found
+}
+
+#[test]
+fn test_read_path_audit () {
+    let mut problems = Vec::new();
+    for &(file, names) in READ_PATH {
+        for name in names {
+            let found = bodies(source(file), name);
+            assert!(!found.is_empty(), "no fn {} in {}", name, file);
+            for body in found {
+                for bad in FORBIDDEN.iter().filter(|bad| body.contains(*bad)) {
+                    problems.push(format!("fn {} in {} uses {}", name, file, bad));
+                }
+            }
+        }
+    }
+    assert!(problems.is_empty(), "{}", problems.join("\n"));
+}
+
+// xorshift64*, so every run throws the same inputs.
+struct Rng(u64);
+
+impl Rng {
+    fn below (&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize % n.max(1)
+    }
+
+    fn byte (&mut self) -> u8 {
+        self.below(256) as u8
+    }
+}
+
+fn seeds () -> Vec<Vec<u8>> {
+    let tree = Tag::sequence(vec![
+        Tag::integer(-129),
+        Tag::set(vec![Tag::null(), Tag::octet_string(b"hello".to_vec())]),
+        Tag::constructed(Number::ContextSpecific(TagNumber::new(300)), vec![Tag::sequence(vec![Tag::integer(1 << 40)])]),
+    ]);
+    let mut indefinite = Vec::new();
+    tree.write(&mut indefinite).unwrap();
+    vec![
+        indefinite,
+        tree.to_der().unwrap(),
+        vec![0x1F, 0x81, 0x80, 0x80, 0x80, 0x00],
+        vec![0x04, 0x84, 0xFF, 0xFF, 0xFF, 0xFF],
+        // the longest length there is, which ends past any offset
+        vec![0x04, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    ]
+}
+
+fn mutate (rng: &mut Rng, input: &mut Vec<u8>) {
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(input.len());
+        match rng.below(5) {
+            0 if !input.is_empty() => input[at] = rng.byte(),
+            1 if !input.is_empty() => input[at] ^= 1 << rng.below(8),
+            2 => input.insert(at, rng.byte()),
+            3 => input.truncate(at),
+            _ => {
+                let from = rng.below(input.len());
+                let piece = input[from..].iter().take(1 + rng.below(8)).copied().collect::<Vec<_>>();
+                input.splice(at..at, piece);
+            },
+        }
+    }
+}
+
+fn read_every_way (input: &[u8]) {
+    let _ = Tag::read(&mut &input[..]);
+    let _ = Tag::read_all(&mut &input[..]).count();
+    for opts in [
+        ReadOptions::builder().lenient(true).build(),
+        ReadOptions::builder().rules(Rules::Der).build(),
+        ReadOptions::builder().validate_utf8(true).max_total_payload_bytes(64).max_children_per_node(4).build(),
+    ] {
+        let _ = Tag::read_with(&mut &input[..], &opts);
+    }
+}
+
+#[test]
+fn test_fuzz () {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let seeds = seeds();
+    for i in 0..20_000 {
+        let mut input = match i % 8 {
+            0 => (0..rng.below(32)).map(|_| rng.byte()).collect(),
+            _ => seeds[rng.below(seeds.len())].clone(),
+        };
+        mutate(&mut rng, &mut input);
+        read_every_way(&input);
+    }
+}
+
+#[test]
+fn test_deep_nesting () {
+    // every way of opening a constructed tag, far deeper than any stack
+    let headers: [&[u8]; 4] = [&[0x30, 0x80], &[0x30, 0x84, 0x7F, 0xFF, 0xFF, 0xFF], &[0xBF, 0x81, 0x00, 0x80], &[0x24, 0x80]];
+    for header in headers {
+        let input = header.repeat(200_000);
+        let e = Tag::read(&mut &input[..]).unwrap_err();
+        assert!(matches!(e.kind, ber::err::Kind::TooDeep { .. }), "{:02x?}: {}", header, e);
+        read_every_way(&input);
+    }
+}
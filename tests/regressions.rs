@@ -0,0 +1,62 @@
+//! Regression corpus: every input that ever crashed or mis-parsed lives on here.
+//!
+//! Each fixture is a pair of files in `tests/regressions/`:
+//!
+//! * `<name>.ber`, the raw input, and
+//! * `<name>.expect`, containing `ok` if `Tag::read` must accept the input or `err` if
+//!   it must reject it.
+//!
+//! To turn a fuzzer finding into a fixture, pick a descriptive name and run
+//!
+//! ```text
+//! cargo run --example add_regression -- <name> <input file> <ok|err>
+//! ```
+//!
+//! then fix the parser until `cargo test --test regressions` passes. Every fixture is
+//! read again under strict DER rules, whose outcome isn't checked, and a panic from
+//! either read fails the test with the fixture's name.
+
+extern crate ber;
+
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+use ber::{ReadOptions, Rules, Tag};
+
+#[test]
+fn test_regressions () {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("regressions");
+    let mut failures = Vec::new();
+    let mut count = 0;
+
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("ber") {
+            continue;
+        }
+
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let input = fs::read(&path).unwrap();
+        let expect = fs::read_to_string(path.with_extension("expect"))
+            .unwrap_or_else(|_| panic!("{}: missing .expect file", name));
+
+        let outcome = panic::catch_unwind(|| {
+            let _ = Tag::read_with(&mut &input[..], &ReadOptions::builder().rules(Rules::Der).build());
+            Tag::read(&mut &input[..]).is_ok()
+        });
+        let outcome = match outcome {
+            Ok(true) => "ok",
+            Ok(false) => "err",
+            Err(_) => "panic",
+        };
+
+        if outcome != expect.trim() {
+            failures.push(format!("{}: expected {}, got {}", name, expect.trim(), outcome));
+        }
+        count += 1;
+    }
+
+    assert!(count > 0, "no fixtures found in {}", dir.display());
+    assert!(failures.is_empty(), "regressions:\n{}", failures.join("\n"));
+}
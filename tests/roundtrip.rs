@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate ber;
+
+roundtrip_tests!(regressions, ["tests/regressions/indefinite-sequence.ber"]);
+roundtrip_tests!(explicit_profiles, ["tests/regressions/indefinite-sequence.ber"], [ber::testing::Profile::ber(), ber::testing::Profile::der(), ber::testing::Profile::cer()]);
+
+#[test]
+fn test_differential () {
+    use ber::testing::{check_differential, Profile};
+
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let fixtures = ["tests/regressions/indefinite-sequence.ber", "tests/regressions/lying-primitive-length.ber"];
+
+    let strict = check_differential(dir, &fixtures[..1], &Profile::ber(), &Profile::strict());
+    assert_eq!(strict.len(), 1, "{:?}", strict);
+    assert!(strict[0].contains("accepted, then rejected with error 19"), "{}", strict[0]);
+
+    let lenient = check_differential(dir, &fixtures, &Profile::ber(), &Profile::lenient());
+    assert_eq!(lenient.len(), 1, "{:?}", lenient);
+    assert!(lenient[0].starts_with("tests/regressions/lying-primitive-length.ber [ber vs lenient]: rejected with error 15"), "{}", lenient[0]);
+}